@@ -0,0 +1,12 @@
+pub use crate::{
+    page_tables::{
+        offset_in_page, page_size, table_index, ENTRIES_PER_TABLE, FOUR_KILOBYTES, TWO_MEGABYTES,
+    },
+    two_tier::PAGE_SIZES,
+};
+
+/// The number of levels of translation tables with the 4 KB granule and 48 bit virtual addresses
+pub const LEVELS: u8 = 4;
+
+/// Memory frame allocator for aarch64 processors using the 4 KB translation granule
+pub type Aarch64FrameAllocator = crate::two_tier::TwoTierFrameAllocator;
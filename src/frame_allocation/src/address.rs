@@ -0,0 +1,60 @@
+use core::ops::{Add, Sub};
+
+macro_rules! address_type {
+    ($(#[$attribute:meta])* $name:ident) => {
+        $(#[$attribute])*
+        #[repr(transparent)]
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Hash)]
+        pub struct $name(usize);
+
+        impl $name {
+            #[must_use]
+            pub const fn new(address: usize) -> Self {
+                Self(address)
+            }
+
+            #[must_use]
+            pub const fn as_usize(self) -> usize {
+                self.0
+            }
+        }
+
+        impl From<usize> for $name {
+            fn from(address: usize) -> Self {
+                Self(address)
+            }
+        }
+
+        impl From<$name> for usize {
+            fn from(address: $name) -> Self {
+                address.0
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = Self;
+
+            fn add(self, offset: usize) -> Self {
+                Self(self.0 + offset)
+            }
+        }
+
+        impl Sub<usize> for $name {
+            type Output = Self;
+
+            fn sub(self, offset: usize) -> Self {
+                Self(self.0 - offset)
+            }
+        }
+    };
+}
+
+address_type!(
+    /// An address in physical memory
+    PhysicalAddress
+);
+
+address_type!(
+    /// An address in a virtual address space
+    VirtualAddress
+);
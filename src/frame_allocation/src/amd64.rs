@@ -1,7 +1,37 @@
-use crate::{FfiOption, FrameAllocator};
+use crate::{range_math::align_inward, FfiOption, FrameAllocator, PhysicalAddress};
+use core::ops::Range;
 
-pub const FOUR_KILOBYTES: usize = 0x1000;
-pub const GIGABYTE: usize = 0x4000_0000;
+pub use crate::page_tables::{
+    offset_in_page, page_size, table_index, ENTRIES_PER_TABLE, FOUR_KILOBYTES, GIGABYTE,
+    TWO_MEGABYTES,
+};
+
+/// The number of levels of page tables
+pub const LEVELS: u8 = 4;
+
+/// The sizes of the frames that the allocator keeps, from smallest to largest. These are also the
+/// sizes of the pages that the page tables can map.
+pub const PAGE_SIZES: &[usize] = &[FOUR_KILOBYTES, TWO_MEGABYTES, GIGABYTE];
+
+/// The sizes of frame that `Amd64FrameAllocator` keeps separate free lists for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granularity {
+    FourKilobyte,
+    TwoMegabyte,
+    Gigabyte,
+}
+
+impl Granularity {
+    /// The size of the frames in bytes
+    #[must_use]
+    pub const fn frame_size(self) -> usize {
+        match self {
+            Self::FourKilobyte => FOUR_KILOBYTES,
+            Self::TwoMegabyte => TWO_MEGABYTES,
+            Self::Gigabyte => GIGABYTE,
+        }
+    }
+}
 
 /// Memory frame allocator for AMD64 processors
 #[repr(C)]
@@ -15,6 +45,16 @@ pub struct Amd64FrameAllocator {
 }
 
 impl Amd64FrameAllocator {
+    /// Constructs an allocator without any frames in it. Gigabyte frames are left out until
+    /// `gigabyte_pages` is set.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            four_kilobyte_pages: FrameAllocator::new(),
+            two_megabyte_pages: FrameAllocator::new(),
+            gigabyte_pages: FfiOption::None,
+        }
+    }
 
     /**
      * Retrieves a 4 kilobyte frame of available memory from the allocator
@@ -25,12 +65,14 @@ impl Amd64FrameAllocator {
      * undefined behavior if invalid or already-in-use memory regions have been added to the
      * allocator previously.
      */
-    pub unsafe fn get_4k_frame(&mut self) -> Option<usize> {
+    #[must_use]
+    pub unsafe fn get_4k_frame(&mut self) -> Option<PhysicalAddress> {
         if let Some(frame) = self.four_kilobyte_pages.get_frame() {
             Some(frame)
         } else if let Some(frame) = self.get_2mb_frame() {
+            let frame_start = frame.as_usize();
             self.four_kilobyte_pages
-                .add_frames((frame + FOUR_KILOBYTES)..(frame + TWO_MEGABYTES));
+                .add_frames((frame_start + FOUR_KILOBYTES)..(frame_start + TWO_MEGABYTES));
             Some(frame)
         } else {
             None
@@ -46,17 +88,680 @@ impl Amd64FrameAllocator {
      * undefined behavior if invalid or already-in-use memory regions have been added to the
      * allocator previously.
      */
-    pub unsafe fn get_2mb_frame(&mut self) -> Option<usize> {
+    #[must_use]
+    pub unsafe fn get_2mb_frame(&mut self) -> Option<PhysicalAddress> {
         if let Some(frame) = self.two_megabyte_pages.get_frame() {
             Some(frame)
         } else if let Some(frame) = self.gigabyte_pages.as_mut()?.get_frame() {
+            let frame_start = frame.as_usize();
             self.two_megabyte_pages
-                .add_frames((frame + TWO_MEGABYTES)..(frame + GIGABYTE));
+                .add_frames((frame_start + TWO_MEGABYTES)..(frame_start + GIGABYTE));
             Some(frame)
         } else {
             None
         }
     }
+
+    /// Returns true if a frame of any size can be allocated. Larger frames are split up when the
+    /// smaller ones run out, so this is also whether `get_4k_frame` can succeed.
+    #[must_use]
+    pub const fn frames_available(&self) -> bool {
+        !self.four_kilobyte_pages.is_empty()
+            || !self.two_megabyte_pages.is_empty()
+            || matches!(&self.gigabyte_pages, FfiOption::Some(pages) if !pages.is_empty())
+    }
+
+    /**
+     * The number of bytes in free frames of every size. Like `FrameAllocator::frame_count`, this
+     * walks every free list without taking any frames.
+     */
+    #[must_use]
+    pub fn total_bytes_free(&self) -> usize {
+        [
+            Granularity::FourKilobyte,
+            Granularity::TwoMegabyte,
+            Granularity::Gigabyte,
+        ]
+        .into_iter()
+        .map(|granularity| self.bytes_free_at_granularity(granularity))
+        .sum()
+    }
+
+    /// The number of bytes in free frames of one size. Larger frames aren't counted even though
+    /// they'd be split up if frames of this size ran out.
+    #[must_use]
+    pub fn bytes_free_at_granularity(&self, granularity: Granularity) -> usize {
+        let frame_count = match granularity {
+            Granularity::FourKilobyte => self.four_kilobyte_pages.frame_count(),
+            Granularity::TwoMegabyte => self.two_megabyte_pages.frame_count(),
+            Granularity::Gigabyte => match &self.gigabyte_pages {
+                FfiOption::Some(pages) => pages.frame_count(),
+                FfiOption::None => 0,
+            },
+        };
+        frame_count * granularity.frame_size()
+    }
+
+    /**
+     * Gives a 4 KB frame back to the allocator. If that makes all 512 of the 4 KB frames in its
+     * 2 MB frame free, they're merged back into the 2 MB frame, which `return_2mb_frame` may merge
+     * further.
+     *
+     * The allocator doesn't record which frames are free, so finding the other 511 frames walks
+     * the 4 KB free list. This takes time proportional to the number of free 4 KB frames.
+     *
+     * # Safety
+     *
+     * `address` must be the start of a 4 KB frame of valid memory that's no longer in use and
+     * isn't already in the allocator.
+     */
+    pub unsafe fn return_4k_frame(&mut self, address: PhysicalAddress) {
+        self.four_kilobyte_pages.add_frame(address);
+        let big_frame = containing_frame(address.as_usize(), TWO_MEGABYTES);
+        if self.four_kilobyte_pages.take_all_in(&big_frame) {
+            self.return_2mb_frame(PhysicalAddress::new(big_frame.start));
+        }
+    }
+
+    /**
+     * Gives a 2 MB frame back to the allocator. If there's a 1 GB allocator and that makes all 512
+     * of the 2 MB frames in its 1 GB frame free, they're merged back into the 1 GB frame. Like
+     * `return_4k_frame`, this walks a free list.
+     *
+     * # Safety
+     *
+     * `address` must be the start of a 2 MB frame of valid memory that's no longer in use and
+     * isn't already in the allocator, in whole or in part.
+     */
+    pub unsafe fn return_2mb_frame(&mut self, address: PhysicalAddress) {
+        self.two_megabyte_pages.add_frame(address);
+        if let FfiOption::Some(ref mut gigabyte_pages) = self.gigabyte_pages {
+            let huge_frame = containing_frame(address.as_usize(), GIGABYTE);
+            if self.two_megabyte_pages.take_all_in(&huge_frame) {
+                gigabyte_pages.add_frame(PhysicalAddress::new(huge_frame.start));
+            }
+        }
+    }
+
+    /**
+     * Adds the frames in a region of available memory to the allocator. Whole 1 GB frames go to
+     * the huge page allocator if there is one, whole 2 MB frames around them go to the big page
+     * allocator, and whole 4 KB frames around those go to the standard page allocator.
+     *
+     * # Safety
+     *
+     * `memory_region` must represent a range of valid and available memory. If there are addresses
+     * in the range that don't represent valid memory or represent memory that is already in use,
+     * then undefined behavior may occur.
+     */
+    pub unsafe fn add_memory_region(&mut self, memory_region: Range<usize>) {
+        if let FfiOption::Some(ref mut gb_allocator) = self.gigabyte_pages {
+            let gb_frames = align_inward(memory_region.clone(), GIGABYTE);
+            if !gb_frames.is_empty() {
+                self.two_megabyte_pages
+                    .add_aligned_frames_with_scrap_allocator(
+                        &mut self.four_kilobyte_pages,
+                        memory_region.start..gb_frames.start,
+                    );
+                gb_allocator.add_frames(gb_frames.clone());
+                self.two_megabyte_pages
+                    .add_aligned_frames_with_scrap_allocator(
+                        &mut self.four_kilobyte_pages,
+                        gb_frames.end..memory_region.end,
+                    );
+                return;
+            }
+        }
+        self.two_megabyte_pages
+            .add_aligned_frames_with_scrap_allocator(&mut self.four_kilobyte_pages, memory_region);
+    }
 }
 
-const TWO_MEGABYTES: usize = 0x20_0000;
+impl Default for Amd64FrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The frame of `size` bytes that `address` is in
+fn containing_frame(address: usize, size: usize) -> Range<usize> {
+    let start = address / size * size;
+    start..start + size
+}
+
+/// A randomized stress test of the allocator. Each run drives the allocator through a seeded
+/// sequence of operations over an arena of host memory and checks it against a model of which
+/// bytes should be free. The default run is short enough for every `cargo test`, and the ignored
+/// long run goes much further (`cargo test -- --ignored`). A failure names the seed and the
+/// operation that went wrong, and `FRAME_ALLOCATOR_STRESS_SEED` and
+/// `FRAME_ALLOCATOR_STRESS_OPERATIONS` replay just that run up to that operation.
+#[cfg(test)]
+mod stress_tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{end_of_last_full_page, first_full_page_address};
+    use std::{collections::BTreeMap, env, thread, vec::Vec};
+    use test_arena::Arena;
+
+    const ARENA_SIZE: usize = 32 * TWO_MEGABYTES;
+    const SEEDS: [u64; 4] = [
+        0x9e37_79b9_7f4a_7c15,
+        0x2545_f491_4f6c_dd1d,
+        0xd1b5_4a32_d192_ed03,
+        0x8cb9_2ba7_2f3d_8dd7,
+    ];
+    /// The operations in each run of the default test, which covers a few phases of each kind
+    const OPERATIONS: usize = 100_000;
+    /// The operations in each run of the ignored long test
+    const LONG_OPERATIONS: usize = 2_000_000;
+    /// Walking the free lists is slow, so they're only compared with the model this often
+    const WALK_INTERVAL: usize = 8192;
+    /// The average number of operations before the run switches between using up memory and
+    /// giving it back
+    const PHASE_LENGTH: usize = 20_000;
+    /// The longest region that's registered at once
+    const MAX_REGION_SIZE: usize = 4 * TWO_MEGABYTES;
+    /// The most frames that are freed together by merging allocators
+    const MAX_BULK_FREE: usize = 64;
+    /// Returning a 4 KB frame walks the free list, so only about one in this many 4 KB frees
+    /// returns the frame that way
+    const RETURN_INTERVAL: usize = 256;
+
+    /// A xorshift generator seeded by the run so that the run can be replayed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            self.next() % bound
+        }
+    }
+
+    /// A set of bytes kept as disjoint ranges, with touching ranges merged
+    #[derive(Default)]
+    struct IntervalSet {
+        ranges: BTreeMap<usize, usize>,
+        bytes: usize,
+    }
+
+    impl IntervalSet {
+        /// Adds `range`. Returns false without changing anything if any of it is already in the set.
+        fn insert(&mut self, range: Range<usize>) -> bool {
+            if range.is_empty() {
+                return true;
+            }
+            let before = self.ranges.range(..range.end).next_back();
+            if before.is_some_and(|(_, &end)| end > range.start) {
+                return false;
+            }
+            let mut merged = range.clone();
+            if let Some((&start, &end)) = before {
+                if end == range.start {
+                    self.ranges.remove(&start);
+                    merged.start = start;
+                }
+            }
+            if let Some(end) = self.ranges.remove(&range.end) {
+                merged.end = end;
+            }
+            self.ranges.insert(merged.start, merged.end);
+            self.bytes += range.len();
+            true
+        }
+
+        /// Returns true if all of `range` is in the set
+        fn contains(&self, range: &Range<usize>) -> bool {
+            self.ranges
+                .range(..=range.start)
+                .next_back()
+                .is_some_and(|(_, &end)| range.end <= end)
+        }
+
+        /// Removes `range`. Returns false without changing anything if any of it isn't in the set.
+        fn remove(&mut self, range: Range<usize>) -> bool {
+            if range.is_empty() {
+                return true;
+            }
+            let Some((&start, &end)) = self.ranges.range(..=range.start).next_back() else {
+                return false;
+            };
+            if end < range.end {
+                return false;
+            }
+            self.ranges.remove(&start);
+            if start < range.start {
+                self.ranges.insert(start, range.start);
+            }
+            if range.end < end {
+                self.ranges.insert(range.end, end);
+            }
+            self.bytes -= range.len();
+            true
+        }
+    }
+
+    /// What the allocator should hold
+    #[derive(Default)]
+    struct Model {
+        /// Every byte that the allocator should be able to hand out
+        free: IntervalSet,
+        free_4k_frames: usize,
+        free_2mb_frames: usize,
+        /// Frames that have been handed out and not returned, with their sizes
+        allocated: Vec<(usize, usize)>,
+        /// Arena memory that hasn't been given to the allocator yet
+        unregistered: IntervalSet,
+    }
+
+    struct Run {
+        seed: u64,
+        operation: usize,
+        rng: Rng,
+        /// Whether the run is currently using up memory or giving it back. Runs alternate so
+        /// that the allocator runs dry and has to split big frames.
+        draining: bool,
+        arena: Range<usize>,
+        allocator: Amd64FrameAllocator,
+        model: Model,
+    }
+
+    impl Run {
+        fn new(seed: u64, arena: Range<usize>) -> Self {
+            let mut model = Model::default();
+            model.unregistered.insert(arena.clone());
+            Self {
+                seed,
+                operation: 0,
+                rng: Rng(seed),
+                draining: true,
+                arena,
+                allocator: Amd64FrameAllocator::new(),
+                model,
+            }
+        }
+
+        #[track_caller]
+        fn check(&self, condition: bool, message: &str) {
+            assert!(
+                condition,
+                "{message} (seed {:#x}, operation {}; replay with FRAME_ALLOCATOR_STRESS_SEED={:#x} \
+                 FRAME_ALLOCATOR_STRESS_OPERATIONS={})",
+                self.seed,
+                self.operation,
+                self.seed,
+                self.operation + 1
+            );
+        }
+
+        fn step(&mut self) {
+            if self.rng.below(PHASE_LENGTH) == 0 {
+                self.draining = !self.draining;
+            }
+            let frees = if self.draining { 15 } else { 85 };
+            match self.rng.below(100) {
+                0..2 => self.add_memory_region(),
+                roll if roll < 2 + frees && self.rng.below(32) == 0 => self.free_frames_in_bulk(),
+                roll if roll < 2 + frees => self.free_frame(),
+                roll if roll % 5 == 0 => self.get_2mb_frame(),
+                _ => self.get_4k_frame(),
+            }
+            if self.operation.is_multiple_of(WALK_INTERVAL) {
+                self.compare_free_lists();
+            }
+        }
+
+        /// Gives the allocator part of the arena that it hasn't seen, with edges that are
+        /// sometimes aligned to 2 MB, sometimes to 4 KB, and sometimes not at all
+        fn add_memory_region(&mut self) {
+            let pieces = self.model.unregistered.ranges.len();
+            if pieces == 0 {
+                return;
+            }
+            let index = self.rng.below(pieces);
+            let (&piece_start, &piece_end) =
+                self.model.unregistered.ranges.iter().nth(index).unwrap();
+            let offset = self.rng.below(piece_end - piece_start);
+            let start = self.edge(piece_start + offset, piece_start..piece_end);
+            if start == piece_end {
+                return;
+            }
+            let size = self.rng.below(MAX_REGION_SIZE.min(piece_end - start)) + 1;
+            let end = self.edge(start + size, start..piece_end);
+            if end == start {
+                return;
+            }
+            let region = start..end;
+            self.model.unregistered.remove(region.clone());
+
+            // Whole 2 MB frames go to the 2 MB allocator and whole 4 KB frames around them go to
+            // the 4 KB allocator. Anything else is lost.
+            let first_big_frame = first_full_page_address(start, TWO_MEGABYTES);
+            let end_of_big_frames = end_of_last_full_page(end, TWO_MEGABYTES);
+            let (small_pieces, big_frames) = if end_of_big_frames > first_big_frame {
+                (
+                    [start..first_big_frame, end_of_big_frames..end],
+                    first_big_frame..end_of_big_frames,
+                )
+            } else {
+                ([region.clone(), end..end], 0..0)
+            };
+            for piece in small_pieces {
+                let frames = first_full_page_address(piece.start, FOUR_KILOBYTES)
+                    ..end_of_last_full_page(piece.end, FOUR_KILOBYTES);
+                if frames.end > frames.start {
+                    self.model.free_4k_frames += frames.len() / FOUR_KILOBYTES;
+                    self.model.free.insert(frames);
+                }
+            }
+            self.model.free_2mb_frames += big_frames.len() / TWO_MEGABYTES;
+            self.model.free.insert(big_frames);
+            unsafe { self.allocator.add_memory_region(region) };
+        }
+
+        /// Moves `address` to a 2 MB or 4 KB boundary most of the time, as long as that keeps it
+        /// inside `bounds`
+        fn edge(&mut self, address: usize, bounds: Range<usize>) -> usize {
+            let aligned = match self.rng.below(3) {
+                0 => address / TWO_MEGABYTES * TWO_MEGABYTES,
+                1 => address / FOUR_KILOBYTES * FOUR_KILOBYTES,
+                _ => address,
+            };
+            aligned.clamp(bounds.start, bounds.end)
+        }
+
+        fn get_4k_frame(&mut self) {
+            let frame = unsafe { self.allocator.get_4k_frame() };
+            let expected = self.model.free_4k_frames > 0 || self.model.free_2mb_frames > 0;
+            self.check(
+                frame.is_some() == expected,
+                "get_4k_frame disagreed with the model about running out",
+            );
+            if let Some(frame) = frame {
+                if self.model.free_4k_frames == 0 {
+                    self.model.free_2mb_frames -= 1;
+                    self.model.free_4k_frames += TWO_MEGABYTES / FOUR_KILOBYTES;
+                }
+                self.model.free_4k_frames -= 1;
+                self.hand_out(frame, FOUR_KILOBYTES);
+            }
+        }
+
+        fn get_2mb_frame(&mut self) {
+            let frame = unsafe { self.allocator.get_2mb_frame() };
+            self.check(
+                frame.is_some() == (self.model.free_2mb_frames > 0),
+                "get_2mb_frame disagreed with the model about running out",
+            );
+            if let Some(frame) = frame {
+                self.model.free_2mb_frames -= 1;
+                self.hand_out(frame, TWO_MEGABYTES);
+            }
+        }
+
+        fn hand_out(&mut self, frame: PhysicalAddress, size: usize) {
+            let frame = frame.as_usize();
+            let range = frame..frame + size;
+            self.check(frame.is_multiple_of(size), "a frame was misaligned");
+            self.check(
+                self.arena.start <= range.start && range.end <= self.arena.end,
+                "a frame was outside the arena",
+            );
+            let was_free = self.model.free.remove(range);
+            self.check(
+                was_free,
+                "a frame was handed out while part of it was already in use",
+            );
+            // Scribbling over the frame corrupts the free lists if the allocator still has it
+            unsafe { (frame as *mut [u8; 32]).write([0xa5; 32]) };
+            self.model.allocated.push((frame, size));
+        }
+
+        fn free_frame(&mut self) {
+            let Some((frame, size)) = self.take_allocated_frame() else {
+                return;
+            };
+            unsafe {
+                if size != FOUR_KILOBYTES {
+                    self.allocator.two_megabyte_pages.add_frame(frame);
+                } else if self.rng.below(RETURN_INTERVAL) == 0 {
+                    self.return_4k_frame(frame);
+                } else {
+                    self.allocator.four_kilobyte_pages.add_frame(frame);
+                }
+            }
+        }
+
+        /// Frees a 4 KB frame in a way that merges its 2 MB frame back together if it can. If
+        /// the whole 2 MB frame is free then the rest of it has to be in the 4 KB free list,
+        /// since part of it was just in use.
+        unsafe fn return_4k_frame(&mut self, frame: PhysicalAddress) {
+            self.allocator.return_4k_frame(frame);
+            if self
+                .model
+                .free
+                .contains(&containing_frame(frame.as_usize(), TWO_MEGABYTES))
+            {
+                self.model.free_4k_frames -= TWO_MEGABYTES / FOUR_KILOBYTES;
+                self.model.free_2mb_frames += 1;
+            }
+        }
+
+        /// Frees several frames at once by gathering them in allocators of their own and merging
+        /// those into the allocator
+        fn free_frames_in_bulk(&mut self) {
+            let mut four_kilobyte_pages = FrameAllocator::new();
+            let mut two_megabyte_pages = FrameAllocator::new();
+            for _ in 0..=self.rng.below(MAX_BULK_FREE) {
+                let Some((frame, size)) = self.take_allocated_frame() else {
+                    break;
+                };
+                unsafe {
+                    if size == FOUR_KILOBYTES {
+                        four_kilobyte_pages.add_frame(frame);
+                    } else {
+                        two_megabyte_pages.add_frame(frame);
+                    }
+                }
+            }
+            unsafe {
+                self.allocator
+                    .four_kilobyte_pages
+                    .merge_from(&mut four_kilobyte_pages);
+                self.allocator
+                    .two_megabyte_pages
+                    .merge_from(&mut two_megabyte_pages);
+            }
+            self.check(
+                four_kilobyte_pages.is_empty() && two_megabyte_pages.is_empty(),
+                "merging left frames behind",
+            );
+        }
+
+        /// Picks a frame that has been handed out and marks it free in the model. The caller
+        /// gives it back to an allocator.
+        fn take_allocated_frame(&mut self) -> Option<(PhysicalAddress, usize)> {
+            let allocated = self.model.allocated.len();
+            if allocated == 0 {
+                return None;
+            }
+            let (frame, size) = self.model.allocated.swap_remove(self.rng.below(allocated));
+            self.model.free.insert(frame..frame + size);
+            if size == FOUR_KILOBYTES {
+                self.model.free_4k_frames += 1;
+            } else {
+                self.model.free_2mb_frames += 1;
+            }
+            Some((PhysicalAddress::new(frame), size))
+        }
+
+        /// Checks that the free lists hold exactly the memory that the model says is free
+        fn compare_free_lists(&self) {
+            let mut frames = self.free_list(&self.allocator.four_kilobyte_pages);
+            self.check(
+                frames.len() == self.model.free_4k_frames,
+                "the 4 KB free list is the wrong length",
+            );
+            self.check(
+                self.allocator.four_kilobyte_pages.frame_count() == frames.len(),
+                "the 4 KB frame count doesn't match the free list",
+            );
+            let big_frames = self.free_list(&self.allocator.two_megabyte_pages);
+            self.check(
+                big_frames.len() == self.model.free_2mb_frames,
+                "the 2 MB free list is the wrong length",
+            );
+            self.check(
+                self.allocator.two_megabyte_pages.frame_count() == big_frames.len(),
+                "the 2 MB frame count doesn't match the free list",
+            );
+            self.check(
+                self.allocator.frames_available() == !(frames.is_empty() && big_frames.is_empty()),
+                "frames_available doesn't match the free lists",
+            );
+            self.check(
+                self.allocator.total_bytes_free()
+                    == frames.len() * FOUR_KILOBYTES + big_frames.len() * TWO_MEGABYTES,
+                "total_bytes_free doesn't match the free lists",
+            );
+            frames.extend(big_frames);
+            frames.sort_unstable_by_key(|frame| frame.start);
+            self.check(
+                frames.windows(2).all(|pair| pair[0].end <= pair[1].start),
+                "free frames overlap",
+            );
+            let mut merged: Vec<Range<usize>> = Vec::new();
+            for frame in frames {
+                match merged.last_mut() {
+                    Some(last) if last.end == frame.start => last.end = frame.end,
+                    _ => merged.push(frame),
+                }
+            }
+            self.check(
+                merged.into_iter().eq(self
+                    .model
+                    .free
+                    .ranges
+                    .iter()
+                    .map(|(&start, &end)| start..end)),
+                "the free lists don't hold exactly the free memory",
+            );
+        }
+
+        fn free_list<const SIZE: usize>(
+            &self,
+            allocator: &FrameAllocator<SIZE>,
+        ) -> Vec<Range<usize>> {
+            let mut frames = Vec::new();
+            let mut next = allocator.next;
+            while let FfiOption::Some(frame) = next {
+                let frame = frame as usize;
+                self.check(frames.len() <= ARENA_SIZE / SIZE, "a free list has a cycle");
+                self.check(
+                    self.arena.start <= frame
+                        && frame + SIZE <= self.arena.end
+                        && frame.is_multiple_of(SIZE),
+                    "a free list holds a frame outside the arena",
+                );
+                frames.push(frame..frame + SIZE);
+                next = unsafe { (*(frame as *const FrameAllocator<SIZE>)).next };
+            }
+            self.check(
+                allocator.iter().eq(frames.iter().map(|frame| frame.start)),
+                "iterating over a free list doesn't follow the list",
+            );
+            frames
+        }
+    }
+
+    fn parse_env(name: &str) -> Option<u64> {
+        let value = env::var(name).ok()?;
+        let parsed = match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        Some(parsed.unwrap_or_else(|_| panic!("{name} isn't a number")))
+    }
+
+    /// Runs every seed, or just the one in `FRAME_ALLOCATOR_STRESS_SEED`, for `operations`
+    /// operations unless `FRAME_ALLOCATOR_STRESS_OPERATIONS` says otherwise
+    fn run_seeds(operations: usize) {
+        let seeds = match parse_env("FRAME_ALLOCATOR_STRESS_SEED") {
+            Some(seed) => std::vec![seed],
+            None => SEEDS.to_vec(),
+        };
+        let operations = parse_env("FRAME_ALLOCATOR_STRESS_OPERATIONS")
+            .map_or(operations, |operations| operations as usize);
+        // The seeds are independent, so they each get an arena and a thread
+        thread::scope(|scope| {
+            for seed in seeds {
+                scope.spawn(move || {
+                    let arena = Arena::new(ARENA_SIZE);
+                    let mut run = Run::new(seed, arena.range());
+                    while run.operation < operations {
+                        run.step();
+                        run.operation += 1;
+                    }
+                    run.compare_free_lists();
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn random_operations_match_the_model() {
+        run_seeds(OPERATIONS);
+    }
+
+    #[test]
+    #[ignore = "slow; run with --ignored"]
+    fn many_random_operations_match_the_model() {
+        run_seeds(LONG_OPERATIONS);
+    }
+
+    #[test]
+    fn returned_frames_are_merged() {
+        let arena = Arena::new(ARENA_SIZE);
+        let big_frame = arena.range().start..arena.range().start + TWO_MEGABYTES;
+        let mut allocator = Amd64FrameAllocator::new();
+        unsafe {
+            allocator.add_memory_region(big_frame.clone());
+            let frames: Vec<PhysicalAddress> = (0..TWO_MEGABYTES / FOUR_KILOBYTES)
+                .map(|_| allocator.get_4k_frame().unwrap())
+                .collect();
+            assert!(!allocator.frames_available());
+            // Every frame but the last one to be returned stays a 4 KB frame
+            for &frame in &frames[1..] {
+                allocator.return_4k_frame(frame);
+            }
+            assert_eq!(
+                allocator.four_kilobyte_pages.frame_count(),
+                frames.len() - 1
+            );
+            assert!(allocator.two_megabyte_pages.is_empty());
+            allocator.return_4k_frame(frames[0]);
+        }
+        assert!(allocator.four_kilobyte_pages.is_empty());
+        assert!(allocator.two_megabyte_pages.iter().eq([big_frame.start]));
+    }
+
+    #[test]
+    fn interval_set_merges_and_splits() {
+        let mut set = IntervalSet::default();
+        assert!(set.insert(10..20));
+        assert!(set.insert(30..40));
+        assert!(set.insert(20..30));
+        assert_eq!(set.ranges.len(), 1);
+        assert!(!set.insert(35..45));
+        assert!(set.remove(15..25));
+        assert_eq!(set.ranges.len(), 2);
+        assert!(!set.remove(14..16));
+        assert_eq!(set.bytes, 20);
+    }
+}
@@ -0,0 +1,39 @@
+//! The layouts of the frame allocators. The kernel hands its allocator to the memory manager, and
+//! the first bytes of every free frame hold a `FrameAllocator`, so both sides have to agree on
+//! these.
+
+#[cfg(target_arch = "x86_64")]
+use crate::amd64::Amd64FrameAllocator;
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+use crate::two_tier::TwoTierFrameAllocator;
+use crate::{FfiOption, FfiResult, FrameAllocator};
+use layout_assert::layout_assert;
+
+// A u32 tag, padding, and the value
+#[cfg(target_pointer_width = "64")]
+layout_assert!(FfiOption<*mut u8>, size = 16, align = 8);
+#[cfg(target_pointer_width = "32")]
+layout_assert!(FfiOption<*mut u8>, size = 8, align = 4);
+#[cfg(target_pointer_width = "64")]
+layout_assert!(FfiResult<*mut u8, u32>, size = 16, align = 8);
+#[cfg(target_pointer_width = "32")]
+layout_assert!(FfiResult<*mut u8, u32>, size = 8, align = 4);
+
+#[cfg(target_pointer_width = "64")]
+layout_assert!(FrameAllocator<0x1000>, size = 16, { next: 0 });
+#[cfg(target_pointer_width = "32")]
+layout_assert!(FrameAllocator<0x1000>, size = 8, { next: 0 });
+
+// The gigabyte allocator is an `FfiOption`, so it takes 8 more bytes than the others
+#[cfg(target_arch = "x86_64")]
+layout_assert!(Amd64FrameAllocator, size = 56, align = 8, {
+    four_kilobyte_pages: 0,
+    two_megabyte_pages: 16,
+    gigabyte_pages: 32,
+});
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+layout_assert!(TwoTierFrameAllocator, size = 32, align = 8, {
+    four_kilobyte_pages: 0,
+    two_megabyte_pages: 16,
+});
@@ -1,13 +1,27 @@
 #![no_std]
 #![feature(try_trait_v2)]
 
+// The aarch64 and RISC-V modules are only page table arithmetic, so they're built for every target
+// and the kernel's host tests can check those page tables
+pub mod aarch64;
+mod address;
 #[cfg(target_arch = "x86_64")]
 pub mod amd64;
+mod layout;
+pub mod page_tables;
+pub mod range_math;
+pub mod riscv64;
+pub mod two_tier;
+
+pub use address::{PhysicalAddress, VirtualAddress};
+pub use page_tables::{end_of_last_full_page, first_full_page_address};
 
 use core::{
     convert::Infallible,
+    marker::PhantomData,
     ops::{ControlFlow, FromResidual, Range, Try},
 };
+use range_math::align_inward;
 
 /// Like `Option`, but with a stable ABI so that it can be used in foreign function interfaces.
 #[repr(C)]
@@ -48,6 +62,44 @@ impl<T> FromResidual<Option<Infallible>> for FfiOption<T> {
     }
 }
 
+/// Like `Result`, but with a stable ABI so that it can be used in foreign function interfaces.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FfiResult<T, E> {
+    Ok(T),
+    Err(E),
+}
+
+impl<T, E> From<Result<T, E>> for FfiResult<T, E> {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Self::Ok(value),
+            Err(error) => Self::Err(error),
+        }
+    }
+}
+
+impl<T, E> Try for FfiResult<T, E> {
+    type Output = T;
+    type Residual = Result<Infallible, E>;
+    fn from_output(output: Self::Output) -> Self {
+        Self::Ok(output)
+    }
+    fn branch(self) -> ControlFlow<Self::Residual, Self::Output> {
+        match self {
+            Self::Ok(output) => ControlFlow::Continue(output),
+            Self::Err(error) => ControlFlow::Break(Err(error)),
+        }
+    }
+}
+
+impl<T, E, F: From<E>> FromResidual<Result<Infallible, E>> for FfiResult<T, F> {
+    fn from_residual(residual: Result<Infallible, E>) -> Self {
+        let Err(error) = residual;
+        Self::Err(error.into())
+    }
+}
+
 /// A memory allocator that allocates memory in fixed-sized frames
 #[repr(C)]
 pub struct FrameAllocator<const FRAME_SIZE: usize> {
@@ -68,7 +120,7 @@ impl<const MEMORY_FRAME_SIZE: usize> FrameAllocator<MEMORY_FRAME_SIZE> {
      */
     pub unsafe fn add_frames(&mut self, memory_area: Range<usize>) {
         for frame in memory_area.step_by(Self::FRAME_SIZE) {
-            self.add_frame(frame);
+            self.add_frame(PhysicalAddress::new(frame));
         }
     }
 
@@ -81,10 +133,11 @@ impl<const MEMORY_FRAME_SIZE: usize> FrameAllocator<MEMORY_FRAME_SIZE> {
      * undefined behavior if invalid or already-in-use memory regions have been added to the
      * allocator previously.
      */
-    unsafe fn get_frame(&mut self) -> Option<usize> {
+    #[must_use]
+    unsafe fn get_frame(&mut self) -> Option<PhysicalAddress> {
         let ret = self.next?;
         self.next = (*ret).next;
-        Some(ret as usize)
+        Some(PhysicalAddress::new(ret as usize))
     }
 
     /**
@@ -95,8 +148,8 @@ impl<const MEMORY_FRAME_SIZE: usize> FrameAllocator<MEMORY_FRAME_SIZE> {
      * `frame_address` must represent the start of a frame of valid and available memory. If the
      * memory frame does not exist or is already in use then undefined behavior may occur.
      */
-    pub unsafe fn add_frame(&mut self, frame_address: usize) {
-        let frame_ptr = frame_address as *mut Self;
+    pub unsafe fn add_frame(&mut self, frame_address: PhysicalAddress) {
+        let frame_ptr = frame_address.as_usize() as *mut Self;
         (*frame_ptr).next = self.next;
         self.next = FfiOption::Some(&mut *frame_ptr);
     }
@@ -117,22 +170,97 @@ impl<const MEMORY_FRAME_SIZE: usize> FrameAllocator<MEMORY_FRAME_SIZE> {
         smaller_allocator: &mut FrameAllocator<SMALLER_FRAME_SIZE>,
         memory_region: Range<usize>,
     ) {
-        let first_page = first_full_page_address(memory_region.start, Self::FRAME_SIZE);
-        let end_of_last_page = end_of_last_full_page(memory_region.end, Self::FRAME_SIZE);
-        if end_of_last_page > first_page {
-            smaller_allocator.add_aligned_frames(memory_region.start..first_page);
-            self.add_frames(first_page..end_of_last_page);
-            smaller_allocator.add_aligned_frames(end_of_last_page..memory_region.end);
-        } else {
+        let frames = align_inward(memory_region.clone(), Self::FRAME_SIZE);
+        if frames.is_empty() {
             smaller_allocator.add_aligned_frames(memory_region);
+        } else {
+            smaller_allocator.add_aligned_frames(memory_region.start..frames.start);
+            self.add_frames(frames.clone());
+            smaller_allocator.add_aligned_frames(frames.end..memory_region.end);
+        }
+    }
+
+    /**
+     * Counts the free frames without taking any of them. The allocator doesn't keep a count, since
+     * its layout is shared with the memory manager, so this walks the whole free list.
+     */
+    #[must_use]
+    pub fn frame_count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Iterates over the addresses of the free frames without taking any of them
+    #[must_use]
+    pub fn iter(&self) -> FrameAllocatorIter<'_, MEMORY_FRAME_SIZE> {
+        FrameAllocatorIter {
+            next: self.next,
+            allocator: PhantomData,
+        }
+    }
+
+    /**
+     * Moves every free frame from `other` to this allocator, leaving `other` empty. This walks
+     * `other`'s list once to find its end and then links the two lists together, rather than
+     * moving the frames one at a time.
+     *
+     * # Safety
+     *
+     * `other` must be in a valid state, and none of its frames may already be in this allocator.
+     */
+    pub unsafe fn merge_from(&mut self, other: &mut Self) {
+        let FfiOption::Some(mut tail) = other.next else {
+            return;
+        };
+        while let FfiOption::Some(next) = (*tail).next {
+            tail = next;
         }
+        (*tail).next = self.next;
+        self.next = other.next;
+        other.next = FfiOption::None;
+    }
+
+    /**
+     * Takes every frame in `region` out of the free list if they're all in it, and returns
+     * whether it did. Nothing is taken if any of them is missing. This walks the free list.
+     *
+     * # Safety
+     *
+     * `self` must be in a valid state, and `region` must be made up of whole frames.
+     */
+    unsafe fn take_all_in(&mut self, region: &Range<usize>) -> bool {
+        let frames = region.len() / Self::FRAME_SIZE;
+        let present = self
+            .iter()
+            .filter(|frame| region.contains(frame))
+            .take(frames)
+            .count();
+        if present < frames {
+            return false;
+        }
+        let mut remaining = frames;
+        let mut link = &raw mut self.next;
+        while remaining > 0 {
+            let FfiOption::Some(frame) = *link else {
+                break;
+            };
+            if region.contains(&(frame as usize)) {
+                *link = (*frame).next;
+                remaining -= 1;
+            } else {
+                link = &raw mut (*frame).next;
+            }
+        }
+        true
+    }
+
+    /// Returns true if there are no free frames. Unlike `frame_count`, this doesn't walk the list.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        matches!(self.next, FfiOption::None)
     }
 
     unsafe fn add_aligned_frames(&mut self, memory_region: Range<usize>) {
-        self.add_frames(
-            first_full_page_address(memory_region.start, Self::FRAME_SIZE)
-                ..end_of_last_full_page(memory_region.end, Self::FRAME_SIZE),
-        );
+        self.add_frames(align_inward(memory_region, Self::FRAME_SIZE));
     }
 
     /// Constructs a new empty `FrameAllocator`.
@@ -150,19 +278,93 @@ impl<const FRAME_SIZE: usize> Default for FrameAllocator<FRAME_SIZE> {
     }
 }
 
-/// Calculates the end address of the last page that ends at or before `end_address`.
-#[must_use]
-pub fn end_of_last_full_page(end_address: usize, page_size: usize) -> usize {
-    end_address - end_address % page_size
+impl<'a, const FRAME_SIZE: usize> IntoIterator for &'a FrameAllocator<FRAME_SIZE> {
+    type Item = usize;
+    type IntoIter = FrameAllocatorIter<'a, FRAME_SIZE>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Walks the free list of a `FrameAllocator`, yielding the address of each free frame
+pub struct FrameAllocatorIter<'a, const FRAME_SIZE: usize> {
+    next: FfiOption<*mut FrameAllocator<FRAME_SIZE>>,
+    /// The allocator can't be changed while its list is being walked
+    allocator: PhantomData<&'a FrameAllocator<FRAME_SIZE>>,
+}
+
+impl<const FRAME_SIZE: usize> Iterator for FrameAllocatorIter<'_, FRAME_SIZE> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.next?;
+        // Every frame in the list holds the link to the next one, which is the invariant that
+        // `get_frame` already relies on
+        self.next = unsafe { (*frame).next };
+        Some(frame as usize)
+    }
 }
 
-/// Calculates the address of the first page that starts at or after `start_address`.
-#[must_use]
-pub fn first_full_page_address(start_address: usize, page_size: usize) -> usize {
-    let page_offset = start_address % page_size;
-    if page_offset == 0 {
-        start_address
-    } else {
-        start_address + page_size - page_offset
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_one(value: FfiOption<u32>) -> FfiOption<u32> {
+        FfiOption::Some(value? + 1)
+    }
+
+    fn first_nonzero(values: [u32; 2]) -> FfiOption<u32> {
+        let first = values.into_iter().find(|&value| value != 0)?;
+        FfiOption::Some(first)
+    }
+
+    #[test]
+    fn question_mark_on_ffi_option() {
+        assert!(matches!(add_one(FfiOption::Some(1)), FfiOption::Some(2)));
+        assert!(matches!(add_one(FfiOption::None), FfiOption::None));
+        assert!(matches!(first_nonzero([0, 3]), FfiOption::Some(3)));
+        assert!(matches!(first_nonzero([0, 0]), FfiOption::None));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct WideError(u64);
+
+    impl From<u32> for WideError {
+        fn from(error: u32) -> Self {
+            Self(error.into())
+        }
+    }
+
+    fn double(value: FfiResult<u32, u32>) -> FfiResult<u32, u32> {
+        FfiResult::Ok(value? * 2)
+    }
+
+    fn parse(value: Result<u32, u32>) -> FfiResult<u32, WideError> {
+        FfiResult::Ok(value? + 1)
+    }
+
+    #[test]
+    fn question_mark_on_ffi_result() {
+        assert!(matches!(double(FfiResult::Ok(4)), FfiResult::Ok(8)));
+        assert!(matches!(double(FfiResult::Err(7)), FfiResult::Err(7)));
+    }
+
+    #[test]
+    fn question_mark_on_result_converts_the_error() {
+        assert!(matches!(parse(Ok(4)), FfiResult::Ok(5)));
+        assert!(matches!(parse(Err(7)), FfiResult::Err(WideError(7))));
+    }
+
+    #[test]
+    fn ffi_result_from_result() {
+        assert!(matches!(
+            FfiResult::from(Ok::<u32, u32>(3)),
+            FfiResult::Ok(3)
+        ));
+        assert!(matches!(
+            FfiResult::from(Err::<u32, u32>(9)),
+            FfiResult::Err(9)
+        ));
     }
 }
@@ -0,0 +1,453 @@
+//! Arithmetic for page tables that are a tree of tables of `ENTRIES_PER_TABLE` entries with 4 KB
+//! pages at the bottom. Every architecture that micros supports lays out its page tables like
+//! this, and they only differ in how many levels there are. Level 0 holds the entries that map
+//! 4 KB pages and level `LEVELS - 1` is the root, where `LEVELS` comes from the architecture's
+//! module.
+//!
+//! The page math that the kernel does while booting lives here too, and the math on ranges of
+//! addresses is in `range_math`. None of it can overflow or underflow, so callers don't need to
+//! guard the inputs that firmware hands them.
+
+use core::{cmp::min, ops::Range};
+
+pub const ENTRIES_PER_TABLE: usize = 512;
+
+/// The number of bits of an address that pick the entry in a table at each level
+const INDEX_BITS: u32 = ENTRIES_PER_TABLE.trailing_zeros();
+
+/// The number of bits of an address that pick the byte in a 4 KB page
+const PAGE_OFFSET_BITS: u32 = 12;
+
+pub const FOUR_KILOBYTES: usize = page_size(0);
+pub const TWO_MEGABYTES: usize = page_size(1);
+pub const GIGABYTE: usize = page_size(2);
+
+/// The size of the memory mapped by an entry in a page table at `page_table_level`
+#[must_use]
+pub const fn page_size(page_table_level: u8) -> usize {
+    1 << (PAGE_OFFSET_BITS + INDEX_BITS * page_table_level as u32)
+}
+
+/// The offset of `address` into the page that maps it at `page_table_level`
+#[must_use]
+pub const fn offset_in_page(page_table_level: u8, address: usize) -> usize {
+    address & (page_size(page_table_level) - 1)
+}
+
+/// The index of the entry that maps `address` in a page table at `page_table_level`
+#[must_use]
+pub const fn table_index(page_table_level: u8, address: usize) -> usize {
+    (address >> (PAGE_OFFSET_BITS + INDEX_BITS * page_table_level as u32)) & (ENTRIES_PER_TABLE - 1)
+}
+
+/**
+ * The indices of the entries in a table at `page_table_level` that map the `size` bytes starting
+ * at `base_address`. The range stops at the end of the table if the bytes go past it and is empty
+ * if `size` is 0.
+ */
+#[must_use]
+pub fn entry_indices(page_table_level: u8, base_address: usize, size: usize) -> Range<usize> {
+    let first_index = table_index(page_table_level, base_address);
+    let Some(last_byte) = size.checked_sub(1) else {
+        return first_index..first_index;
+    };
+    let last_address = base_address.saturating_add(last_byte);
+    let extra_pages =
+        last_address / page_size(page_table_level) - base_address / page_size(page_table_level);
+    first_index
+        ..min(
+            first_index.saturating_add(extra_pages).saturating_add(1),
+            ENTRIES_PER_TABLE,
+        )
+}
+
+/**
+ * The number of bytes to copy into the page at `page_table_level` that the copy has reached when
+ * `data_offset` of `size` bytes have been copied and the copy is `page_offset` bytes into the
+ * page. This is the rest of the page or the rest of the data, whichever is smaller.
+ */
+#[must_use]
+pub fn number_of_bytes_for_page(
+    page_table_level: u8,
+    page_offset: usize,
+    size: usize,
+    data_offset: usize,
+) -> usize {
+    page_size(page_table_level)
+        .saturating_sub(page_offset)
+        .min(size.saturating_sub(data_offset))
+}
+
+/// Calculates the end address of the last page that ends at or before `end_address`. `page_size`
+/// doesn't have to be a power of two, but it can't be 0.
+#[must_use]
+pub fn end_of_last_full_page(end_address: usize, page_size: usize) -> usize {
+    end_address - end_address % page_size
+}
+
+/// Calculates the address of the first page that starts at or after `start_address`. If there is
+/// no such page below the top of the address space then `usize::MAX` is returned so that a range
+/// starting at the result is empty. `page_size` doesn't have to be a power of two, but it can't
+/// be 0.
+#[must_use]
+pub fn first_full_page_address(start_address: usize, page_size: usize) -> usize {
+    let page_offset = start_address % page_size;
+    if page_offset == 0 {
+        start_address
+    } else {
+        start_address.saturating_add(page_size - page_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::range_math::intersect;
+
+    /// The page sizes that the properties are checked against. Page sizes that aren't powers of
+    /// two are included because the region math doesn't assume that they are.
+    const PAGE_SIZES: [usize; 7] = [
+        1,
+        3,
+        0x1000 - 1,
+        FOUR_KILOBYTES,
+        0x1000 + 1,
+        TWO_MEGABYTES,
+        GIGABYTE,
+    ];
+
+    const RANDOM_CASES: usize = 100_000;
+
+    /// A xorshift generator, which is plenty for picking test inputs and keeps the crate free of
+    /// dependencies. The seed is fixed so that failures can be reproduced.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new() -> Self {
+            Self(0x9e37_79b9_7f4a_7c15)
+        }
+
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+
+        /// An address that's often near a page boundary or the ends of the address space, since
+        /// that's where the math goes wrong
+        fn address(&mut self) -> usize {
+            let page_size = PAGE_SIZES[self.next() % PAGE_SIZES.len()];
+            let wobble = self.next() % 3;
+            match self.next() % 4 {
+                0 => self.next(),
+                1 => (self.next() / page_size * page_size)
+                    .wrapping_add(wobble)
+                    .wrapping_sub(1),
+                2 => wobble,
+                _ => usize::MAX - wobble,
+            }
+        }
+    }
+
+    /// Addresses around 0, around the top of the address space, and around multiples of each
+    /// page size
+    fn boundary_addresses() -> impl Iterator<Item = usize> {
+        let near =
+            |address: usize| (0..3).map(move |delta| address.wrapping_add(delta).wrapping_sub(1));
+        near(0)
+            .chain(near(usize::MAX))
+            .chain(PAGE_SIZES.into_iter().flat_map(move |page_size| {
+                [1, 2, ENTRIES_PER_TABLE, usize::MAX / page_size]
+                    .into_iter()
+                    .flat_map(move |pages| near(pages * page_size))
+            }))
+    }
+
+    fn check_first_full_page_address(address: usize, page_size: usize) {
+        let page = first_full_page_address(address, page_size);
+        if page == usize::MAX && !address.is_multiple_of(page_size) {
+            // There's no page after the address in the address space
+            assert!(usize::MAX - address < page_size);
+            return;
+        }
+        assert!(page >= address, "{address:#x} rounded up to {page:#x}");
+        assert!(
+            page - address < page_size,
+            "{address:#x} rounded up to {page:#x}"
+        );
+        assert_eq!(page % page_size, 0, "{address:#x} rounded up to {page:#x}");
+    }
+
+    fn check_end_of_last_full_page(address: usize, page_size: usize) {
+        let end = end_of_last_full_page(address, page_size);
+        assert!(end <= address, "{address:#x} rounded down to {end:#x}");
+        assert!(
+            address - end < page_size,
+            "{address:#x} rounded down to {end:#x}"
+        );
+        assert_eq!(end % page_size, 0, "{address:#x} rounded down to {end:#x}");
+    }
+
+    fn check_intersect(a: Range<usize>, b: Range<usize>) {
+        let both = intersect(a.clone(), b.clone());
+        assert_eq!(both, intersect(b.clone(), a.clone()));
+        assert!(
+            both.start <= both.end,
+            "{a:?} and {b:?} intersect in {both:?}"
+        );
+        if !both.is_empty() {
+            for range in [&a, &b] {
+                assert!(
+                    range.start <= both.start && both.end <= range.end,
+                    "{a:?} and {b:?} intersect in {both:?}"
+                );
+            }
+        }
+        // Every address in both ranges is in the intersection
+        for address in [
+            a.start,
+            a.end.wrapping_sub(1),
+            b.start,
+            b.end.wrapping_sub(1),
+        ] {
+            assert_eq!(
+                both.contains(&address),
+                a.contains(&address) && b.contains(&address),
+                "{a:?} and {b:?} intersect in {both:?}"
+            );
+        }
+    }
+
+    fn check_level_math(page_table_level: u8, address: usize) {
+        let page_size = page_size(page_table_level);
+        let offset = offset_in_page(page_table_level, address);
+        assert!(offset < page_size);
+        assert_eq!((address - offset) % page_size, 0);
+        let index = table_index(page_table_level, address);
+        assert!(index < ENTRIES_PER_TABLE);
+        assert_eq!(index, address / page_size % ENTRIES_PER_TABLE);
+    }
+
+    fn check_entry_indices(page_table_level: u8, address: usize, size: usize) {
+        let indices = entry_indices(page_table_level, address, size);
+        let first_index = table_index(page_table_level, address);
+        assert_eq!(indices.start, first_index);
+        assert!(indices.end <= ENTRIES_PER_TABLE);
+        if size == 0 {
+            assert!(indices.is_empty());
+            return;
+        }
+        assert!(!indices.is_empty());
+        let end = address.saturating_add(size - 1);
+        let last_page = end / page_size(page_table_level);
+        let pages = last_page - address / page_size(page_table_level) + 1;
+        assert_eq!(indices.len(), pages.min(ENTRIES_PER_TABLE - first_index));
+    }
+
+    fn check_number_of_bytes_for_page(
+        page_table_level: u8,
+        address: usize,
+        size: usize,
+        data_offset: usize,
+    ) {
+        let page_offset = offset_in_page(page_table_level, address);
+        let bytes = number_of_bytes_for_page(page_table_level, page_offset, size, data_offset);
+        assert!(bytes <= page_size(page_table_level) - page_offset);
+        assert!(bytes <= size.saturating_sub(data_offset));
+        assert!(
+            bytes == page_size(page_table_level) - page_offset
+                || bytes == size.saturating_sub(data_offset)
+        );
+    }
+
+    #[test]
+    fn page_rounding_at_boundaries() {
+        for page_size in PAGE_SIZES {
+            for address in boundary_addresses() {
+                check_first_full_page_address(address, page_size);
+                check_end_of_last_full_page(address, page_size);
+            }
+        }
+    }
+
+    #[test]
+    fn page_rounding_at_random() {
+        let mut rng = Rng::new();
+        for _ in 0..RANDOM_CASES {
+            let page_size = PAGE_SIZES[rng.next() % PAGE_SIZES.len()];
+            let address = rng.address();
+            check_first_full_page_address(address, page_size);
+            check_end_of_last_full_page(address, page_size);
+        }
+    }
+
+    #[test]
+    fn rounding_past_the_top_of_the_address_space_saturates() {
+        assert_eq!(
+            first_full_page_address(usize::MAX, FOUR_KILOBYTES),
+            usize::MAX
+        );
+        assert_eq!(
+            first_full_page_address(usize::MAX - 1, FOUR_KILOBYTES),
+            usize::MAX
+        );
+        assert_eq!(
+            end_of_last_full_page(usize::MAX, FOUR_KILOBYTES),
+            usize::MAX - (FOUR_KILOBYTES - 1)
+        );
+    }
+
+    #[test]
+    fn intersect_at_boundaries() {
+        let ranges: [Range<usize>; 6] = [
+            0..0,
+            0..1,
+            1..usize::MAX,
+            usize::MAX..usize::MAX,
+            Range { start: 5, end: 3 },
+            0..usize::MAX,
+        ];
+        for a in &ranges {
+            for b in &ranges {
+                check_intersect(a.clone(), b.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_at_random() {
+        let mut rng = Rng::new();
+        for _ in 0..RANDOM_CASES {
+            let a = rng.address()..rng.address();
+            let b = rng.address()..rng.address();
+            check_intersect(a, b);
+        }
+    }
+
+    #[test]
+    fn disjoint_ranges_intersect_in_an_empty_range() {
+        assert_eq!(intersect(0..10, 20..30), 20..20);
+        assert_eq!(intersect(20..30, 0..10), 20..20);
+        assert_eq!(intersect(0..10, 10..20), 10..10);
+    }
+
+    #[test]
+    fn level_math_at_boundaries() {
+        for page_table_level in 0..4 {
+            for address in boundary_addresses() {
+                check_level_math(page_table_level, address);
+            }
+        }
+    }
+
+    #[test]
+    fn entry_indices_at_boundaries() {
+        for page_table_level in 0..4 {
+            for address in boundary_addresses() {
+                for size in [
+                    0,
+                    1,
+                    2,
+                    page_size(page_table_level),
+                    page_size(page_table_level) + 1,
+                    usize::MAX,
+                ] {
+                    check_entry_indices(page_table_level, address, size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn entry_indices_at_random() {
+        let mut rng = Rng::new();
+        for _ in 0..RANDOM_CASES {
+            let page_table_level = (rng.next() % 4) as u8;
+            let address = rng.address();
+            let size = rng.next() >> (rng.next() % usize::BITS as usize);
+            check_entry_indices(page_table_level, address, size);
+        }
+    }
+
+    #[test]
+    fn entry_indices_for_a_zero_size_range_are_empty() {
+        assert_eq!(entry_indices(0, 0, 0), 0..0);
+        assert_eq!(entry_indices(0, 0x5000, 0), 5..5);
+    }
+
+    #[test]
+    fn entry_indices_stop_at_the_end_of_the_table() {
+        let last_page = (ENTRIES_PER_TABLE - 1) * FOUR_KILOBYTES;
+        assert_eq!(
+            entry_indices(0, last_page, 2 * FOUR_KILOBYTES),
+            ENTRIES_PER_TABLE - 1..ENTRIES_PER_TABLE
+        );
+        assert_eq!(
+            entry_indices(0, usize::MAX, usize::MAX),
+            ENTRIES_PER_TABLE - 1..ENTRIES_PER_TABLE
+        );
+    }
+
+    #[test]
+    fn number_of_bytes_for_page_at_random() {
+        let mut rng = Rng::new();
+        for _ in 0..RANDOM_CASES {
+            let page_table_level = (rng.next() % 4) as u8;
+            let address = rng.address();
+            let size = rng.next() % (2 * page_size(page_table_level));
+            let data_offset = rng.next() % (size + 2);
+            check_number_of_bytes_for_page(page_table_level, address, size, data_offset);
+        }
+    }
+
+    #[test]
+    fn number_of_bytes_for_page_never_underflows() {
+        assert_eq!(number_of_bytes_for_page(0, 0, 10, 20), 0);
+        assert_eq!(number_of_bytes_for_page(0, FOUR_KILOBYTES + 1, 10, 0), 0);
+        assert_eq!(number_of_bytes_for_page(0, 0x800, 0x1000, 0), 0x800);
+        assert_eq!(number_of_bytes_for_page(1, 0, 0x1000, 0x800), 0x800);
+    }
+
+    #[test]
+    fn page_sizes_match_the_hardware() {
+        assert_eq!(FOUR_KILOBYTES, 0x1000);
+        assert_eq!(TWO_MEGABYTES, 0x20_0000);
+        assert_eq!(GIGABYTE, 0x4000_0000);
+        assert_eq!(page_size(3), 0x80_0000_0000);
+    }
+
+    #[test]
+    fn indices_of_known_addresses() {
+        let address = 0xffff_8000_4020_3fff;
+        assert_eq!(table_index(3, address), 256);
+        assert_eq!(table_index(2, address), 1);
+        assert_eq!(table_index(1, address), 1);
+        assert_eq!(table_index(0, address), 3);
+        assert_eq!(offset_in_page(0, address), 0xfff);
+        assert_eq!(offset_in_page(1, address), 0x3fff);
+        assert_eq!(offset_in_page(2, address), 0x20_3fff);
+    }
+
+    /// Checks that an architecture's frame sizes are the sizes of its lowest levels' pages and that
+    /// its tables translate `virtual_address_bits` bits
+    fn check_architecture(levels: u8, page_sizes: &[usize], virtual_address_bits: u32) {
+        assert!(page_sizes.len() <= usize::from(levels));
+        for (level, &size) in page_sizes.iter().enumerate() {
+            assert_eq!(size, page_size(u8::try_from(level).unwrap()));
+        }
+        assert_eq!(
+            page_size(levels - 1) * ENTRIES_PER_TABLE,
+            1 << virtual_address_bits
+        );
+    }
+
+    #[test]
+    fn architectures_are_described_consistently() {
+        #[cfg(target_arch = "x86_64")]
+        check_architecture(crate::amd64::LEVELS, crate::amd64::PAGE_SIZES, 48);
+        check_architecture(crate::aarch64::LEVELS, crate::aarch64::PAGE_SIZES, 48);
+        // Sv39
+        check_architecture(crate::riscv64::LEVELS, crate::riscv64::PAGE_SIZES, 39);
+    }
+}
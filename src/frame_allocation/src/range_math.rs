@@ -0,0 +1,514 @@
+//! Arithmetic on ranges of addresses, shared by the kernel and the memory manager.
+//!
+//! A range is empty whenever its start isn't below its end, so an inverted range like `5..3` is
+//! just another empty range and holds no addresses. Every function here accepts empty and inverted
+//! ranges and treats them the same way, and none of them return an inverted range. None of them
+//! can overflow either, so callers don't need to guard the ranges that firmware hands them.
+
+use crate::page_tables::{end_of_last_full_page, first_full_page_address};
+use core::{
+    cmp::{max, min},
+    ops::{Deref, Range},
+};
+
+/// The addresses that are in both `a` and `b`. The result is `start..start` rather than inverted
+/// when they don't overlap.
+#[must_use]
+pub fn intersect(a: Range<usize>, b: Range<usize>) -> Range<usize> {
+    let start = max(a.start, b.start);
+    start..max(start, min(a.end, b.end))
+}
+
+/// The addresses in `a` that aren't in `b`, as the piece below `b` and the piece above it. Pieces
+/// with nothing in them are `None`.
+#[must_use]
+pub fn subtract(a: Range<usize>, b: Range<usize>) -> (Option<Range<usize>>, Option<Range<usize>>) {
+    if b.is_empty() {
+        return (Some(a).filter(|a| !a.is_empty()), None);
+    }
+    let below = a.start..min(a.end, b.start);
+    let above = max(a.start, b.end)..a.end;
+    (
+        Some(below).filter(|below| !below.is_empty()),
+        Some(above).filter(|above| !above.is_empty()),
+    )
+}
+
+/**
+ * Sorts `ranges` and combines any that overlap or touch. The merged ranges are moved to the front
+ * of the slice and returned, so they're sorted, not empty, and have a gap between each of them.
+ * Empty ranges are dropped.
+ */
+pub fn merge_sorted(ranges: &mut [Range<usize>]) -> &[Range<usize>] {
+    ranges.sort_unstable_by_key(|range| range.start);
+    let mut merged = 0;
+    for index in 0..ranges.len() {
+        let range = ranges[index].clone();
+        if range.is_empty() {
+            continue;
+        }
+        if merged > 0 && range.start <= ranges[merged - 1].end {
+            ranges[merged - 1].end = max(ranges[merged - 1].end, range.end);
+        } else {
+            ranges[merged] = range;
+            merged += 1;
+        }
+    }
+    &ranges[..merged]
+}
+
+/// Returns true if both ends of `range` are multiples of `align`, which must not be 0
+#[must_use]
+pub fn is_aligned(range: &Range<usize>, align: usize) -> bool {
+    range.start.is_multiple_of(align) && range.end.is_multiple_of(align)
+}
+
+/**
+ * The largest range of whole `align` sized blocks inside `range`. The result is empty if no whole
+ * block fits, and it's empty at the first block boundary after `range.start`, which may be past
+ * `range.end`. `align` doesn't have to be a power of two but must not be 0.
+ */
+#[must_use]
+pub fn align_inward(range: Range<usize>, align: usize) -> Range<usize> {
+    let start = first_full_page_address(range.start, align);
+    start..max(start, end_of_last_full_page(range.end, align))
+}
+
+/**
+ * The smallest range of whole `align` sized blocks that covers `range`. An empty range stays
+ * empty, at the block boundary at or below its start. The end saturates at `usize::MAX` when there's
+ * no block boundary above it. `align` doesn't have to be a power of two but must not be 0.
+ */
+#[must_use]
+pub fn align_outward(range: Range<usize>, align: usize) -> Range<usize> {
+    let start = end_of_last_full_page(range.start, align);
+    if range.is_empty() {
+        return start..start;
+    }
+    start..first_full_page_address(range.end, align)
+}
+
+/**
+ * A set of addresses stored as up to `N` ranges without allocating. The ranges are kept sorted,
+ * not empty, and apart from one another, so each run of addresses in the set is exactly one range.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeSet<const N: usize> {
+    ranges: [Range<usize>; N],
+    len: usize,
+}
+
+impl<const N: usize> RangeSet<N> {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            ranges: [const { 0..0 }; N],
+            len: 0,
+        }
+    }
+
+    /**
+     * Adds the addresses in `range`, merging it with any ranges that it overlaps or touches.
+     * Returns `range` back without changing the set if that would take more than `N` ranges.
+     */
+    pub fn insert(&mut self, range: Range<usize>) -> Result<(), Range<usize>> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let first = self
+            .ranges()
+            .partition_point(|other| other.end < range.start);
+        let end = self
+            .ranges()
+            .partition_point(|other| other.start <= range.end);
+        let touching = &self.ranges()[first..end];
+        let merged = match (touching.first(), touching.last()) {
+            (Some(lowest), Some(highest)) => {
+                min(lowest.start, range.start)..max(highest.end, range.end)
+            }
+            _ => range.clone(),
+        };
+        self.splice(first..end, [Some(merged), None])
+            .map_err(|()| range)
+    }
+
+    /**
+     * Removes the addresses in `range`. Returns `range` back without changing the set if that
+     * would split a range into more than the set can hold.
+     */
+    pub fn subtract(&mut self, range: Range<usize>) -> Result<(), Range<usize>> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let first = self
+            .ranges()
+            .partition_point(|other| other.end <= range.start);
+        let end = self
+            .ranges()
+            .partition_point(|other| other.start < range.end);
+        let overlapping = &self.ranges()[first..end];
+        let (Some(lowest), Some(highest)) = (overlapping.first(), overlapping.last()) else {
+            return Ok(());
+        };
+        let below = subtract(lowest.clone(), range.clone()).0;
+        let above = subtract(highest.clone(), range.clone()).1;
+        self.splice(first..end, [below, above]).map_err(|()| range)
+    }
+
+    /// Returns true if `address` is in the set
+    #[must_use]
+    pub fn contains(&self, address: usize) -> bool {
+        let index = self.ranges().partition_point(|range| range.end <= address);
+        self.ranges()
+            .get(index)
+            .is_some_and(|range| range.contains(&address))
+    }
+
+    fn ranges(&self) -> &[Range<usize>] {
+        &self.ranges[..self.len]
+    }
+
+    /// Replaces the ranges at `indices` with the pieces that aren't `None`, or fails without
+    /// changing anything if they don't fit
+    fn splice(
+        &mut self,
+        indices: Range<usize>,
+        pieces: [Option<Range<usize>>; 2],
+    ) -> Result<(), ()> {
+        let count = pieces.iter().flatten().count();
+        let new_len = self.len - indices.len() + count;
+        if new_len > N {
+            return Err(());
+        }
+        // The ranges after `indices` are rotated into place, and the spare slots past `len` are
+        // rotated in ahead of them when the pieces take up more room than what they replace
+        let after = &mut self.ranges[indices.start..max(self.len, new_len)];
+        if count < indices.len() {
+            after.rotate_left(indices.len() - count);
+        } else {
+            after.rotate_right(count - indices.len());
+        }
+        for (slot, piece) in after.iter_mut().zip(pieces.into_iter().flatten()) {
+            *slot = piece;
+        }
+        self.len = new_len;
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for RangeSet<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for RangeSet<N> {
+    type Target = [Range<usize>];
+
+    fn deref(&self) -> &Self::Target {
+        self.ranges()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Expected results hold a single range in an array because they're lists of ranges
+    #![allow(clippy::single_range_in_vec_init)]
+
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// Every address in the ranges that the tests enumerate is below this
+    const LIMIT: usize = 8;
+
+    /// Every range with ends up to `LIMIT`, including the empty and inverted ones
+    fn small_ranges() -> impl Iterator<Item = Range<usize>> + Clone {
+        (0..=LIMIT).flat_map(|start| (0..=LIMIT).map(move |end| start..end))
+    }
+
+    /// Ranges around the top and bottom of the address space, where the math can overflow
+    fn boundary_ranges() -> [Range<usize>; 8] {
+        [
+            0..0,
+            0..1,
+            Range { start: 1, end: 0 },
+            0..usize::MAX,
+            usize::MAX..usize::MAX,
+            usize::MAX - 1..usize::MAX,
+            Range {
+                start: usize::MAX,
+                end: 0,
+            },
+            usize::MAX - 0x1000..usize::MAX,
+        ]
+    }
+
+    /// The addresses in `range` as a bit mask, for ranges inside `0..LIMIT`
+    fn bits(range: &Range<usize>) -> u16 {
+        range.clone().fold(0, |bits, address| bits | 1 << address)
+    }
+
+    /// The runs of addresses in `bits`, which is how a set with those addresses must store them
+    fn runs(bits: u16) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        for address in (0..LIMIT).filter(|address| bits & 1 << address != 0) {
+            match runs.last_mut() {
+                Some(run) if run.end == address => run.end += 1,
+                _ => runs.push(address..address + 1),
+            }
+        }
+        runs
+    }
+
+    fn check_well_formed(range: &Range<usize>) {
+        assert!(range.start <= range.end, "{range:?} is inverted");
+    }
+
+    #[test]
+    fn intersect_every_small_range() {
+        for a in small_ranges() {
+            for b in small_ranges() {
+                let both = intersect(a.clone(), b.clone());
+                check_well_formed(&both);
+                assert_eq!(bits(&both), bits(&a) & bits(&b), "{a:?} and {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_at_the_ends_of_the_address_space() {
+        for a in boundary_ranges() {
+            for b in boundary_ranges() {
+                let both = intersect(a.clone(), b.clone());
+                check_well_formed(&both);
+                if !both.is_empty() {
+                    assert!(a.start <= both.start && both.end <= a.end);
+                    assert!(b.start <= both.start && both.end <= b.end);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn subtract_every_small_range() {
+        for a in small_ranges() {
+            for b in small_ranges() {
+                let (below, above) = subtract(a.clone(), b.clone());
+                let mut left = 0;
+                for piece in [&below, &above].into_iter().flatten() {
+                    assert!(!piece.is_empty(), "{a:?} - {b:?} left {piece:?}");
+                    assert_eq!(bits(piece) & bits(&b), 0, "{a:?} - {b:?} left {piece:?}");
+                    left |= bits(piece);
+                }
+                assert_eq!(left, bits(&a) & !bits(&b), "{a:?} - {b:?}");
+                if let (Some(below), Some(above)) = (&below, &above) {
+                    assert!(below.end < above.start, "{a:?} - {b:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn subtract_at_the_ends_of_the_address_space() {
+        assert_eq!(subtract(0..usize::MAX, 0..usize::MAX), (None, None));
+        assert_eq!(
+            subtract(0..usize::MAX, 1..usize::MAX - 1),
+            (Some(0..1), Some(usize::MAX - 1..usize::MAX))
+        );
+        assert_eq!(
+            subtract(
+                0..usize::MAX,
+                Range {
+                    start: usize::MAX,
+                    end: 0
+                }
+            ),
+            (Some(0..usize::MAX), None)
+        );
+        for a in boundary_ranges() {
+            for b in boundary_ranges() {
+                let (below, above) = subtract(a.clone(), b.clone());
+                for piece in [below, above].into_iter().flatten() {
+                    check_well_formed(&piece);
+                    assert!(intersect(piece.clone(), b.clone()).is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_range_subtracts_nothing() {
+        // Subtracting the inverted range naively would leave 0..5 and 3..10, which overlap
+        assert_eq!(
+            subtract(0..10, Range { start: 5, end: 3 }),
+            (Some(0..10), None)
+        );
+        assert_eq!(subtract(0..10, 5..5), (Some(0..10), None));
+        assert_eq!(subtract(Range { start: 5, end: 3 }, 0..10), (None, None));
+    }
+
+    #[test]
+    fn merge_every_pair_and_triple_of_small_ranges() {
+        let ranges: Vec<_> = small_ranges().step_by(2).collect();
+        for a in &ranges {
+            for b in &ranges {
+                for c in &ranges {
+                    let mut input = [a.clone(), b.clone(), c.clone()];
+                    let expected = runs(bits(a) | bits(b) | bits(c));
+                    assert_eq!(merge_sorted(&mut input), expected, "{a:?} {b:?} {c:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_touching_and_empty_ranges() {
+        let mut ranges = [4..6, 0..0, 2..4, Range { start: 9, end: 7 }, 0..2];
+        assert_eq!(merge_sorted(&mut ranges), [0..6]);
+        let mut ranges = [usize::MAX - 1..usize::MAX, 0..usize::MAX - 1];
+        assert_eq!(merge_sorted(&mut ranges), [0..usize::MAX]);
+        assert_eq!(merge_sorted(&mut []), []);
+    }
+
+    #[test]
+    fn alignment() {
+        assert!(is_aligned(&(0x1000..0x3000), 0x1000));
+        assert!(!is_aligned(&(0x1000..0x3001), 0x1000));
+        assert!(!is_aligned(&(0x1001..0x3000), 0x1000));
+        assert!(is_aligned(&Range { start: 6, end: 3 }, 3));
+        assert!(is_aligned(&(0..usize::MAX), 1));
+    }
+
+    #[test]
+    fn align_every_small_range_inward() {
+        for align in 1..=LIMIT {
+            for range in small_ranges() {
+                let aligned = align_inward(range.clone(), align);
+                check_well_formed(&aligned);
+                assert!(is_aligned(&aligned, align), "{range:?} to {align}");
+                assert_eq!(bits(&aligned) & !bits(&range), 0, "{range:?} to {align}");
+                // Every whole block in the range is kept
+                for block in (0..LIMIT / align).map(|block| block * align..(block + 1) * align) {
+                    if bits(&block) & !bits(&range) == 0 && !block.is_empty() {
+                        assert_eq!(bits(&block) & !bits(&aligned), 0, "{range:?} to {align}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn align_every_small_range_outward() {
+        for align in 1..=LIMIT {
+            for range in small_ranges() {
+                let aligned = align_outward(range.clone(), align);
+                check_well_formed(&aligned);
+                assert!(is_aligned(&aligned, align), "{range:?} to {align}");
+                assert_eq!(bits(&range) & !bits(&aligned), 0, "{range:?} to {align}");
+                assert_eq!(aligned.is_empty(), range.is_empty(), "{range:?} to {align}");
+                if !range.is_empty() {
+                    assert!(aligned.start + align > range.start, "{range:?} to {align}");
+                    assert!(aligned.end < range.end + align, "{range:?} to {align}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alignment_at_the_top_of_the_address_space() {
+        let top = usize::MAX - 0x1fff..usize::MAX;
+        assert_eq!(
+            align_inward(top.clone(), 0x1000),
+            usize::MAX - 0x1fff..usize::MAX - 0xfff
+        );
+        // There's no block boundary above the end, so it saturates
+        assert_eq!(align_outward(top, 0x1000), usize::MAX - 0x1fff..usize::MAX);
+        assert_eq!(
+            align_inward(
+                Range {
+                    start: usize::MAX,
+                    end: 0
+                },
+                0x1000
+            ),
+            usize::MAX..usize::MAX
+        );
+    }
+
+    /// Applies `operations` to a set of two ranges and a bit mask, and checks that they agree
+    /// after each one
+    fn check_set(operations: &[(bool, Range<usize>)]) {
+        let mut set = RangeSet::<2>::new();
+        let mut model = 0u16;
+        for (insert, range) in operations {
+            let expected = if *insert {
+                model | bits(range)
+            } else {
+                model & !bits(range)
+            };
+            let result = if *insert {
+                set.insert(range.clone())
+            } else {
+                set.subtract(range.clone())
+            };
+            if runs(expected).len() <= 2 {
+                assert_eq!(result, Ok(()), "{operations:?}");
+                model = expected;
+            } else {
+                assert_eq!(result, Err(range.clone()), "{operations:?}");
+            }
+            assert_eq!(&*set, runs(model), "{operations:?}");
+            for address in 0..=LIMIT {
+                assert_eq!(set.contains(address), model & 1 << address != 0);
+            }
+        }
+    }
+
+    #[test]
+    fn set_operations_on_every_small_range() {
+        let operations: Vec<_> = small_ranges()
+            .flat_map(|range| [(true, range.clone()), (false, range)])
+            .collect();
+        for first in &operations {
+            for second in &operations {
+                for third in operations.iter().step_by(3) {
+                    check_set(&[first.clone(), second.clone(), third.clone()]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_merges_touching_ranges() {
+        let mut set = RangeSet::<1>::new();
+        assert_eq!(set.insert(0..4), Ok(()));
+        assert_eq!(set.insert(4..8), Ok(()));
+        assert_eq!(set.insert(8..8), Ok(()));
+        assert_eq!(&*set, [0..8]);
+        assert_eq!(set.subtract(2..4), Err(2..4));
+        assert_eq!(&*set, [0..8]);
+        assert_eq!(set.subtract(0..2), Ok(()));
+        assert_eq!(set.subtract(7..usize::MAX), Ok(()));
+        assert_eq!(&*set, [2..7]);
+    }
+
+    #[test]
+    fn set_at_the_top_of_the_address_space() {
+        let mut set = RangeSet::<2>::default();
+        assert_eq!(set.insert(0..usize::MAX), Ok(()));
+        assert_eq!(set.subtract(1..usize::MAX - 1), Ok(()));
+        assert_eq!(&*set, [0..1, usize::MAX - 1..usize::MAX]);
+        assert!(set.contains(usize::MAX - 1));
+        assert!(!set.contains(usize::MAX));
+        assert_eq!(
+            set.insert(Range {
+                start: usize::MAX,
+                end: 0
+            }),
+            Ok(())
+        );
+        assert_eq!(set.len(), 2);
+    }
+}
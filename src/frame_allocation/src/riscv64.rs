@@ -0,0 +1,12 @@
+pub use crate::{
+    page_tables::{
+        offset_in_page, page_size, table_index, ENTRIES_PER_TABLE, FOUR_KILOBYTES, TWO_MEGABYTES,
+    },
+    two_tier::PAGE_SIZES,
+};
+
+/// The number of levels of page tables with Sv39 paging
+pub const LEVELS: u8 = 3;
+
+/// Memory frame allocator for 64 bit RISC-V processors using Sv39 paging
+pub type Riscv64FrameAllocator = crate::two_tier::TwoTierFrameAllocator;
@@ -0,0 +1,200 @@
+use crate::{
+    page_tables::{FOUR_KILOBYTES, TWO_MEGABYTES},
+    range_math::align_outward,
+    FrameAllocator, PhysicalAddress,
+};
+use core::ops::Range;
+
+/// The sizes of the frames that the allocator keeps, from smallest to largest
+pub const PAGE_SIZES: &[usize] = &[FOUR_KILOBYTES, TWO_MEGABYTES];
+
+/**
+ * Memory frame allocator for processors whose page tables map 4 KB pages and 2 MB blocks, like
+ * aarch64 with the 4 KB granule and RISC-V with Sv39. 2 MB frames are kept apart like the AMD64
+ * allocator's big pages. 1 GB blocks aren't used.
+ */
+#[repr(C)]
+pub struct TwoTierFrameAllocator {
+    /// The allocator for 4 KB pages
+    pub four_kilobyte_pages: FrameAllocator<FOUR_KILOBYTES>,
+    /// The allocator for 2 MB blocks
+    pub two_megabyte_pages: FrameAllocator<TWO_MEGABYTES>,
+}
+
+impl TwoTierFrameAllocator {
+    /// Constructs an allocator without any frames in it.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            four_kilobyte_pages: FrameAllocator::new(),
+            two_megabyte_pages: FrameAllocator::new(),
+        }
+    }
+
+    /**
+     * Retrieves a 4 kilobyte frame of available memory from the allocator
+     *
+     * # Safety
+     *
+     * This function should be safe so long as `self` is in a valid state, but may trigger
+     * undefined behavior if invalid or already-in-use memory regions have been added to the
+     * allocator previously.
+     */
+    #[must_use]
+    pub unsafe fn get_4k_frame(&mut self) -> Option<PhysicalAddress> {
+        if let Some(frame) = self.four_kilobyte_pages.get_frame() {
+            Some(frame)
+        } else {
+            let frame = self.two_megabyte_pages.get_frame()?;
+            let frame_start = frame.as_usize();
+            self.four_kilobyte_pages
+                .add_frames((frame_start + FOUR_KILOBYTES)..(frame_start + TWO_MEGABYTES));
+            Some(frame)
+        }
+    }
+
+    /**
+     * Retrieves a 2 megabyte frame of available memory from the allocator
+     *
+     * # Safety
+     *
+     * This function should be safe so long as `self` is in a valid state, but may trigger
+     * undefined behavior if invalid or already-in-use memory regions have been added to the
+     * allocator previously.
+     */
+    #[must_use]
+    pub unsafe fn get_2mb_frame(&mut self) -> Option<PhysicalAddress> {
+        self.two_megabyte_pages.get_frame()
+    }
+
+    /// Returns true if a frame of any size can be allocated. 2 MB frames are split up when the
+    /// 4 KB frames run out, so this is also whether `get_4k_frame` can succeed.
+    #[must_use]
+    pub const fn frames_available(&self) -> bool {
+        !self.four_kilobyte_pages.is_empty() || !self.two_megabyte_pages.is_empty()
+    }
+
+    /// The number of bytes in free frames of both sizes. This walks both free lists.
+    #[must_use]
+    pub fn total_bytes_free(&self) -> usize {
+        self.four_kilobyte_pages.frame_count() * FOUR_KILOBYTES
+            + self.two_megabyte_pages.frame_count() * TWO_MEGABYTES
+    }
+
+    /**
+     * Gives a 4 KB frame back to the allocator. If that makes all 512 of the 4 KB frames in its
+     * 2 MB frame free, they're merged back into the 2 MB frame. Like the AMD64 allocator, this
+     * walks the 4 KB free list.
+     *
+     * # Safety
+     *
+     * `address` must be the start of a 4 KB frame of valid memory that's no longer in use and
+     * isn't already in the allocator.
+     */
+    pub unsafe fn return_4k_frame(&mut self, address: PhysicalAddress) {
+        self.four_kilobyte_pages.add_frame(address);
+        let start = address.as_usize();
+        let big_frame = align_outward(start..start + 1, TWO_MEGABYTES);
+        if self.four_kilobyte_pages.take_all_in(&big_frame) {
+            self.return_2mb_frame(PhysicalAddress::new(big_frame.start));
+        }
+    }
+
+    /**
+     * Gives a 2 MB frame back to the allocator
+     *
+     * # Safety
+     *
+     * `address` must be the start of a 2 MB frame of valid memory that's no longer in use and
+     * isn't already in the allocator, in whole or in part.
+     */
+    pub unsafe fn return_2mb_frame(&mut self, address: PhysicalAddress) {
+        self.two_megabyte_pages.add_frame(address);
+    }
+
+    /**
+     * Adds the frames in a region of available memory to the allocator. Whole 2 MB frames go to
+     * the block allocator and whole 4 KB frames around them go to the page allocator.
+     *
+     * # Safety
+     *
+     * `memory_region` must represent a range of valid and available memory. If there are addresses
+     * in the range that don't represent valid memory or represent memory that is already in use,
+     * then undefined behavior may occur.
+     */
+    pub unsafe fn add_memory_region(&mut self, memory_region: Range<usize>) {
+        self.two_megabyte_pages
+            .add_aligned_frames_with_scrap_allocator(&mut self.four_kilobyte_pages, memory_region);
+    }
+}
+
+impl Default for TwoTierFrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+    use test_arena::Arena;
+
+    const ARENA_SIZE: usize = 4 * TWO_MEGABYTES;
+
+    #[test]
+    fn empty_allocator_has_no_frames() {
+        let mut allocator = TwoTierFrameAllocator::new();
+        assert!(!allocator.frames_available());
+        assert_eq!(allocator.total_bytes_free(), 0);
+        unsafe {
+            assert!(allocator.get_4k_frame().is_none());
+            assert!(allocator.get_2mb_frame().is_none());
+        }
+    }
+
+    #[test]
+    fn unaligned_region_is_split_by_frame_size() {
+        let arena = Arena::new(ARENA_SIZE);
+        let start = arena.range().start;
+        let region = start + FOUR_KILOBYTES..start + 2 * TWO_MEGABYTES + 2 * FOUR_KILOBYTES;
+        let mut allocator = TwoTierFrameAllocator::new();
+        unsafe { allocator.add_memory_region(region.clone()) };
+        assert!(allocator
+            .two_megabyte_pages
+            .iter()
+            .eq([start + TWO_MEGABYTES]));
+        assert_eq!(
+            allocator.four_kilobyte_pages.frame_count(),
+            TWO_MEGABYTES / FOUR_KILOBYTES - 1 + 2
+        );
+        assert_eq!(allocator.total_bytes_free(), region.len());
+    }
+
+    #[test]
+    fn big_frames_are_split_and_merged_back() {
+        let arena = Arena::new(ARENA_SIZE);
+        let big_frame = arena.range().start..arena.range().start + TWO_MEGABYTES;
+        let mut allocator = TwoTierFrameAllocator::new();
+        unsafe {
+            allocator.add_memory_region(big_frame.clone());
+            let frames: Vec<PhysicalAddress> = (0..TWO_MEGABYTES / FOUR_KILOBYTES)
+                .map(|_| allocator.get_4k_frame().unwrap())
+                .collect();
+            assert!(frames
+                .iter()
+                .all(|frame| big_frame.contains(&frame.as_usize())));
+            assert!(!allocator.frames_available());
+            for &frame in &frames[1..] {
+                allocator.return_4k_frame(frame);
+            }
+            assert!(allocator.two_megabyte_pages.is_empty());
+            allocator.return_4k_frame(frames[0]);
+        }
+        assert!(allocator.four_kilobyte_pages.is_empty());
+        assert!(allocator.two_megabyte_pages.iter().eq([big_frame.start]));
+        assert_eq!(allocator.total_bytes_free(), TWO_MEGABYTES);
+    }
+}
@@ -0,0 +1,53 @@
+//! Times `fill_span` against filling the same pixels one `draw_pixel` at a time. Run it with
+//! `cargo bench -p framebuffer`.
+
+use framebuffer::StandardRgbFramebuffer;
+use multiboot2::FramebufferTag;
+use std::{hint::black_box, time::Instant};
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+const FRAMES: u32 = 20;
+/// Red, green, and blue in the top three bytes of each 32-bit pixel
+const XRGB_8888: [u8; 6] = [16, 8, 8, 8, 0, 8];
+const RGB_COLOR_MODE: u8 = 1;
+
+fn main() {
+    let mut memory = vec![0; (WIDTH * HEIGHT * 4) as usize];
+    let tag = FramebufferTag {
+        framebuffer: memory.as_mut_ptr(),
+        pitch: WIDTH * 4,
+        width: WIDTH,
+        height: HEIGHT,
+        bits_per_pixel: 32,
+        framebuffer_type: RGB_COLOR_MODE,
+        color_data: &XRGB_8888,
+    };
+    let mut framebuffer =
+        unsafe { StandardRgbFramebuffer::from_tag(tag) }.expect("the framebuffer is valid");
+    let per_pixel = time("draw_pixel", || {
+        for row in 0..HEIGHT {
+            for column in 0..WIDTH {
+                framebuffer.draw_pixel(row, column, black_box([0x12; 8]));
+            }
+        }
+    });
+    let spans = time("fill_span", || {
+        for row in 0..HEIGHT {
+            framebuffer.fill_span(row, 0, WIDTH, black_box([0x34; 8]));
+        }
+    });
+    println!("fill_span is {:.1}x as fast", per_pixel / spans);
+}
+
+/// Runs `fill_screen` `FRAMES` times and prints and returns the nanoseconds that each pixel took
+fn time(name: &str, mut fill_screen: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        fill_screen();
+    }
+    let pixels = f64::from(FRAMES) * f64::from(WIDTH) * f64::from(HEIGHT);
+    let nanoseconds_per_pixel = start.elapsed().as_secs_f64() * 1e9 / pixels;
+    println!("{name}: {nanoseconds_per_pixel:.3} ns per pixel");
+    nanoseconds_per_pixel
+}
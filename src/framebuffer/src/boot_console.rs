@@ -0,0 +1,272 @@
+use crate::{
+    Framebuffer, FramebufferConsole, Rgb, StandardRgbFramebuffer, TextBuffer, TextModeConsole,
+};
+use core::fmt;
+use multiboot2::{BootInformation, FramebufferTag};
+
+/// A device that text can be written to
+pub trait Console: fmt::Write {
+    /// Blanks the console and moves the cursor to the top left corner.
+    fn clear(&mut self);
+
+    /// Sets the colors used for text written after this call.
+    fn set_color(&mut self, foreground: Rgb, background: Rgb);
+
+    /// Returns the number of `(columns, rows)` of text that fit in the console
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Moves the cursor. Positions outside of the console are clamped to its last row and column.
+    fn set_cursor(&mut self, row: u32, column: u32);
+}
+
+/// The best console available for the framebuffer that the bootloader set up
+// The framebuffer's dirty rectangles make its console much bigger than a text mode console, but
+// there's no heap to box it in and only one of these exists
+#[allow(clippy::large_enum_variant)]
+pub enum BootConsole<'a> {
+    Framebuffer(FramebufferConsole<'a>),
+    TextMode(TextModeConsole<'a>),
+}
+
+impl<'a> BootConsole<'a> {
+    /**
+     * Picks a console for the framebuffer described by `tag`. RGB framebuffers get a
+     * `FramebufferConsole` and EGA text buffers get a `TextModeConsole`. Returns `None` for
+     * indexed color framebuffers and for framebuffers that can't be used.
+     *
+     * `text` is only used by a `FramebufferConsole`. See `FramebufferConsole::new`.
+     */
+    pub unsafe fn from_tag(tag: FramebufferTag<'a>, text: &'a mut TextBuffer) -> Option<Self> {
+        Self::new(Framebuffer::new(tag)?, text)
+    }
+
+    /**
+     * Picks the best console for the framebuffers in the boot information. See `select`.
+     *
+     * # Safety
+     *
+     * Every framebuffer in the boot information must be mapped at its physical address and not
+     * be in use.
+     */
+    pub unsafe fn from_boot_info(
+        boot_info: BootInformation<'a>,
+        text: &'a mut TextBuffer,
+    ) -> Option<Self> {
+        Self::select(boot_info.tags_of_type::<FramebufferTag>(), text)
+    }
+
+    /**
+     * Picks the best console for the framebuffers described by `tags`. The first RGB framebuffer
+     * that a `FramebufferConsole` can draw on is preferred, then the first EGA text buffer.
+     * Returns `None` if there's neither.
+     */
+    pub unsafe fn select(
+        tags: impl IntoIterator<Item = FramebufferTag<'a>>,
+        text: &'a mut TextBuffer,
+    ) -> Option<Self> {
+        let mut text_mode = None;
+        for framebuffer in tags.into_iter().filter_map(|tag| Framebuffer::new(tag)) {
+            match framebuffer {
+                Framebuffer::RgbColor(_) => {
+                    if let Some(framebuffer) = StandardRgbFramebuffer::new(framebuffer) {
+                        return Some(Self::Framebuffer(FramebufferConsole::new(
+                            framebuffer,
+                            text,
+                        )));
+                    }
+                }
+                Framebuffer::Ega(framebuffer) => {
+                    text_mode.get_or_insert(framebuffer);
+                }
+                Framebuffer::IndexedColor(_) => {}
+            }
+        }
+        text_mode.map(|framebuffer| Self::TextMode(TextModeConsole::new(framebuffer)))
+    }
+
+    #[must_use]
+    pub fn new(framebuffer: Framebuffer<'a>, text: &'a mut TextBuffer) -> Option<Self> {
+        match framebuffer {
+            Framebuffer::RgbColor(_) => Some(Self::Framebuffer(FramebufferConsole::new(
+                StandardRgbFramebuffer::new(framebuffer)?,
+                text,
+            ))),
+            Framebuffer::Ega(text) => Some(Self::TextMode(TextModeConsole::new(text))),
+            Framebuffer::IndexedColor(_) => None,
+        }
+    }
+
+    fn console(&mut self) -> &mut dyn Console {
+        match self {
+            Self::Framebuffer(console) => console,
+            Self::TextMode(console) => console,
+        }
+    }
+}
+
+impl fmt::Write for BootConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.console().write_str(s)
+    }
+}
+
+impl Console for BootConsole<'_> {
+    fn clear(&mut self) {
+        self.console().clear();
+    }
+
+    fn set_color(&mut self, foreground: Rgb, background: Rgb) {
+        self.console().set_color(foreground, background);
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Self::Framebuffer(console) => Console::dimensions(console),
+            Self::TextMode(console) => console.dimensions(),
+        }
+    }
+
+    fn set_cursor(&mut self, row: u32, column: u32) {
+        self.console().set_cursor(row, column);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::XRGB_8888;
+    use std::{boxed::Box, vec, vec::Vec};
+
+    const INDEXED: u8 = 0;
+    const RGB: u8 = 1;
+    const EGA: u8 = 2;
+
+    /// Two palette entries after the number of entries
+    const PALETTE: [u8; 10] = [2, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff];
+
+    /// Memory for a framebuffer and the tag that the bootloader would pass for it
+    struct FakeFramebuffer {
+        memory: Vec<u8>,
+        framebuffer_type: u8,
+        bits_per_pixel: u8,
+        color_data: &'static [u8],
+        width: u32,
+        height: u32,
+    }
+
+    impl FakeFramebuffer {
+        fn rgb(bits_per_pixel: u8) -> Self {
+            Self::new(RGB, bits_per_pixel, &XRGB_8888, 32, 32)
+        }
+
+        fn ega() -> Self {
+            // Each cell is a character byte and an attribute byte
+            Self::new(EGA, 16, &[], 80, 25)
+        }
+
+        fn indexed() -> Self {
+            Self::new(INDEXED, 8, &PALETTE, 32, 32)
+        }
+
+        fn new(
+            framebuffer_type: u8,
+            bits_per_pixel: u8,
+            color_data: &'static [u8],
+            width: u32,
+            height: u32,
+        ) -> Self {
+            Self {
+                memory: vec![0; (width * height * u32::from(bits_per_pixel / 8)) as usize],
+                framebuffer_type,
+                bits_per_pixel,
+                color_data,
+                width,
+                height,
+            }
+        }
+
+        fn tag(&mut self) -> FramebufferTag<'_> {
+            FramebufferTag {
+                framebuffer: self.memory.as_mut_ptr(),
+                pitch: self.width * u32::from(self.bits_per_pixel / 8),
+                width: self.width,
+                height: self.height,
+                bits_per_pixel: self.bits_per_pixel,
+                framebuffer_type: self.framebuffer_type,
+                color_data: self.color_data,
+            }
+        }
+    }
+
+    /// What `select` picks, as the console's kind and dimensions
+    fn selected(framebuffers: &mut [FakeFramebuffer]) -> Option<(&'static str, (u32, u32))> {
+        let mut text = Box::<TextBuffer>::default();
+        let console = unsafe {
+            BootConsole::select(framebuffers.iter_mut().map(FakeFramebuffer::tag), &mut text)
+        }?;
+        let kind = match console {
+            BootConsole::Framebuffer(_) => "framebuffer",
+            BootConsole::TextMode(_) => "text mode",
+        };
+        Some((kind, Console::dimensions(&console)))
+    }
+
+    #[test]
+    fn nothing_to_draw_on() {
+        assert_eq!(selected(&mut []), None);
+        assert_eq!(selected(&mut [FakeFramebuffer::indexed()]), None);
+    }
+
+    #[test]
+    fn rgb_framebuffer() {
+        assert_eq!(
+            selected(&mut [FakeFramebuffer::rgb(32)]),
+            Some(("framebuffer", (4, 2)))
+        );
+    }
+
+    #[test]
+    fn ega_text_buffer() {
+        assert_eq!(
+            selected(&mut [FakeFramebuffer::ega()]),
+            Some(("text mode", (80, 25)))
+        );
+        assert_eq!(
+            selected(&mut [FakeFramebuffer::indexed(), FakeFramebuffer::ega()]),
+            Some(("text mode", (80, 25)))
+        );
+    }
+
+    #[test]
+    fn rgb_is_preferred_over_ega() {
+        for mut framebuffers in [
+            [FakeFramebuffer::ega(), FakeFramebuffer::rgb(32)],
+            [FakeFramebuffer::rgb(32), FakeFramebuffer::ega()],
+        ] {
+            assert_eq!(selected(&mut framebuffers), Some(("framebuffer", (4, 2))));
+        }
+    }
+
+    #[test]
+    fn unusable_rgb_falls_back_to_ega() {
+        let mut short_color_data = FakeFramebuffer::rgb(32);
+        short_color_data.color_data = &XRGB_8888[..4];
+        for unusable in [FakeFramebuffer::rgb(12), short_color_data] {
+            assert_eq!(
+                selected(&mut [unusable, FakeFramebuffer::ega()]),
+                Some(("text mode", (80, 25)))
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_framebuffer_types_are_skipped() {
+        let unknown = FakeFramebuffer::new(3, 32, &XRGB_8888, 32, 32);
+        assert_eq!(
+            selected(&mut [unknown, FakeFramebuffer::rgb(24)]),
+            Some(("framebuffer", (4, 2)))
+        );
+    }
+}
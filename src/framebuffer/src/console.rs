@@ -0,0 +1,497 @@
+use crate::{
+    font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH},
+    text_buffer::{ColorPair, TextBuffer, MAX_COLUMNS, MAX_ROWS},
+    Console, Rect, Rgb, StandardRgbFramebuffer,
+};
+use core::fmt;
+
+/// A text console drawn onto a framebuffer
+pub struct FramebufferConsole<'a> {
+    framebuffer: StandardRgbFramebuffer<'a>,
+    scale: u32,
+    rows: u32,
+    columns: u32,
+    row: u32,
+    column: u32,
+    colors: ColorPair,
+    text: &'a mut TextBuffer,
+    cursor_visible: bool,
+}
+
+impl<'a> FramebufferConsole<'a> {
+    /// Creates a console that covers the whole framebuffer and clears the screen. The font scale
+    /// is chosen based on the height of the framebuffer. `text` keeps a copy of what's on the
+    /// screen and is usually a static, since it's too big for a kernel stack.
+    #[must_use]
+    pub fn new(framebuffer: StandardRgbFramebuffer<'a>, text: &'a mut TextBuffer) -> Self {
+        let scale = default_scale(framebuffer.height);
+        let mut console = Self {
+            framebuffer,
+            scale,
+            rows: 0,
+            columns: 0,
+            row: 0,
+            column: 0,
+            colors: DEFAULT_COLORS,
+            text,
+            cursor_visible: false,
+        };
+        console.set_scale(scale);
+        console
+    }
+
+    /**
+     * Sets the font scale so that each pixel of a glyph is drawn as an `scale` by `scale` block.
+     *
+     * Changing the scale changes the number of rows and columns in the console, so the screen is
+     * cleared and the cursor is moved to the top left corner. A scale of 0 is treated as 1.
+     *
+     * The console uses at most `MAX_COLUMNS` by `MAX_ROWS` of the screen.
+     */
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale.max(1);
+        self.columns = (self.framebuffer.width / (GLYPH_WIDTH * self.scale)).min(MAX_COLUMNS);
+        self.rows = (self.framebuffer.height / (GLYPH_HEIGHT * self.scale)).min(MAX_ROWS);
+        self.clear();
+    }
+
+    #[must_use]
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Returns the number of `(columns, rows)` of text that fit in the console
+    #[must_use]
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.columns, self.rows)
+    }
+
+    pub fn set_colors(&mut self, foreground: [u8; 8], background: [u8; 8]) {
+        self.colors = ColorPair::new(foreground, background);
+    }
+
+    /// Fills the screen with the background color and moves the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        self.framebuffer.fill_rect(
+            0,
+            0,
+            self.framebuffer.width,
+            self.framebuffer.height,
+            self.colors.background,
+        );
+        self.text.resize(self.columns, self.rows, self.colors);
+        self.row = 0;
+        self.column = 0;
+        self.draw_cursor();
+    }
+
+    /// Returns the `(row, column)` where the next character will be written
+    #[must_use]
+    pub fn cursor(&self) -> (u32, u32) {
+        (self.row, self.column)
+    }
+
+    /// Moves the cursor. Positions outside of the console are clamped to its last row and column.
+    pub fn set_cursor(&mut self, row: u32, column: u32) {
+        self.erase_cursor();
+        self.row = row.min(self.rows.saturating_sub(1));
+        self.column = column.min(self.columns.saturating_sub(1));
+        self.draw_cursor();
+    }
+
+    /// Draws the cursor as an inverted block over the cell where the next character will go.
+    pub fn show_cursor(&mut self) {
+        self.cursor_visible = true;
+        self.draw_cursor();
+    }
+
+    pub fn hide_cursor(&mut self) {
+        self.erase_cursor();
+        self.cursor_visible = false;
+    }
+
+    /**
+     * Draws a translucent red band over the top `text_rows` rows of the console and moves the
+     * cursor to the top left corner so that a message can be written over it.
+     *
+     * Framebuffers that don't support alpha blending get an opaque band instead.
+     */
+    pub fn draw_panic_banner(&mut self, text_rows: u32) {
+        let banner = Rect::new(
+            0,
+            0,
+            self.framebuffer.width,
+            text_rows.min(self.rows) * GLYPH_HEIGHT * self.scale,
+        );
+        if self
+            .framebuffer
+            .blend_rect(banner, PANIC_BANNER_COLOR, PANIC_BANNER_ALPHA)
+            .is_err()
+        {
+            let color = self.framebuffer.pack_color(PANIC_BANNER_COLOR);
+            self.framebuffer.fill_rect(
+                banner.row,
+                banner.column,
+                banner.width,
+                banner.height,
+                color,
+            );
+        }
+        self.row = 0;
+        self.column = 0;
+        self.draw_cursor();
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.erase_cursor();
+        self.put_byte(byte);
+        self.draw_cursor();
+    }
+
+    /// Writes `value` as a `0x`-prefixed hexadecimal number without going through `fmt`.
+    pub fn write_hex_u64(&mut self, value: u64) {
+        self.write_byte(b'0');
+        self.write_byte(b'x');
+        let digits = (u64::BITS - value.leading_zeros()).div_ceil(4).max(1);
+        for digit in (0..digits).rev() {
+            let nibble = (value >> (digit * 4)) & 0xf;
+            self.write_byte(HEX_DIGITS[nibble as usize]);
+        }
+    }
+
+    /// Writes `value` as a `0x`-prefixed hexadecimal number without going through `fmt`.
+    pub fn write_hex_usize(&mut self, value: usize) {
+        self.write_hex_u64(value as u64);
+    }
+
+    /// Writes `value` as a decimal number without going through `fmt`.
+    pub fn write_dec_u64(&mut self, value: u64) {
+        let mut digits = [0; MAX_DECIMAL_DIGITS];
+        let mut remaining = value;
+        let mut length = 0;
+        loop {
+            // The remainder is always a single digit
+            digits[length] = b'0' + (remaining % 10) as u8;
+            length += 1;
+            remaining /= 10;
+            if remaining == 0 {
+                break;
+            }
+        }
+        for &digit in digits[..length].iter().rev() {
+            self.write_byte(digit);
+        }
+    }
+
+    pub fn framebuffer(&mut self) -> &mut StandardRgbFramebuffer<'a> {
+        &mut self.framebuffer
+    }
+
+    /// Gives back the framebuffer and text buffer that the console was created with
+    #[must_use]
+    pub fn into_parts(self) -> (StandardRgbFramebuffer<'a>, &'a mut TextBuffer) {
+        (self.framebuffer, self.text)
+    }
+
+    fn put_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            _ => {
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+                self.text.set(self.row, self.column, byte, self.colors);
+                self.draw_glyph(self.row, self.column, byte, self.colors);
+                self.column += 1;
+            }
+        }
+    }
+
+    // Scrolling redraws the text from the shadow buffer rather than copying pixels within the
+    // framebuffer, since reading from video memory is slow.
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.text.scroll_up(self.colors);
+            self.redraw();
+        }
+    }
+
+    fn redraw(&mut self) {
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                self.draw_cell(row, column, false);
+            }
+        }
+    }
+
+    fn draw_cursor(&mut self) {
+        if self.cursor_visible {
+            self.draw_cell(self.row, self.column, true);
+        }
+    }
+
+    fn erase_cursor(&mut self) {
+        if self.cursor_visible {
+            self.draw_cell(self.row, self.column, false);
+        }
+    }
+
+    fn draw_cell(&mut self, row: u32, column: u32, inverted: bool) {
+        if let Some((character, colors)) = self.text.get(row, column) {
+            let colors = if inverted { colors.inverted() } else { colors };
+            self.draw_glyph(row, column, character, colors);
+        }
+    }
+
+    // Draws each row of the glyph as runs of same-colored pixels so that scaled glyphs are drawn
+    // with a handful of rectangle fills rather than one write per pixel.
+    fn draw_glyph(&mut self, row: u32, column: u32, character: u8, colors: ColorPair) {
+        let scale = self.scale;
+        let top = row * GLYPH_HEIGHT * scale;
+        let left = column * GLYPH_WIDTH * scale;
+        for (glyph_row, bits) in (0..).zip(glyph(character)) {
+            let mut run_start = 0;
+            while run_start < GLYPH_WIDTH {
+                let lit = pixel_is_lit(*bits, run_start);
+                let mut run_end = run_start + 1;
+                while run_end < GLYPH_WIDTH && pixel_is_lit(*bits, run_end) == lit {
+                    run_end += 1;
+                }
+                let color = if lit {
+                    colors.foreground
+                } else {
+                    colors.background
+                };
+                self.framebuffer.fill_rect(
+                    top + glyph_row * scale,
+                    left + run_start * scale,
+                    (run_end - run_start) * scale,
+                    scale,
+                    color,
+                );
+                run_start = run_end;
+            }
+        }
+    }
+}
+
+impl fmt::Write for FramebufferConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.erase_cursor();
+        for byte in s.bytes() {
+            self.put_byte(byte);
+        }
+        self.draw_cursor();
+        Ok(())
+    }
+}
+
+impl Console for FramebufferConsole<'_> {
+    fn clear(&mut self) {
+        FramebufferConsole::clear(self);
+    }
+
+    fn set_color(&mut self, foreground: Rgb, background: Rgb) {
+        let foreground = self.framebuffer.pack_color(foreground);
+        let background = self.framebuffer.pack_color(background);
+        self.set_colors(foreground, background);
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        FramebufferConsole::dimensions(self)
+    }
+
+    fn set_cursor(&mut self, row: u32, column: u32) {
+        FramebufferConsole::set_cursor(self, row, column);
+    }
+}
+
+const DEFAULT_COLORS: ColorPair =
+    ColorPair::new(StandardRgbFramebuffer::WHITE, StandardRgbFramebuffer::BLACK);
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+// u64::MAX has 20 decimal digits
+const MAX_DECIMAL_DIGITS: usize = 20;
+
+const PANIC_BANNER_COLOR: Rgb = Rgb::new(0xc0, 0, 0);
+// 75% opacity
+const PANIC_BANNER_ALPHA: u8 = 191;
+
+/// Framebuffers at least this tall get a font scale of 2 by default
+pub const HIGH_DPI_HEIGHT: u32 = 1600;
+
+/// Chooses a font scale that keeps text readable on a framebuffer of the given height.
+#[must_use]
+pub fn default_scale(height: u32) -> u32 {
+    if height >= HIGH_DPI_HEIGHT {
+        2
+    } else {
+        1
+    }
+}
+
+fn pixel_is_lit(bits: u8, column: u32) -> bool {
+    bits & (0x80 >> column) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{framebuffer_over, XRGB_8888};
+    use std::{boxed::Box, vec, vec::Vec};
+
+    const BYTES_PER_PIXEL: usize = 4;
+
+    /// A console over a 32 bit framebuffer in ordinary memory
+    struct Screen {
+        memory: Vec<u8>,
+        text: Box<TextBuffer>,
+        width: u32,
+        height: u32,
+    }
+
+    impl Screen {
+        fn new(width: u32, height: u32) -> Self {
+            Self {
+                memory: vec![0x55; (width * height) as usize * BYTES_PER_PIXEL],
+                text: Box::default(),
+                width,
+                height,
+            }
+        }
+
+        fn console(&mut self) -> FramebufferConsole<'_> {
+            FramebufferConsole::new(
+                framebuffer_over(&mut self.memory, self.width, self.height, 32, &XRGB_8888),
+                &mut self.text,
+            )
+        }
+    }
+
+    /// Checks that the cell at `row` and `column` holds `character` with every pixel of its glyph
+    /// drawn as a `scale` by `scale` block
+    fn check_cell(
+        console: &FramebufferConsole,
+        row: u32,
+        column: u32,
+        character: u8,
+        inverted: bool,
+    ) {
+        let scale = console.scale;
+        let framebuffer = &console.framebuffer;
+        let top = row * GLYPH_HEIGHT * scale;
+        let left = column * GLYPH_WIDTH * scale;
+        for y in 0..GLYPH_HEIGHT * scale {
+            for x in 0..GLYPH_WIDTH * scale {
+                let lit = pixel_is_lit(glyph(character)[(y / scale) as usize], x / scale);
+                let location = (top + y) as usize * framebuffer.pitch as usize
+                    + (left + x) as usize * BYTES_PER_PIXEL;
+                let expected = if lit == inverted { [0; 4] } else { [0xff; 4] };
+                assert_eq!(
+                    framebuffer.framebuffer[location..location + BYTES_PER_PIXEL],
+                    expected,
+                    "pixel ({y}, {x}) of cell ({row}, {column}) at scale {scale}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scaled_glyphs_are_drawn_as_blocks() {
+        for scale in [2, 4] {
+            let mut screen = Screen::new(GLYPH_WIDTH * 8, GLYPH_HEIGHT * 4);
+            let mut console = screen.console();
+            console.set_scale(scale);
+            console.write_byte(b'A');
+            assert_eq!(console.cursor(), (0, 1));
+            check_cell(&console, 0, 0, b'A', false);
+        }
+    }
+
+    #[test]
+    fn setting_the_scale_recomputes_the_dimensions() {
+        let mut screen = Screen::new(GLYPH_WIDTH * 10, GLYPH_HEIGHT * 6);
+        let mut console = screen.console();
+        assert_eq!(console.scale(), 1);
+        assert_eq!(console.dimensions(), (10, 6));
+        console.set_scale(2);
+        assert_eq!(console.dimensions(), (5, 3));
+        console.set_scale(4);
+        // Partial cells at the right and bottom edges aren't used
+        assert_eq!(console.dimensions(), (2, 1));
+        console.set_scale(0);
+        assert_eq!(console.scale(), 1);
+        assert_eq!(console.dimensions(), (10, 6));
+    }
+
+    #[test]
+    fn cursor_moves_by_whole_scaled_cells() {
+        let mut screen = Screen::new(GLYPH_WIDTH * 8, GLYPH_HEIGHT * 4);
+        let mut console = screen.console();
+        console.set_scale(2);
+        console.set_cursor(10, 10);
+        assert_eq!(console.cursor(), (1, 3));
+        // Writing past the last row scrolls by one row of scaled text
+        console.write_byte(b'\n');
+        console.write_byte(b'B');
+        assert_eq!(console.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn default_scale_doubles_at_high_dpi_heights() {
+        assert_eq!(default_scale(0), 1);
+        assert_eq!(default_scale(HIGH_DPI_HEIGHT - 1), 1);
+        assert_eq!(default_scale(HIGH_DPI_HEIGHT), 2);
+        assert_eq!(default_scale(2160), 2);
+    }
+
+    #[test]
+    fn new_consoles_use_the_default_scale() {
+        let mut screen = Screen::new(GLYPH_WIDTH * 2, HIGH_DPI_HEIGHT);
+        let console = screen.console();
+        assert_eq!(console.scale(), 2);
+        assert_eq!(
+            console.dimensions(),
+            (1, HIGH_DPI_HEIGHT / (GLYPH_HEIGHT * 2))
+        );
+    }
+
+    #[test]
+    fn cursor_is_drawn_inverted_and_erased_when_it_moves() {
+        let mut screen = Screen::new(GLYPH_WIDTH * 4, GLYPH_HEIGHT * 2);
+        let mut console = screen.console();
+        console.write_byte(b'A');
+        console.set_cursor(0, 0);
+        console.show_cursor();
+        check_cell(&console, 0, 0, b'A', true);
+        // The glyph under the old position is redrawn from the text buffer
+        console.set_cursor(1, 2);
+        check_cell(&console, 0, 0, b'A', false);
+        check_cell(&console, 1, 2, b' ', true);
+        console.hide_cursor();
+        check_cell(&console, 1, 2, b' ', false);
+        // Writing moves the cursor along with the text
+        console.show_cursor();
+        console.write_byte(b'C');
+        check_cell(&console, 1, 2, b'C', false);
+        check_cell(&console, 1, 3, b' ', true);
+    }
+
+    #[test]
+    fn scrolling_redraws_from_the_text_buffer() {
+        let mut screen = Screen::new(GLYPH_WIDTH * 2, GLYPH_HEIGHT * 2);
+        let mut console = screen.console();
+        fmt::Write::write_str(&mut console, "ab\ncd\nef").unwrap();
+        check_cell(&console, 0, 0, b'c', false);
+        check_cell(&console, 0, 1, b'd', false);
+        check_cell(&console, 1, 0, b'e', false);
+        check_cell(&console, 1, 1, b'f', false);
+        assert_eq!(console.cursor(), (1, 2));
+    }
+}
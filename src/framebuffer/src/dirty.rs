@@ -0,0 +1,297 @@
+use core::cmp::{max, min};
+
+/// A rectangular region of the framebuffer measured in pixels
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub row: u32,
+    pub column: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    #[must_use]
+    pub const fn new(row: u32, column: u32, width: u32, height: u32) -> Self {
+        Self {
+            row,
+            column,
+            width,
+            height,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    #[must_use]
+    pub const fn end_row(&self) -> u32 {
+        self.row.saturating_add(self.height)
+    }
+
+    #[must_use]
+    pub const fn end_column(&self) -> u32 {
+        self.column.saturating_add(self.width)
+    }
+
+    #[must_use]
+    pub const fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// Returns the smallest rectangle containing both `self` and `other`.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        let row = min(self.row, other.row);
+        let column = min(self.column, other.column);
+        Self {
+            row,
+            column,
+            width: max(self.end_column(), other.end_column()) - column,
+            height: max(self.end_row(), other.end_row()) - row,
+        }
+    }
+
+    /// Returns true if `self` and `other` overlap or share an entire edge.
+    #[must_use]
+    pub fn touches(&self, other: &Self) -> bool {
+        let rows_overlap = self.row < other.end_row() && other.row < self.end_row();
+        let columns_overlap = self.column < other.end_column() && other.column < self.end_column();
+        let rows_adjacent = self.row == other.row && self.height == other.height;
+        let columns_adjacent = self.column == other.column && self.width == other.width;
+        (rows_overlap && columns_overlap)
+            || (rows_adjacent
+                && (self.end_column() == other.column || other.end_column() == self.column))
+            || (columns_adjacent && (self.end_row() == other.row || other.end_row() == self.row))
+    }
+
+    #[must_use]
+    pub fn contains(&self, other: &Self) -> bool {
+        self.row <= other.row
+            && self.column <= other.column
+            && other.end_row() <= self.end_row()
+            && other.end_column() <= self.end_column()
+    }
+
+    /// Shrinks the rectangle so that it lies within a `width` by `height` area.
+    #[must_use]
+    pub fn clip(&self, width: u32, height: u32) -> Self {
+        let row = min(self.row, height);
+        let column = min(self.column, width);
+        Self {
+            row,
+            column,
+            width: min(self.end_column(), width) - column,
+            height: min(self.end_row(), height) - row,
+        }
+    }
+}
+
+/// The maximum number of separate dirty regions that are tracked before regions are merged
+pub const MAX_DIRTY_RECTS: usize = 64;
+
+/// A fixed-capacity set of regions of the framebuffer that have been modified since they were
+/// last flushed
+pub struct DirtyRects {
+    rects: [Rect; MAX_DIRTY_RECTS],
+    len: usize,
+}
+
+impl DirtyRects {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            rects: [Rect::new(0, 0, 0, 0); MAX_DIRTY_RECTS],
+            len: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn as_slice(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /**
+     * Marks a region as dirty.
+     *
+     * The new region is merged with any existing region that it overlaps or is adjacent to. If
+     * there's no room left for the region then it's merged with the existing region whose area
+     * grows the least as a result.
+     */
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        let mut rect = rect;
+        // Merging two regions can cause the result to touch a third, so keep going until no
+        // remaining region touches the accumulated one.
+        while let Some(index) = self.as_slice().iter().position(|r| r.touches(&rect)) {
+            rect = rect.union(&self.remove(index));
+        }
+        if self.len == MAX_DIRTY_RECTS {
+            let index = self.cheapest_merge(&rect);
+            let merged = rect.union(&self.remove(index));
+            self.add(merged);
+        } else {
+            self.rects[self.len] = rect;
+            self.len += 1;
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Rect {
+        let rect = self.rects[index];
+        self.len -= 1;
+        self.rects[index] = self.rects[self.len];
+        rect
+    }
+
+    fn cheapest_merge(&self, rect: &Rect) -> usize {
+        let growth = |r: &Rect| r.union(rect).area() - r.area();
+        self.as_slice()
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, r)| growth(r))
+            .map_or(0, |(index, _)| index)
+    }
+}
+
+impl Default for DirtyRects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirty(rects: &[Rect]) -> DirtyRects {
+        let mut dirty = DirtyRects::new();
+        for rect in rects {
+            dirty.add(*rect);
+        }
+        dirty
+    }
+
+    /// Tiny rectangles that neither overlap nor share an edge, so none of them get merged
+    fn scattered(count: usize) -> impl Iterator<Item = Rect> {
+        (0..u32::try_from(count).unwrap()).map(|index| Rect::new(0, index * 2, 1, 1))
+    }
+
+    #[test]
+    fn empty_rects_are_ignored() {
+        assert_eq!(
+            dirty(&[Rect::new(5, 5, 0, 3), Rect::new(5, 5, 3, 0)]).as_slice(),
+            []
+        );
+    }
+
+    #[test]
+    fn disjoint_rects_are_kept_apart() {
+        let rects = [
+            Rect::new(0, 0, 4, 4),
+            Rect::new(10, 10, 4, 4),
+            Rect::new(0, 5, 4, 4),
+        ];
+        assert_eq!(dirty(&rects).as_slice(), rects);
+    }
+
+    #[test]
+    fn overlapping_rects_merge() {
+        assert_eq!(
+            dirty(&[Rect::new(0, 0, 4, 4), Rect::new(2, 3, 4, 4)]).as_slice(),
+            [Rect::new(0, 0, 7, 6)]
+        );
+        // A rect inside another disappears into it
+        assert_eq!(
+            dirty(&[Rect::new(0, 0, 4, 4), Rect::new(1, 1, 2, 2)]).as_slice(),
+            [Rect::new(0, 0, 4, 4)]
+        );
+    }
+
+    #[test]
+    fn rects_sharing_a_whole_edge_merge() {
+        assert_eq!(
+            dirty(&[Rect::new(0, 0, 4, 2), Rect::new(0, 4, 3, 2)]).as_slice(),
+            [Rect::new(0, 0, 7, 2)]
+        );
+        assert_eq!(
+            dirty(&[Rect::new(2, 1, 4, 3), Rect::new(0, 1, 4, 2)]).as_slice(),
+            [Rect::new(0, 1, 4, 5)]
+        );
+    }
+
+    #[test]
+    fn rects_sharing_part_of_an_edge_are_kept_apart() {
+        // Merging these would mark pixels that aren't dirty
+        let rects = [Rect::new(0, 0, 4, 2), Rect::new(1, 4, 4, 2)];
+        assert_eq!(dirty(&rects).as_slice(), rects);
+        let corners = [Rect::new(0, 0, 2, 2), Rect::new(2, 2, 2, 2)];
+        assert_eq!(dirty(&corners).as_slice(), corners);
+    }
+
+    #[test]
+    fn merging_cascades() {
+        // The last rect bridges the first two, and their union then reaches the third
+        let rects = [
+            Rect::new(0, 0, 2, 2),
+            Rect::new(0, 4, 2, 2),
+            Rect::new(2, 0, 6, 1),
+            Rect::new(1, 1, 4, 1),
+        ];
+        assert_eq!(dirty(&rects).as_slice(), [Rect::new(0, 0, 6, 3)]);
+    }
+
+    #[test]
+    fn full_set_merges_with_the_cheapest_rect() {
+        let mut dirty = DirtyRects::new();
+        for rect in scattered(MAX_DIRTY_RECTS) {
+            dirty.add(rect);
+        }
+        assert_eq!(dirty.as_slice().len(), MAX_DIRTY_RECTS);
+        // Growing the rect in the first column to row 10 adds the least area
+        let extra = Rect::new(10, 0, 1, 1);
+        assert_eq!(
+            dirty.as_slice()[dirty.cheapest_merge(&extra)],
+            Rect::new(0, 0, 1, 1)
+        );
+        dirty.add(extra);
+        assert_eq!(dirty.as_slice().len(), MAX_DIRTY_RECTS);
+        assert!(dirty.as_slice().contains(&Rect::new(0, 0, 1, 11)));
+        assert!(!dirty.as_slice().contains(&Rect::new(0, 0, 1, 1)));
+        assert!(scattered(MAX_DIRTY_RECTS)
+            .skip(1)
+            .all(|rect| dirty.as_slice().contains(&rect)));
+    }
+
+    #[test]
+    fn forced_merges_keep_merging() {
+        let mut dirty = DirtyRects::new();
+        for rect in scattered(MAX_DIRTY_RECTS) {
+            dirty.add(rect);
+        }
+        // This is cheapest to merge with the rect in column 2, and the result covers the one in
+        // column 4 as well
+        dirty.add(Rect::new(1, 1, 4, 1));
+        assert_eq!(dirty.as_slice().len(), MAX_DIRTY_RECTS - 1);
+        assert!(dirty.as_slice().contains(&Rect::new(0, 1, 4, 2)));
+        for rect in scattered(MAX_DIRTY_RECTS) {
+            assert!(
+                dirty.as_slice().iter().any(|dirty| dirty.contains(&rect)),
+                "{rect:?} was lost"
+            );
+        }
+    }
+
+    #[test]
+    fn clearing_empties_the_set() {
+        let mut dirty = dirty(&[Rect::new(0, 0, 1, 1)]);
+        dirty.clear();
+        assert_eq!(dirty.as_slice(), []);
+    }
+}
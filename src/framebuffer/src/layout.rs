@@ -0,0 +1,18 @@
+//! The layouts of the parts of the multiboot2 framebuffer tag that are read in place
+
+use crate::{FramebufferPixelColorDescriptor, FramebufferPixelDescriptor, Rgb};
+use layout_assert::layout_assert;
+
+// An entry of the palette of an indexed color framebuffer
+layout_assert!(Rgb, size = 3, align = 1, { red: 0, green: 1, blue: 2 });
+
+layout_assert!(FramebufferPixelColorDescriptor, size = 2, align = 1, {
+    position: 0,
+    size: 1,
+});
+
+layout_assert!(FramebufferPixelDescriptor, size = 6, align = 1, {
+    red: 0,
+    green: 2,
+    blue: 4,
+});
@@ -1,13 +1,27 @@
 #![no_std]
 #![allow(clippy::missing_safety_doc)]
 
-use core::{mem::size_of, slice};
+mod boot_console;
+mod console;
+mod dirty;
+pub mod font;
+mod layout;
+mod text_buffer;
+mod text_mode;
+
+pub use boot_console::{BootConsole, Console};
+pub use console::{default_scale, FramebufferConsole, HIGH_DPI_HEIGHT};
+pub use dirty::{DirtyRects, Rect, MAX_DIRTY_RECTS};
+pub use text_buffer::{ColorPair, TextBuffer, MAX_COLUMNS, MAX_ROWS};
+pub use text_mode::TextModeConsole;
+
+use core::{fmt, mem::size_of, ops::Range, ptr, slice};
 use multiboot2::{aligned_pointer_cast, FramebufferTag};
 
 pub enum Framebuffer<'a> {
     IndexedColor(IndexedColorFramebuffer<'a>),
     RgbColor(RgbColorFramebuffer<'a>),
-    Ega,
+    Ega(EgaTextFramebuffer<'a>),
 }
 
 impl<'a> Framebuffer<'a> {
@@ -50,7 +64,7 @@ impl<'a> Framebuffer<'a> {
                     }))
                 }
             }
-            EGA_TEXT_MODE => Some(Self::Ega),
+            EGA_TEXT_MODE => Some(Self::Ega(EgaTextFramebuffer { core })),
             _ => None,
         }
     }
@@ -59,10 +73,11 @@ impl<'a> Framebuffer<'a> {
 pub struct StandardRgbFramebuffer<'a> {
     framebuffer: &'a mut [u8],
     pitch: u32,
-    _width: u32,
-    _height: u32,
+    width: u32,
+    height: u32,
     bytes_per_pixel: u8,
-    _pixel_descriptor: FramebufferPixelDescriptor,
+    pixel_descriptor: FramebufferPixelDescriptor,
+    dirty: Option<DirtyRects>,
 }
 
 impl<'a> StandardRgbFramebuffer<'a> {
@@ -73,10 +88,11 @@ impl<'a> StandardRgbFramebuffer<'a> {
                     Some(Self {
                         framebuffer: buffer.core.framebuffer,
                         pitch: buffer.core.pitch,
-                        _width: buffer.core.width,
-                        _height: buffer.core.height,
+                        width: buffer.core.width,
+                        height: buffer.core.height,
                         bytes_per_pixel: buffer.core.bits_per_pixel >> 3,
-                        _pixel_descriptor: buffer.pixel_descriptor,
+                        pixel_descriptor: buffer.pixel_descriptor,
+                        dirty: None,
                     })
                 } else {
                     None
@@ -90,19 +106,284 @@ impl<'a> StandardRgbFramebuffer<'a> {
         Self::new(Framebuffer::new(tag)?)
     }
 
+    /// The width of the screen in pixels
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the screen in pixels
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the `(width, height)` of the screen in pixels
+    #[must_use]
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The number of bytes that the screen takes up, including any padding at the ends of rows
+    #[must_use]
+    pub fn size_in_bytes(&self) -> usize {
+        self.framebuffer.len()
+    }
+
+    /// Returns true if the pixel at `row` and `column` is on the screen.
+    #[must_use]
+    pub fn contains_pixel(&self, row: u32, column: u32) -> bool {
+        row < self.height && column < self.width
+    }
+
+    /// Draws a single pixel. Pixels that are off the screen are ignored rather than wrapping
+    /// around into the next row.
     pub fn draw_pixel(&mut self, row: u32, column: u32, color: [u8; 8]) {
         let bpp = self.bytes_per_pixel as usize;
         let location = row as usize * self.pitch as usize + column as usize * bpp;
-        if location + bpp <= self.framebuffer.len() {
-            self.framebuffer[location..location + bpp].copy_from_slice(&color[..bpp]);
+        if self.contains_pixel(row, column) && location + bpp <= self.framebuffer.len() {
+            write_volatile_bytes(
+                &mut self.framebuffer[location..location + bpp],
+                &color[..bpp],
+            );
+            self.mark_dirty(Rect::new(row, column, 1, 1));
+        }
+    }
+
+    /**
+     * Returns the bytes of the pixels in one row of the screen, not including any padding at the
+     * end of the row. Returns `None` if `row` is off the screen.
+     *
+     * The whole row is marked dirty since the caller may modify any of it. The row is usually video
+     * memory, so the caller should write to it with `ptr::write_volatile` like the rest of this
+     * type does.
+     */
+    pub fn row_mut(&mut self, row: u32) -> Option<&mut [u8]> {
+        let range = self.row_range(row, 0, self.width)?;
+        self.mark_dirty(Rect::new(row, 0, self.width, 1));
+        self.framebuffer.get_mut(range)
+    }
+
+    /**
+     * Fills the pixels from `start_column` up to but not including `end_column` in one row with
+     * `color`.
+     *
+     * Nothing is drawn if `row` is off the screen or if `end_column` is not greater than
+     * `start_column`. Columns past the right edge of the screen are ignored.
+     */
+    pub fn fill_span(&mut self, row: u32, start_column: u32, end_column: u32, color: [u8; 8]) {
+        let end_column = end_column.min(self.width);
+        if start_column < end_column
+            && self.fill_span_unmarked(row, start_column, end_column, color)
+        {
+            self.mark_dirty(Rect::new(row, start_column, end_column - start_column, 1));
+        }
+    }
+
+    /// Fills a rectangle with `color`. Any part of the rectangle that's off the screen is ignored.
+    pub fn fill_rect(&mut self, row: u32, column: u32, width: u32, height: u32, color: [u8; 8]) {
+        let rect = Rect::new(row, column, width, height).clip(self.width, self.height);
+        if rect.is_empty() {
+            return;
+        }
+        for pixel_row in rect.row..rect.end_row() {
+            self.fill_span_unmarked(pixel_row, rect.column, rect.end_column(), color);
         }
+        self.mark_dirty(rect);
+    }
+
+    /// Moves the contents of the screen up by `rows` rows of pixels and fills the rows that are
+    /// uncovered at the bottom with `color`.
+    pub fn scroll_up(&mut self, rows: u32, color: [u8; 8]) {
+        let rows = rows.min(self.height);
+        let pitch = self.pitch as usize;
+        let visible_bytes = self.height as usize * pitch;
+        copy_within_volatile(self.framebuffer, rows as usize * pitch..visible_bytes, 0);
+        self.fill_rect(self.height - rows, 0, self.width, rows, color);
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
     }
 
     pub fn paint_the_screen_white(&mut self) {
-        self.framebuffer.fill(0xff);
+        for byte in self.framebuffer.iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0xff) };
+        }
+        self.mark_dirty(Rect::new(0, 0, self.width, self.height));
+    }
+
+    /// Starts recording which regions of the framebuffer are modified by drawing operations.
+    pub fn enable_dirty_tracking(&mut self) {
+        self.dirty.get_or_insert_with(DirtyRects::new);
+    }
+
+    pub fn disable_dirty_tracking(&mut self) {
+        self.dirty = None;
+    }
+
+    /// Records that a region of the framebuffer has been modified. Does nothing unless dirty
+    /// tracking is enabled.
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let (width, height) = (self.width, self.height);
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.add(rect.clip(width, height));
+        }
+    }
+
+    /**
+     * Passes every dirty region to `target` one scanline at a time and then clears the dirty set.
+     *
+     * `target` receives a single-row `Rect` and the bytes of the pixels in that row of the rect.
+     * Returns the number of pixels that were passed to `target`.
+     */
+    pub fn flush_dirty(&mut self, target: &mut dyn FnMut(Rect, &[u8])) -> usize {
+        let Some(dirty) = self.dirty.as_mut() else {
+            return 0;
+        };
+        let bpp = self.bytes_per_pixel as usize;
+        let mut pixels_copied = 0;
+        for rect in dirty.as_slice() {
+            for row in rect.row..rect.end_row() {
+                let start = row as usize * self.pitch as usize + rect.column as usize * bpp;
+                let end = start + rect.width as usize * bpp;
+                if let Some(bytes) = self.framebuffer.get(start..end) {
+                    target(Rect::new(row, rect.column, rect.width, 1), bytes);
+                    pixels_copied += rect.width as usize;
+                }
+            }
+        }
+        dirty.clear();
+        pixels_copied
+    }
+
+    /**
+     * Creates a framebuffer with the same size and pixel format in `memory`, for drawing off screen
+     * and then copying the result onto this one with `present`. Dirty tracking is enabled so that
+     * only what changes gets copied. Returns `None` if `memory` is too small.
+     */
+    #[must_use]
+    pub fn back_buffer<'b>(&self, memory: &'b mut [u8]) -> Option<StandardRgbFramebuffer<'b>> {
+        Some(StandardRgbFramebuffer {
+            framebuffer: memory.get_mut(..self.size_in_bytes())?,
+            pitch: self.pitch,
+            width: self.width,
+            height: self.height,
+            bytes_per_pixel: self.bytes_per_pixel,
+            pixel_descriptor: self.pixel_descriptor,
+            dirty: Some(DirtyRects::new()),
+        })
+    }
+
+    /**
+     * Copies every region of this framebuffer that has changed since the last call onto `front`,
+     * which should be the framebuffer that this one is the `back_buffer` of. Returns the number of
+     * pixels copied.
+     */
+    pub fn present(&mut self, front: &mut StandardRgbFramebuffer) -> usize {
+        self.flush_dirty(&mut |rect, pixels| front.copy_into_row(rect.row, rect.column, pixels))
+    }
+
+    /// Converts a color into the raw bytes of a pixel in this framebuffer's pixel format.
+    #[must_use]
+    pub fn pack_color(&self, color: Rgb) -> [u8; 8] {
+        u64::from(self.pixel_descriptor.pack(color)).to_le_bytes()
+    }
+
+    /**
+     * Draws a pixel by blending `color` with the pixel that's already on the screen. An `alpha` of
+     * 255 draws `color` as-is and an `alpha` of 0 leaves the pixel unchanged.
+     *
+     * Only framebuffers with 32 bits per pixel are supported.
+     */
+    pub fn blend_pixel(
+        &mut self,
+        row: u32,
+        column: u32,
+        color: Rgb,
+        alpha: u8,
+    ) -> Result<(), DrawError> {
+        if self.bytes_per_pixel != 4 {
+            return Err(DrawError::Unsupported);
+        }
+        if self.contains_pixel(row, column) && self.blend_pixel_unmarked(row, column, color, alpha)
+        {
+            self.mark_dirty(Rect::new(row, column, 1, 1));
+        }
+        Ok(())
+    }
+
+    /// Blends `color` over a rectangle of the screen. See `blend_pixel`.
+    pub fn blend_rect(&mut self, rect: Rect, color: Rgb, alpha: u8) -> Result<(), DrawError> {
+        if self.bytes_per_pixel != 4 {
+            return Err(DrawError::Unsupported);
+        }
+        let rect = rect.clip(self.width, self.height);
+        for row in rect.row..rect.end_row() {
+            for column in rect.column..rect.end_column() {
+                self.blend_pixel_unmarked(row, column, color, alpha);
+            }
+        }
+        self.mark_dirty(rect);
+        Ok(())
+    }
+
+    // Returns the range of bytes in the framebuffer holding the given columns of a row, or `None`
+    // if the row is off the screen. The columns must already be clipped to the screen width.
+    fn row_range(&self, row: u32, start_column: u32, end_column: u32) -> Option<Range<usize>> {
+        if row >= self.height {
+            return None;
+        }
+        let bpp = self.bytes_per_pixel as usize;
+        let row_start = row as usize * self.pitch as usize;
+        let range =
+            (row_start + start_column as usize * bpp)..(row_start + end_column as usize * bpp);
+        (range.end <= self.framebuffer.len()).then_some(range)
+    }
+
+    // Writes packed pixels into a row without marking them dirty. Pixels past the right edge of the
+    // screen are dropped.
+    fn copy_into_row(&mut self, row: u32, column: u32, pixels: &[u8]) {
+        if column >= self.width {
+            return;
+        }
+        let columns =
+            u32::try_from(pixels.len() / self.bytes_per_pixel as usize).unwrap_or(u32::MAX);
+        let end_column = column.saturating_add(columns).min(self.width);
+        if let Some(range) = self.row_range(row, column, end_column) {
+            let length = range.len();
+            write_volatile_bytes(&mut self.framebuffer[range], &pixels[..length]);
+        }
+    }
+
+    // Blends a 32-bit pixel that's on the screen without marking it dirty. Returns false if the
+    // pixel is outside the framebuffer's memory.
+    fn blend_pixel_unmarked(&mut self, row: u32, column: u32, color: Rgb, alpha: u8) -> bool {
+        let location = row as usize * self.pitch as usize + column as usize * 4;
+        let Some(pixel) = self.framebuffer.get_mut(location..location + 4) else {
+            return false;
+        };
+        let destination = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+        let blended = blend(color, self.pixel_descriptor.unpack(destination), alpha);
+        write_volatile_bytes(pixel, &self.pixel_descriptor.pack(blended).to_le_bytes());
+        true
+    }
+
+    // Returns false if nothing was drawn.
+    fn fill_span_unmarked(
+        &mut self,
+        row: u32,
+        start_column: u32,
+        end_column: u32,
+        color: [u8; 8],
+    ) -> bool {
+        let Some(range) = self.row_range(row, start_column, end_column) else {
+            return false;
+        };
+        let bpp = self.bytes_per_pixel as usize;
+        fill_volatile(&mut self.framebuffer[range], &color[..bpp]);
+        true
     }
 
     pub const WHITE: [u8; 8] = [0xff; 8];
+    pub const BLACK: [u8; 8] = [0; 8];
 }
 
 pub struct IndexedColorFramebuffer<'a> {
@@ -110,16 +391,120 @@ pub struct IndexedColorFramebuffer<'a> {
     _color_palette: &'a [Rgb],
 }
 
+/// A text mode buffer. Its width and height are measured in characters rather than pixels.
+pub struct EgaTextFramebuffer<'a> {
+    core: FramebufferCore<'a>,
+}
+
 pub struct RgbColorFramebuffer<'a> {
     core: FramebufferCore<'a>,
     pixel_descriptor: FramebufferPixelDescriptor,
 }
 
 #[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Rgb {
-    red: u8,
-    green: u8,
-    blue: u8,
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    #[must_use]
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+/// An error from a drawing operation
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DrawError {
+    /// The operation isn't supported for the framebuffer's pixel format
+    Unsupported,
+}
+
+impl fmt::Display for DrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => f.write_str("unsupported by the framebuffer's pixel format"),
+        }
+    }
+}
+
+// Framebuffers are memory mapped devices, so writes to them must not be merged or elided.
+pub(crate) fn write_volatile_bytes(destination: &mut [u8], source: &[u8]) {
+    for (destination, source) in destination.iter_mut().zip(source) {
+        unsafe { ptr::write_volatile(destination, *source) };
+    }
+}
+
+// Fills `destination` with copies of `pixel`. Video memory is slow to write to, so whole 64-bit
+// words of pixels are stored at once wherever the alignment allows it. Pixels that don't fit in a
+// word evenly, like 24-bit ones, are written a byte at a time.
+pub(crate) fn fill_volatile(destination: &mut [u8], pixel: &[u8]) {
+    let bpp = pixel.len();
+    if !matches!(bpp, 1 | 2 | 4) {
+        fill_volatile_bytes(destination, pixel);
+        return;
+    }
+    let mut pattern = [0; size_of::<u64>()];
+    for (index, byte) in pattern.iter_mut().enumerate() {
+        *byte = pixel[index % bpp];
+    }
+    let pattern = u64::from_ne_bytes(pattern);
+    // Every bit pattern is a valid `u64`
+    let (head, words, tail) = unsafe { destination.align_to_mut::<u64>() };
+    if head.len() % bpp != 0 {
+        // The words wouldn't start at the beginning of a pixel
+        fill_volatile_bytes(destination, pixel);
+        return;
+    }
+    fill_volatile_bytes(head, pixel);
+    for word in words {
+        unsafe { ptr::write_volatile(word, pattern) };
+    }
+    fill_volatile_bytes(tail, pixel);
+}
+
+fn fill_volatile_bytes(destination: &mut [u8], pixel: &[u8]) {
+    for (byte, &value) in destination.iter_mut().zip(pixel.iter().cycle()) {
+        unsafe { ptr::write_volatile(byte, value) };
+    }
+}
+
+// `copy_within` for video memory. The bytes are copied from first to last, so nothing is copied
+// if `destination` is after the start of `source`.
+pub(crate) fn copy_within_volatile(buffer: &mut [u8], source: Range<usize>, destination: usize) {
+    if destination > source.start || source.end > buffer.len() {
+        return;
+    }
+    let base = buffer.as_mut_ptr();
+    for (offset, from) in source.enumerate() {
+        // Every destination byte comes before the source byte it's copied from, so both are in
+        // the buffer
+        unsafe {
+            ptr::write_volatile(
+                base.add(destination + offset),
+                ptr::read_volatile(base.add(from)),
+            );
+        }
+    }
+}
+
+/// Blends two colors channel by channel, weighting `source` by `alpha` out of 255.
+#[must_use]
+pub fn blend(source: Rgb, destination: Rgb, alpha: u8) -> Rgb {
+    let channel = |source: u8, destination: u8| {
+        let alpha = u16::from(alpha);
+        let blended = (u16::from(source) * alpha + u16::from(destination) * (255 - alpha)) / 255;
+        // The weights sum to 255, so the result always fits in a byte
+        blended as u8
+    };
+    Rgb {
+        red: channel(source.red, destination.red),
+        green: channel(source.green, destination.green),
+        blue: channel(source.blue, destination.blue),
+    }
 }
 
 const INDEXED_COLOR_MODE: u8 = 0;
@@ -141,6 +526,25 @@ struct FramebufferPixelColorDescriptor {
     size: u8,
 }
 
+impl FramebufferPixelColorDescriptor {
+    fn size(self) -> u8 {
+        self.size.min(8)
+    }
+
+    fn pack(self, value: u8) -> u32 {
+        (u32::from(value) >> (8 - self.size()))
+            .checked_shl(self.position.into())
+            .unwrap_or(0)
+    }
+
+    fn unpack(self, pixel: u32) -> u8 {
+        let mask = (1u32 << self.size()) - 1;
+        let value = pixel.checked_shr(self.position.into()).unwrap_or(0) & mask;
+        // The mask limits the value to at most 8 bits
+        (value << (8 - self.size())) as u8
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct FramebufferPixelDescriptor {
@@ -148,3 +552,307 @@ struct FramebufferPixelDescriptor {
     green: FramebufferPixelColorDescriptor,
     blue: FramebufferPixelColorDescriptor,
 }
+
+impl FramebufferPixelDescriptor {
+    fn pack(self, color: Rgb) -> u32 {
+        self.red.pack(color.red) | self.green.pack(color.green) | self.blue.pack(color.blue)
+    }
+
+    fn unpack(self, pixel: u32) -> Rgb {
+        Rgb {
+            red: self.red.unpack(pixel),
+            green: self.green.unpack(pixel),
+            blue: self.blue.unpack(pixel),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{FramebufferConsole, TextBuffer};
+    use std::{boxed::Box, vec, vec::Vec};
+
+    /// The position and size of the red, green, and blue channels, as in a framebuffer tag
+    pub(crate) const XRGB_8888: [u8; 6] = [16, 8, 8, 8, 0, 8];
+    const BGRX_8888: [u8; 6] = [8, 8, 16, 8, 24, 8];
+    const RGB_565: [u8; 6] = [11, 5, 5, 6, 0, 5];
+    const XRGB_1555: [u8; 6] = [10, 5, 5, 5, 0, 5];
+
+    /// A framebuffer over ordinary memory, described the way the bootloader would describe it
+    pub(crate) fn framebuffer_over<'a>(
+        memory: &'a mut [u8],
+        width: u32,
+        height: u32,
+        bits_per_pixel: u8,
+        color_data: &'static [u8],
+    ) -> StandardRgbFramebuffer<'a> {
+        let pitch = width * u32::from(bits_per_pixel / 8);
+        assert!(memory.len() >= (pitch * height) as usize);
+        let tag = FramebufferTag {
+            framebuffer: memory.as_mut_ptr(),
+            pitch,
+            width,
+            height,
+            bits_per_pixel,
+            framebuffer_type: RGB_COLOR_MODE,
+            color_data,
+        };
+        unsafe { StandardRgbFramebuffer::from_tag(tag) }.unwrap()
+    }
+
+    fn descriptor(color_data: [u8; 6]) -> FramebufferPixelDescriptor {
+        let channel = |index: usize| FramebufferPixelColorDescriptor {
+            position: color_data[index],
+            size: color_data[index + 1],
+        };
+        FramebufferPixelDescriptor {
+            red: channel(0),
+            green: channel(2),
+            blue: channel(4),
+        }
+    }
+
+    /// The value that a channel of `size` bits holds after `value` has been packed into it
+    fn truncated(value: u8, size: u8) -> u8 {
+        value >> (8 - size) << (8 - size)
+    }
+
+    #[test]
+    fn channels_round_trip_in_every_layout() {
+        for layout in [XRGB_8888, BGRX_8888, RGB_565, XRGB_1555] {
+            let descriptor = descriptor(layout);
+            for value in 0..=u8::MAX {
+                let color = Rgb::new(value, value.wrapping_mul(7), !value);
+                let round_trip = descriptor.unpack(descriptor.pack(color));
+                assert_eq!(
+                    round_trip,
+                    Rgb::new(
+                        truncated(color.red, layout[1]),
+                        truncated(color.green, layout[3]),
+                        truncated(color.blue, layout[5])
+                    ),
+                    "{layout:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn channels_are_packed_where_the_layout_says() {
+        let color = Rgb::new(0x12, 0x34, 0x56);
+        assert_eq!(descriptor(XRGB_8888).pack(color), 0x0012_3456);
+        assert_eq!(descriptor(BGRX_8888).pack(color), 0x5634_1200);
+        // Five bits of red, six of green, and five of blue
+        assert_eq!(descriptor(RGB_565).pack(Rgb::new(0xff, 0, 0xff)), 0xf81f);
+        let mut memory = vec![0; 4];
+        let framebuffer = framebuffer_over(&mut memory, 1, 1, 32, &XRGB_8888);
+        assert_eq!(
+            framebuffer.pack_color(color),
+            [0x56, 0x34, 0x12, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn blend_identities() {
+        let source = Rgb::new(0xc0, 0x10, 0x80);
+        let destination = Rgb::new(0x20, 0xff, 0x7f);
+        assert_eq!(blend(source, destination, u8::MAX), source);
+        assert_eq!(blend(source, destination, 0), destination);
+        assert_eq!(blend(source, source, 191), source);
+    }
+
+    #[test]
+    fn blend_at_three_quarters() {
+        // (0xc0 * 191 + 0xff * 64) / 255 = 207 and (0 * 191 + 0xff * 64) / 255 = 64
+        assert_eq!(
+            blend(Rgb::new(0xc0, 0, 0), Rgb::new(0xff, 0xff, 0xff), 191),
+            Rgb::new(207, 64, 64)
+        );
+    }
+
+    #[test]
+    fn blend_pixel_reads_back_the_screen() {
+        let mut memory = vec![0; 2 * 4];
+        let mut framebuffer = framebuffer_over(&mut memory, 2, 1, 32, &XRGB_8888);
+        framebuffer.fill_rect(
+            0,
+            0,
+            2,
+            1,
+            framebuffer.pack_color(Rgb::new(0xff, 0xff, 0xff)),
+        );
+        let red = Rgb::new(0xc0, 0, 0);
+        framebuffer.blend_pixel(0, 0, red, 191).unwrap();
+        framebuffer.blend_pixel(0, 1, red, 0).unwrap();
+        // Pixels off the screen are ignored
+        framebuffer.blend_pixel(0, 2, red, u8::MAX).unwrap();
+        assert_eq!(memory, [64, 64, 207, 0, 0xff, 0xff, 0xff, 0]);
+    }
+
+    #[test]
+    fn blend_rect_covers_the_clipped_rect() {
+        let mut memory = vec![0; 3 * 2 * 4];
+        let mut framebuffer = framebuffer_over(&mut memory, 3, 2, 32, &XRGB_8888);
+        let rect = Rect::new(1, 1, 10, 10);
+        framebuffer
+            .blend_rect(rect, Rgb::new(0x10, 0x20, 0x30), u8::MAX)
+            .unwrap();
+        let pixels: Vec<_> = memory.chunks_exact(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(pixels, [0, 0, 0, 0, 0x30, 0x30]);
+    }
+
+    #[test]
+    fn blend_rect_marks_the_clipped_rect_once() {
+        let mut memory = vec![0; 3 * 2 * 4];
+        let mut framebuffer = framebuffer_over(&mut memory, 3, 2, 32, &XRGB_8888);
+        framebuffer.enable_dirty_tracking();
+        framebuffer
+            .blend_rect(Rect::new(1, 1, 10, 10), Rgb::new(0x10, 0x20, 0x30), 128)
+            .unwrap();
+        assert_eq!(
+            framebuffer.dirty.as_ref().unwrap().as_slice(),
+            [Rect::new(1, 1, 2, 1)]
+        );
+    }
+
+    #[test]
+    fn blending_needs_32_bit_pixels() {
+        let mut memory = vec![0x55; 2 * 2 * 3];
+        let mut framebuffer = framebuffer_over(&mut memory, 2, 2, 24, &XRGB_8888);
+        let red = Rgb::new(0xc0, 0, 0);
+        assert_eq!(
+            framebuffer.blend_pixel(0, 0, red, 191),
+            Err(DrawError::Unsupported)
+        );
+        assert_eq!(
+            framebuffer.blend_rect(Rect::new(0, 0, 2, 2), red, 191),
+            Err(DrawError::Unsupported)
+        );
+        assert!(memory.iter().all(|&byte| byte == 0x55));
+    }
+
+    #[test]
+    fn panic_banner_is_opaque_without_blending() {
+        let mut memory = vec![0; 8 * 32 * 3];
+        let mut text = Box::<TextBuffer>::default();
+        let mut console = FramebufferConsole::new(
+            framebuffer_over(&mut memory, 8, 32, 24, &XRGB_8888),
+            &mut text,
+        );
+        console.draw_panic_banner(1);
+        // The banner covers the first row of text, which is 16 pixels tall
+        let (banner, rest) = memory.split_at(8 * 16 * 3);
+        assert!(banner.chunks_exact(3).all(|pixel| pixel == [0, 0, 0xc0]));
+        assert!(rest.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn present_copies_only_what_changed() {
+        let (width, height) = (8, 4);
+        let mut front_memory = vec![0; width * height * 4];
+        let mut back_memory = vec![0; width * height * 4];
+        let mut front = framebuffer_over(&mut front_memory, 8, 4, 32, &XRGB_8888);
+        let mut back = front.back_buffer(&mut back_memory).unwrap();
+        assert_eq!(back.dimensions(), (8, 4));
+
+        back.fill_rect(1, 2, 3, 2, [7; 8]);
+        back.draw_pixel(3, 7, [9; 8]);
+        assert_eq!(back.present(&mut front), 3 * 2 + 1);
+        // Nothing has changed since
+        assert_eq!(back.present(&mut front), 0);
+
+        let pixels: Vec<_> = front_memory.chunks_exact(4).map(|pixel| pixel[0]).collect();
+        #[rustfmt::skip]
+        assert_eq!(pixels, [
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 7, 7, 7, 0, 0, 0,
+            0, 0, 7, 7, 7, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 9,
+        ]);
+    }
+
+    #[test]
+    fn back_buffers_need_enough_memory() {
+        let mut front_memory = vec![0; 8 * 4 * 4];
+        let mut back_memory = vec![0; 8 * 4 * 4 - 1];
+        let front = framebuffer_over(&mut front_memory, 8, 4, 32, &XRGB_8888);
+        assert!(front.back_buffer(&mut back_memory).is_none());
+    }
+
+    #[test]
+    fn rows_off_the_screen_are_ignored() {
+        let mut memory = vec![0; 4 * 2 * 4];
+        let mut framebuffer = framebuffer_over(&mut memory, 4, 2, 32, &XRGB_8888);
+        framebuffer.enable_dirty_tracking();
+        for row in [2, u32::MAX] {
+            framebuffer.fill_span(row, 0, 4, [0xff; 8]);
+            assert!(framebuffer.row_mut(row).is_none());
+        }
+        assert!(framebuffer.dirty.as_ref().unwrap().as_slice().is_empty());
+        assert!(memory.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn spans_are_clipped_and_reversed_ones_draw_nothing() {
+        let mut memory = vec![0; 4 * 4];
+        let mut framebuffer = framebuffer_over(&mut memory, 4, 1, 32, &XRGB_8888);
+        framebuffer.enable_dirty_tracking();
+        framebuffer.fill_span(0, 3, 1, [1; 8]);
+        framebuffer.fill_span(0, 2, 2, [1; 8]);
+        assert!(framebuffer.dirty.as_ref().unwrap().as_slice().is_empty());
+        framebuffer.fill_span(0, 2, u32::MAX, [2; 8]);
+        assert_eq!(
+            framebuffer.dirty.as_ref().unwrap().as_slice(),
+            [Rect::new(0, 2, 2, 1)]
+        );
+        let pixels: Vec<_> = memory.chunks_exact(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(pixels, [0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn wide_fills_match_filling_pixel_by_pixel() {
+        let pixel = [1, 2, 3, 4];
+        for bpp in 1..=pixel.len() {
+            for offset in 0..8 {
+                for pixels in 0..12 {
+                    let mut memory = [0x55; 64];
+                    let span = offset..offset + pixels * bpp;
+                    fill_volatile(&mut memory[span.clone()], &pixel[..bpp]);
+                    let mut expected = [0x55; 64];
+                    for chunk in expected[span].chunks_exact_mut(bpp) {
+                        chunk.copy_from_slice(&pixel[..bpp]);
+                    }
+                    assert_eq!(memory, expected, "{bpp} bytes per pixel at offset {offset}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flushing_without_dirty_tracking_copies_nothing() {
+        let mut memory = vec![0; 8 * 4 * 4];
+        let mut framebuffer = framebuffer_over(&mut memory, 8, 4, 32, &XRGB_8888);
+        framebuffer.fill_rect(0, 0, 8, 4, [1; 8]);
+        assert_eq!(
+            framebuffer.flush_dirty(&mut |_, _| panic!("nothing is dirty")),
+            0
+        );
+        framebuffer.enable_dirty_tracking();
+        framebuffer.fill_rect(0, 0, 8, 4, [2; 8]);
+        let mut rows = Vec::new();
+        let copied = framebuffer.flush_dirty(&mut |rect, pixels| {
+            assert_eq!(pixels.len(), rect.width as usize * 4);
+            rows.push(rect);
+        });
+        assert_eq!(copied, 8 * 4);
+        assert_eq!(
+            rows,
+            (0..4)
+                .map(|row| Rect::new(row, 0, 8, 1))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,252 @@
+/// The largest number of columns of text that the console keeps track of
+pub const MAX_COLUMNS: u32 = 240;
+/// The largest number of rows of text that the console keeps track of
+pub const MAX_ROWS: u32 = 100;
+
+const MAX_CELLS: usize = (MAX_COLUMNS * MAX_ROWS) as usize;
+const MAX_COLOR_PAIRS: usize = 16;
+
+/// The packed foreground and background colors of a character cell
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ColorPair {
+    pub foreground: [u8; 8],
+    pub background: [u8; 8],
+}
+
+impl ColorPair {
+    #[must_use]
+    pub const fn new(foreground: [u8; 8], background: [u8; 8]) -> Self {
+        Self {
+            foreground,
+            background,
+        }
+    }
+
+    /// Swaps the foreground and background colors
+    #[must_use]
+    pub const fn inverted(self) -> Self {
+        Self::new(self.background, self.foreground)
+    }
+}
+
+/**
+ * A copy of the characters on the console and the colors that they were drawn in.
+ *
+ * Cells store an index into a small table of color pairs rather than the colors themselves so that
+ * the buffer stays small enough to keep alongside the console. It's still too big for a kernel
+ * stack, so `new` is a `const fn` that lets the buffer live in a static.
+ */
+pub struct TextBuffer {
+    characters: [u8; MAX_CELLS],
+    colors: [u8; MAX_CELLS],
+    color_pairs: [ColorPair; MAX_COLOR_PAIRS],
+    color_pair_count: usize,
+    columns: u32,
+    rows: u32,
+}
+
+impl TextBuffer {
+    /// Creates a buffer with no rows or columns. Call `resize` to give it some.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            characters: [b' '; MAX_CELLS],
+            colors: [0; MAX_CELLS],
+            color_pairs: [ColorPair::new([0; 8], [0; 8]); MAX_COLOR_PAIRS],
+            color_pair_count: 0,
+            columns: 0,
+            rows: 0,
+        }
+    }
+
+    /// Changes the dimensions of the buffer and fills it with spaces. Dimensions larger than
+    /// `MAX_COLUMNS` by `MAX_ROWS` are clamped.
+    pub fn resize(&mut self, columns: u32, rows: u32, colors: ColorPair) {
+        self.columns = columns.min(MAX_COLUMNS);
+        self.rows = rows.min(MAX_ROWS);
+        self.color_pairs[0] = colors;
+        self.color_pair_count = 1;
+        self.characters.fill(b' ');
+        self.colors.fill(0);
+    }
+
+    /// Returns the character and colors of a cell, or `None` if the cell is outside the buffer.
+    #[must_use]
+    pub fn get(&self, row: u32, column: u32) -> Option<(u8, ColorPair)> {
+        let index = self.index(row, column)?;
+        let colors = self.color_pairs[self.colors[index] as usize];
+        Some((self.characters[index], colors))
+    }
+
+    /// Stores a character in a cell. Cells outside the buffer are ignored.
+    pub fn set(&mut self, row: u32, column: u32, character: u8, colors: ColorPair) {
+        if let Some(index) = self.index(row, column) {
+            let color_index = self.color_index(colors);
+            self.characters[index] = character;
+            self.colors[index] = color_index;
+        }
+    }
+
+    /// Moves every row up by one and fills the bottom row with spaces.
+    pub fn scroll_up(&mut self, colors: ColorPair) {
+        let columns = self.columns as usize;
+        let cells = columns * self.rows as usize;
+        if cells == 0 {
+            return;
+        }
+        self.characters.copy_within(columns..cells, 0);
+        self.colors.copy_within(columns..cells, 0);
+        let color_index = self.color_index(colors);
+        self.characters[cells - columns..cells].fill(b' ');
+        self.colors[cells - columns..cells].fill(color_index);
+    }
+
+    fn index(&self, row: u32, column: u32) -> Option<usize> {
+        (row < self.rows && column < self.columns)
+            .then(|| row as usize * self.columns as usize + column as usize)
+    }
+
+    // Finds the slot for a color pair, adding it to the table if it's new. When the table is full
+    // a slot that no cell refers to is reused. If every slot is in use the last one is replaced,
+    // which changes the colors of any cells using it the next time they're redrawn.
+    fn color_index(&mut self, colors: ColorPair) -> u8 {
+        let pairs = &self.color_pairs[..self.color_pair_count];
+        let index = if let Some(index) = pairs.iter().position(|pair| *pair == colors) {
+            index
+        } else if self.color_pair_count < MAX_COLOR_PAIRS {
+            self.color_pair_count += 1;
+            self.color_pair_count - 1
+        } else {
+            let cells = &self.colors[..self.columns as usize * self.rows as usize];
+            (0..MAX_COLOR_PAIRS)
+                .find(|slot| !cells.iter().any(|index| *index as usize == *slot))
+                .unwrap_or(MAX_COLOR_PAIRS - 1)
+        };
+        self.color_pairs[index] = colors;
+        // There are at most MAX_COLOR_PAIRS slots, so the index fits in a byte
+        index as u8
+    }
+}
+
+impl Default for TextBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+
+    /// A color pair that's different for every `n`
+    fn colors(n: u8) -> ColorPair {
+        ColorPair::new([n; 8], [!n; 8])
+    }
+
+    fn buffer(columns: u32, rows: u32) -> Box<TextBuffer> {
+        let mut buffer = Box::<TextBuffer>::default();
+        buffer.resize(columns, rows, colors(0));
+        buffer
+    }
+
+    #[test]
+    fn new_buffers_are_empty() {
+        let buffer = TextBuffer::new();
+        assert_eq!(buffer.get(0, 0), None);
+    }
+
+    #[test]
+    fn cells_hold_what_was_set() {
+        let mut buffer = buffer(3, 2);
+        assert_eq!(buffer.get(1, 2), Some((b' ', colors(0))));
+        buffer.set(1, 2, b'x', colors(1));
+        buffer.set(0, 0, b'y', colors(0));
+        assert_eq!(buffer.get(1, 2), Some((b'x', colors(1))));
+        assert_eq!(buffer.get(0, 0), Some((b'y', colors(0))));
+        // Cells outside the buffer are ignored rather than wrapping into the next row
+        buffer.set(0, 3, b'z', colors(2));
+        assert_eq!(buffer.get(1, 0), Some((b' ', colors(0))));
+        assert_eq!(buffer.get(0, 3), None);
+        assert_eq!(buffer.get(2, 0), None);
+    }
+
+    #[test]
+    fn resizing_clears_and_clamps() {
+        let mut buffer = buffer(3, 2);
+        buffer.set(0, 0, b'x', colors(1));
+        buffer.resize(MAX_COLUMNS + 1, MAX_ROWS + 1, colors(2));
+        assert_eq!(buffer.get(0, 0), Some((b' ', colors(2))));
+        assert!(buffer.get(MAX_ROWS - 1, MAX_COLUMNS - 1).is_some());
+        assert_eq!(buffer.get(MAX_ROWS, 0), None);
+        assert_eq!(buffer.get(0, MAX_COLUMNS), None);
+    }
+
+    #[test]
+    fn scrolling_moves_rows_up() {
+        let mut buffer = buffer(2, 3);
+        for (row, character) in (0..3).zip(b"abc") {
+            buffer.set(row, 0, *character, colors(row as u8 + 1));
+        }
+        buffer.scroll_up(colors(9));
+        assert_eq!(buffer.get(0, 0), Some((b'b', colors(2))));
+        assert_eq!(buffer.get(1, 0), Some((b'c', colors(3))));
+        assert_eq!(buffer.get(0, 1), Some((b' ', colors(0))));
+        // The new bottom row is blank in the colors passed in
+        assert_eq!(buffer.get(2, 0), Some((b' ', colors(9))));
+        assert_eq!(buffer.get(2, 1), Some((b' ', colors(9))));
+    }
+
+    #[test]
+    fn scrolling_an_empty_buffer_does_nothing() {
+        let mut buffer = buffer(0, 0);
+        buffer.scroll_up(colors(1));
+        assert_eq!(buffer.get(0, 0), None);
+    }
+
+    #[test]
+    fn color_pairs_are_shared() {
+        let mut buffer = buffer(4, 1);
+        buffer.set(0, 0, b'a', colors(1));
+        buffer.set(0, 1, b'b', colors(1));
+        buffer.set(0, 2, b'c', colors(0));
+        assert_eq!(buffer.color_pair_count, 2);
+        assert_eq!(buffer.colors[..3], [1, 1, 0]);
+    }
+
+    #[test]
+    fn unreferenced_color_pairs_are_reused_when_the_table_is_full() {
+        let count = u8::try_from(MAX_COLOR_PAIRS).unwrap();
+        let mut buffer = buffer(u32::from(count), 1);
+        for n in 1..count {
+            buffer.set(0, u32::from(n), b'x', colors(n));
+        }
+        assert_eq!(buffer.color_pair_count, MAX_COLOR_PAIRS);
+        // Overwriting the only cell in colors(5) frees its slot for the next new pair
+        buffer.set(0, 5, b'y', colors(1));
+        buffer.set(0, 0, b'z', colors(100));
+        assert_eq!(buffer.colors[0], 5);
+        assert_eq!(buffer.get(0, 0), Some((b'z', colors(100))));
+        // Every other cell keeps its colors
+        for n in 1..count {
+            let expected = if n == 5 { colors(1) } else { colors(n) };
+            assert_eq!(buffer.get(0, u32::from(n)).unwrap().1, expected);
+        }
+    }
+
+    #[test]
+    fn last_color_pair_is_replaced_when_every_slot_is_in_use() {
+        let count = u8::try_from(MAX_COLOR_PAIRS).unwrap();
+        let mut buffer = buffer(u32::from(count) + 1, 1);
+        for n in 0..count {
+            buffer.set(0, u32::from(n), b'x', colors(n));
+        }
+        buffer.set(0, u32::from(count), b'y', colors(100));
+        assert_eq!(buffer.get(0, u32::from(count)), Some((b'y', colors(100))));
+        // The cell that used the replaced pair picks up the new colors
+        assert_eq!(buffer.get(0, u32::from(count) - 1).unwrap().1, colors(100));
+        assert_eq!(buffer.get(0, 0).unwrap().1, colors(0));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{copy_within_volatile, write_volatile_bytes, Console, EgaTextFramebuffer, Rgb};
+use core::fmt;
+
+/// A console for an EGA text mode buffer where each character cell is a character byte followed by
+/// an attribute byte holding the foreground and background colors.
+pub struct TextModeConsole<'a> {
+    buffer: &'a mut [u8],
+    pitch: u32,
+    columns: u32,
+    rows: u32,
+    row: u32,
+    column: u32,
+    attribute: u8,
+}
+
+impl<'a> TextModeConsole<'a> {
+    /// Creates a console that covers the whole text buffer and clears the screen.
+    #[must_use]
+    pub fn new(framebuffer: EgaTextFramebuffer<'a>) -> Self {
+        let mut console = Self {
+            buffer: framebuffer.core.framebuffer,
+            pitch: framebuffer.core.pitch,
+            columns: framebuffer.core.width,
+            rows: framebuffer.core.height,
+            row: 0,
+            column: 0,
+            attribute: DEFAULT_ATTRIBUTE,
+        };
+        console.clear();
+        console
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            _ => {
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+                self.put_cell(self.row, self.column, byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            let pitch = self.pitch as usize;
+            let visible_bytes = self.rows as usize * pitch;
+            copy_within_volatile(self.buffer, pitch..visible_bytes, 0);
+            for column in 0..self.columns {
+                self.put_cell(self.rows - 1, column, b' ');
+            }
+        }
+    }
+
+    fn put_cell(&mut self, row: u32, column: u32, character: u8) {
+        let location = row as usize * self.pitch as usize + column as usize * 2;
+        if let Some(cell) = self.buffer.get_mut(location..location + 2) {
+            write_volatile_bytes(cell, &[character, self.attribute]);
+        }
+    }
+}
+
+impl fmt::Write for TextModeConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+impl Console for TextModeConsole<'_> {
+    fn clear(&mut self) {
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                self.put_cell(row, column, b' ');
+            }
+        }
+        self.row = 0;
+        self.column = 0;
+    }
+
+    // Background colors are limited to the first 8 palette entries because the top bit of the
+    // attribute makes the character blink.
+    fn set_color(&mut self, foreground: Rgb, background: Rgb) {
+        let foreground = nearest_palette_color(foreground, &PALETTE);
+        let background = nearest_palette_color(background, &PALETTE[..8]);
+        self.attribute = (background << 4) | foreground;
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.columns, self.rows)
+    }
+
+    fn set_cursor(&mut self, row: u32, column: u32) {
+        self.row = row.min(self.rows.saturating_sub(1));
+        self.column = column.min(self.columns.saturating_sub(1));
+    }
+}
+
+// Light gray on black
+const DEFAULT_ATTRIBUTE: u8 = 0x07;
+
+// The standard 16 color text mode palette
+const PALETTE: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+fn nearest_palette_color(color: Rgb, palette: &[Rgb]) -> u8 {
+    let distance = |other: &Rgb| {
+        let channel = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+        channel(color.red, other.red)
+            + channel(color.green, other.green)
+            + channel(color.blue, other.blue)
+    };
+    let (index, _) = palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, other)| distance(other))
+        .unwrap_or((0, &PALETTE[0]));
+    // The palette has 16 entries, so the index fits in a nibble
+    index as u8
+}
@@ -0,0 +1,55 @@
+#![no_std]
+
+//! Compile time checks of the layouts of structures that are shared with firmware, bootloaders,
+//! other programs, or assembly. Each check names the type and the field that moved, so a change
+//! that breaks a layout fails the build with a message that says what broke.
+
+/**
+ * Asserts the size and optionally the alignment and field offsets of a type at compile time. The
+ * numbers should come from the specification or the other side of the interface, not from the
+ * type itself.
+ *
+ * ```
+ * # use layout_assert::layout_assert;
+ * #[repr(C)]
+ * struct MemoryMapEntry {
+ *     base_addr: u64,
+ *     length: u64,
+ *     region_type: u32,
+ *     reserved: u32,
+ * }
+ *
+ * layout_assert!(MemoryMapEntry, size = 24, align = 8, {
+ *     base_addr: 0,
+ *     length: 8,
+ *     region_type: 16,
+ *     reserved: 20,
+ * });
+ * ```
+ */
+#[macro_export]
+macro_rules! layout_assert {
+    ($type:ty, size = $size:expr $(, align = $align:expr)? $(, { $($field:ident: $offset:expr),* $(,)? })? $(,)?) => {
+        const _: () = {
+            assert!(
+                ::core::mem::size_of::<$type>() == $size,
+                concat!("`", stringify!($type), "` must be ", stringify!($size), " bytes"),
+            );
+            $(
+                assert!(
+                    ::core::mem::align_of::<$type>() == $align,
+                    concat!("`", stringify!($type), "` must be aligned to ", stringify!($align), " bytes"),
+                );
+            )?
+            $($(
+                assert!(
+                    ::core::mem::offset_of!($type, $field) == $offset,
+                    concat!(
+                        "`", stringify!($type), "::", stringify!($field), "` must be at offset ",
+                        stringify!($offset),
+                    ),
+                );
+            )*)?
+        };
+    };
+}
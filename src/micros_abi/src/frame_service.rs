@@ -0,0 +1,254 @@
+//! The messages that ask the memory manager for physical frames and carry its answers back. Each
+//! one fits in a single ring buffer `Message` with its fields stored as little endian `u64`s.
+
+use crate::ring::{Message, MESSAGE_ALLOC_FRAMES, MESSAGE_FRAMES_ALLOCATED, MESSAGE_FREE_FRAMES};
+use frame_allocation::{FfiResult, PhysicalAddress};
+
+/// The caller id of requests that the kernel makes for itself
+pub const KERNEL_CALLER: u32 = 0;
+
+/// Asks the memory manager for `count` physically contiguous 4 KB frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocFramesRequest {
+    /// Who the frames will belong to. The kernel fills this in, so it can be trusted.
+    pub caller: u32,
+    /// Copied into the response so that the caller can match them up
+    pub request_id: u32,
+    pub count: usize,
+    /// The largest frame that the memory manager may break up to satisfy the request, as a power
+    /// of two number of 4 KB frames
+    pub max_order: u8,
+}
+
+/// Gives `count` frames starting at `address` back to the memory manager. They must be exactly
+/// the frames of an earlier allocation by the same caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreeFramesRequest {
+    pub caller: u32,
+    pub address: PhysicalAddress,
+    pub count: usize,
+}
+
+/// Why the memory manager couldn't satisfy an `AllocFramesRequest`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AllocFramesError {
+    /// The count was 0, or more frames than `max_order` allows
+    InvalidCount = 1,
+    /// There weren't enough free frames
+    OutOfMemory = 2,
+}
+
+impl AllocFramesError {
+    /// Returns `None` if `code` isn't the code of an error
+    fn from_code(code: u64) -> Option<Self> {
+        match code {
+            1 => Some(Self::InvalidCount),
+            2 => Some(Self::OutOfMemory),
+            _ => None,
+        }
+    }
+}
+
+/// The memory manager's answer to an `AllocFramesRequest`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocFramesResponse {
+    pub request_id: u32,
+    /// The first allocated frame, or why the request couldn't be satisfied. The frame at address 0
+    /// is never handed out, so the message has address 0 and an error code for a failure and an
+    /// error code of 0 for a success.
+    pub result: FfiResult<PhysicalAddress, AllocFramesError>,
+    pub count: usize,
+}
+
+impl AllocFramesRequest {
+    #[must_use]
+    pub fn to_message(&self) -> Message {
+        Message::with_fields(
+            MESSAGE_ALLOC_FRAMES,
+            &[
+                self.caller.into(),
+                self.request_id.into(),
+                self.count as u64,
+                self.max_order.into(),
+            ],
+        )
+    }
+
+    /// Returns `None` if `message` isn't a well formed `MESSAGE_ALLOC_FRAMES` message.
+    #[must_use]
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let [caller, request_id, count, max_order] = message.fields(MESSAGE_ALLOC_FRAMES)?;
+        Some(Self {
+            caller: caller.try_into().ok()?,
+            request_id: request_id.try_into().ok()?,
+            count: count.try_into().ok()?,
+            max_order: max_order.try_into().ok()?,
+        })
+    }
+}
+
+impl FreeFramesRequest {
+    #[must_use]
+    pub fn to_message(&self) -> Message {
+        Message::with_fields(
+            MESSAGE_FREE_FRAMES,
+            &[
+                self.caller.into(),
+                self.address.as_usize() as u64,
+                self.count as u64,
+            ],
+        )
+    }
+
+    /// Returns `None` if `message` isn't a well formed `MESSAGE_FREE_FRAMES` message.
+    #[must_use]
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let [caller, address, count] = message.fields(MESSAGE_FREE_FRAMES)?;
+        Some(Self {
+            caller: caller.try_into().ok()?,
+            address: PhysicalAddress::new(address.try_into().ok()?),
+            count: count.try_into().ok()?,
+        })
+    }
+}
+
+impl AllocFramesResponse {
+    #[must_use]
+    pub fn to_message(&self) -> Message {
+        let (address, error) = match self.result {
+            FfiResult::Ok(address) => (address.as_usize() as u64, 0),
+            FfiResult::Err(error) => (0, error as u64),
+        };
+        Message::with_fields(
+            MESSAGE_FRAMES_ALLOCATED,
+            &[self.request_id.into(), address, self.count as u64, error],
+        )
+    }
+
+    /// Returns `None` if `message` isn't a well formed `MESSAGE_FRAMES_ALLOCATED` message.
+    #[must_use]
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let [request_id, address, count, error] = message.fields(MESSAGE_FRAMES_ALLOCATED)?;
+        let result = match (address, error) {
+            (0, error) => FfiResult::Err(AllocFramesError::from_code(error)?),
+            (address, 0) => FfiResult::Ok(PhysicalAddress::new(address.try_into().ok()?)),
+            _ => return None,
+        };
+        Some(Self {
+            request_id: request_id.try_into().ok()?,
+            result,
+            count: count.try_into().ok()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring::MESSAGE_KEY;
+
+    const REQUEST: AllocFramesRequest = AllocFramesRequest {
+        caller: 3,
+        request_id: u32::MAX,
+        count: 0x200,
+        max_order: 9,
+    };
+
+    fn free_request() -> FreeFramesRequest {
+        FreeFramesRequest {
+            caller: KERNEL_CALLER,
+            address: PhysicalAddress::new(0x1234_5000),
+            count: 4,
+        }
+    }
+
+    #[test]
+    fn requests_and_responses_round_trip() {
+        assert_eq!(
+            AllocFramesRequest::from_message(&REQUEST.to_message()),
+            Some(REQUEST)
+        );
+        assert_eq!(
+            FreeFramesRequest::from_message(&free_request().to_message()),
+            Some(free_request())
+        );
+        let response = AllocFramesResponse {
+            request_id: 7,
+            result: FfiResult::Ok(PhysicalAddress::new(0x20_0000)),
+            count: 1,
+        };
+        assert_eq!(
+            AllocFramesResponse::from_message(&response.to_message()),
+            Some(response)
+        );
+    }
+
+    #[test]
+    fn a_failed_allocation_is_sent_as_address_zero_and_an_error_code() {
+        for (error, code) in [
+            (AllocFramesError::InvalidCount, 1),
+            (AllocFramesError::OutOfMemory, 2),
+        ] {
+            let response = AllocFramesResponse {
+                request_id: 7,
+                result: FfiResult::Err(error),
+                count: 1,
+            };
+            let message = response.to_message();
+            assert_eq!(
+                message.fields(MESSAGE_FRAMES_ALLOCATED),
+                Some([7u64, 0, 1, code])
+            );
+            assert_eq!(AllocFramesResponse::from_message(&message), Some(response));
+        }
+    }
+
+    #[test]
+    fn responses_that_are_neither_a_frame_nor_an_error_are_rejected() {
+        for fields in [[7, 0, 1, 0], [7, 0x1000, 1, 2], [7, 0, 1, 3]] {
+            let message = Message::with_fields(MESSAGE_FRAMES_ALLOCATED, &fields);
+            assert_eq!(AllocFramesResponse::from_message(&message), None);
+        }
+    }
+
+    #[test]
+    fn fields_are_little_endian_u64s() {
+        let message = REQUEST.to_message();
+        assert_eq!(message.kind, MESSAGE_ALLOC_FRAMES);
+        assert_eq!(message.len, 32);
+        assert_eq!(message.payload[..8], 3u64.to_le_bytes());
+        assert_eq!(message.payload[8..16], u64::from(u32::MAX).to_le_bytes());
+        assert_eq!(message.payload[16..24], 0x200u64.to_le_bytes());
+        assert_eq!(message.payload[24..32], 9u64.to_le_bytes());
+    }
+
+    #[test]
+    fn messages_of_another_kind_are_rejected() {
+        let message = free_request().to_message();
+        assert_eq!(AllocFramesRequest::from_message(&message), None);
+        assert_eq!(AllocFramesResponse::from_message(&message), None);
+        let mut message = REQUEST.to_message();
+        message.kind = MESSAGE_KEY;
+        assert_eq!(AllocFramesRequest::from_message(&message), None);
+    }
+
+    #[test]
+    fn messages_with_the_wrong_number_of_fields_are_rejected() {
+        let message = Message::with_fields(MESSAGE_ALLOC_FRAMES, &[1, 2, 3]);
+        assert_eq!(AllocFramesRequest::from_message(&message), None);
+        let message = Message::with_fields(MESSAGE_FREE_FRAMES, &[1, 2, 3, 4]);
+        assert_eq!(FreeFramesRequest::from_message(&message), None);
+    }
+
+    #[test]
+    fn fields_too_big_for_their_type_are_rejected() {
+        let caller = u64::from(u32::MAX) + 1;
+        let message = Message::with_fields(MESSAGE_ALLOC_FRAMES, &[caller, 0, 1, 0]);
+        assert_eq!(AllocFramesRequest::from_message(&message), None);
+        let message = Message::with_fields(MESSAGE_ALLOC_FRAMES, &[0, 0, 1, 0x100]);
+        assert_eq!(AllocFramesRequest::from_message(&message), None);
+        let message = Message::with_fields(MESSAGE_FRAMES_ALLOCATED, &[caller, 0x1000, 1, 0]);
+        assert_eq!(AllocFramesResponse::from_message(&message), None);
+    }
+}
@@ -0,0 +1,30 @@
+//! The messages that carry keyboard input from the kernel to the memory manager
+
+use crate::ring::{Message, MESSAGE_KEY};
+
+/// A key press that produced a character
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// The ASCII character for the key. Enter is `b'\n'` and backspace is `0x08`.
+    pub character: u8,
+    /// The number of key presses that the kernel has dropped so far because the ring buffer was
+    /// full
+    pub dropped: u64,
+}
+
+impl KeyEvent {
+    #[must_use]
+    pub fn to_message(&self) -> Message {
+        Message::with_fields(MESSAGE_KEY, &[self.character.into(), self.dropped])
+    }
+
+    /// Returns `None` if `message` isn't a well formed `MESSAGE_KEY` message.
+    #[must_use]
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let [character, dropped] = message.fields(MESSAGE_KEY)?;
+        Some(Self {
+            character: character.try_into().ok()?,
+            dropped,
+        })
+    }
+}
@@ -0,0 +1,76 @@
+//! The layouts of everything that crosses from the kernel to the processes it launches. Both sides
+//! of the handoff compile these, so a change to the layout that isn't reflected here breaks the
+//! build instead of the boot.
+
+use crate::{
+    ring::{Message, MESSAGE_SIZE},
+    FramebufferHandoff, MemoryStats,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::{AllocatorHandoff, BootHandoff, ProcessHandoff};
+use core::mem::size_of;
+#[cfg(target_arch = "x86_64")]
+use frame_allocation::amd64::Amd64FrameAllocator;
+use layout_assert::layout_assert;
+
+#[cfg(target_arch = "x86_64")]
+layout_assert!(BootHandoff, size = 216, align = 8, {
+    magic: 0,
+    version: 8,
+    allocator: 16,
+    boot_info: 24,
+    framebuffer: 32,
+    init: 64,
+    memory_stats: 88,
+    root_page_table: 192,
+    events: 200,
+    replies: 208,
+});
+
+#[cfg(target_arch = "x86_64")]
+layout_assert!(Amd64FrameAllocator, size = 56);
+
+#[cfg(target_arch = "x86_64")]
+layout_assert!(AllocatorHandoff, size = 72, align = 8, {
+    magic: 0,
+    abi_version: 8,
+    allocator: 16,
+});
+
+#[cfg(target_pointer_width = "64")]
+layout_assert!(FramebufferHandoff, size = 24, align = 8, {
+    address: 0,
+    pitch: 8,
+    width: 12,
+    height: 16,
+    bits_per_pixel: 20,
+    framebuffer_type: 21,
+});
+#[cfg(target_pointer_width = "32")]
+layout_assert!(FramebufferHandoff, size = 20, align = 4, {
+    address: 0,
+    pitch: 4,
+    width: 8,
+    height: 12,
+    bits_per_pixel: 16,
+    framebuffer_type: 17,
+});
+
+#[cfg(target_arch = "x86_64")]
+layout_assert!(ProcessHandoff, size = 16, align = 8, {
+    root_page_table_address: 0,
+    entry_point: 8,
+});
+
+layout_assert!(MemoryStats, size = 13 * size_of::<usize>(), {
+    total: 0,
+    available: size_of::<usize>(),
+    free_by_frame_size: 6 * size_of::<usize>(),
+    low_memory: 12 * size_of::<usize>(),
+});
+
+layout_assert!(Message, size = MESSAGE_SIZE, align = 4, {
+    kind: 0,
+    len: 4,
+    payload: 8,
+});
@@ -0,0 +1,221 @@
+#![no_std]
+
+//! Data structures that the kernel shares with the processes it launches
+//!
+//! The kernel starts the memory manager at its `main` symbol, which must have the type
+//! `MemoryManagerEntry`. The first argument register holds a pointer to a `BootHandoff` and the
+//! stack pointer is at the top of the address space. Nothing checks the signature across the
+//! boundary at link time, so the memory manager has to check the magic and version of the handoff
+//! before it trusts anything else in it.
+
+pub mod frame_service;
+pub mod keyboard;
+mod layout;
+#[cfg(target_arch = "x86_64")]
+pub mod paging;
+pub mod ring;
+pub mod syscall;
+
+use frame_allocation::page_tables::{FOUR_KILOBYTES, GIGABYTE, TWO_MEGABYTES};
+#[cfg(target_arch = "x86_64")]
+use frame_allocation::{amd64::Amd64FrameAllocator, FfiOption, PhysicalAddress, VirtualAddress};
+#[cfg(target_arch = "x86_64")]
+use ring::RingBuffer;
+
+/// The value of `BootHandoff::magic`. It spells "MICROSBH" in ASCII.
+pub const BOOT_HANDOFF_MAGIC: u64 = 0x4842_534f_5243_494d;
+
+/// The value of `BootHandoff::version`. This must be incremented whenever the layout of
+/// `BootHandoff` or anything it contains changes.
+pub const BOOT_HANDOFF_VERSION: u32 = 6;
+
+/// The value of `AllocatorHandoff::magic`. It spells "MICROSFA" in ASCII.
+pub const ALLOCATOR_HANDOFF_MAGIC: u64 = 0x4146_534f_5243_494d;
+
+/// The value of `AllocatorHandoff::abi_version`. This must be incremented whenever the layout of
+/// the frame allocator changes.
+pub const ALLOCATOR_ABI_VERSION: u32 = 1;
+
+/// The signature of the memory manager's entry point
+#[cfg(target_arch = "x86_64")]
+pub type MemoryManagerEntry = unsafe extern "C" fn(handoff: *const BootHandoff) -> !;
+
+/// Everything that the kernel passes to the memory manager when it launches it. The kernel writes
+/// this to its own page and passes the memory manager a pointer to it.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct BootHandoff {
+    /// Always `BOOT_HANDOFF_MAGIC`
+    pub magic: u64,
+    /// Always `BOOT_HANDOFF_VERSION`
+    pub version: u32,
+    /// The frame allocator that holds all of the free memory
+    pub allocator: *mut AllocatorHandoff,
+    /// The multiboot2 boot information
+    pub boot_info: *const u8,
+    /// The framebuffer that the bootloader set up, if there is one
+    pub framebuffer: FfiOption<FramebufferHandoff>,
+    /// The init process, if the bootloader loaded one. The memory manager is responsible for
+    /// starting it.
+    pub init: FfiOption<ProcessHandoff>,
+    /// Where physical memory went while booting
+    pub memory_stats: MemoryStats,
+    /// The physical address of the memory manager's own root page table
+    pub root_page_table: PhysicalAddress,
+    /// The ring buffer that the kernel sends events to the memory manager through. The memory
+    /// manager is its only consumer.
+    pub events: *mut RingBuffer,
+    /// The ring buffer that the memory manager answers the kernel's requests through. The kernel
+    /// is its only consumer.
+    pub replies: *mut RingBuffer,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl BootHandoff {
+    /// Returns true if this was written by a kernel that uses the same layout as this crate.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.magic == BOOT_HANDOFF_MAGIC && self.version == BOOT_HANDOFF_VERSION
+    }
+}
+
+/// The frame allocator along with what's needed to check that both sides agree on its layout
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct AllocatorHandoff {
+    /// Always `ALLOCATOR_HANDOFF_MAGIC`
+    pub magic: u64,
+    /// Always `ALLOCATOR_ABI_VERSION`
+    pub abi_version: u32,
+    pub allocator: Amd64FrameAllocator,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl AllocatorHandoff {
+    #[must_use]
+    pub const fn new(allocator: Amd64FrameAllocator) -> Self {
+        Self {
+            magic: ALLOCATOR_HANDOFF_MAGIC,
+            abi_version: ALLOCATOR_ABI_VERSION,
+            allocator,
+        }
+    }
+
+    /// Returns true if this was written by a kernel that uses the same allocator layout as this
+    /// crate.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.magic == ALLOCATOR_HANDOFF_MAGIC && self.abi_version == ALLOCATOR_ABI_VERSION
+    }
+}
+
+/// A linear framebuffer that the bootloader set up
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct FramebufferHandoff {
+    /// A pointer to the framebuffer
+    pub address: *mut u8,
+    /// The size of a row in the framebuffer in bytes
+    pub pitch: u32,
+    /// The size of a row in the framebuffer in pixels
+    pub width: u32,
+    /// The number of rows in the framebuffer
+    pub height: u32,
+    /// The size of a pixel in bits
+    pub bits_per_pixel: u8,
+    /// The multiboot2 framebuffer type
+    pub framebuffer_type: u8,
+}
+
+/// A process whose address space the kernel has already set up
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ProcessHandoff {
+    pub root_page_table_address: PhysicalAddress,
+    pub entry_point: VirtualAddress,
+}
+
+/// The frame sizes that `MemoryStats::free_by_frame_size` counts, from smallest to largest
+pub const STATS_FRAME_SIZES: [usize; 3] = [FOUR_KILOBYTES, TWO_MEGABYTES, GIGABYTE];
+
+/// A summary of where physical memory went while booting. All sizes are in bytes.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct MemoryStats {
+    /// The size of every region in the memory map
+    pub total: usize,
+    /// Memory that the memory map reports as available
+    pub available: usize,
+    /// Memory holding ACPI tables that can be reclaimed once they've been read
+    pub acpi: usize,
+    /// Memory that the firmware reserved
+    pub reserved: usize,
+    /// Memory that the firmware reported as defective
+    pub defective: usize,
+    /// Memory that was handed to the frame allocator
+    pub free: usize,
+    /// The memory that was left in the frame allocator's free lists for each of
+    /// `STATS_FRAME_SIZES` once all of `free` had been handed to it. The page tables for the
+    /// identity map come out of `free`, so these can add up to a little less.
+    pub free_by_frame_size: [usize; STATS_FRAME_SIZES.len()],
+    pub kernel_image: usize,
+    pub boot_information: usize,
+    pub modules: usize,
+    /// Available memory below 1 MB that was kept out of the frame allocator
+    pub low_memory: usize,
+}
+
+impl MemoryStats {
+    /// The amount of available memory that is neither free nor known to be in use. Some of this is
+    /// expected because partial frames can't be allocated.
+    #[must_use]
+    pub fn unaccounted(&self) -> usize {
+        self.available.saturating_sub(
+            self.free + self.kernel_image + self.boot_information + self.modules + self.low_memory,
+        )
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use core::{mem::offset_of, ptr};
+
+    #[test]
+    fn new_allocator_handoff_is_valid() {
+        assert!(AllocatorHandoff::new(Amd64FrameAllocator::new()).is_valid());
+    }
+
+    #[test]
+    fn allocator_handoff_with_the_wrong_magic_is_rejected() {
+        let mut handoff = AllocatorHandoff::new(Amd64FrameAllocator::new());
+        handoff.magic = BOOT_HANDOFF_MAGIC;
+        assert!(!handoff.is_valid());
+        handoff.magic = 0;
+        assert!(!handoff.is_valid());
+    }
+
+    #[test]
+    fn allocator_handoff_from_another_abi_version_is_rejected() {
+        let mut handoff = AllocatorHandoff::new(Amd64FrameAllocator::new());
+        for abi_version in [0, ALLOCATOR_ABI_VERSION - 1, ALLOCATOR_ABI_VERSION + 1] {
+            handoff.abi_version = abi_version;
+            assert!(!handoff.is_valid(), "{abi_version}");
+        }
+    }
+
+    #[test]
+    fn allocator_handoff_with_a_corrupted_header_is_rejected() {
+        let header_end = offset_of!(AllocatorHandoff, abi_version) + size_of::<u32>();
+        for offset in 0..header_end {
+            let mut handoff = AllocatorHandoff::new(Amd64FrameAllocator::new());
+            // Flip one bit of the header the way a stray write from the kernel would
+            unsafe {
+                let byte = ptr::from_mut(&mut handoff).cast::<u8>().add(offset);
+                byte.write(byte.read() ^ 0x10);
+            }
+            assert!(!handoff.is_valid(), "byte {offset}");
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! The layout of AMD64 4-level page table entries that the kernel and the memory manager share.
+//! Level 0 is the page table that maps 4 KB pages and level 3 is the root page table. The
+//! arithmetic for finding entries comes from `frame_allocation::amd64`.
+
+use core::arch::x86_64::__cpuid;
+use frame_allocation::amd64::LEVELS;
+
+/// The level of the root page table
+pub const ROOT_PAGE_TABLE_LEVEL: u8 = LEVELS - 1;
+
+/// The entry maps something
+pub const PRESENT: u64 = 1;
+/// The mapped memory can be written to
+pub const WRITABLE: u64 = 1 << 1;
+/// The mapped memory can be accessed from user mode
+pub const USER_ACCESSIBLE: u64 = 1 << 2;
+/// The entry maps a 2 MB or 1 GB page instead of a page table
+pub const HUGE_PAGE: u64 = 1 << 7;
+/// Code in the mapped memory can't be executed
+pub const NO_EXECUTE: u64 = 1 << 63;
+/// The bits of an entry that hold the physical address of what it maps
+pub const ENTRY_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The first extended CPUID leaf, which reports the highest extended leaf
+const EXTENDED_LEAF_BASE: u32 = 0x8000_0000;
+/// Extended leaf 1 sets this bit of EDX if the processor has a no-execute bit
+const NO_EXECUTE_CPUID_BIT: u32 = 1 << 20;
+
+/**
+ * Returns `NO_EXECUTE` if the processor has a no-execute bit and 0 otherwise. The kernel only
+ * turns the bit on when the processor has it, and an entry that sets it while it's off causes a
+ * page fault.
+ */
+#[must_use]
+pub fn no_execute() -> u64 {
+    if __cpuid(EXTENDED_LEAF_BASE).eax > EXTENDED_LEAF_BASE
+        && __cpuid(EXTENDED_LEAF_BASE + 1).edx & NO_EXECUTE_CPUID_BIT != 0
+    {
+        NO_EXECUTE
+    } else {
+        0
+    }
+}
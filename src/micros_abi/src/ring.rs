@@ -0,0 +1,301 @@
+//! A single-producer single-consumer queue of fixed size messages that fills one page of memory
+//! shared between two address spaces
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use layout_assert::layout_assert;
+
+/// The size of a ring buffer, which is exactly one 4 KB page
+pub const RING_BUFFER_SIZE: usize = 0x1000;
+
+/// The size of a message, which is also the size of the ring buffer's header
+pub const MESSAGE_SIZE: usize = 64;
+
+/// The number of message slots in a ring buffer. One slot is always left empty so that a full
+/// buffer can be told apart from an empty one.
+pub const SLOT_COUNT: usize = (RING_BUFFER_SIZE - MESSAGE_SIZE) / MESSAGE_SIZE;
+
+/// The kernel has finished booting and launched the memory manager
+pub const MESSAGE_BOOT_COMPLETE: u32 = 0;
+/// An `AllocFramesRequest` for the memory manager
+pub const MESSAGE_ALLOC_FRAMES: u32 = 1;
+/// A `FreeFramesRequest` for the memory manager
+pub const MESSAGE_FREE_FRAMES: u32 = 2;
+/// The memory manager's `AllocFramesResponse`
+pub const MESSAGE_FRAMES_ALLOCATED: u32 = 3;
+/// Asks the memory manager to report who owns how much memory
+pub const MESSAGE_DUMP_OWNERS: u32 = 4;
+/// A `KeyEvent` from the keyboard
+pub const MESSAGE_KEY: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Message {
+    /// What the message means. One of the `MESSAGE_*` constants.
+    pub kind: u32,
+    /// The number of bytes of `payload` that are used
+    pub len: u32,
+    pub payload: [u8; MESSAGE_SIZE - 8],
+}
+
+impl Message {
+    /// A message with an empty payload
+    #[must_use]
+    pub const fn new(kind: u32) -> Self {
+        Self {
+            kind,
+            len: 0,
+            payload: [0; MESSAGE_SIZE - 8],
+        }
+    }
+
+    /// A message whose payload holds `fields` as little endian `u64`s. Fields that don't fit are left
+    /// out.
+    #[must_use]
+    pub fn with_fields(kind: u32, fields: &[u64]) -> Self {
+        let mut message = Self::new(kind);
+        for (bytes, field) in message.payload.chunks_exact_mut(FIELD_SIZE).zip(fields) {
+            bytes.copy_from_slice(&field.to_le_bytes());
+            message.len += FIELD_LEN;
+        }
+        message
+    }
+
+    /// Reads back the fields of a message made by `with_fields`. Returns `None` if the message
+    /// isn't of type `kind` or doesn't have exactly `FIELDS` fields.
+    #[must_use]
+    pub fn fields<const FIELDS: usize>(&self, kind: u32) -> Option<[u64; FIELDS]> {
+        if self.kind != kind || self.len as usize != FIELDS * FIELD_SIZE {
+            return None;
+        }
+        let mut fields = [0; FIELDS];
+        for (field, bytes) in fields.iter_mut().zip(self.payload.chunks_exact(FIELD_SIZE)) {
+            *field = u64::from_le_bytes(bytes.try_into().ok()?);
+        }
+        Some(fields)
+    }
+}
+
+const FIELD_SIZE: usize = size_of::<u64>();
+/// `FIELD_SIZE` as a `Message::len`
+const FIELD_LEN: u32 = u64::BITS / u8::BITS;
+
+/**
+ * A ring buffer that fills a page. An all-zero page is an empty ring buffer, so the owner of the
+ * page only needs to zero it before handing it out.
+ *
+ * The producer only ever writes `tail` and the slots between `tail` and `head`, and the consumer
+ * only ever writes `head`. A slot is published by storing `tail` with release ordering after the
+ * slot is written, and released by storing `head` after the slot has been copied out, so neither
+ * side ever sees a slot that the other side is halfway through.
+ */
+#[repr(C, align(64))]
+pub struct RingBuffer {
+    /// The index of the next slot to be popped
+    head: AtomicU32,
+    /// The index of the next slot to be pushed
+    tail: AtomicU32,
+    _reserved: [u8; MESSAGE_SIZE - 8],
+    slots: [UnsafeCell<Message>; SLOT_COUNT],
+}
+
+impl RingBuffer {
+    /**
+     * Adds `message` to the end of the buffer. Returns false if the buffer is full.
+     *
+     * # Safety
+     *
+     * Only one thread in one address space may push to a given ring buffer.
+     */
+    #[must_use = "the message isn't sent if the buffer is full"]
+    pub unsafe fn try_push(&self, message: &Message) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let Some(next) = next_index(tail) else {
+            return false;
+        };
+        if next == self.head.load(Ordering::Acquire) {
+            return false;
+        }
+        let Some(slot) = self.slots.get(tail as usize) else {
+            return false;
+        };
+        slot.get().write_volatile(*message);
+        self.tail.store(next, Ordering::Release);
+        true
+    }
+
+    /// Returns true if there's nothing to pop. Either side can call this.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /**
+     * Removes the message at the front of the buffer. Returns `None` if the buffer is empty.
+     *
+     * # Safety
+     *
+     * Only one thread in one address space may pop from a given ring buffer.
+     */
+    pub unsafe fn try_pop(&self) -> Option<Message> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // The other side can write anything to the indices, so they can't be trusted to be in
+        // bounds
+        let message = self.slots.get(head as usize)?.get().read_volatile();
+        self.head.store(next_index(head)?, Ordering::Release);
+        Some(message)
+    }
+}
+
+// The buffer is only ever accessed through `try_push` and `try_pop`, whose callers promise that
+// there's only one producer and one consumer
+unsafe impl Sync for RingBuffer {}
+
+/// The slot after `index`, or `None` if `index` isn't a slot
+fn next_index(index: u32) -> Option<u32> {
+    let index = usize::try_from(index).ok()?;
+    if index >= SLOT_COUNT {
+        return None;
+    }
+    u32::try_from((index + 1) % SLOT_COUNT).ok()
+}
+
+// The fields are private, so this is checked here rather than with the rest of the crate's layouts
+layout_assert!(RingBuffer, size = RING_BUFFER_SIZE, align = 64, {
+    head: 0,
+    tail: 4,
+    slots: MESSAGE_SIZE,
+});
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{boxed::Box, thread, vec::Vec};
+
+    /// An empty ring buffer, made the way the kernel makes one: by zeroing a page
+    fn ring_buffer() -> Box<RingBuffer> {
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+
+    fn key(value: u64) -> Message {
+        Message::with_fields(MESSAGE_KEY, &[value])
+    }
+
+    #[test]
+    fn a_zeroed_page_is_an_empty_ring_buffer() {
+        let ring = ring_buffer();
+        assert!(ring.is_empty());
+        assert_eq!(unsafe { ring.try_pop() }, None);
+    }
+
+    #[test]
+    fn messages_come_out_in_the_order_they_went_in() {
+        let ring = ring_buffer();
+        unsafe {
+            assert!(ring.try_push(&Message::new(MESSAGE_BOOT_COMPLETE)));
+            assert!(ring.try_push(&key(7)));
+            assert!(!ring.is_empty());
+            assert_eq!(ring.try_pop(), Some(Message::new(MESSAGE_BOOT_COMPLETE)));
+            assert_eq!(ring.try_pop(), Some(key(7)));
+            assert_eq!(ring.try_pop(), None);
+        }
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn a_full_buffer_refuses_messages_until_one_is_popped() {
+        let ring = ring_buffer();
+        unsafe {
+            // One slot is always left empty
+            for value in 0..SLOT_COUNT - 1 {
+                assert!(ring.try_push(&key(value as u64)));
+            }
+            assert!(!ring.try_push(&key(100)));
+            assert_eq!(ring.try_pop(), Some(key(0)));
+            assert!(ring.try_push(&key(100)));
+            assert!(!ring.try_push(&key(101)));
+        }
+    }
+
+    #[test]
+    fn indices_wrap_around_the_end_of_the_page() {
+        let ring = ring_buffer();
+        unsafe {
+            for value in 0..3 * SLOT_COUNT as u64 {
+                assert!(ring.try_push(&key(value)));
+                assert!(ring.try_push(&key(value + 1000)));
+                assert_eq!(ring.try_pop(), Some(key(value)));
+                assert_eq!(ring.try_pop(), Some(key(value + 1000)));
+            }
+        }
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn indices_out_of_bounds_are_not_trusted() {
+        let ring = ring_buffer();
+        let slots = u32::try_from(SLOT_COUNT).unwrap();
+        ring.tail.store(slots, Ordering::Relaxed);
+        assert!(!unsafe { ring.try_push(&key(1)) });
+        ring.tail.store(0, Ordering::Relaxed);
+        ring.head.store(slots, Ordering::Relaxed);
+        assert_eq!(unsafe { ring.try_pop() }, None);
+        assert_eq!(ring.head.load(Ordering::Relaxed), slots);
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let message = Message::with_fields(MESSAGE_ALLOC_FRAMES, &[1, u64::MAX]);
+        assert_eq!(message.len, 16);
+        assert_eq!(
+            message.fields::<2>(MESSAGE_ALLOC_FRAMES),
+            Some([1, u64::MAX])
+        );
+        assert_eq!(message.fields::<1>(MESSAGE_ALLOC_FRAMES), None);
+        assert_eq!(message.fields::<2>(MESSAGE_FREE_FRAMES), None);
+    }
+
+    #[test]
+    fn fields_that_do_not_fit_are_left_out() {
+        let fields: Vec<u64> = (0..10).collect();
+        let message = Message::with_fields(MESSAGE_KEY, &fields);
+        assert_eq!(
+            message.fields::<7>(MESSAGE_KEY),
+            Some([0, 1, 2, 3, 4, 5, 6])
+        );
+    }
+
+    #[test]
+    fn a_consumer_on_another_thread_sees_whole_messages_in_order() {
+        const COUNT: u64 = 100_000;
+        let ring = ring_buffer();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for value in 0..COUNT {
+                    // Every field is the same, so a torn read would show up as a mismatch
+                    let message = Message::with_fields(MESSAGE_KEY, &[value; 7]);
+                    while !unsafe { ring.try_push(&message) } {
+                        thread::yield_now();
+                    }
+                }
+            });
+            let mut expected = 0;
+            while expected < COUNT {
+                match unsafe { ring.try_pop() } {
+                    Some(message) => {
+                        assert_eq!(message.fields(MESSAGE_KEY), Some([expected; 7]));
+                        expected += 1;
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+        });
+        assert!(ring.is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+//! System call numbers and error codes. System calls return a non-negative value on success and one
+//! of the negative error codes on failure.
+
+/// Writes a UTF-8 string to the kernel's console. Takes a pointer to the string and its length in
+/// bytes.
+pub const WRITE_CONSOLE: usize = 0;
+/// Asks the memory manager for physically contiguous 4 KB frames. Takes the number of frames and
+/// the largest order of frame that may be broken up, and returns the physical address of the first
+/// frame. The kernel forwards these to the memory manager as `AllocFramesRequest`s. The kernel is
+/// the only client so far and sends the requests itself, so processes can't make this call yet.
+pub const MM_ALLOC_FRAMES: usize = 1;
+/// Gives frames back to the memory manager. Takes the address of the first frame and the number
+/// of frames. Like `MM_ALLOC_FRAMES`, this is only forwarded on the kernel's behalf so far.
+pub const MM_FREE_FRAMES: usize = 2;
+/// Sleeps until the kernel has sent the memory manager an event. Takes no arguments.
+pub const YIELD_UNTIL_EVENT: usize = 3;
+/// Reports an error that the caller can't recover from. The kernel does what its `on-fatal` boot
+/// option says, so this never returns. Takes no arguments.
+pub const FATAL_ERROR: usize = 4;
+
+/// There's no system call with the requested number
+pub const ERROR_UNKNOWN_SYSCALL: isize = -1;
+/// A pointer argument refers to memory that the caller can't access
+pub const ERROR_INVALID_ADDRESS: isize = -2;
+/// An argument isn't valid for the system call
+pub const ERROR_INVALID_ARGUMENT: isize = -3;
@@ -0,0 +1,111 @@
+; Application processors start here in real mode after a startup IPI. The kernel copies everything
+; from ap_trampoline_start to ap_trampoline_end to AP_TRAMPOLINE_ADDRESS and fills in the variables
+; at the end before starting each processor, so addresses in the trampoline have to be computed
+; relative to where it's copied to rather than where it's linked.
+global ap_trampoline_start
+global ap_trampoline_end
+global ap_trampoline_page_table
+global ap_trampoline_stack_top
+global ap_trampoline_entry
+global ap_trampoline_argument
+
+; This must match TRAMPOLINE_ADDRESS in smp.rs
+AP_TRAMPOLINE_ADDRESS equ 0x8000
+PROTECTED_MODE_FLAG   equ 1
+PHYSICAL_ADDRESS_EXPANSION equ 0x20
+EFER_MSR              equ 0xC0000080
+EFER_LONG_MODE        equ 0x100
+EFER_NO_EXECUTE       equ 0x800
+NO_EXECUTE_CPUID_BIT  equ 0x100000
+PAGING_FLAG           equ 0x80000000
+CODE_32_SEGMENT       equ 0x00cf9a000000ffff
+DATA_32_SEGMENT       equ 0x00cf92000000ffff
+LONG_CODE_SEGMENT     equ 0x20980000000000
+
+%define RELOCATED(label) (AP_TRAMPOLINE_ADDRESS + (label) - ap_trampoline_start)
+
+section .rodata
+bits 16
+ap_trampoline_start:
+    cli
+    cld
+    xor ax, ax
+    mov ds, ax
+    lgdt [RELOCATED(gdt.pointer)]
+    mov eax, cr0
+    or eax, PROTECTED_MODE_FLAG
+    mov cr0, eax
+    jmp dword gdt.code32:RELOCATED(protected_mode)
+
+bits 32
+protected_mode:
+    mov ax, gdt.data32
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; Long mode is entered the same way that boot.asm enters it, but with the kernel's page tables
+    mov eax, cr4
+    or eax, PHYSICAL_ADDRESS_EXPANSION
+    mov cr4, eax
+    mov eax, [RELOCATED(ap_trampoline_page_table)]
+    mov cr3, eax
+    ; The kernel's page tables use the no-execute bit whenever the processor has it, so it has to
+    ; be enabled before they're walked. Setting it on a processor without it faults.
+    mov eax, 0x80000001
+    cpuid
+    mov ebx, EFER_LONG_MODE
+    test edx, NO_EXECUTE_CPUID_BIT
+    jz .set_efer
+    or ebx, EFER_NO_EXECUTE
+.set_efer:
+    mov ecx, EFER_MSR
+    rdmsr
+    or eax, ebx
+    wrmsr
+    mov eax, cr0
+    or eax, PAGING_FLAG
+    mov cr0, eax
+    jmp gdt.code64:RELOCATED(long_mode)
+
+bits 64
+long_mode:
+    xor eax, eax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    mov fs, ax
+    mov gs, ax
+    mov rsp, [RELOCATED(ap_trampoline_stack_top)]
+    mov rdi, [RELOCATED(ap_trampoline_argument)]
+    mov rax, [RELOCATED(ap_trampoline_entry)]
+    ; The entry point never returns
+    call rax
+
+align 8
+gdt:
+    dq 0
+.code32: equ $ - gdt
+    dq CODE_32_SEGMENT
+.data32: equ $ - gdt
+    dq DATA_32_SEGMENT
+.code64: equ $ - gdt
+    dq LONG_CODE_SEGMENT
+.pointer:
+    dw $ - gdt - 1
+    dd RELOCATED(gdt)
+
+; Filled in by the kernel
+align 8
+; The physical address of the kernel's root page table. It has to be below 4 GB.
+ap_trampoline_page_table:
+    dq 0
+ap_trampoline_stack_top:
+    dq 0
+; An extern "C" fn(usize) -> !
+ap_trampoline_entry:
+    dq 0
+; Passed to the entry point in rdi
+ap_trampoline_argument:
+    dq 0
+ap_trampoline_end:
@@ -17,7 +17,7 @@ PAGE_TABLE_FLAGS      equ 7
 PAGE_FLAGS equ (0x80 + PAGE_TABLE_FLAGS)
 LAST_PAGE_TABLE_ENTRY equ PAGE_SIZE - PAGE_TABLE_ENTRY_SIZE
 PHYSICAL_ADDRESS_EXPANSION equ 0x20
-EFER_LONG_MODE_NO_EXECUTE equ 0x900
+EFER_LONG_MODE        equ 0x100
 EFER_MSR              equ 0xC0000080
 PAGING_FLAG           equ 0x80000000
 LONG_CODE_SEGMENT     equ 0x20980000000000
@@ -183,11 +183,12 @@ _start:
     mov eax, cr4
     or eax, PHYSICAL_ADDRESS_EXPANSION
     mov cr4, eax
-    ; set long mode bit
+    ; set long mode bit. The no-execute bit is left for the kernel to enable since not every
+    ; processor has it.
     mov ecx, EFER_MSR
 
     rdmsr
-    or eax, EFER_LONG_MODE_NO_EXECUTE
+    or eax, EFER_LONG_MODE
     wrmsr
 
     ; enable paging
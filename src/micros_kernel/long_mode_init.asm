@@ -1,12 +1,10 @@
 global long_mode_start
-global launch_memory_manager
-
-USER_DATA_SEGMENT equ 0x23
-USER_CODE_SEGMENT equ 0x2b
+global syscall_entry
 
 section .text
 bits 64
 extern main
+extern handle_syscall
 long_mode_start:
     mov rsp, 0
 
@@ -20,24 +18,37 @@ long_mode_start:
 
     call main
 
-; Args:
-; rdi: virtual address of root Amd64FrameAllocator structure
-; rsi: virtual address of the multiboot2 information struct
-; rdx: physical address of root page table for memory manager
-; rcx: virtual address of memory manager main function
-launch_memory_manager:
-    mov cr3, rdx
-    mov rsp, 0
-    mov ax, USER_DATA_SEGMENT
-    mov ds, ax
-    mov es, ax
-    mov fs, ax
-    mov gs, ax
-
-    push USER_DATA_SEGMENT
-    push 0
-    pushf
-    push USER_CODE_SEGMENT
+; Entered through the syscall instruction with interrupts disabled. The system call number is in rax
+; and the arguments are in rdi, rsi, rdx, r10, and r8. The result is returned in rax.
+; After swapgs, gs points to a SyscallScratch struct with the fields
+;   +0: top of the kernel stack for system calls
+;   +8: space to save the caller's stack pointer
+syscall_entry:
+    swapgs
+    mov [gs:8], rsp
+    mov rsp, [gs:0]
+    push qword [gs:8]
+    push r11
     push rcx
-    iretq
+    ; Keep the stack 16 byte aligned for the call
+    sub rsp, 8
+
+    ; handle_syscall takes the arguments followed by the system call number
+    mov rcx, r10
+    mov r9, rax
+    call handle_syscall
+
+    ; Don't leak kernel values through the scratch registers
+    xor edi, edi
+    xor esi, esi
+    xor edx, edx
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
 
+    add rsp, 8
+    pop rcx
+    pop r11
+    pop rsp
+    swapgs
+    o64 sysret
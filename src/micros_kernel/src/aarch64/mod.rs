@@ -0,0 +1,31 @@
+// Host tests only build the page table encodings
+#[cfg(target_arch = "aarch64")]
+mod address_space;
+mod paging;
+
+#[cfg(target_arch = "aarch64")]
+use crate::Architecture;
+#[cfg(target_arch = "aarch64")]
+pub use address_space::Aarch64;
+#[cfg(target_arch = "aarch64")]
+use core::{arch::asm, panic::PanicInfo};
+
+#[cfg(target_arch = "aarch64")]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut registers = [("sp", 0), ("x29", 0), ("x30", 0)];
+    unsafe {
+        asm!(
+            "mov {}, sp",
+            "mov {}, x29",
+            "mov {}, x30",
+            out(reg) registers[0].1,
+            out(reg) registers[1].1,
+            out(reg) registers[2].1,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    // There's nowhere else to write the report
+    crate::panic::write_panic_to_log(info, &registers);
+    Aarch64::halt()
+}
@@ -0,0 +1,217 @@
+//! aarch64 stage 1 translation tables with the 4 KB granule and 48 bit virtual addresses. Levels
+//! are numbered the same way as on AMD64, so level 0 holds page descriptors and level 3 is the
+//! root. The Arm architecture manual numbers them the other way around.
+
+use crate::{table_walk::TableEntry, SegmentFlags};
+use frame_allocation::{aarch64::LEVELS, PhysicalAddress};
+use multiboot2::phys_to_usize;
+
+/// The level of the root translation table
+pub const ROOT_TABLE_LEVEL: u8 = LEVELS - 1;
+
+/// The descriptor maps something
+const VALID: u64 = 1;
+/// Set in descriptors that point to another table and in level 0 page descriptors. Clear in block
+/// descriptors, which map 2 MB or 1 GB directly.
+const TABLE_OR_PAGE: u64 = 1 << 1;
+/// The lowest bit of the index into `MAIR_EL1` that gives the memory's attributes
+const ATTRIBUTE_INDEX_SHIFT: u32 = 2;
+/// AP[1]: the memory can be accessed from EL0
+const EL0_ACCESSIBLE: u64 = 1 << 6;
+/// AP[2]: the memory can't be written to
+const READ_ONLY: u64 = 1 << 7;
+const INNER_SHAREABLE: u64 = 0b11 << 8;
+/// The access flag. Accessing memory without it faults unless the hardware manages it.
+const ACCESSED: u64 = 1 << 10;
+/// The translation is only cached for the current address space id
+const NOT_GLOBAL: u64 = 1 << 11;
+/// PXN: code in the memory can't be executed at EL1
+const PRIVILEGED_EXECUTE_NEVER: u64 = 1 << 53;
+/// UXN: code in the memory can't be executed at EL0
+const UNPRIVILEGED_EXECUTE_NEVER: u64 = 1 << 54;
+/// The bits of a descriptor that hold the physical address of what it maps
+const OUTPUT_ADDRESS_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// The `MAIR_EL1` entry for normal write-back memory. The boot code has to program `MAIR_EL1` to
+/// match.
+const NORMAL_MEMORY_ATTRIBUTE_INDEX: u64 = 0;
+
+/// An entry in a translation table
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Descriptor(u64);
+
+#[cfg(target_arch = "aarch64")]
+pub type PageTable = crate::table_walk::PageTable<Descriptor>;
+
+impl TableEntry for Descriptor {
+    const INVALID: Self = Self(0);
+
+    /// A descriptor that points to the translation table at `address`. Access permissions are left
+    /// to the descriptors that map the memory.
+    fn table(address: PhysicalAddress) -> Self {
+        Self(address.as_usize() as u64 | TABLE_OR_PAGE | VALID)
+    }
+
+    /// A level 0 descriptor that maps the 4 KB page at `address` into a process with the
+    /// permissions in `flags`. The kernel never executes process memory.
+    fn process_page(address: PhysicalAddress, flags: SegmentFlags) -> Self {
+        Self(
+            address.as_usize() as u64
+                | TABLE_OR_PAGE
+                | VALID
+                | (NORMAL_MEMORY_ATTRIBUTE_INDEX << ATTRIBUTE_INDEX_SHIFT)
+                | INNER_SHAREABLE
+                | ACCESSED
+                | NOT_GLOBAL
+                | EL0_ACCESSIBLE
+                | READ_ONLY
+                | PRIVILEGED_EXECUTE_NEVER
+                | UNPRIVILEGED_EXECUTE_NEVER,
+        )
+        .allow(flags)
+    }
+
+    fn allow(self, flags: SegmentFlags) -> Self {
+        let mut bits = self.0;
+        if flags.writable() {
+            bits &= !READ_ONLY;
+        }
+        if flags.executable() {
+            bits &= !UNPRIVILEGED_EXECUTE_NEVER;
+        }
+        Self(bits)
+    }
+
+    fn is_valid(self) -> bool {
+        self.0 & VALID != 0
+    }
+
+    fn points_to_table(self, page_table_level: u8) -> bool {
+        page_table_level > 0 && self.is_valid() && self.0 & TABLE_OR_PAGE != 0
+    }
+
+    fn address(self) -> Option<PhysicalAddress> {
+        phys_to_usize(self.0 & OUTPUT_ADDRESS_MASK).map(PhysicalAddress::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{table_walk::tests::load_segment, ELF_EXECUTABLE_SEGMENT, ELF_WRITABLE_SEGMENT};
+
+    const READABLE: SegmentFlags = SegmentFlags(4);
+    const WRITABLE: SegmentFlags = SegmentFlags(4 | ELF_WRITABLE_SEGMENT);
+    const EXECUTABLE: SegmentFlags = SegmentFlags(4 | ELF_EXECUTABLE_SEGMENT);
+
+    /// The bits that every page mapped into a process has
+    const PROCESS_PAGE: u64 = VALID
+        | TABLE_OR_PAGE
+        | INNER_SHAREABLE
+        | ACCESSED
+        | NOT_GLOBAL
+        | EL0_ACCESSIBLE
+        | PRIVILEGED_EXECUTE_NEVER;
+
+    fn attributes(descriptor: Descriptor) -> u64 {
+        descriptor.0 & !OUTPUT_ADDRESS_MASK
+    }
+
+    #[test]
+    fn table_descriptors_only_point_to_the_table() {
+        let descriptor = Descriptor::table(PhysicalAddress::new(0x8000_3000));
+        assert_eq!(descriptor.0, 0x8000_3003);
+        assert!(descriptor.points_to_table(ROOT_TABLE_LEVEL));
+        assert!(descriptor.points_to_table(1));
+        assert_eq!(
+            descriptor.address(),
+            Some(PhysicalAddress::new(0x8000_3000))
+        );
+    }
+
+    #[test]
+    fn process_pages_are_only_as_permissive_as_their_segment() {
+        let address = PhysicalAddress::new(0x4020_1000);
+        let read_only = Descriptor::process_page(address, READABLE);
+        assert_eq!(
+            attributes(read_only),
+            PROCESS_PAGE | READ_ONLY | UNPRIVILEGED_EXECUTE_NEVER
+        );
+        let writable = Descriptor::process_page(address, WRITABLE);
+        assert_eq!(
+            attributes(writable),
+            PROCESS_PAGE | UNPRIVILEGED_EXECUTE_NEVER
+        );
+        let executable = Descriptor::process_page(address, EXECUTABLE);
+        assert_eq!(attributes(executable), PROCESS_PAGE | READ_ONLY);
+        for descriptor in [read_only, writable, executable] {
+            assert_eq!(descriptor.address(), Some(address));
+            // The attribute index picks normal memory
+            assert_eq!(descriptor.0 >> ATTRIBUTE_INDEX_SHIFT & 0b111, 0);
+        }
+    }
+
+    #[test]
+    fn pages_shared_by_segments_allow_what_either_allows() {
+        let page = Descriptor::process_page(PhysicalAddress::new(0x1000), EXECUTABLE);
+        assert_eq!(attributes(page.allow(WRITABLE)), PROCESS_PAGE);
+        assert_eq!(attributes(page.allow(READABLE)), attributes(page));
+    }
+
+    #[test]
+    fn block_and_page_descriptors_are_told_apart_from_tables() {
+        let page = Descriptor::process_page(PhysicalAddress::new(0x1000), READABLE);
+        // Level 0 descriptors have the table bit set too, but they map pages
+        assert!(!page.points_to_table(0));
+        let block = Descriptor(0x20_0000 | ACCESSED | VALID);
+        assert!(block.is_valid());
+        assert!(!block.points_to_table(1));
+        assert!(!Descriptor::INVALID.is_valid());
+        assert!(!Descriptor(TABLE_OR_PAGE).points_to_table(1));
+    }
+
+    #[test]
+    fn the_output_address_leaves_out_the_attributes() {
+        let descriptor = Descriptor(
+            0x0000_1234_5678_9000 | UNPRIVILEGED_EXECUTE_NEVER | PRIVILEGED_EXECUTE_NEVER | 0xfff,
+        );
+        assert_eq!(
+            descriptor.address(),
+            Some(PhysicalAddress::new(0x1234_5678_9000))
+        );
+    }
+
+    #[test]
+    fn a_segment_is_loaded_through_four_levels() {
+        let data: [u8; 0x20] = core::array::from_fn(|index| u8::try_from(index + 1).unwrap());
+        let segment =
+            load_segment::<Descriptor>(ROOT_TABLE_LEVEL, 0x40_0ff0, &data, 0x2000, WRITABLE);
+        // The segment starts 16 bytes before the end of its first page
+        assert_eq!(segment.pages.len(), 3);
+        assert_eq!(segment.tables, 3);
+        assert_eq!(segment.bytes[..data.len()], data);
+        assert!(segment.bytes[data.len()..].iter().all(|&byte| byte == 0));
+        for page in segment.pages {
+            assert_eq!(attributes(page), PROCESS_PAGE | UNPRIVILEGED_EXECUTE_NEVER);
+        }
+    }
+
+    #[test]
+    fn a_segment_across_two_root_entries_gets_tables_on_both_sides() {
+        let segment = load_segment::<Descriptor>(
+            ROOT_TABLE_LEVEL,
+            0x7f_ffff_f000,
+            b"code",
+            0x2000,
+            EXECUTABLE,
+        );
+        // The segment crosses from the first 512 GB into the next, so each half has its own table at
+        // every level below the root
+        assert_eq!(segment.tables, 6);
+        assert_eq!(segment.bytes[..4], *b"code");
+        for page in segment.pages {
+            assert_eq!(attributes(page), PROCESS_PAGE | READ_ONLY);
+        }
+    }
+}
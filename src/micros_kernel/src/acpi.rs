@@ -0,0 +1,674 @@
+//! Finds the MADT, the FADT, and the DSDT through the RSDP that the bootloader passes along and
+//! pulls out what the kernel needs to know about the processors, interrupt controllers, real-time
+//! clock, and how to turn the machine off. Firmware tables are untrusted, so every table's checksum
+//! is checked and nothing is read past the end of the table or of the bytes that hold it.
+//! Everything but the functions that find tables in memory works on byte slices.
+
+use crate::fixed_vec::FixedVec;
+use core::slice;
+use multiboot2::{phys_to_usize, AcpiRoot, BootInformation};
+
+/// The most processors whose local APIC IDs are kept. Any beyond this are still counted.
+pub const MAX_PROCESSORS: usize = 64;
+/// The most I/O APICs that are kept. Any beyond this are ignored.
+pub const MAX_IO_APICS: usize = 8;
+
+/// What the MADT says about the machine
+pub struct PlatformInfo {
+    /// The physical address of every processor's local APIC
+    pub local_apic_address: u64,
+    /// The number of enabled processors
+    pub processor_count: usize,
+    /// The local APIC IDs of the first `MAX_PROCESSORS` enabled processors
+    pub local_apic_ids: FixedVec<u32, MAX_PROCESSORS>,
+    pub io_apics: FixedVec<IoApicInfo, MAX_IO_APICS>,
+}
+
+/// An I/O APIC from the MADT
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: u32,
+    /// The global system interrupt that the I/O APIC's first input is wired to
+    pub gsi_base: u32,
+}
+
+/// Where to find the RSDT or XSDT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RootTablePointer {
+    pub address: u64,
+    /// The size of each address in the root table. The RSDT holds 4 byte addresses and the XSDT
+    /// holds 8 byte addresses.
+    pub entry_size: usize,
+}
+
+/**
+ * Finds the MADT and parses it. Returns `None` if the bootloader didn't pass on the RSDP, any table
+ * on the way is broken, or a table is outside of the first `mapped_size` bytes of memory.
+ *
+ * # Safety
+ *
+ * The first `mapped_size` bytes of memory must be identity mapped.
+ */
+pub unsafe fn platform_info(
+    boot_info: BootInformation,
+    mapped_size: usize,
+) -> Option<PlatformInfo> {
+    parse_madt(find_table(boot_info, mapped_size, MADT_SIGNATURE)?)
+}
+
+/**
+ * Finds the CMOS register that holds the century, which the FADT names if the real-time clock has
+ * one. Returns `None` if it doesn't or if the FADT can't be found the way `platform_info` finds
+ * the MADT.
+ *
+ * # Safety
+ *
+ * The first `mapped_size` bytes of memory must be identity mapped.
+ */
+pub unsafe fn century_register(boot_info: BootInformation, mapped_size: usize) -> Option<u8> {
+    parse_fadt_century(find_table(boot_info, mapped_size, FADT_SIGNATURE)?)
+}
+
+/// How to put the machine in the S5 sleep state, which turns it off
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SoftOff {
+    /// The I/O port of the `PM1a` control register
+    pub pm1a_control: u16,
+    /// The I/O port of the `PM1b` control register if the machine has one
+    pub pm1b_control: Option<u16>,
+    /// What `_S5` in the DSDT says to write to the `SLP_TYP` field of each control register
+    pub sleep_type_a: u8,
+    pub sleep_type_b: u8,
+    /// The SMI command port and the value to write to it to switch the machine to ACPI mode, if
+    /// the machine isn't always in ACPI mode
+    pub acpi_enable: Option<(u16, u8)>,
+}
+
+/**
+ * Finds what the FADT and the DSDT say about turning the machine off. Returns `None` if either
+ * table can't be found the way `platform_info` finds the MADT or doesn't have what's needed.
+ *
+ * # Safety
+ *
+ * The first `mapped_size` bytes of memory must be identity mapped.
+ */
+pub unsafe fn soft_off(boot_info: BootInformation, mapped_size: usize) -> Option<SoftOff> {
+    let fadt = find_table(boot_info, mapped_size, FADT_SIGNATURE)?;
+    let dsdt = physical_table(fadt_dsdt_address(fadt)?, mapped_size)?;
+    parse_soft_off(fadt, dsdt)
+}
+
+/**
+ * Finds the first table listed in the root table that starts with `signature`
+ *
+ * # Safety
+ *
+ * The first `mapped_size` bytes of memory must be identity mapped.
+ */
+unsafe fn find_table(
+    boot_info: BootInformation,
+    mapped_size: usize,
+    signature: &[u8],
+) -> Option<&'static [u8]> {
+    let root = root_table_pointer(boot_info)?;
+    let root_table = physical_table(root.address, mapped_size)?;
+    let root_signature = if root.entry_size == XSDT_ENTRY_SIZE {
+        XSDT_SIGNATURE
+    } else {
+        RSDT_SIGNATURE
+    };
+    if !root_table.starts_with(root_signature) {
+        return None;
+    }
+    root_table_entries(root_table, root.entry_size)
+        .filter_map(|address| physical_table(address, mapped_size))
+        .find(|table| table.starts_with(signature))
+}
+
+/// Finds the root table through the RSDP that the bootloader copied into the boot information
+fn root_table_pointer(boot_info: BootInformation) -> Option<RootTablePointer> {
+    Some(match boot_info.acpi_root()? {
+        AcpiRoot::Xsdt(address) => RootTablePointer {
+            address,
+            entry_size: XSDT_ENTRY_SIZE,
+        },
+        AcpiRoot::Rsdt(address) => RootTablePointer {
+            address: address.into(),
+            entry_size: RSDT_ENTRY_SIZE,
+        },
+    })
+}
+
+/// Returns the table at the start of `bytes`, cut down to the length in its header, if the length
+/// fits in `bytes` and the checksum is valid.
+pub fn validate_table(bytes: &[u8]) -> Option<&[u8]> {
+    let length = usize::try_from(read_u32(bytes, TABLE_LENGTH_OFFSET)?).ok()?;
+    let table = bytes
+        .get(..length)
+        .filter(|table| table.len() >= TABLE_HEADER_SIZE)?;
+    checksum_is_valid(table).then_some(table)
+}
+
+/// The addresses of the tables listed in an RSDT or XSDT with addresses of `entry_size` bytes.
+/// A partial entry at the end is ignored.
+pub fn root_table_entries(root_table: &[u8], entry_size: usize) -> impl Iterator<Item = u64> + '_ {
+    root_table
+        .get(TABLE_HEADER_SIZE..)
+        .unwrap_or_default()
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let mut address = [0; 8];
+            address[..entry.len()].copy_from_slice(entry);
+            u64::from_le_bytes(address)
+        })
+}
+
+/// Parses a validated MADT. Returns `None` if it isn't a MADT or an entry is too short for its
+/// type or runs past the end of the table.
+pub fn parse_madt(madt: &[u8]) -> Option<PlatformInfo> {
+    if !madt.starts_with(MADT_SIGNATURE) {
+        return None;
+    }
+    let mut info = PlatformInfo {
+        local_apic_address: read_u32(madt, MADT_LOCAL_APIC_ADDRESS_OFFSET)?.into(),
+        processor_count: 0,
+        local_apic_ids: FixedVec::new(),
+        io_apics: FixedVec::new(),
+    };
+    let mut entries = madt.get(MADT_ENTRIES_OFFSET..)?;
+    while let [entry_type, length, ..] = *entries {
+        let entry = entries.get(..usize::from(length))?;
+        if entry.len() < MADT_ENTRY_HEADER_SIZE {
+            return None;
+        }
+        match entry_type {
+            MADT_LOCAL_APIC => {
+                let flags = read_u32(entry, 4)?;
+                add_processor(&mut info, (*entry.get(3)?).into(), flags);
+            }
+            MADT_IO_APIC => {
+                let io_apic = IoApicInfo {
+                    id: *entry.get(2)?,
+                    address: read_u32(entry, 4)?,
+                    gsi_base: read_u32(entry, 8)?,
+                };
+                if info.io_apics.push(io_apic).is_err() {
+                    warn!("The MADT lists more than {MAX_IO_APICS} I/O APICs");
+                }
+            }
+            MADT_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                info.local_apic_address = read_u64(entry, 4)?;
+            }
+            MADT_LOCAL_X2APIC => {
+                let flags = read_u32(entry, 8)?;
+                add_processor(&mut info, read_u32(entry, 4)?, flags);
+            }
+            _ => {}
+        }
+        entries = &entries[entry.len()..];
+    }
+    Some(info)
+}
+
+/// Returns the CMOS register that a validated FADT says holds the century. Returns `None` if it
+/// isn't a FADT, it's too old to have the field, or the field is 0 because there's no such
+/// register.
+pub fn parse_fadt_century(fadt: &[u8]) -> Option<u8> {
+    if !fadt.starts_with(FADT_SIGNATURE) {
+        return None;
+    }
+    fadt.get(FADT_CENTURY_OFFSET)
+        .copied()
+        .filter(|&register| register != 0)
+}
+
+/// Returns the physical address of the DSDT from a validated FADT. The 64 bit address is used if
+/// the FADT is new enough to have one and it's set.
+pub fn fadt_dsdt_address(fadt: &[u8]) -> Option<u64> {
+    if !fadt.starts_with(FADT_SIGNATURE) {
+        return None;
+    }
+    read_u64(fadt, FADT_X_DSDT_OFFSET)
+        .filter(|&address| address != 0)
+        .or_else(|| read_u32(fadt, FADT_DSDT_OFFSET).map(u64::from))
+}
+
+/// Reads how to turn the machine off from a validated FADT and DSDT. Returns `None` if either
+/// isn't the table it should be, there's no `PM1a` control register, or the DSDT doesn't have an
+/// `_S5` package that `parse_s5_package` understands.
+pub fn parse_soft_off(fadt: &[u8], dsdt: &[u8]) -> Option<SoftOff> {
+    if !fadt.starts_with(FADT_SIGNATURE) || !dsdt.starts_with(DSDT_SIGNATURE) {
+        return None;
+    }
+    let port = |offset| {
+        read_u32(fadt, offset)
+            .and_then(|port| u16::try_from(port).ok())
+            .filter(|&port| port != 0)
+    };
+    let (sleep_type_a, sleep_type_b) = parse_s5_package(dsdt.get(TABLE_HEADER_SIZE..)?)?;
+    let acpi_enable = fadt
+        .get(FADT_ACPI_ENABLE_OFFSET)
+        .copied()
+        .filter(|&value| value != 0);
+    Some(SoftOff {
+        pm1a_control: port(FADT_PM1A_CONTROL_OFFSET)?,
+        pm1b_control: port(FADT_PM1B_CONTROL_OFFSET),
+        sleep_type_a,
+        sleep_type_b,
+        acpi_enable: port(FADT_SMI_COMMAND_OFFSET).zip(acpi_enable),
+    })
+}
+
+/**
+ * Finds the `_S5` object in AML bytecode and returns its first two elements, which are the
+ * `SLP_TYP` values for the `PM1a` and `PM1b` control registers.
+ *
+ * This doesn't interpret AML. It only recognizes `_S5` when it's named directly with a package of
+ * integer constants, which is how firmware almost always declares it. An `_S5` method isn't
+ * understood.
+ */
+pub fn parse_s5_package(aml: &[u8]) -> Option<(u8, u8)> {
+    (1..aml.len()).find_map(|start| {
+        let name = &aml[start..];
+        if !name.starts_with(S5_NAME) {
+            return None;
+        }
+        if !matches!(
+            aml[..start],
+            [.., AML_NAME_OP] | [.., AML_NAME_OP, AML_ROOT_PREFIX]
+        ) {
+            return None;
+        }
+        let package = name.get(S5_NAME.len()..)?;
+        let [AML_PACKAGE_OP, length_lead, ..] = *package else {
+            return None;
+        };
+        // The top two bits of the first byte of a package length say how many bytes follow it
+        let elements_offset = 2 + usize::from(length_lead >> 6);
+        // Then comes the number of elements, which has to be at least 2
+        let mut elements = package.get(elements_offset + 1..)?;
+        if *package.get(elements_offset)? < 2 {
+            return None;
+        }
+        let sleep_type_a = parse_aml_integer(&mut elements)?;
+        let sleep_type_b = parse_aml_integer(&mut elements)?;
+        Some((
+            sleep_type_a & SLEEP_TYPE_MASK,
+            sleep_type_b & SLEEP_TYPE_MASK,
+        ))
+    })
+}
+
+/// Reads an integer constant from the start of `aml` and moves `aml` past it. Only the low byte is
+/// kept, which is all that a sleep type needs.
+fn parse_aml_integer(aml: &mut &[u8]) -> Option<u8> {
+    let (value, size) = match **aml {
+        [AML_ZERO_OP, ..] => (0, 1),
+        [AML_ONE_OP, ..] => (1, 1),
+        [AML_ONES_OP, ..] => (u8::MAX, 1),
+        [AML_BYTE_PREFIX, value, ..] => (value, 2),
+        [AML_WORD_PREFIX, value, _, ..] => (value, 3),
+        [AML_DWORD_PREFIX, value, _, _, _, ..] => (value, 5),
+        _ => return None,
+    };
+    *aml = &aml[size..];
+    Some(value)
+}
+
+/// Counts a processor from the MADT if it's enabled
+fn add_processor(info: &mut PlatformInfo, apic_id: u32, flags: u32) {
+    if flags & LOCAL_APIC_ENABLED == 0 {
+        return;
+    }
+    info.processor_count += 1;
+    // The count is still right if the ID doesn't fit
+    let _ = info.local_apic_ids.push(apic_id);
+}
+
+/**
+ * Returns the validated table at physical address `address`, or `None` if it isn't within the first
+ * `mapped_size` bytes of memory or is broken.
+ *
+ * # Safety
+ *
+ * The first `mapped_size` bytes of memory must be identity mapped.
+ */
+unsafe fn physical_table(address: u64, mapped_size: usize) -> Option<&'static [u8]> {
+    let address = phys_to_usize(address)?;
+    let available = mapped_size.checked_sub(address)?;
+    if address == 0 || available < TABLE_HEADER_SIZE {
+        return None;
+    }
+    let header = slice::from_raw_parts(address as *const u8, TABLE_HEADER_SIZE);
+    let length = usize::try_from(read_u32(header, TABLE_LENGTH_OFFSET)?).ok()?;
+    if length > available {
+        return None;
+    }
+    validate_table(slice::from_raw_parts(address as *const u8, length))
+}
+
+/// The bytes of every ACPI structure add up to 0 modulo 256
+fn checksum_is_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(*bytes.get(offset..)?.first_chunk()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(*bytes.get(offset..)?.first_chunk()?))
+}
+
+const RSDT_SIGNATURE: &[u8] = b"RSDT";
+const XSDT_SIGNATURE: &[u8] = b"XSDT";
+const RSDT_ENTRY_SIZE: usize = 4;
+const XSDT_ENTRY_SIZE: usize = 8;
+
+/// Every table starts with a header that has its signature, length, and checksum
+const TABLE_HEADER_SIZE: usize = 36;
+const TABLE_LENGTH_OFFSET: usize = 4;
+
+const FADT_SIGNATURE: &[u8] = b"FACP";
+const FADT_DSDT_OFFSET: usize = 40;
+const FADT_SMI_COMMAND_OFFSET: usize = 48;
+const FADT_ACPI_ENABLE_OFFSET: usize = 52;
+const FADT_PM1A_CONTROL_OFFSET: usize = 64;
+const FADT_PM1B_CONTROL_OFFSET: usize = 68;
+const FADT_CENTURY_OFFSET: usize = 108;
+const FADT_X_DSDT_OFFSET: usize = 140;
+
+const DSDT_SIGNATURE: &[u8] = b"DSDT";
+const S5_NAME: &[u8] = b"_S5_";
+/// `SLP_TYP` is a 3 bit field
+const SLEEP_TYPE_MASK: u8 = 0b111;
+
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_WORD_PREFIX: u8 = 0x0b;
+const AML_DWORD_PREFIX: u8 = 0x0c;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_PREFIX: u8 = b'\\';
+const AML_ONES_OP: u8 = 0xff;
+
+const MADT_SIGNATURE: &[u8] = b"APIC";
+const MADT_LOCAL_APIC_ADDRESS_OFFSET: usize = 36;
+const MADT_ENTRIES_OFFSET: usize = 44;
+/// Every MADT entry starts with its type and length
+const MADT_ENTRY_HEADER_SIZE: usize = 2;
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+const LOCAL_APIC_ENABLED: u32 = 1;
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{vec, vec::Vec};
+
+    /// `Name (_S5, Package (0x04) { 0x05, Zero, Zero, Zero })` the way iasl compiles it
+    const S5: &[u8] = &[
+        0x08, b'_', b'S', b'5', b'_', 0x12, 0x07, 0x04, 0x0a, 0x05, 0x00, 0x00, 0x00,
+    ];
+
+    /// The size of an ACPI 2.0 FADT
+    const FADT_SIZE: usize = 244;
+
+    /// A validated table with `signature`, `body` after the header, and a correct checksum
+    fn table(signature: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut table = vec![0; TABLE_HEADER_SIZE];
+        table[..4].copy_from_slice(signature);
+        table.extend_from_slice(body);
+        let length = u32::try_from(table.len()).unwrap();
+        table[TABLE_LENGTH_OFFSET..TABLE_LENGTH_OFFSET + 4].copy_from_slice(&length.to_le_bytes());
+        let sum = table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        table[9] = 0u8.wrapping_sub(sum);
+        table
+    }
+
+    #[test]
+    fn finds_the_s5_package() {
+        assert_eq!(
+            parse_s5_package(&[&[0x10, 0x20][..], S5].concat()),
+            Some((5, 0))
+        );
+    }
+
+    #[test]
+    fn s5_may_be_named_from_the_root() {
+        let aml = [&[0x08, b'\\'][..], &S5[1..]].concat();
+        assert_eq!(parse_s5_package(&aml), Some((5, 0)));
+    }
+
+    #[test]
+    fn reads_every_kind_of_integer() {
+        let package = |elements: &[u8]| {
+            let mut aml = vec![0x08, b'_', b'S', b'5', b'_', 0x12, 0x00, 0x02];
+            aml.extend_from_slice(elements);
+            aml
+        };
+        assert_eq!(parse_s5_package(&package(&[0x01, 0xff])), Some((1, 7)));
+        assert_eq!(
+            parse_s5_package(&package(&[0x0b, 0x07, 0x01, 0x0c, 0x06, 0, 0, 0])),
+            Some((7, 6))
+        );
+        // A package length with a byte after the lead byte
+        assert_eq!(
+            parse_s5_package(&[
+                0x08, b'_', b'S', b'5', b'_', 0x12, 0x40, 0x00, 0x02, 0x0a, 0x03, 0x0a, 0x04
+            ]),
+            Some((3, 4))
+        );
+    }
+
+    #[test]
+    fn ignores_s5_when_it_isnt_a_named_package() {
+        // A method, a reference to `_S5_` in some other code, and a package cut short
+        let method = [0x14, 0x0a, b'_', b'S', b'5', b'_', 0x00, 0xa4, 0x0a, 0x05];
+        assert_eq!(parse_s5_package(&method), None);
+        assert_eq!(parse_s5_package(&S5[1..]), None);
+        assert_eq!(parse_s5_package(&S5[..10]), None);
+        assert_eq!(parse_s5_package(&[]), None);
+    }
+
+    /// Writes `bytes` at `offset` in a table whose `body` comes after the header
+    fn set(body: &mut [u8], offset: usize, bytes: &[u8]) {
+        body[offset - TABLE_HEADER_SIZE..][..bytes.len()].copy_from_slice(bytes);
+    }
+
+    #[test]
+    fn reads_soft_off_from_the_fadt() {
+        let mut body = [0; FADT_SIZE - TABLE_HEADER_SIZE];
+        set(&mut body, FADT_SMI_COMMAND_OFFSET, &0xb2u32.to_le_bytes());
+        set(&mut body, FADT_ACPI_ENABLE_OFFSET, &[0xf1]);
+        set(&mut body, FADT_PM1A_CONTROL_OFFSET, &0x604u32.to_le_bytes());
+        set(&mut body, FADT_DSDT_OFFSET, &0x1000u32.to_le_bytes());
+        let fadt = table(FADT_SIGNATURE, &body);
+        let dsdt = table(DSDT_SIGNATURE, S5);
+        assert_eq!(fadt_dsdt_address(&fadt), Some(0x1000));
+        assert_eq!(
+            parse_soft_off(&fadt, &dsdt),
+            Some(SoftOff {
+                pm1a_control: 0x604,
+                pm1b_control: None,
+                sleep_type_a: 5,
+                sleep_type_b: 0,
+                acpi_enable: Some((0xb2, 0xf1)),
+            })
+        );
+        // The FADT and DSDT can't be mixed up, and there has to be a `PM1a` control register
+        assert_eq!(parse_soft_off(&dsdt, &fadt), None);
+        set(&mut body, FADT_PM1A_CONTROL_OFFSET, &0u32.to_le_bytes());
+        assert_eq!(parse_soft_off(&table(FADT_SIGNATURE, &body), &dsdt), None);
+    }
+
+    #[test]
+    fn prefers_the_64_bit_dsdt_address() {
+        let mut body = [0; FADT_SIZE - TABLE_HEADER_SIZE];
+        set(&mut body, FADT_DSDT_OFFSET, &0x10u32.to_le_bytes());
+        assert_eq!(fadt_dsdt_address(&table(FADT_SIGNATURE, &body)), Some(0x10));
+        set(
+            &mut body,
+            FADT_X_DSDT_OFFSET,
+            &0x1_0000_0000u64.to_le_bytes(),
+        );
+        assert_eq!(
+            fadt_dsdt_address(&table(FADT_SIGNATURE, &body)),
+            Some(0x1_0000_0000)
+        );
+    }
+
+    /// The body of the MADT that QEMU's q35 machine has with two processors: the local APIC
+    /// address and flags, then a local APIC for each processor, the I/O APIC, five interrupt source
+    /// overrides, and a local APIC NMI
+    const QEMU_MADT_BODY: &[u8] = &[
+        0x00, 0x00, 0xe0, 0xfe, 0x01, 0x00, 0x00, 0x00, //
+        0x00, 0x08, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, //
+        0x00, 0x08, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, //
+        0x01, 0x0c, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xfe, 0x00, 0x00, 0x00, 0x00, //
+        0x02, 0x0a, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x02, 0x0a, 0x00, 0x05, 0x05, 0x00, 0x00, 0x00, 0x0d, 0x00, //
+        0x02, 0x0a, 0x00, 0x09, 0x09, 0x00, 0x00, 0x00, 0x0d, 0x00, //
+        0x02, 0x0a, 0x00, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x00, //
+        0x02, 0x0a, 0x00, 0x0b, 0x0b, 0x00, 0x00, 0x00, 0x0d, 0x00, //
+        0x04, 0x06, 0xff, 0x00, 0x00, 0x01, //
+    ];
+
+    /// A MADT with the local APIC at its usual address and `entries`
+    fn madt(entries: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![0x00, 0x00, 0xe0, 0xfe, 0x01, 0x00, 0x00, 0x00];
+        for entry in entries {
+            body.extend_from_slice(entry);
+        }
+        table(MADT_SIGNATURE, &body)
+    }
+
+    fn local_apic(apic_id: u8, flags: u32) -> Vec<u8> {
+        let mut entry = vec![MADT_LOCAL_APIC, 8, 0, apic_id];
+        entry.extend_from_slice(&flags.to_le_bytes());
+        entry
+    }
+
+    fn io_apic(id: u8, address: u32, gsi_base: u32) -> Vec<u8> {
+        let mut entry = vec![MADT_IO_APIC, 12, id, 0];
+        entry.extend_from_slice(&address.to_le_bytes());
+        entry.extend_from_slice(&gsi_base.to_le_bytes());
+        entry
+    }
+
+    #[test]
+    fn parses_qemus_madt() {
+        let madt = table(MADT_SIGNATURE, QEMU_MADT_BODY);
+        let info = parse_madt(validate_table(&madt).unwrap()).unwrap();
+        assert_eq!(info.local_apic_address, 0xfee0_0000);
+        assert_eq!(info.processor_count, 2);
+        assert_eq!(*info.local_apic_ids, [0, 1]);
+        assert_eq!(
+            *info.io_apics,
+            [IoApicInfo {
+                id: 0,
+                address: 0xfec0_0000,
+                gsi_base: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn only_enabled_processors_are_counted() {
+        let mut x2apic = vec![MADT_LOCAL_X2APIC, 16, 0, 0];
+        x2apic.extend_from_slice(&0x1_0000u32.to_le_bytes());
+        x2apic.extend_from_slice(&LOCAL_APIC_ENABLED.to_le_bytes());
+        x2apic.extend_from_slice(&[0; 4]);
+        let info = parse_madt(&madt(&[
+            &local_apic(0, LOCAL_APIC_ENABLED),
+            &local_apic(1, 0),
+            &x2apic,
+        ]))
+        .unwrap();
+        assert_eq!(info.processor_count, 2);
+        assert_eq!(*info.local_apic_ids, [0, 0x1_0000]);
+    }
+
+    #[test]
+    fn processors_past_the_limit_are_still_counted() {
+        let entries: Vec<Vec<u8>> = (0..=MAX_PROCESSORS)
+            .map(|id| local_apic(u8::try_from(id).unwrap(), LOCAL_APIC_ENABLED))
+            .collect();
+        let entries: Vec<&[u8]> = entries.iter().map(Vec::as_slice).collect();
+        let info = parse_madt(&madt(&entries)).unwrap();
+        assert_eq!(info.processor_count, MAX_PROCESSORS + 1);
+        assert_eq!(info.local_apic_ids.len(), MAX_PROCESSORS);
+    }
+
+    #[test]
+    fn io_apics_past_the_limit_are_ignored() {
+        let entries: Vec<Vec<u8>> = (0..=MAX_IO_APICS)
+            .map(|index| {
+                let id = u8::try_from(index).unwrap();
+                io_apic(id, 0xfec0_0000 + u32::from(id) * 0x1000, u32::from(id) * 24)
+            })
+            .collect();
+        let entries: Vec<&[u8]> = entries.iter().map(Vec::as_slice).collect();
+        let info = parse_madt(&madt(&entries)).unwrap();
+        assert_eq!(info.io_apics.len(), MAX_IO_APICS);
+        assert_eq!(info.io_apics[1].gsi_base, 24);
+    }
+
+    #[test]
+    fn the_local_apic_address_can_be_overridden() {
+        let mut address_override = vec![MADT_LOCAL_APIC_ADDRESS_OVERRIDE, 12, 0, 0];
+        address_override.extend_from_slice(&0x1_fee0_0000u64.to_le_bytes());
+        let info = parse_madt(&madt(&[&address_override])).unwrap();
+        assert_eq!(info.local_apic_address, 0x1_fee0_0000);
+    }
+
+    #[test]
+    fn broken_madt_entries_are_rejected() {
+        let processor = local_apic(0, LOCAL_APIC_ENABLED);
+        // Runs past the end of the table
+        assert!(parse_madt(&madt(&[&processor[..6]])).is_none());
+        // Too short for their type
+        assert!(parse_madt(&madt(&[&[MADT_LOCAL_APIC, 4, 0, 0]])).is_none());
+        assert!(parse_madt(&madt(&[&[MADT_IO_APIC, 8, 0, 0, 0, 0, 0, 0]])).is_none());
+        // Would never end
+        assert!(parse_madt(&madt(&[&[MADT_LOCAL_APIC, 0]])).is_none());
+        // Entries of types that aren't used are skipped
+        assert!(parse_madt(&madt(&[&[0x7f, 3, 0], &processor])).is_some());
+        assert!(parse_madt(&table(FADT_SIGNATURE, QEMU_MADT_BODY)).is_none());
+    }
+
+    #[test]
+    fn tables_must_fit_and_add_up() {
+        let madt = table(MADT_SIGNATURE, QEMU_MADT_BODY);
+        // Bytes after the table aren't part of it
+        let padded = [&madt[..], &[1, 2, 3]].concat();
+        assert_eq!(validate_table(&padded), Some(&madt[..]));
+        assert_eq!(validate_table(&madt[..madt.len() - 1]), None);
+        let mut corrupted = madt.clone();
+        corrupted[TABLE_HEADER_SIZE] ^= 1;
+        assert_eq!(validate_table(&corrupted), None);
+        let mut too_short = madt;
+        too_short[TABLE_LENGTH_OFFSET] = 8;
+        assert_eq!(validate_table(&too_short), None);
+        assert_eq!(validate_table(&[]), None);
+    }
+
+    #[test]
+    fn root_tables_list_addresses_of_their_size() {
+        let rsdt = table(
+            RSDT_SIGNATURE,
+            &[0x00, 0x10, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0xff],
+        );
+        assert!(root_table_entries(&rsdt, RSDT_ENTRY_SIZE).eq([0x1000, 0x2000]));
+        let xsdt = table(XSDT_SIGNATURE, &0x1_0000_1000u64.to_le_bytes());
+        assert!(root_table_entries(&xsdt, XSDT_ENTRY_SIZE).eq([0x1_0000_1000]));
+        assert_eq!(root_table_entries(&rsdt[..10], RSDT_ENTRY_SIZE).count(), 0);
+    }
+}
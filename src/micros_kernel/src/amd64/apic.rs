@@ -1,5 +1,11 @@
+use super::pit;
+use crate::acpi::{IoApicInfo, PlatformInfo};
 use spin::Mutex;
-use x2apic::lapic::{xapic_base, LocalApic, LocalApicBuilder};
+use x2apic::{
+    ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry},
+    lapic::{xapic_base, LocalApic, LocalApicBuilder},
+};
+use x86_64::instructions::{interrupts, port::Port};
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -7,19 +13,87 @@ pub enum InterruptIndex {
     Error = PIC_OFFSET,
     Spurious,
     Timer,
+    Keyboard,
 }
 
-pub unsafe fn init() -> Option<()> {
+/**
+ * Sets up the local APIC and routes the keyboard through the I/O APIC that `platform` says handles
+ * it. The I/O APIC is assumed to be at its usual address if the MADT couldn't be read.
+ *
+ * # Safety
+ *
+ * The local APIC and the I/O APICs must be identity mapped.
+ */
+pub unsafe fn init(platform: Option<&PlatformInfo>) -> Option<()> {
     let mut apic = create_apic_builder()
         .set_xapic_base(xapic_base())
         .build()
         .ok()?;
     apic.enable();
     apic.disable_timer();
+    disable_legacy_pics();
+    let default_io_apic = [IoApicInfo {
+        id: 0,
+        address: DEFAULT_IO_APIC_ADDRESS,
+        gsi_base: 0,
+    }];
+    let io_apics = platform.map_or(&default_io_apic[..], |platform| &platform.io_apics);
+    route_keyboard_interrupt(io_apics, apic.id());
     set_local_apic(apic);
+    // The PIT times the waits between the IPIs that start the other processors
+    pit::check_against_tsc();
     Some(())
 }
 
+/**
+ * Enables the local APIC of the application processor that this runs on. Its interrupts stay
+ * masked since nothing runs there yet.
+ *
+ * # Safety
+ *
+ * The local APIC must be identity mapped.
+ */
+pub unsafe fn init_application_processor() -> Option<()> {
+    let mut apic = create_apic_builder()
+        .set_xapic_base(xapic_base())
+        .build()
+        .ok()?;
+    apic.enable();
+    apic.disable_timer();
+    Some(())
+}
+
+/// The ID of the boot processor's local APIC, or `None` if it hasn't been set up
+pub fn local_apic_id() -> Option<u32> {
+    interrupts::without_interrupts(|| LOCAL_APIC.lock().as_ref().map(|apic| unsafe { apic.id() }))
+}
+
+/**
+ * Sends the INIT-SIPI-SIPI sequence that starts the processor whose local APIC has ID `apic_id` in
+ * real mode at the start of page `start_page`. The second startup IPI is only sent if `started` is
+ * still false after the first.
+ *
+ * # Safety
+ *
+ * The page must hold code that the processor can run.
+ */
+pub unsafe fn start_processor(apic_id: u32, start_page: u8, started: impl Fn() -> bool) {
+    // The lock is also taken by interrupt handlers
+    interrupts::without_interrupts(|| {
+        let mut local_apic = LOCAL_APIC.lock();
+        let Some(apic) = local_apic.as_mut() else {
+            return;
+        };
+        apic.send_init_ipi(apic_id);
+        pit::busy_wait_us(INIT_DELAY_MICROSECONDS);
+        apic.send_sipi(start_page, apic_id);
+        pit::busy_wait_us(STARTUP_DELAY_MICROSECONDS);
+        if !started() {
+            apic.send_sipi(start_page, apic_id);
+        }
+    });
+}
+
 pub unsafe fn end_interrupt() {
     if let Some(apic) = LOCAL_APIC.lock().as_mut() {
         apic.end_of_interrupt();
@@ -28,6 +102,20 @@ pub unsafe fn end_interrupt() {
 
 const PIC_OFFSET: u8 = 32;
 
+/// How long the MP specification says to wait after an INIT IPI and after a startup IPI
+const INIT_DELAY_MICROSECONDS: u64 = 10_000;
+const STARTUP_DELAY_MICROSECONDS: u64 = 200;
+
+const PRIMARY_PIC_DATA_PORT: u16 = 0x21;
+const SECONDARY_PIC_DATA_PORT: u16 = 0xa1;
+
+/// Where the I/O APIC is unless the firmware moved it. This is only used if the MADT can't be read.
+pub const DEFAULT_IO_APIC_ADDRESS: u32 = 0xfec0_0000;
+/// The global system interrupt that the PS/2 keyboard raises. ISA interrupts are identity mapped
+/// to global system interrupts unless the MADT overrides them, which firmware doesn't do for the
+/// keyboard.
+const KEYBOARD_GSI: u32 = 1;
+
 static LOCAL_APIC: Mutex<Option<LocalApic>> = Mutex::new(None);
 
 fn create_apic_builder() -> LocalApicBuilder {
@@ -39,6 +127,51 @@ fn create_apic_builder() -> LocalApicBuilder {
     apic_builder
 }
 
+/// Masks every interrupt on the 8259 PICs. They're never remapped, so anything they deliver
+/// would land on an exception vector.
+unsafe fn disable_legacy_pics() {
+    Port::<u8>::new(PRIMARY_PIC_DATA_PORT).write(0xff);
+    Port::<u8>::new(SECONDARY_PIC_DATA_PORT).write(0xff);
+}
+
+/// Sends the PS/2 keyboard's interrupts to the local APIC with ID `apic_id` through whichever of
+/// `io_apics` handles them.
+unsafe fn route_keyboard_interrupt(io_apics: &[IoApicInfo], apic_id: u32) {
+    let mut keyboard_input = None;
+    for info in io_apics {
+        let mut io_apic = IoApic::new(info.address.into());
+        let gsi_end = info
+            .gsi_base
+            .saturating_add(u32::from(io_apic.max_table_entry()) + 1);
+        info!(
+            "I/O APIC {} at {:#x} handles GSI {}..{gsi_end}",
+            info.id, info.address, info.gsi_base
+        );
+        if keyboard_input.is_none() && (info.gsi_base..gsi_end).contains(&KEYBOARD_GSI) {
+            keyboard_input = u8::try_from(KEYBOARD_GSI - info.gsi_base)
+                .ok()
+                .map(|input| (io_apic, input));
+        }
+    }
+    let Some((mut io_apic, input)) = keyboard_input else {
+        warn!("No I/O APIC handles the keyboard's interrupt");
+        return;
+    };
+    // The I/O APIC can only name xAPIC IDs, which are 8 bits
+    let Ok(destination) = u8::try_from(apic_id) else {
+        warn!("The keyboard's interrupt can't be sent to the local APIC with ID {apic_id}");
+        return;
+    };
+    let mut entry = RedirectionTableEntry::default();
+    // ISA interrupts are edge triggered and active high
+    entry.set_mode(IrqMode::Fixed);
+    entry.set_flags(IrqFlags::empty());
+    entry.set_vector(InterruptIndex::Keyboard as u8);
+    entry.set_dest(destination);
+    io_apic.set_table_entry(input, entry);
+    io_apic.enable_irq(input);
+}
+
 fn set_local_apic(apic: LocalApic) {
     *LOCAL_APIC.lock() = Some(apic);
 }
@@ -0,0 +1,360 @@
+use super::init::physical_address;
+use core::{
+    ops::Range,
+    ptr::{self, addr_of_mut},
+};
+use frame_allocation::{
+    amd64::{table_index, FOUR_KILOBYTES, GIGABYTE, TWO_MEGABYTES},
+    PhysicalAddress,
+};
+use x86_64::{
+    addr::PhysAddr,
+    structures::paging::page_table::{PageTable, PageTableEntry, PageTableFlags},
+};
+
+extern "C" {
+    static mut p4_table: PageTable;
+    static mut p2_tables: [PageTable; 4];
+    static mut p1_table_for_stack: PageTable;
+}
+
+/// Tables for splitting the 2 MB pages at either end of the kernel image, which it shares with
+/// whatever the boot loader put next to it
+static mut P1_TABLES_FOR_KERNEL: [PageTable; 2] = [const { PageTable::new() }; 2];
+
+/// The page tables that boot.asm sets up before jumping to the kernel. Everything that the kernel
+/// does with them goes through here rather than through the statics.
+pub struct BootPageTables {
+    root: *mut PageTable,
+    p2_tables: *mut [PageTable; 4],
+    p1_tables: *mut [PageTable; 2],
+    /// How many of `p1_tables` have been used to split 2 MB pages
+    p1_tables_used: usize,
+    p1_table_for_stack: *mut PageTable,
+}
+
+impl BootPageTables {
+    /**
+     * Wraps the root page table, the four tables for 2 MB pages, the spare tables for 4 KB pages
+     * and the table that maps the static stacks
+     *
+     * # Safety
+     *
+     * The tables must be identity mapped, and nothing else may access them while the
+     * `BootPageTables` exists. `root` must be the root page table of the current address space.
+     */
+    pub unsafe fn new(
+        root: *mut PageTable,
+        two_megabyte_tables: *mut [PageTable; 4],
+        four_kilobyte_tables: *mut [PageTable; 2],
+        stack_table: *mut PageTable,
+    ) -> Self {
+        Self {
+            root,
+            p2_tables: two_megabyte_tables,
+            p1_tables: four_kilobyte_tables,
+            p1_tables_used: 0,
+            p1_table_for_stack: stack_table,
+        }
+    }
+
+    /**
+     * Takes over the tables built by boot.asm
+     *
+     * # Safety
+     *
+     * This must only be called once.
+     */
+    pub unsafe fn from_boot_code() -> Self {
+        Self::new(
+            addr_of_mut!(p4_table),
+            addr_of_mut!(p2_tables),
+            addr_of_mut!(P1_TABLES_FOR_KERNEL),
+            addr_of_mut!(p1_table_for_stack),
+        )
+    }
+
+    /// The kernel's root page table. Its first entry holds the identity map.
+    pub fn root(&self) -> &PageTable {
+        unsafe { &*self.root }
+    }
+
+    /**
+     * Takes user mode's access to everything that the root table maps away. Processes have root
+     * tables of their own, which give user mode the identity map back, so this only changes the
+     * kernel's view of memory.
+     *
+     * # Safety
+     *
+     * The translation lookaside buffer must be flushed afterwards.
+     */
+    pub unsafe fn make_root_supervisor_only(&mut self) {
+        for entry in (&mut *self.root).iter_mut() {
+            if !entry.is_unused() {
+                entry.set_flags(entry.flags() - PageTableFlags::USER_ACCESSIBLE);
+            }
+        }
+    }
+
+    /**
+     * Takes user mode's access to the identity mapped memory in `range` away. Every address space
+     * shares the identity map's tables, so only the kernel can reach the memory afterwards.
+     * Gigabyte pages are split with the tables that boot.asm uses for 2 MB pages when the
+     * processor doesn't have gigabyte pages, and 2 MB pages that `range` only partly covers are
+     * split with the spare tables for 4 KB pages. Returns `None` if there aren't enough of those.
+     *
+     * # Safety
+     *
+     * `range` must be aligned to 4 KB and be in the first 4 GB, which boot.asm identity maps. The
+     * translation lookaside buffer must be flushed afterwards.
+     */
+    pub unsafe fn make_supervisor_only(&mut self, range: Range<usize>) -> Option<()> {
+        let p3_table = table_under(&self.root()[0])?;
+        let mut address = range.start;
+        while address < range.end {
+            let gigabyte = address / GIGABYTE;
+            let p3_entry = &mut p3_table[gigabyte];
+            if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                let p2_table = (&mut *self.p2_tables).get_mut(gigabyte)?;
+                split_huge_page(p3_entry, p2_table, TWO_MEGABYTES);
+            }
+            let p2_entry = &mut table_under(p3_entry)?[table_index(1, address)];
+            let covers_page =
+                address.is_multiple_of(TWO_MEGABYTES) && address + TWO_MEGABYTES <= range.end;
+            if covers_page && p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                p2_entry.set_flags(p2_entry.flags() - PageTableFlags::USER_ACCESSIBLE);
+                address += TWO_MEGABYTES;
+                continue;
+            }
+            if p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                let p1_table = (&mut *self.p1_tables).get_mut(self.p1_tables_used)?;
+                self.p1_tables_used += 1;
+                split_huge_page(p2_entry, p1_table, FOUR_KILOBYTES);
+            }
+            let p1_entry = &mut table_under(p2_entry)?[table_index(0, address)];
+            p1_entry.set_flags(p1_entry.flags() - PageTableFlags::USER_ACCESSIBLE);
+            address += FOUR_KILOBYTES;
+        }
+        Some(())
+    }
+
+    /// Maps the frame at `address` into the page at `index` in the static stack region
+    pub fn map_static_stack(
+        &mut self,
+        index: usize,
+        address: PhysicalAddress,
+        flags: PageTableFlags,
+    ) {
+        let p1_table = unsafe { &mut *self.p1_table_for_stack };
+        p1_table[index].set_addr(PhysAddr::new_truncate(address.as_usize() as u64), flags);
+    }
+
+    /// Returns true if the page at `index` in the static stack region is mapped
+    pub fn is_static_stack_mapped(&self, index: usize) -> bool {
+        let p1_table = unsafe { &*self.p1_table_for_stack };
+        p1_table[index].flags().contains(PageTableFlags::PRESENT)
+    }
+}
+
+/**
+ * The table that `entry` points to, if it points to one
+ *
+ * # Safety
+ *
+ * The table must be identity mapped, and nothing else may access it while the reference exists.
+ */
+unsafe fn table_under<'a>(entry: &PageTableEntry) -> Option<&'a mut PageTable> {
+    let flags = entry.flags();
+    if flags.contains(PageTableFlags::PRESENT) && !flags.contains(PageTableFlags::HUGE_PAGE) {
+        Some(&mut *(physical_address(entry.addr())?.as_usize() as *mut PageTable))
+    } else {
+        None
+    }
+}
+
+/// Points `entry` at `table` instead of the huge page that it maps, and fills `table` in to map the
+/// same memory with the same permissions in pages of `page_size`
+fn split_huge_page(entry: &mut PageTableEntry, table: &mut PageTable, page_size: usize) {
+    let huge_page = entry.addr();
+    let table_flags = entry.flags() - PageTableFlags::HUGE_PAGE;
+    // The huge page bit is the PAT bit in entries for 4 KB pages
+    let page_flags = if page_size == FOUR_KILOBYTES {
+        table_flags
+    } else {
+        entry.flags()
+    };
+    for (index, small_page) in table.iter_mut().enumerate() {
+        small_page.set_addr(huge_page + (index * page_size) as u64, page_flags);
+    }
+    entry.set_addr(PhysAddr::new(ptr::from_mut(table) as u64), table_flags);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::amd64::page_walk::translate;
+    use test_arena::Arena;
+
+    /// The arena frame that the tests' identity map table goes in
+    const P3_TABLE_FRAME: usize = 8;
+
+    /// Boot page tables in frames of an arena: the root in frame 0, the tables for 2 MB pages in
+    /// frames 1 to 4, the spare tables for 4 KB pages in frames 5 and 6, and the table for the
+    /// static stacks in frame 7
+    fn boot_page_tables(arena: &Arena) -> BootPageTables {
+        let frame = |index| arena.frame(FOUR_KILOBYTES, index).as_usize();
+        unsafe {
+            BootPageTables::new(
+                frame(0) as *mut _,
+                frame(1) as *mut _,
+                frame(5) as *mut _,
+                frame(7) as *mut _,
+            )
+        }
+    }
+
+    fn identity_map_flags() -> PageTableFlags {
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
+    }
+
+    /// Identity maps the first 4 GB the way boot.asm does, with gigabyte pages or with the tables
+    /// for 2 MB pages
+    fn with_identity_map(arena: &Arena, gigabyte_pages: bool) -> BootPageTables {
+        let tables = boot_page_tables(arena);
+        let p3_table = unsafe {
+            &mut *(arena.frame(FOUR_KILOBYTES, P3_TABLE_FRAME).as_usize() as *mut PageTable)
+        };
+        unsafe {
+            (&mut *tables.root)[0].set_addr(
+                PhysAddr::new(ptr::from_ref(p3_table) as u64),
+                identity_map_flags(),
+            );
+        }
+        for gigabyte in 0..4 {
+            let entry = &mut p3_table[gigabyte];
+            entry.set_addr(
+                PhysAddr::new((gigabyte * GIGABYTE) as u64),
+                identity_map_flags() | PageTableFlags::HUGE_PAGE,
+            );
+            if !gigabyte_pages {
+                split_huge_page(
+                    entry,
+                    unsafe { &mut (*tables.p2_tables)[gigabyte] },
+                    TWO_MEGABYTES,
+                );
+            }
+        }
+        tables
+    }
+
+    /// Checks that `address` is still identity mapped and writable with pages of `page_size`, and
+    /// returns whether user mode can reach it
+    fn user_accessible(tables: &BootPageTables, address: usize, page_size: usize) -> bool {
+        let translation = unsafe { translate(tables.root(), address) }
+            .unwrap_or_else(|| panic!("{address:#x} isn't mapped"));
+        assert_eq!(translation.address, PhysicalAddress::new(address));
+        assert_eq!(translation.page_size, page_size, "{address:#x}");
+        assert!(translation.writable, "{address:#x}");
+        translation.user_accessible
+    }
+
+    #[test]
+    fn the_root_is_the_table_that_it_was_given() {
+        let arena = Arena::new(8 * FOUR_KILOBYTES);
+        let tables = boot_page_tables(&arena);
+        assert_eq!(
+            ptr::from_ref(tables.root()) as usize,
+            arena.frame(FOUR_KILOBYTES, 0).as_usize()
+        );
+    }
+
+    #[test]
+    fn root_entries_lose_user_access() {
+        let arena = Arena::new(9 * FOUR_KILOBYTES);
+        let mut tables = with_identity_map(&arena, true);
+        unsafe { tables.make_root_supervisor_only() };
+        let root = tables.root();
+        assert_eq!(
+            root[0].flags(),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+        );
+        assert!(root.iter().skip(1).all(PageTableEntry::is_unused));
+        assert!(!user_accessible(&tables, 0x1234_5000, GIGABYTE));
+    }
+
+    #[test]
+    fn partly_covered_pages_are_split_down_to_four_kilobytes() {
+        let arena = Arena::new(9 * FOUR_KILOBYTES);
+        let mut tables = with_identity_map(&arena, true);
+        let kernel_image = 0x10_0000..0x30_5000;
+        unsafe { tables.make_supervisor_only(kernel_image.clone()) }.unwrap();
+        assert!(user_accessible(
+            &tables,
+            kernel_image.start - FOUR_KILOBYTES,
+            FOUR_KILOBYTES
+        ));
+        for address in [
+            kernel_image.start,
+            TWO_MEGABYTES,
+            kernel_image.end - FOUR_KILOBYTES,
+        ] {
+            assert!(
+                !user_accessible(&tables, address, FOUR_KILOBYTES),
+                "{address:#x}"
+            );
+        }
+        assert!(user_accessible(&tables, kernel_image.end, FOUR_KILOBYTES));
+        assert!(user_accessible(&tables, 2 * TWO_MEGABYTES, TWO_MEGABYTES));
+        assert!(user_accessible(&tables, GIGABYTE, GIGABYTE));
+    }
+
+    #[test]
+    fn covered_two_megabyte_pages_are_not_split() {
+        let arena = Arena::new(9 * FOUR_KILOBYTES);
+        let mut tables = with_identity_map(&arena, false);
+        let local_apic = 0xfee0_0000..0xff00_0000;
+        unsafe { tables.make_supervisor_only(local_apic.clone()) }.unwrap();
+        assert!(!user_accessible(&tables, local_apic.start, TWO_MEGABYTES));
+        assert!(user_accessible(
+            &tables,
+            local_apic.start - 1,
+            TWO_MEGABYTES
+        ));
+        assert!(user_accessible(&tables, local_apic.end, TWO_MEGABYTES));
+        assert_eq!(tables.p1_tables_used, 0);
+    }
+
+    #[test]
+    fn running_out_of_tables_for_four_kilobyte_pages_fails() {
+        let arena = Arena::new(9 * FOUR_KILOBYTES);
+        let mut tables = with_identity_map(&arena, false);
+        let page = |index| index * TWO_MEGABYTES..index * TWO_MEGABYTES + FOUR_KILOBYTES;
+        unsafe {
+            assert!(tables.make_supervisor_only(page(0)).is_some());
+            assert!(tables.make_supervisor_only(page(1)).is_some());
+            // Pages that were already split don't need another table
+            assert!(tables
+                .make_supervisor_only(page(1).end..page(1).end + FOUR_KILOBYTES)
+                .is_some());
+            assert!(tables.make_supervisor_only(page(2)).is_none());
+        }
+        assert!(user_accessible(&tables, page(2).start, TWO_MEGABYTES));
+    }
+
+    #[test]
+    fn static_stack_pages_are_mapped_one_at_a_time() {
+        let arena = Arena::new(8 * FOUR_KILOBYTES);
+        let mut tables = boot_page_tables(&arena);
+        assert!(!tables.is_static_stack_mapped(5));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        tables.map_static_stack(5, PhysicalAddress::new(0x12_3000), flags);
+        assert!(tables.is_static_stack_mapped(5));
+        assert!(!tables.is_static_stack_mapped(4));
+        assert!(!tables.is_static_stack_mapped(6));
+        let entry = &unsafe { &*tables.p1_table_for_stack }[5];
+        assert_eq!(entry.addr(), PhysAddr::new(0x12_3000));
+        assert_eq!(entry.flags(), flags);
+    }
+}
@@ -0,0 +1,312 @@
+//! Reads the date and time from the real-time clock in the CMOS. The RTC is assumed to keep UTC.
+//! Firmware picks whether the registers hold BCD or binary and whether hours are 12 or 24 hour
+//! based, and status register B says which.
+
+use super::pit;
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY_OF_MONTH: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+/// Set in status register A while the RTC is changing the time, when the registers can't be trusted
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Set in status register B if the registers hold binary rather than BCD
+const BINARY_MODE: u8 = 1 << 2;
+/// Set in status register B if hours go up to 23 rather than 12
+const TWENTY_FOUR_HOUR_MODE: u8 = 1 << 1;
+/// Set in the hours register for hours after noon in 12 hour mode
+const PM: u8 = 1 << 7;
+
+/// The century that's assumed when there's no century register
+const DEFAULT_CENTURY: u8 = 20;
+/// How long an update can take before the RTC is assumed to be missing. An update takes about
+/// 2 ms at most.
+const UPDATE_TIMEOUT_MICROSECONDS: u64 = 10_000;
+/// How many times the registers are read before giving up on getting the same time twice in a row
+const MAX_READS: usize = 8;
+
+/// A date and time in UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The number of seconds since the Unix epoch, or 0 for times before it
+    #[must_use]
+    pub fn unix_timestamp(self) -> u64 {
+        let seconds = days_since_epoch(self.year, self.month, self.day) * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        u64::try_from(seconds).unwrap_or(0)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The time registers as the RTC holds them
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+/**
+ * Reads the date and time. `century_register` is the CMOS register that holds the century, if the
+ * FADT names one, and the century is assumed to be 20 otherwise. Returns `None` if the RTC never
+ * holds still long enough to be read.
+ *
+ * # Safety
+ *
+ * Nothing else may use the CMOS at the same time.
+ */
+pub unsafe fn read_rtc(century_register: Option<u8>) -> Option<DateTime> {
+    // The registers are read until they hold the same time twice in a row, since an update can
+    // start at any point while they're being read
+    let mut last = read_raw_time(century_register)?;
+    for _ in 0..MAX_READS {
+        let raw = read_raw_time(century_register)?;
+        if raw == last {
+            return Some(decode(raw, read_register(STATUS_B)));
+        }
+        last = raw;
+    }
+    None
+}
+
+/// Waits for any update to finish and reads the time registers
+unsafe fn read_raw_time(century_register: Option<u8>) -> Option<RawTime> {
+    if !pit::wait_for(UPDATE_TIMEOUT_MICROSECONDS, || unsafe {
+        read_register(STATUS_A) & UPDATE_IN_PROGRESS == 0
+    }) {
+        return None;
+    }
+    Some(RawTime {
+        second: read_register(SECONDS),
+        minute: read_register(MINUTES),
+        hour: read_register(HOURS),
+        day: read_register(DAY_OF_MONTH),
+        month: read_register(MONTH),
+        year: read_register(YEAR),
+        century: century_register.map(|register| read_register(register)),
+    })
+}
+
+unsafe fn read_register(register: u8) -> u8 {
+    Port::<u8>::new(INDEX_PORT).write(register);
+    Port::<u8>::new(DATA_PORT).read()
+}
+
+/// Converts the registers to a date and time according to the format in `status_b`
+fn decode(raw: RawTime, status_b: u8) -> DateTime {
+    let binary = status_b & BINARY_MODE != 0;
+    let convert = |value| {
+        if binary {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+    let mut hour = convert(raw.hour & !PM);
+    if status_b & TWENTY_FOUR_HOUR_MODE == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        hour %= 12;
+        if raw.hour & PM != 0 {
+            hour += 12;
+        }
+    }
+    let century = raw.century.map_or(DEFAULT_CENTURY, convert);
+    DateTime {
+        year: u16::from(century) * 100 + u16::from(convert(raw.year)),
+        month: convert(raw.month),
+        day: convert(raw.day),
+        hour,
+        minute: convert(raw.minute),
+        second: convert(raw.second),
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xf)
+}
+
+/// The number of days from 1970-01-01 to a date in the proleptic Gregorian calendar. The year is
+/// counted from March so that the leap day comes at the end of it.
+fn days_since_epoch(year: u16, month: u8, day: u8) -> i64 {
+    let (month, day) = (i64::from(month), i64::from(day));
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 1970-01-01 is day 719,468 counting from 0000-03-01
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    const BCD_24_HOUR: u8 = TWENTY_FOUR_HOUR_MODE;
+    const BINARY_24_HOUR: u8 = BINARY_MODE | TWENTY_FOUR_HOUR_MODE;
+
+    fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn converts_bcd() {
+        assert_eq!(bcd_to_binary(0x00), 0);
+        assert_eq!(bcd_to_binary(0x09), 9);
+        assert_eq!(bcd_to_binary(0x10), 10);
+        assert_eq!(bcd_to_binary(0x59), 59);
+        assert_eq!(bcd_to_binary(0x99), 99);
+    }
+
+    #[test]
+    fn decodes_bcd_registers() {
+        let raw = RawTime {
+            second: 0x56,
+            minute: 0x34,
+            hour: 0x12,
+            day: 0x01,
+            month: 0x06,
+            year: 0x24,
+            century: None,
+        };
+        assert_eq!(decode(raw, BCD_24_HOUR), date_time(2024, 6, 1, 12, 34, 56));
+    }
+
+    #[test]
+    fn decodes_binary_registers_with_a_century() {
+        let raw = RawTime {
+            second: 59,
+            minute: 59,
+            hour: 23,
+            day: 31,
+            month: 12,
+            year: 99,
+            century: Some(19),
+        };
+        assert_eq!(
+            decode(raw, BINARY_24_HOUR),
+            date_time(1999, 12, 31, 23, 59, 59)
+        );
+    }
+
+    #[test]
+    fn decodes_twelve_hour_clocks() {
+        let raw = |hour| RawTime {
+            second: 0,
+            minute: 0,
+            hour,
+            day: 1,
+            month: 1,
+            year: 0x24,
+            century: None,
+        };
+        let hour = |raw_hour, status_b| decode(raw(raw_hour), status_b).hour;
+        // 12 AM, 1 AM, 12 PM, and 11 PM in BCD
+        assert_eq!(hour(0x12, 0), 0);
+        assert_eq!(hour(0x01, 0), 1);
+        assert_eq!(hour(PM | 0x12, 0), 12);
+        assert_eq!(hour(PM | 0x11, 0), 23);
+        // And 11 PM in binary
+        assert_eq!(hour(PM | 0x0b, BINARY_MODE), 23);
+    }
+
+    #[test]
+    fn days_since_the_epoch() {
+        assert_eq!(days_since_epoch(1970, 1, 1), 0);
+        assert_eq!(days_since_epoch(1970, 3, 1), 59);
+        assert_eq!(days_since_epoch(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn unix_timestamps() {
+        assert_eq!(
+            date_time(2024, 6, 1, 12, 34, 56).unix_timestamp(),
+            1_717_245_296
+        );
+        assert_eq!(
+            date_time(1999, 12, 31, 23, 59, 59).unix_timestamp(),
+            946_684_799
+        );
+        assert_eq!(date_time(1960, 1, 1, 0, 0, 0).unix_timestamp(), 0);
+    }
+
+    #[test]
+    fn leap_years() {
+        // Divisible by 4
+        assert_eq!(
+            date_time(2024, 2, 29, 0, 0, 0).unix_timestamp(),
+            1_709_164_800
+        );
+        // Divisible by 400
+        assert_eq!(
+            date_time(2000, 2, 29, 23, 59, 59).unix_timestamp(),
+            951_868_799
+        );
+        // Divisible by 100 but not 400, so there's no February 29
+        assert_eq!(
+            date_time(2100, 3, 1, 0, 0, 0).unix_timestamp(),
+            4_107_542_400
+        );
+        assert_eq!(
+            days_since_epoch(2100, 3, 1) - days_since_epoch(2100, 2, 28),
+            1
+        );
+        assert_eq!(
+            days_since_epoch(2000, 3, 1) - days_since_epoch(2000, 2, 28),
+            2
+        );
+    }
+
+    #[test]
+    fn formats_like_a_timestamp() {
+        assert_eq!(
+            date_time(2024, 6, 1, 2, 4, 6).to_string(),
+            "2024-06-01 02:04:06 UTC"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use core::{
+    arch::x86_64::{__cpuid, __cpuid_count, CpuidResult},
+    fmt,
+};
+
+/// Optional processor features that the kernel cares about
+#[derive(Clone, Copy, Default, Debug)]
+// Each feature is independent, so there's no state machine or enum to replace the bools with
+#[allow(clippy::struct_excessive_bools)]
+pub struct CpuFeatures {
+    /// 1 GB pages
+    pub gigabyte_pages: bool,
+    /// The no-execute page table bit
+    pub nx: bool,
+    /// 5-level paging
+    pub la57: bool,
+    /// The x2APIC interface to the local APIC
+    pub x2apic: bool,
+    /// The TSC-deadline mode of the local APIC timer
+    pub tsc_deadline: bool,
+    /// Process-context identifiers
+    pub pcid: bool,
+    /// Supervisor mode execution prevention
+    pub smep: bool,
+    /// Supervisor mode access prevention
+    pub smap: bool,
+    /// Memory type range registers
+    pub mtrr: bool,
+    /// The machine check exception
+    pub mce: bool,
+    /// The machine check architecture's error reporting banks
+    pub mca: bool,
+}
+
+impl CpuFeatures {
+    /// Queries the processor for the features it supports.
+    #[must_use]
+    pub fn detect() -> Self {
+        let empty = CpuidResult {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+        };
+        let max_leaf = __cpuid(0).eax;
+        let max_extended_leaf = __cpuid(EXTENDED_LEAF_BASE).eax;
+        let leaf_1 = if max_leaf >= 1 { __cpuid(1) } else { empty };
+        let leaf_7 = if max_leaf >= 7 {
+            __cpuid_count(7, 0)
+        } else {
+            empty
+        };
+        let extended_leaf_1 = if max_extended_leaf > EXTENDED_LEAF_BASE {
+            __cpuid(EXTENDED_LEAF_BASE + 1)
+        } else {
+            empty
+        };
+        Self::decode(&leaf_1, &leaf_7, &extended_leaf_1)
+    }
+
+    /// Decodes the results of CPUID leaf 1, leaf 7 (subleaf 0), and extended leaf `0x8000_0001`.
+    #[must_use]
+    pub fn decode(
+        leaf_1: &CpuidResult,
+        leaf_7: &CpuidResult,
+        extended_leaf_1: &CpuidResult,
+    ) -> Self {
+        let bit = |register: u32, bit: u32| register & (1 << bit) != 0;
+        Self {
+            gigabyte_pages: bit(extended_leaf_1.edx, 26),
+            nx: bit(extended_leaf_1.edx, 20),
+            la57: bit(leaf_7.ecx, 16),
+            x2apic: bit(leaf_1.ecx, 21),
+            tsc_deadline: bit(leaf_1.ecx, 24),
+            pcid: bit(leaf_1.ecx, 17),
+            smep: bit(leaf_7.ebx, 7),
+            smap: bit(leaf_7.ebx, 20),
+            mtrr: bit(leaf_1.edx, 12),
+            mce: bit(leaf_1.edx, 7),
+            mca: bit(leaf_1.edx, 14),
+        }
+    }
+}
+
+/// Lists the features that the processor has by the names that Linux gives them in /proc/cpuinfo
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let features = [
+            (self.gigabyte_pages, "pdpe1gb"),
+            (self.nx, "nx"),
+            (self.la57, "la57"),
+            (self.x2apic, "x2apic"),
+            (self.tsc_deadline, "tsc_deadline_timer"),
+            (self.pcid, "pcid"),
+            (self.smep, "smep"),
+            (self.smap, "smap"),
+            (self.mtrr, "mtrr"),
+            (self.mce, "mce"),
+            (self.mca, "mca"),
+        ];
+        let mut names = features
+            .into_iter()
+            .filter_map(|(supported, name)| supported.then_some(name));
+        let Some(first) = names.next() else {
+            return f.write_str("none");
+        };
+        f.write_str(first)?;
+        names.try_for_each(|name| write!(f, " {name}"))
+    }
+}
+
+const EXTENDED_LEAF_BASE: u32 = 0x8000_0000;
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    fn registers(eax: u32, ebx: u32, ecx: u32, edx: u32) -> CpuidResult {
+        CpuidResult { eax, ebx, ecx, edx }
+    }
+
+    #[test]
+    fn features_are_decoded_from_their_bits() {
+        let leaf_1 = registers(0, 0, 1 << 21, 1 << 12 | 1 << 7);
+        let leaf_7 = registers(0, 1 << 7, 0, 0);
+        let extended_leaf_1 = registers(0, 0, 0, 1 << 20);
+        let features = CpuFeatures::decode(&leaf_1, &leaf_7, &extended_leaf_1);
+        assert_eq!(features.to_string(), "nx x2apic smep mtrr mce");
+    }
+
+    #[test]
+    fn a_processor_without_features_says_so() {
+        assert_eq!(CpuFeatures::default().to_string(), "none");
+    }
+}
@@ -1,79 +1,377 @@
 use crate::{
+    acpi::{self, PlatformInfo},
     amd64::{
-        apic, breakpoint_handler, double_fault_handler, elf, error_interrupt_handler,
-        launch_memory_manager, p1_table_for_stack, p2_tables, p4_table, page_fault_handler,
-        spurious_interrupt_handler, timer_interrupt_handler,
+        apic,
+        boot_page_tables::BootPageTables,
+        breakpoint_handler, cmos,
+        cpu::CpuFeatures,
+        double_fault_handler, error_interrupt_handler, general_protection_fault_handler,
+        keyboard_interrupt_handler, machine_check_handler, mce, memory_service, page_fault_handler,
+        pci, power, serial, smp, spurious_interrupt_handler,
+        syscall::{self, SyscallSegments},
+        time::{self, Instant},
+        timer_interrupt_handler,
+        user_access::{self, UserAccessGuard},
     },
-    boot_os, copy_and_zero_fill, slice_with_bounds_check, Architecture, SegmentFlags,
+    boot_options::BootOptions,
+    boot_os, console, copy_and_zero_fill,
+    elf::{self, ProgramHeader, EM_X86_64},
+    kernel_image, log, may_register_memory_region, memory_stats, reclaim_acpi_memory,
+    slice_with_bounds_check, validate_boot_information, Architecture, BootReport,
+    ProcessLaunchInfo, SegmentFlags,
 };
 use apic::InterruptIndex;
 use core::{
+    arch::asm,
+    iter::once,
+    mem::{self, size_of},
     ops::Range,
-    ptr::{addr_of, addr_of_mut},
+    ptr::{self, addr_of, addr_of_mut},
     slice,
 };
-use elf::ProgramHeader;
 use frame_allocation::{
-    amd64::{Amd64FrameAllocator, FOUR_KILOBYTES, GIGABYTE},
-    end_of_last_full_page, first_full_page_address, FfiOption, FrameAllocator,
+    amd64::{
+        offset_in_page, page_size, table_index, Amd64FrameAllocator, Granularity, FOUR_KILOBYTES,
+        GIGABYTE, TWO_MEGABYTES,
+    },
+    page_tables::{entry_indices, number_of_bytes_for_page},
+    range_math::align_outward,
+    FfiOption, FrameAllocator, PhysicalAddress, VirtualAddress,
+};
+use micros_abi::{
+    ring::{Message, RingBuffer, MESSAGE_BOOT_COMPLETE},
+    AllocatorHandoff, BootHandoff, FramebufferHandoff, ProcessHandoff, BOOT_HANDOFF_MAGIC,
+    BOOT_HANDOFF_VERSION, STATS_FRAME_SIZES,
 };
+use multiboot2::{phys_to_usize, BootInformation, FramebufferTag};
+use serial::{SerialPort, COM1};
+use x2apic::lapic::xapic_base;
 use x86_64::{
     addr::PhysAddr,
-    instructions::{interrupts, tables::load_tss},
-    registers::segmentation::{Segment, SegmentSelector, CS},
+    instructions::{hlt, interrupts, tables::load_tss, tlb},
+    registers::{
+        control::{Cr0, Cr0Flags, Cr3},
+        model_specific::{Efer, EferFlags},
+        segmentation::{Segment, SegmentSelector, CS},
+    },
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable},
         idt::InterruptDescriptorTable,
         paging::page_table::{PageTable, PageTableEntry, PageTableFlags},
         tss::TaskStateSegment,
     },
-    VirtAddr,
+    PrivilegeLevel, VirtAddr,
 };
 
-pub unsafe fn initialize_operating_system(multiboot_info_ptr: u32, cpu_info: u32) -> Option<()> {
-    p1_table_for_stack[0x001].set_addr(
-        PhysAddr::new_truncate(addr_of!(DOUBLE_FAULT_STACK) as u64),
-        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+// The kernel tests end the boot when testing in QEMU
+#[cfg_attr(feature = "qemu-test", allow(unreachable_code))]
+pub unsafe fn initialize_operating_system(multiboot_info_ptr: u32) -> Option<()> {
+    let cpu_features = CpuFeatures::detect();
+    let no_execute = enable_no_execute(&cpu_features);
+    let boot_info_ptr = multiboot_info_ptr as *const u8;
+    if let Err(err) = validate_boot_information(boot_info_ptr, Amd64::INITIAL_VIRTUAL_MEMORY_SIZE) {
+        // The command line can't be read without the boot information, so log to the serial port
+        // regardless of the options
+        log_to_serial_port();
+        error!("Invalid boot information: {err}");
+        return None;
+    }
+    let options = apply_boot_options(boot_info_ptr);
+    info!("Processor features: {cpu_features}");
+
+    let mut boot_page_tables = BootPageTables::from_boot_code();
+    boot_page_tables.map_static_stack(
+        DOUBLE_FAULT_STACK_PAGE,
+        PhysicalAddress::new(addr_of!(DOUBLE_FAULT_STACK) as usize),
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | no_execute,
     );
+    protect_kernel_memory(&mut boot_page_tables, &cpu_features);
 
     let segment_selectors = load_gdt(&mut *addr_of_mut!(GDT), &mut *addr_of_mut!(TSS));
     CS::set_reg(segment_selectors.code_selector);
     load_tss(segment_selectors.tss_selector);
-    IDT.breakpoint.set_handler_fn(breakpoint_handler);
-    let double_fault_interrupt = IDT.double_fault.set_handler_fn(double_fault_handler);
-    double_fault_interrupt.set_stack_index(DOUBLE_FAULT_IST_INDEX);
-    IDT.page_fault.set_handler_fn(page_fault_handler);
-    set_interrupt_handlers(&mut *addr_of_mut!(IDT));
+    syscall::init(&segment_selectors.syscall_segments)?;
+    build_idt(&mut *addr_of_mut!(IDT));
     IDT.load();
-    apic::init()?;
-    interrupts::enable();
+    mce::init(&cpu_features);
+    let platform_info = read_platform_info(boot_info_ptr);
+    apic::init(platform_info.as_ref())?;
+    time::init();
+    read_wall_clock(boot_info_ptr);
+    pci::init();
+    Amd64::enable_interrupts();
 
     // Without this line the double fault handler triggers a page fault and I have no idea why
     // I've tried flushing the translation lookaside buffer and that doesn't appear to have any
     // affect
     DOUBLE_FAULT_STACK_BOTTOM.write_volatile(0xff);
 
-    let proc = &mut *addr_of_mut!(PROC);
-    if supports_gigabyte_pages(cpu_info) {
-        proc.allocator
-            .four_kilobyte_pages
-            .add_frame(addr_of!(p2_tables[0]) as usize);
-        proc.allocator
-            .four_kilobyte_pages
-            .add_frame(addr_of!(p2_tables[1]) as usize);
+    let proc = (*addr_of_mut!(PROC)).insert(Amd64 {
+        allocator: Amd64FrameAllocator::new(),
+        boot_page_tables,
+        no_execute,
+    });
+    // The boot code identity maps memory with gigabyte pages whenever the processor supports them.
+    // The `no-gbpages` option only keeps gigabyte frames out of the allocator.
+    if cpu_features.gigabyte_pages && options.gigabyte_pages {
         proc.allocator.gigabyte_pages = FfiOption::Some(FrameAllocator::default());
     }
-    let boot_info_ptr = multiboot_info_ptr as *const u8;
-    let memory_manager_launch_info = boot_os(proc, boot_info_ptr)?;
+    let boot_info = BootInformation::new(boot_info_ptr);
+    let boot_report = match boot_os(proc, boot_info, options, [smp::TRAMPOLINE_PAGE]) {
+        Ok(boot_report) => boot_report,
+        Err(err) => {
+            error!("Failed to boot: {err}");
+            return None;
+        }
+    };
+    // The page tables are in their final state for booting, and nothing writes through a read-only
+    // mapping from here on
+    enable_write_protection();
+    // Everything that's needed from the MADT and the FADT was read before booting, and the ACPI
+    // tables aren't read again
+    reclaim_acpi_memory(proc, &boot_report);
+    info!(
+        "Loaded {} boot modules and registered {} memory regions",
+        boot_report.modules.len(),
+        boot_report.registered_memory.len()
+    );
+    memory_stats::log(&boot_report.memory_stats, &boot_report.modules);
+    if let Some(platform_info) = &platform_info {
+        start_application_processors(proc, platform_info, boot_info, &boot_report);
+    }
+
+    if cfg!(debug_assertions) {
+        if proc.verify_page_table_integrity() {
+            debug!("Page table integrity check passed");
+        } else {
+            warn!("Page table integrity check failed");
+        }
+    }
+    #[cfg(feature = "qemu-test")]
+    super::self_checks::exit_qemu(crate::self_test::run_kernel_tests(proc, &boot_report));
 
-    launch_memory_manager(
-        addr_of_mut!(proc.allocator),
+    let Some(handoff_page_address) = proc.allocator.get_4k_frame() else {
+        error!("Failed to boot: there's no memory left for the boot handoff");
+        return None;
+    };
+    // Memory is identity mapped in every address space, so the memory manager can reach the ring
+    // buffers at the same addresses. They have to be writable on both sides because the consumer
+    // moves the head.
+    let (Some(events), Some(replies)) = (
+        new_ring_buffer(&mut proc.allocator),
+        new_ring_buffer(&mut proc.allocator),
+    ) else {
+        error!("Failed to boot: there's no memory left for the ring buffers");
+        return None;
+    };
+    // The handoff page and the ring buffers are the memory manager's memory
+    let user_access = UserAccessGuard::begin();
+    if !(*events).try_push(&Message::new(MESSAGE_BOOT_COMPLETE)) {
+        warn!("Failed to send the boot complete message");
+    }
+    let handoff_page = identity_mapped::<HandoffPage>(handoff_page_address);
+    // The memory manager owns every free frame from here on
+    let allocator = mem::take(&mut proc.allocator);
+    let allocator_handoff = addr_of_mut!((*handoff_page).allocator);
+    allocator_handoff.write(AllocatorHandoff::new(allocator));
+    addr_of_mut!((*handoff_page).boot).write(boot_handoff(
+        allocator_handoff,
+        events,
+        replies,
         boot_info_ptr,
-        memory_manager_launch_info.root_page_table_address,
-        memory_manager_launch_info.entry_point,
+        &boot_report,
+    ));
+    drop(user_access);
+    let mut memory_manager = boot_report.memory_manager;
+    // The memory manager's entry point is a `MemoryManagerEntry`, which takes the handoff in the
+    // first argument register
+    memory_manager.arguments = [handoff_page_address.as_usize(), 0, 0, 0];
+    memory_service::init(events, replies);
+    // The memory manager draws on the screen from here on
+    console::detach();
+    enter_user_process(&memory_manager);
+}
+
+/**
+ * Starts the other processors in `platform_info` and logs how many are online
+ *
+ * # Safety
+ *
+ * The boot processor's descriptor tables and local APIC must be set up, and the trampoline page
+ * must have been kept out of the frame allocator.
+ */
+unsafe fn start_application_processors(
+    proc: &mut Amd64,
+    platform_info: &PlatformInfo,
+    boot_info: BootInformation,
+    boot_report: &BootReport,
+) {
+    let in_use = boot_report
+        .modules
+        .iter()
+        .map(|module| module.range.clone())
+        .chain(once(boot_info.address_range()));
+    if !smp::trampoline_page_is_free(in_use) {
+        warn!("Boot data is where the other processors have to start, so they can't be started");
+        return;
+    }
+    let Some(apic_id) = apic::local_apic_id() else {
+        return;
+    };
+    let start = Instant::now();
+    let online = smp::start_application_processors(platform_info, apic_id, &mut proc.allocator);
+    info!(
+        "{online} CPUs online after {} us",
+        start.elapsed().as_micros()
+    );
+}
+
+/**
+ * Reads the boot options and applies the ones that take effect before the rest of the kernel is
+ * set up: where the log goes and what happens after a fatal error. The log also goes to the
+ * screen if the bootloader set up a framebuffer.
+ *
+ * # Safety
+ *
+ * `boot_info_ptr` must point to valid multiboot2 boot information, and nothing else may use the
+ * serial port or the screen.
+ */
+unsafe fn apply_boot_options(boot_info_ptr: *const u8) -> BootOptions {
+    let options = BootOptions::from_boot_info(BootInformation::new(boot_info_ptr));
+    log::set_max_level(options.log_level);
+    if options.serial {
+        log_to_serial_port();
+    }
+    if !console::init(
+        BootInformation::new(boot_info_ptr),
+        Amd64::INITIAL_VIRTUAL_MEMORY_SIZE,
+    ) {
+        info!("There's no framebuffer to log to");
+    }
+    power::init(boot_info_ptr, options.on_fatal);
+    options
+}
+
+/**
+ * Sends the log to the first serial port
+ *
+ * # Safety
+ *
+ * Nothing else may use the serial port.
+ */
+unsafe fn log_to_serial_port() {
+    let serial_port = &mut *addr_of_mut!(SERIAL_PORT);
+    serial_port.init();
+    log::set_sink(serial_port);
+}
+
+/**
+ * Reads the MADT and logs what it says about the machine
+ *
+ * # Safety
+ *
+ * `boot_info_ptr` must point to valid multiboot2 boot information.
+ */
+unsafe fn read_platform_info(boot_info_ptr: *const u8) -> Option<PlatformInfo> {
+    let platform_info = acpi::platform_info(
+        BootInformation::new(boot_info_ptr),
+        Amd64::INITIAL_VIRTUAL_MEMORY_SIZE,
+    );
+    match &platform_info {
+        Some(platform_info) => info!(
+            "{} CPUs, local APICs at {:#x}",
+            platform_info.processor_count, platform_info.local_apic_address
+        ),
+        None => warn!("Couldn't read the MADT, so only the boot processor is known about"),
+    }
+    platform_info
+}
+
+/**
+ * Logs the time that the RTC holds and sets the wall clock from it. Log messages start with the
+ * wall clock time from then on.
+ *
+ * # Safety
+ *
+ * `boot_info_ptr` must point to valid multiboot2 boot information.
+ */
+unsafe fn read_wall_clock(boot_info_ptr: *const u8) {
+    let century_register = acpi::century_register(
+        BootInformation::new(boot_info_ptr),
+        Amd64::INITIAL_VIRTUAL_MEMORY_SIZE,
     );
+    let Some(now) = cmos::read_rtc(century_register) else {
+        warn!("Couldn't read the RTC, so the wall clock isn't set");
+        return;
+    };
+    info!("RTC time: {now}");
+    time::set_wall_clock(now.unix_timestamp());
+    log::set_clock(time::wall_clock);
 }
 
+/**
+ * Gathers what the memory manager needs to know about the system
+ *
+ * # Safety
+ *
+ * `boot_info_ptr` must point to valid multiboot2 boot information.
+ */
+unsafe fn boot_handoff(
+    allocator: *mut AllocatorHandoff,
+    events: *mut RingBuffer,
+    replies: *mut RingBuffer,
+    boot_info_ptr: *const u8,
+    boot_report: &BootReport,
+) -> BootHandoff {
+    let framebuffer = BootInformation::new(boot_info_ptr)
+        .tags_of_type::<FramebufferTag>()
+        .next()
+        .map_or(FfiOption::None, |tag| {
+            FfiOption::Some(FramebufferHandoff {
+                address: tag.framebuffer,
+                pitch: tag.pitch,
+                width: tag.width,
+                height: tag.height,
+                bits_per_pixel: tag.bits_per_pixel,
+                framebuffer_type: tag.framebuffer_type,
+            })
+        });
+    let init = boot_report.init.as_ref().map_or(FfiOption::None, |init| {
+        FfiOption::Some(ProcessHandoff {
+            root_page_table_address: init.root_page_table_address,
+            entry_point: init.entry_point,
+        })
+    });
+    BootHandoff {
+        magic: BOOT_HANDOFF_MAGIC,
+        version: BOOT_HANDOFF_VERSION,
+        allocator,
+        boot_info: boot_info_ptr,
+        framebuffer,
+        init,
+        memory_stats: boot_report.memory_stats,
+        root_page_table: boot_report.memory_manager.root_page_table_address,
+        events,
+        replies,
+    }
+}
+
+/// Takes a page from `allocator` and sets up an empty ring buffer in it
+unsafe fn new_ring_buffer(allocator: &mut Amd64FrameAllocator) -> Option<*mut RingBuffer> {
+    let ring_buffer = identity_mapped::<RingBuffer>(allocator.get_4k_frame()?);
+    ptr::write_bytes(ring_buffer.cast::<u8>(), 0, FOUR_KILOBYTES);
+    Some(ring_buffer)
+}
+
+/// What the kernel writes to the page that it hands to the memory manager
+#[repr(C)]
+struct HandoffPage {
+    boot: BootHandoff,
+    allocator: AllocatorHandoff,
+}
+
+const _: () = assert!(size_of::<HandoffPage>() <= FOUR_KILOBYTES);
+
 static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
 
 static mut TSS: TaskStateSegment = TaskStateSegment::new();
@@ -82,32 +380,95 @@ static mut GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
 
 static mut DOUBLE_FAULT_STACK: DoubleFaultStack = DoubleFaultStack([0; DOUBLE_FAULT_STACK_SIZE]);
 
-static mut PROC: Amd64 = Amd64 {
-    allocator: Amd64FrameAllocator {
-        four_kilobyte_pages: FrameAllocator::new(),
-        two_megabyte_pages: FrameAllocator::new(),
-        gigabyte_pages: FfiOption::None,
-    },
-};
+static mut SERIAL_PORT: SerialPort = SerialPort::new(COM1);
+
+static mut PROC: Option<Amd64> = None;
+
+/**
+ * Enables the no-execute bit if the processor has one and returns the page table flag that uses
+ * it. The flag is empty otherwise, since the bit is reserved while it's disabled and any entry
+ * that sets it causes a page fault.
+ *
+ * # Safety
+ *
+ * This must be called before any page table entry sets the no-execute bit.
+ */
+unsafe fn enable_no_execute(cpu_features: &CpuFeatures) -> PageTableFlags {
+    if cpu_features.nx {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        PageTableFlags::NO_EXECUTE
+    } else {
+        PageTableFlags::empty()
+    }
+}
+
+/**
+ * Makes the kernel's memory supervisor only and turns SMEP and SMAP on. The kernel's own view of
+ * memory stops being user accessible, and so do the kernel image and the local APIC's registers in
+ * the identity map that processes share, since the kernel goes on running in their address spaces.
+ * SMEP and SMAP are left off if the identity map can't be split around the kernel image.
+ *
+ * # Safety
+ *
+ * This must be called once, before any process's address space is built.
+ */
+unsafe fn protect_kernel_memory(boot_page_tables: &mut BootPageTables, cpu_features: &CpuFeatures) {
+    boot_page_tables.make_root_supervisor_only();
+    let local_apic =
+        phys_to_usize(xapic_base()).map(|base| align_outward(base..base + 1, TWO_MEGABYTES));
+    let protected = local_apic.and_then(|local_apic| {
+        boot_page_tables.make_supervisor_only(kernel_image())?;
+        boot_page_tables.make_supervisor_only(local_apic)
+    });
+    tlb::flush_all();
+    if protected.is_some() {
+        user_access::enable(cpu_features);
+    } else {
+        warn!("SMEP and SMAP are off because the identity map couldn't be split around the kernel");
+    }
+}
 
-const GIGABYTE_PAGES_CPUID_BIT: u32 = 0x400_0000;
+/**
+ * Makes the kernel honor read-only mappings. Without CR0.WP the processor ignores the writable bit
+ * for writes from ring 0.
+ *
+ * # Safety
+ *
+ * The kernel mustn't write through any read-only mapping afterwards. Page tables are written
+ * through the identity map, which is writable.
+ */
+pub(super) unsafe fn enable_write_protection() {
+    Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+}
 
 const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const DOUBLE_FAULT_STACK_PAGE: usize = 0x001;
 const DOUBLE_FAULT_STACK_SIZE: usize = FOUR_KILOBYTES;
 
 const DOUBLE_FAULT_STACK_BOTTOM: *mut u8 = 0xffff_ffff_ffe0_1000 as *mut u8;
 const DOUBLE_FAULT_STACK_TOP: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_ffe0_2000);
 
-const INTERRUPT_STACK_BOTTOM: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_fff0_1000);
+/// The selectors that `load_gdt` gives the kernel's code segment and the user segments
+const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(5, PrivilegeLevel::Ring3);
+const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(6, PrivilegeLevel::Ring3);
+/// The flags that processes start with: only interrupts enabled and the reserved bit that is
+/// always set
+const USER_RFLAGS: u64 = 0x202;
 
-struct Amd64 {
-    allocator: Amd64FrameAllocator,
+/// The top of the page that `initialize_process_page_tables` maps for handling interrupts from
+/// user mode
+const INTERRUPT_STACK_TOP: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_e020_0000);
+
+pub struct Amd64 {
+    pub(super) allocator: Amd64FrameAllocator,
+    pub(super) boot_page_tables: BootPageTables,
+    /// The flag that keeps code in a page from being executed, or nothing if the processor can't
+    /// do that. See `enable_no_execute`.
+    pub(super) no_execute: PageTableFlags,
 }
 
 impl Amd64 {
-    // This code is explicitly only enabled for 64 bit processors, so casting from u64 to usize is
-    // safe here.
-    #[allow(clippy::cast_possible_truncation)]
     unsafe fn copy_into_address_space(
         &mut self,
         page_table_level: u8,
@@ -121,12 +482,12 @@ impl Amd64 {
         for entry in page_table_entries(page_table, page_table_level, address, size) {
             let page = if entry.is_unused() {
                 let page_address = self.allocator.get_4k_frame()?;
-                set_page_table_entry(entry, page_address, flags);
-                (page_address as *mut u8).write_bytes(0, FOUR_KILOBYTES);
+                set_page_table_entry(entry, page_address, flags, self.no_execute);
+                identity_mapped::<u8>(page_address).write_bytes(0, FOUR_KILOBYTES);
                 page_address
             } else {
                 update_page_table_entry_flags(entry, flags);
-                entry.addr().as_u64() as usize
+                physical_address(entry.addr())?
             };
             let page_offset = offset_in_page(page_table_level, address);
             let bytes_for_page =
@@ -135,11 +496,11 @@ impl Amd64 {
 
             if page_table_level == 0 || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
                 copy_and_zero_fill(
-                    slice::from_raw_parts_mut((page + page_offset) as *mut u8, bytes_for_page),
+                    slice::from_raw_parts_mut(identity_mapped(page + page_offset), bytes_for_page),
                     data_for_entry,
                 );
             } else {
-                let sub_page_table = &mut *(page as *mut PageTable);
+                let sub_page_table = &mut *identity_mapped::<PageTable>(page);
                 self.copy_into_address_space(
                     page_table_level - 1,
                     sub_page_table,
@@ -154,6 +515,40 @@ impl Amd64 {
         }
         Some(())
     }
+
+    /// Returns every frame mapped by `page_table`, including the frames of its sub tables, to the
+    /// allocator and clears its entries.
+    unsafe fn free_page_table(&mut self, page_table_level: u8, page_table: &mut PageTable) {
+        for entry in page_table.iter_mut() {
+            if !entry.flags().contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            // A frame past the end of the address space can't have come from the allocator
+            if let Some(frame) = physical_address(entry.addr()) {
+                if page_table_level == 0 || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                    self.free_frame(page_table_level, frame);
+                } else {
+                    self.free_page_table(page_table_level - 1, &mut *identity_mapped(frame));
+                    self.free_frame(0, frame);
+                }
+            }
+            entry.set_unused();
+        }
+    }
+
+    /// Returns a frame that was mapped by an entry in a page table at `page_table_level` to the
+    /// allocator of the matching size.
+    unsafe fn free_frame(&mut self, page_table_level: u8, frame: PhysicalAddress) {
+        match page_table_level {
+            0 => self.allocator.four_kilobyte_pages.add_frame(frame),
+            1 => self.allocator.two_megabyte_pages.add_frame(frame),
+            _ => {
+                if let FfiOption::Some(ref mut gb_allocator) = self.allocator.gigabyte_pages {
+                    gb_allocator.add_frame(frame);
+                }
+            }
+        }
+    }
 }
 
 impl Architecture for Amd64 {
@@ -161,35 +556,41 @@ impl Architecture for Amd64 {
 
     type PageTable = PageTable;
 
-    type ExecutableHeader = elf::Header;
+    type ExecutableHeader = elf::Header<EM_X86_64>;
 
     type SegmentHeader = ProgramHeader;
 
-    unsafe fn initialize_memory_manager_page_tables(&mut self) -> Option<*mut Self::PageTable> {
-        let root_table_pointer = self.allocator.get_4k_frame()? as *mut PageTable;
+    unsafe fn initialize_process_page_tables(
+        &mut self,
+    ) -> Option<(*mut Self::PageTable, VirtualAddress)> {
+        let root_table_pointer = identity_mapped::<PageTable>(self.allocator.get_4k_frame()?);
         let root_table = &mut (*root_table_pointer);
         root_table.zero();
-        root_table[0] = (*addr_of!(p4_table))[0].clone();
+        // The kernel's own entry for the identity map is supervisor only, but the memory manager
+        // reaches every frame through it
+        root_table[0] = self.boot_page_tables.root()[0].clone();
+        let identity_map_flags = root_table[0].flags() | PageTableFlags::USER_ACCESSIBLE;
+        root_table[0].set_flags(identity_map_flags);
 
         let p3_table_addr = self.allocator.get_4k_frame()?;
-        let p3_table = p3_table_addr as *mut PageTable;
+        let p3_table = identity_mapped::<PageTable>(p3_table_addr);
         let flags = user_accessible_page() | PageTableFlags::WRITABLE;
         set_last_entry(root_table, p3_table_addr, flags);
 
         let p2_table_addr = self.allocator.get_4k_frame()?;
-        let p2_table = p2_table_addr as *mut PageTable;
+        let p2_table = identity_mapped::<PageTable>(p2_table_addr);
         clear_and_set_last_entry(&mut *p3_table, p2_table_addr, flags);
 
         if let Some(huge_stack) = self.allocator.get_2mb_frame() {
             clear_and_set_last_entry(
                 &mut *p2_table,
                 huge_stack,
-                flags | PageTableFlags::HUGE_PAGE | PageTableFlags::NO_EXECUTE,
+                flags | PageTableFlags::HUGE_PAGE | self.no_execute,
             );
         } else {
-            let stack_flags = flags | PageTableFlags::NO_EXECUTE;
+            let stack_flags = flags | self.no_execute;
             let p1_table_addr = self.allocator.get_4k_frame()?;
-            let p1_table = p1_table_addr as *mut PageTable;
+            let p1_table = identity_mapped::<PageTable>(p1_table_addr);
             clear_and_set_last_entry(&mut *p2_table, p1_table_addr, flags);
 
             clear_and_set_last_entry(&mut *p1_table, self.allocator.get_4k_frame()?, stack_flags);
@@ -214,50 +615,113 @@ impl Architecture for Amd64 {
         }
 
         let p1_table_addr = self.allocator.get_4k_frame()?;
-        let p1_table = p1_table_addr as *mut PageTable;
+        let p1_table = identity_mapped::<PageTable>(p1_table_addr);
         set_entry(
             &mut *p2_table,
             0x100,
             p1_table_addr,
-            interrupt_stack_flags(),
+            interrupt_stack_flags(self.no_execute),
         );
 
         set_last_entry(
             &mut *p1_table,
             self.allocator.get_4k_frame()?,
-            interrupt_stack_flags(),
+            interrupt_stack_flags(self.no_execute),
         );
 
-        Some(root_table_pointer)
+        // The stack is mapped at the very top of the address space, so the stack pointer starts
+        // at the end of the address space and wraps around to 0
+        Some((root_table_pointer, VirtualAddress::new(0)))
+    }
+
+    unsafe fn abandon_address_space(&mut self, root_page_table: *mut Self::PageTable) {
+        let root_table = &mut *root_page_table;
+        // The first entry is shared with the kernel, so it's left alone
+        root_table[0].set_unused();
+        self.free_page_table(3, root_table);
+        self.free_frame(0, PhysicalAddress::new(root_page_table as usize));
     }
 
     unsafe fn register_memory_region(&mut self, memory_region: Range<usize>) {
-        if let FfiOption::Some(ref mut gb_allocator) = self.allocator.gigabyte_pages {
-            let first_gb_page = first_full_page_address(memory_region.start, GIGABYTE);
-            let end_of_last_gb_page = end_of_last_full_page(memory_region.end, GIGABYTE);
-            if end_of_last_gb_page > first_gb_page {
-                self.allocator
-                    .two_megabyte_pages
-                    .add_aligned_frames_with_scrap_allocator(
-                        &mut self.allocator.four_kilobyte_pages,
-                        memory_region.start..first_gb_page,
-                    );
-                gb_allocator.add_frames(first_gb_page..end_of_last_gb_page);
-                self.allocator
-                    .two_megabyte_pages
-                    .add_aligned_frames_with_scrap_allocator(
-                        &mut self.allocator.four_kilobyte_pages,
-                        end_of_last_gb_page..end_of_last_gb_page,
+        if may_register_memory_region(&memory_region) {
+            self.allocator.add_memory_region(memory_region);
+        }
+    }
+
+    fn free_memory_by_frame_size(&self) -> [usize; STATS_FRAME_SIZES.len()] {
+        [
+            Granularity::FourKilobyte,
+            Granularity::TwoMegabyte,
+            Granularity::Gigabyte,
+        ]
+        .map(|granularity| self.allocator.bytes_free_at_granularity(granularity))
+    }
+
+    unsafe fn extend_identity_map(&mut self, end: usize) -> usize {
+        // The identity map lives in the page tables under the first entry of the root page table,
+        // and the memory manager shares them, so it sees the new mappings too.
+        let Some(p3_table_address) = physical_address(self.boot_page_tables.root()[0].addr())
+        else {
+            return end.min(Self::INITIAL_VIRTUAL_MEMORY_SIZE);
+        };
+        let p3_table = &mut *identity_mapped::<PageTable>(p3_table_address);
+        let end = end.min(page_size(3));
+        let huge_page_flags =
+            user_accessible_page() | PageTableFlags::WRITABLE | PageTableFlags::HUGE_PAGE;
+        let first_gigabyte = Self::INITIAL_VIRTUAL_MEMORY_SIZE / GIGABYTE;
+        for gigabyte in first_gigabyte..end.div_ceil(GIGABYTE) {
+            let address = PhysicalAddress::new(gigabyte * GIGABYTE);
+            if !p3_table[gigabyte].is_unused() {
+                continue;
+            }
+            if let FfiOption::Some(_) = self.allocator.gigabyte_pages {
+                set_entry(p3_table, gigabyte, address, huge_page_flags);
+            } else {
+                let Some(p2_table_addr) = self.allocator.get_4k_frame() else {
+                    return address.as_usize();
+                };
+                let p2_table = &mut *identity_mapped::<PageTable>(p2_table_addr);
+                for index in 0..GIGABYTE / page_size(1) {
+                    set_entry(
+                        p2_table,
+                        index,
+                        address + index * page_size(1),
+                        huge_page_flags,
                     );
-                return;
+                }
+                set_entry(
+                    p3_table,
+                    gigabyte,
+                    p2_table_addr,
+                    user_accessible_page() | PageTableFlags::WRITABLE,
+                );
             }
         }
-        self.allocator
-            .two_megabyte_pages
-            .add_aligned_frames_with_scrap_allocator(
-                &mut self.allocator.four_kilobyte_pages,
-                memory_region.clone(),
-            );
+        end
+    }
+
+    unsafe fn verify_page_table_integrity(&self) -> bool {
+        let (root_page_table, _) = Cr3::read();
+        let boot_root_table = self.boot_page_tables.root();
+        if root_page_table.start_address().as_u64() != ptr::from_ref(boot_root_table) as u64 {
+            return false;
+        }
+        let p3_entry = &boot_root_table[0];
+        if !is_page_table(p3_entry) {
+            return false;
+        }
+        let Some(p3_table_address) = physical_address(p3_entry.addr()) else {
+            return false;
+        };
+        let p3_table = &*identity_mapped::<PageTable>(p3_table_address);
+        let identity_map_intact =
+            (0..Self::INITIAL_VIRTUAL_MEMORY_SIZE / GIGABYTE).all(|gigabyte| {
+                is_identity_mapped(&p3_table[gigabyte], 2, (gigabyte * GIGABYTE) as u64)
+            });
+        let double_fault_stack_mapped = self
+            .boot_page_tables
+            .is_static_stack_mapped(DOUBLE_FAULT_STACK_PAGE);
+        identity_map_intact && double_fault_stack_mapped
     }
 
     unsafe fn copy_into_address_space(
@@ -270,11 +734,29 @@ impl Architecture for Amd64 {
     ) -> Option<()> {
         self.copy_into_address_space(3, root_page_table, address, data, size, flags)
     }
+
+    fn halt() -> ! {
+        loop {
+            hlt();
+        }
+    }
+
+    fn wait_for_interrupt() {
+        // `sti` only takes effect after the next instruction, so nothing can be handled between
+        // enabling interrupts and halting
+        interrupts::enable_and_hlt();
+        interrupts::disable();
+    }
+
+    unsafe fn enable_interrupts() {
+        interrupts::enable();
+    }
 }
 
 struct SegmentSelectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
+    syscall_segments: SyscallSegments,
 }
 
 #[repr(C, align(0x1000))]
@@ -285,20 +767,202 @@ fn load_gdt(
     tss: &'static mut TaskStateSegment,
 ) -> SegmentSelectors {
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = DOUBLE_FAULT_STACK_TOP;
-    tss.privilege_stack_table[0] = INTERRUPT_STACK_BOTTOM;
+    tss.interrupt_stack_table[mce::STACK_IST_INDEX as usize] = mce::stack_top();
+    tss.privilege_stack_table[0] = INTERRUPT_STACK_TOP;
+    let segment_selectors = append_segments(gdt, tss);
+    kernel_assert_eq!(segment_selectors.code_selector, KERNEL_CODE_SELECTOR);
+    kernel_assert_eq!(
+        segment_selectors.syscall_segments.user_data,
+        USER_DATA_SELECTOR
+    );
+    kernel_assert_eq!(
+        segment_selectors.syscall_segments.user_code,
+        USER_CODE_SELECTOR
+    );
+    gdt.load();
+    segment_selectors
+}
+
+/// Adds the kernel's segments, the TSS, and the user segments to `gdt`
+fn append_segments(
+    gdt: &mut GlobalDescriptorTable,
+    tss: &'static TaskStateSegment,
+) -> SegmentSelectors {
+    // `syscall` and `sysret` require the kernel data segment to directly follow the kernel code
+    // segment and the user code segment to directly follow the user data segment
     let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
     let tss_selector = gdt.append(Descriptor::tss_segment(tss));
-    gdt.append(Descriptor::user_data_segment());
-    gdt.append(Descriptor::user_code_segment());
-    gdt.load();
+    let user_data_selector = gdt.append(Descriptor::user_data_segment());
+    let user_code_selector = gdt.append(Descriptor::user_code_segment());
     SegmentSelectors {
         code_selector,
         tss_selector,
+        syscall_segments: SyscallSegments {
+            kernel_code: code_selector,
+            kernel_data: data_selector,
+            user_code: user_code_selector,
+            user_data: user_data_selector,
+        },
+    }
+}
+
+/// Installs every handler that the kernel uses in `idt`
+unsafe fn build_idt(idt: &mut InterruptDescriptorTable) {
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    let double_fault_interrupt = idt.double_fault.set_handler_fn(double_fault_handler);
+    double_fault_interrupt.set_stack_index(DOUBLE_FAULT_IST_INDEX);
+    // The handler returns after corrected errors, which the diverging handler type doesn't allow
+    let machine_check = idt
+        .machine_check
+        .set_handler_addr(VirtAddr::from_ptr(machine_check_handler as *const ()));
+    machine_check.set_stack_index(mce::STACK_IST_INDEX);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    set_interrupt_handlers(idt);
+}
+
+/**
+ * Loads the GDT and IDT that the boot processor built on an application processor. The TSS isn't
+ * loaded because only one processor can use it at a time.
+ *
+ * # Safety
+ *
+ * `initialize_operating_system` must have built the tables.
+ */
+pub unsafe fn load_descriptor_tables() {
+    (*addr_of!(GDT)).load();
+    CS::set_reg(KERNEL_CODE_SELECTOR);
+    (*addr_of!(IDT)).load();
+}
+
+/**
+ * Switches to the address space in `launch` and starts running the process at its entry point in
+ * ring 3 with its stack pointer and arguments in place. Everything is read from `launch` before
+ * switching address spaces since it may not be mapped afterwards.
+ *
+ * # Safety
+ *
+ * `launch` must describe an address space that maps the process's entry point and stack for user
+ * mode, and the GDT that `load_gdt` builds must be loaded.
+ */
+unsafe fn enter_user_process(launch: &ProcessLaunchInfo) -> ! {
+    let [first, second, third, fourth] = launch.arguments;
+    // The kernel's stack isn't mapped in the process's address space, so the stack pointer is
+    // switched along with the address space. The process is entered with `iretq` rather than a
+    // jump so that it can't use privileged instructions. The frame for `iretq` goes on the process's
+    // stack, and `iretq` clears RFLAGS.AC again for the process.
+    let _access = UserAccessGuard::begin();
+    asm!(
+        "mov cr3, {root_page_table}",
+        "mov rsp, {stack_pointer}",
+        "mov ds, {data_selector:x}",
+        "mov es, {data_selector:x}",
+        "mov fs, {data_selector:x}",
+        "mov gs, {data_selector:x}",
+        "push {data_selector}",
+        "push {stack_pointer}",
+        "push {rflags}",
+        "push {code_selector}",
+        "push {entry_point}",
+        "iretq",
+        root_page_table = in(reg) launch.root_page_table_address.as_usize(),
+        stack_pointer = in(reg) launch.stack_pointer.as_usize(),
+        entry_point = in(reg) launch.entry_point.as_usize(),
+        data_selector = in(reg) u64::from(USER_DATA_SELECTOR.0),
+        code_selector = in(reg) u64::from(USER_CODE_SELECTOR.0),
+        rflags = in(reg) USER_RFLAGS,
+        in("rdi") first,
+        in("rsi") second,
+        in("rdx") third,
+        in("rcx") fourth,
+        options(noreturn),
+    )
+}
+
+/**
+ * Returns true if every address in `range` is mapped into the current address space and can be
+ * accessed from user mode.
+ *
+ * # Safety
+ *
+ * The current page tables must be identity mapped.
+ */
+pub unsafe fn is_user_accessible(range: Range<usize>) -> bool {
+    let (root_page_table, _) = Cr3::read();
+    let Some(root_page_table) = physical_address(root_page_table.start_address()) else {
+        return false;
+    };
+    let root_page_table = &*identity_mapped::<PageTable>(root_page_table);
+    let mut address = range.start;
+    while address < range.end {
+        let Some(page_size) = user_page_size(root_page_table, 3, address) else {
+            return false;
+        };
+        match (address & !(page_size - 1)).checked_add(page_size) {
+            Some(next_page) => address = next_page,
+            None => break,
+        }
     }
+    true
 }
 
-fn supports_gigabyte_pages(cpu_info: u32) -> bool {
-    (cpu_info & GIGABYTE_PAGES_CPUID_BIT) != 0
+/// Returns the size of the page that maps `address` if it can be accessed from user mode.
+unsafe fn user_page_size(
+    page_table: &PageTable,
+    page_table_level: u8,
+    address: usize,
+) -> Option<usize> {
+    let entry = &page_table[table_index(page_table_level, address)];
+    if !entry.flags().contains(user_accessible_page()) {
+        None
+    } else if page_table_level == 0 || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        Some(page_size(page_table_level))
+    } else {
+        user_page_size(
+            &*identity_mapped(physical_address(entry.addr())?),
+            page_table_level - 1,
+            address,
+        )
+    }
+}
+
+/// Converts a physical address to a pointer. This relies on the low physical memory being identity
+/// mapped while the operating system boots.
+fn identity_mapped<T>(address: PhysicalAddress) -> *mut T {
+    address.as_usize() as *mut T
+}
+
+/// Converts an address read from a page table entry or `CR3`. Returns `None` if it doesn't fit in
+/// the address space, which only a corrupt entry could cause.
+pub(super) fn physical_address(address: PhysAddr) -> Option<PhysicalAddress> {
+    phys_to_usize(address.as_u64()).map(PhysicalAddress::new)
+}
+
+fn is_page_table(entry: &PageTableEntry) -> bool {
+    let flags = entry.flags();
+    flags.contains(PageTableFlags::PRESENT) && !flags.contains(PageTableFlags::HUGE_PAGE)
+}
+
+/// Returns true if `entry`, in a table at `page_table_level`, identity maps the memory at `address`
+/// with a huge page or with a table of smaller pages
+unsafe fn is_identity_mapped(entry: &PageTableEntry, page_table_level: u8, address: u64) -> bool {
+    let flags = entry.flags();
+    if page_table_level == 0 || flags.contains(PageTableFlags::HUGE_PAGE) {
+        return flags.contains(PageTableFlags::PRESENT) && entry.addr().as_u64() == address;
+    }
+    let Some(table_address) = is_page_table(entry)
+        .then(|| physical_address(entry.addr()))
+        .flatten()
+    else {
+        return false;
+    };
+    let table = &*identity_mapped::<PageTable>(table_address);
+    table.iter().enumerate().all(|(index, entry)| {
+        let offset = index * page_size(page_table_level - 1);
+        is_identity_mapped(entry, page_table_level - 1, address + offset as u64)
+    })
 }
 
 fn conditionally_add_flag(flags: &mut PageTableFlags, condition: bool, new_flag: PageTableFlags) {
@@ -311,20 +975,29 @@ fn user_accessible_page() -> PageTableFlags {
     PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE
 }
 
-fn interrupt_stack_flags() -> PageTableFlags {
-    PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+fn interrupt_stack_flags(no_execute: PageTableFlags) -> PageTableFlags {
+    PageTableFlags::PRESENT | PageTableFlags::WRITABLE | no_execute
 }
 
-fn set_entry(page_table: &mut PageTable, index: usize, address: usize, flags: PageTableFlags) {
-    page_table[index].set_addr(PhysAddr::new_truncate(address as u64), flags);
+fn set_entry(
+    page_table: &mut PageTable,
+    index: usize,
+    address: PhysicalAddress,
+    flags: PageTableFlags,
+) {
+    page_table[index].set_addr(PhysAddr::new_truncate(address.as_usize() as u64), flags);
 }
 
-fn clear_and_set_last_entry(page_table: &mut PageTable, address: usize, flags: PageTableFlags) {
+fn clear_and_set_last_entry(
+    page_table: &mut PageTable,
+    address: PhysicalAddress,
+    flags: PageTableFlags,
+) {
     page_table.zero();
     set_last_entry(page_table, address, flags);
 }
 
-fn set_last_entry(page_table: &mut PageTable, address: usize, flags: PageTableFlags) {
+fn set_last_entry(page_table: &mut PageTable, address: PhysicalAddress, flags: PageTableFlags) {
     set_entry(page_table, 0x1ff, address, flags);
 }
 
@@ -332,18 +1005,7 @@ fn set_interrupt_handlers(idt: &mut InterruptDescriptorTable) {
     idt[InterruptIndex::Timer as u8].set_handler_fn(timer_interrupt_handler);
     idt[InterruptIndex::Spurious as u8].set_handler_fn(spurious_interrupt_handler);
     idt[InterruptIndex::Error as u8].set_handler_fn(error_interrupt_handler);
-}
-
-const fn page_size(page_table_level: u8) -> usize {
-    if page_table_level == 0 {
-        FOUR_KILOBYTES
-    } else {
-        page_size(page_table_level - 1) << 9
-    }
-}
-
-const fn offset_in_page(page_table_level: u8, address: usize) -> usize {
-    address & (page_size(page_table_level) - 1)
+    idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
 }
 
 fn page_table_entries(
@@ -352,26 +1014,15 @@ fn page_table_entries(
     base_address: usize,
     size: usize,
 ) -> impl Iterator<Item = &mut PageTableEntry> {
-    let first_index = page_table_entry(page_table_level, base_address);
-    page_table
-        .iter_mut()
-        .skip(first_index)
-        .take(page_table_entry(page_table_level, base_address + size - 1) + 1 - first_index)
-}
-
-fn number_of_bytes_for_page(
-    page_table_level: u8,
-    page_offset: usize,
-    size: usize,
-    data_offset: usize,
-) -> usize {
-    (page_size(page_table_level) - page_offset).min(size - data_offset)
+    let indices = entry_indices(page_table_level, base_address, size);
+    page_table.iter_mut().take(indices.end).skip(indices.start)
 }
 
 fn set_page_table_entry(
     page_table_entry: &mut PageTableEntry,
-    address: usize,
+    address: PhysicalAddress,
     segment_flags: SegmentFlags,
+    no_execute: PageTableFlags,
 ) {
     let mut page_flags = user_accessible_page();
     conditionally_add_flag(
@@ -379,12 +1030,11 @@ fn set_page_table_entry(
         segment_flags.writable(),
         PageTableFlags::WRITABLE,
     );
-    conditionally_add_flag(
-        &mut page_flags,
-        !segment_flags.executable(),
-        PageTableFlags::NO_EXECUTE,
+    conditionally_add_flag(&mut page_flags, !segment_flags.executable(), no_execute);
+    page_table_entry.set_addr(
+        PhysAddr::new_truncate(address.as_usize() as u64),
+        page_flags,
     );
-    page_table_entry.set_addr(PhysAddr::new_truncate(address as u64), page_flags);
 }
 
 fn update_page_table_entry_flags(
@@ -403,19 +1053,556 @@ fn update_page_table_entry_flags(
     page_table_entry.set_flags(page_flags);
 }
 
-const fn page_table_entry(page_table_level: u8, address: usize) -> usize {
-    (address & page_table_entry_mask(page_table_level))
-        >> page_table_entry_offset_in_address(page_table_level)
-}
+#[cfg(test)]
+mod tests {
+    extern crate std;
 
-const fn page_table_entry_offset_in_address(page_table_level: u8) -> u8 {
-    12 + 9 * page_table_level
-}
+    use super::*;
+    use crate::{amd64::page_walk::translate, ELF_EXECUTABLE_SEGMENT, ELF_WRITABLE_SEGMENT};
+    use frame_allocation::amd64::{Granularity, ENTRIES_PER_TABLE, TWO_MEGABYTES};
+    use std::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+    use test_arena::Arena;
+    use x86_64::registers::rflags::RFlags;
 
-const fn page_table_entry_mask(page_table_level: u8) -> usize {
-    if page_table_level == 0 {
-        0x0000_0000_001f_f000
-    } else {
-        (page_table_entry_mask(page_table_level - 1) << 9) | 0x0000_0000_001f_f000
+    const READ_ONLY: SegmentFlags = SegmentFlags(0);
+    const WRITABLE: SegmentFlags = SegmentFlags(ELF_WRITABLE_SEGMENT);
+    const EXECUTABLE: SegmentFlags = SegmentFlags(ELF_EXECUTABLE_SEGMENT);
+
+    /// The arena frames that aren't given to the allocator: the root table, the boot tables, and
+    /// two spare tables for tests to build by hand
+    const RESERVED_FRAMES: usize = 10;
+
+    struct Segment {
+        address: usize,
+        data: Vec<u8>,
+        size: usize,
+        flags: SegmentFlags,
+    }
+
+    impl Segment {
+        /// A segment whose data is a pattern that's different at every offset and for every seed
+        fn new(
+            address: usize,
+            data_size: usize,
+            size: usize,
+            flags: SegmentFlags,
+            seed: u8,
+        ) -> Self {
+            let data = (0..data_size)
+                .map(|offset| u8::try_from(offset % 251).unwrap() ^ seed)
+                .collect();
+            Self {
+                address,
+                data,
+                size,
+                flags,
+            }
+        }
+    }
+
+    /// What a 4 KB page should hold after the segments are copied
+    struct ExpectedPage {
+        bytes: Vec<u8>,
+        writable: bool,
+        executable: bool,
+    }
+
+    /// An address space in an arena. The first 2 MB of the arena holds page tables and the frames
+    /// given to the allocator, and the second is left free for big pages.
+    struct Fixture {
+        arena: Arena,
+        proc: Amd64,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Self::with_frames(FOUR_KILOBYTE_FRAMES_IN_FIRST_TWO_MEGABYTES - RESERVED_FRAMES)
+        }
+
+        fn with_frames(frames: usize) -> Self {
+            let arena = Arena::new(2 * TWO_MEGABYTES);
+            let frame = |index| arena.frame(FOUR_KILOBYTES, index).as_usize();
+            let mut allocator = Amd64FrameAllocator::new();
+            unsafe {
+                allocator
+                    .four_kilobyte_pages
+                    .add_frames(frame(RESERVED_FRAMES)..frame(RESERVED_FRAMES + frames));
+            }
+            let boot_page_tables = unsafe {
+                BootPageTables::new(
+                    frame(0) as *mut _,
+                    frame(1) as *mut _,
+                    frame(5) as *mut _,
+                    frame(7) as *mut _,
+                )
+            };
+            Self {
+                proc: Amd64 {
+                    allocator,
+                    boot_page_tables,
+                    no_execute: PageTableFlags::NO_EXECUTE,
+                },
+                arena,
+            }
+        }
+
+        fn root(&self) -> *mut PageTable {
+            self.arena.frame(FOUR_KILOBYTES, 0).as_usize() as *mut PageTable
+        }
+
+        fn spare_table(&self, index: usize) -> *mut PageTable {
+            self.arena.frame(FOUR_KILOBYTES, 8 + index).as_usize() as *mut PageTable
+        }
+
+        fn copy(&mut self, segment: &Segment) -> Option<()> {
+            let root = self.root();
+            unsafe {
+                self.proc.copy_into_address_space(
+                    3,
+                    &mut *root,
+                    segment.address,
+                    &segment.data,
+                    segment.size,
+                    segment.flags,
+                )
+            }
+        }
+
+        /// Copies every segment and checks that the address space holds exactly what
+        /// `expected_image` says it should
+        fn copy_and_check(&mut self, segments: &[Segment]) {
+            for segment in segments {
+                self.copy(segment).expect("ran out of frames");
+            }
+            let expected = expected_image(segments);
+            let mut mapped = Vec::new();
+            unsafe { mapped_pages(&*self.root(), 3, 0, &mut mapped) };
+            assert_eq!(mapped, expected.keys().copied().collect::<Vec<_>>());
+            for (&page, expected_page) in &expected {
+                let translation = unsafe { translate(&*self.root(), page) }.unwrap();
+                assert!(self.arena.contains(translation.address, FOUR_KILOBYTES));
+                assert!(
+                    translation.user_accessible,
+                    "{page:#x} isn't user accessible"
+                );
+                assert_eq!(translation.writable, expected_page.writable, "{page:#x}");
+                assert_eq!(
+                    translation.executable, expected_page.executable,
+                    "{page:#x}"
+                );
+                let bytes = unsafe {
+                    slice::from_raw_parts(
+                        translation.address.as_usize() as *const u8,
+                        FOUR_KILOBYTES,
+                    )
+                };
+                if let Some(offset) =
+                    (0..FOUR_KILOBYTES).find(|&i| bytes[i] != expected_page.bytes[i])
+                {
+                    panic!(
+                        "{:#x} holds {:#x} instead of {:#x}",
+                        page + offset,
+                        bytes[offset],
+                        expected_page.bytes[offset]
+                    );
+                }
+            }
+        }
+    }
+
+    const FOUR_KILOBYTE_FRAMES_IN_FIRST_TWO_MEGABYTES: usize = TWO_MEGABYTES / FOUR_KILOBYTES;
+
+    /// Works out what the segments should leave in memory without going through page tables.
+    /// Later segments overwrite earlier ones, including with their zero filled tails, and a page's
+    /// permissions are the union of those of every segment that touches it.
+    fn expected_image(segments: &[Segment]) -> BTreeMap<usize, ExpectedPage> {
+        let mut image = BTreeMap::new();
+        for segment in segments {
+            for offset in 0..segment.size {
+                let address = segment.address + offset;
+                let page = image
+                    .entry(address - address % FOUR_KILOBYTES)
+                    .or_insert_with(|| ExpectedPage {
+                        bytes: vec![0; FOUR_KILOBYTES],
+                        writable: false,
+                        executable: false,
+                    });
+                page.bytes[address % FOUR_KILOBYTES] =
+                    segment.data.get(offset).copied().unwrap_or(0);
+                page.writable |= segment.flags.writable();
+                page.executable |= segment.flags.executable();
+            }
+        }
+        image
+    }
+
+    /// Collects the addresses of the 4 KB pages mapped under `page_table` in address order
+    unsafe fn mapped_pages(page_table: &PageTable, level: u8, base: usize, pages: &mut Vec<usize>) {
+        for (index, entry) in page_table.iter().enumerate() {
+            if !entry.flags().contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            let address = base + index * page_size(level);
+            if level == 0 {
+                pages.push(address);
+            } else {
+                assert!(!entry.flags().contains(PageTableFlags::HUGE_PAGE));
+                let table_address = usize::try_from(entry.addr().as_u64()).unwrap();
+                let table = &*identity_mapped::<PageTable>(PhysicalAddress::new(table_address));
+                mapped_pages(table, level - 1, address, pages);
+            }
+        }
+    }
+
+    #[test]
+    fn unaligned_start() {
+        Fixture::new().copy_and_check(&[Segment::new(0x40_0123, 200, 200, READ_ONLY, 1)]);
+    }
+
+    #[test]
+    fn segment_spanning_a_two_megabyte_boundary() {
+        Fixture::new().copy_and_check(&[Segment::new(0x5f_f800, 0x1000, 0x1000, WRITABLE, 2)]);
+    }
+
+    #[test]
+    fn segment_spanning_a_gigabyte_boundary() {
+        Fixture::new().copy_and_check(&[Segment::new(0x3fff_f800, 0x1000, 0x1000, EXECUTABLE, 3)]);
+    }
+
+    #[test]
+    fn segment_spanning_several_pages() {
+        Fixture::new().copy_and_check(&[Segment::new(0x40_0010, 0x5000, 0x5000, READ_ONLY, 4)]);
+    }
+
+    #[test]
+    fn overlapping_segments_merge_their_permissions() {
+        Fixture::new().copy_and_check(&[
+            Segment::new(0x40_0000, 0x1800, 0x1800, EXECUTABLE, 5),
+            Segment::new(0x40_1000, 0x1000, 0x1000, WRITABLE, 6),
+        ]);
+    }
+
+    #[test]
+    fn bss_tail_is_zero_filled() {
+        Fixture::new().copy_and_check(&[Segment::new(0x40_0000, 0x100, 0x3000, WRITABLE, 7)]);
+    }
+
+    #[test]
+    fn bss_tail_overwrites_earlier_data() {
+        Fixture::new().copy_and_check(&[
+            Segment::new(0x40_0000, 0x2000, 0x2000, READ_ONLY, 8),
+            Segment::new(0x40_0800, 0x10, 0x1000, WRITABLE, 9),
+        ]);
+    }
+
+    #[test]
+    fn zero_size_segment_maps_nothing() {
+        Fixture::new().copy_and_check(&[Segment::new(0x40_0000, 0, 0, WRITABLE, 10)]);
+    }
+
+    #[test]
+    fn segments_are_executable_without_the_no_execute_bit() {
+        let mut fixture = Fixture::new();
+        fixture.proc.no_execute = PageTableFlags::empty();
+        let segment = Segment::new(0x40_0000, 0x10, 0x10, WRITABLE, 12);
+        fixture.copy(&segment).expect("ran out of frames");
+        let translation = unsafe { translate(&*fixture.root(), segment.address) }.unwrap();
+        assert!(translation.writable);
+        assert!(translation.executable);
+    }
+
+    #[test]
+    fn running_out_of_frames_fails() {
+        // The segment needs three tables below the root and a page
+        let mut fixture = Fixture::with_frames(3);
+        assert!(fixture
+            .copy(&Segment::new(0x40_0000, 0x10, 0x10, READ_ONLY, 11))
+            .is_none());
+    }
+
+    #[test]
+    fn big_page_is_reused() {
+        let mut fixture = Fixture::with_frames(0);
+        let big_page = fixture.arena.frame(TWO_MEGABYTES, 1);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        unsafe {
+            set_entry(
+                &mut *fixture.root(),
+                0,
+                PhysicalAddress::new(fixture.spare_table(0) as usize),
+                flags,
+            );
+            set_entry(
+                &mut *fixture.spare_table(0),
+                0,
+                PhysicalAddress::new(fixture.spare_table(1) as usize),
+                flags,
+            );
+            set_entry(
+                &mut *fixture.spare_table(1),
+                1,
+                big_page,
+                flags | PageTableFlags::HUGE_PAGE,
+            );
+        }
+        let segment = Segment::new(0x20_1234, 0x100, 0x200, WRITABLE, 12);
+        // Nothing needs to be allocated, so an empty allocator is enough
+        fixture.copy(&segment).expect("tried to allocate a frame");
+        let translation = unsafe { translate(&*fixture.root(), segment.address) }.unwrap();
+        assert_eq!(translation.address, big_page + 0x1234);
+        assert_eq!(translation.page_size, TWO_MEGABYTES);
+        assert!(translation.writable);
+        let bytes = unsafe {
+            slice::from_raw_parts(translation.address.as_usize() as *const u8, segment.size)
+        };
+        assert_eq!(&bytes[..segment.data.len()], &segment.data[..]);
+        assert!(bytes[segment.data.len()..].iter().all(|&byte| byte == 0));
+    }
+
+    /// The gigabytes that boot.asm identity maps before the kernel starts
+    const INITIAL_GIGABYTES: usize = Amd64::INITIAL_VIRTUAL_MEMORY_SIZE / GIGABYTE;
+
+    impl Fixture {
+        /// Puts an identity map of the first `INITIAL_GIGABYTES` gigabytes in the first spare
+        /// table and points the first entry of the root table at it, the way boot.asm leaves them
+        fn with_initial_identity_map(mut self, gigabyte_pages: bool) -> Self {
+            if gigabyte_pages {
+                self.proc.allocator.gigabyte_pages = FfiOption::Some(FrameAllocator::new());
+            }
+            let p3_table = PhysicalAddress::new(self.spare_table(0) as usize);
+            unsafe {
+                set_entry(
+                    &mut *self.root(),
+                    0,
+                    p3_table,
+                    user_accessible_page() | PageTableFlags::WRITABLE,
+                );
+                for gigabyte in 0..INITIAL_GIGABYTES {
+                    set_entry(
+                        &mut *self.spare_table(0),
+                        gigabyte,
+                        PhysicalAddress::new(gigabyte * GIGABYTE),
+                        user_accessible_page()
+                            | PageTableFlags::WRITABLE
+                            | PageTableFlags::HUGE_PAGE,
+                    );
+                }
+            }
+            self
+        }
+
+        fn extend_identity_map(&mut self, end: usize) -> usize {
+            unsafe { self.proc.extend_identity_map(end) }
+        }
+
+        /// The gigabytes that have an entry in the identity map's table, in order
+        fn populated_gigabytes(&self) -> Vec<usize> {
+            let p3_table = unsafe { &*self.spare_table(0) };
+            (0..ENTRIES_PER_TABLE)
+                .filter(|&gigabyte| !p3_table[gigabyte].is_unused())
+                .collect()
+        }
+
+        fn free_4k_frames(&mut self) -> usize {
+            let mut frames = Vec::new();
+            while let Some(frame) = unsafe { self.proc.allocator.get_4k_frame() } {
+                frames.push(frame);
+            }
+            for &frame in frames.iter().rev() {
+                unsafe { self.proc.allocator.four_kilobyte_pages.add_frame(frame) };
+            }
+            frames.len()
+        }
+
+        /// Checks that `gigabyte` is identity mapped for the kernel and user mode with pages of
+        /// `page_size`
+        fn check_identity_mapped(&self, gigabyte: usize, page_size: usize) {
+            let start = gigabyte * GIGABYTE;
+            for address in [start, start + 0x1234_5678, start + GIGABYTE - 1] {
+                let translation = unsafe { translate(&*self.root(), address) }
+                    .unwrap_or_else(|| panic!("{address:#x} isn't mapped"));
+                assert_eq!(translation.address, PhysicalAddress::new(address));
+                assert_eq!(translation.page_size, page_size, "{address:#x}");
+                assert!(translation.writable, "{address:#x}");
+                assert!(translation.user_accessible, "{address:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn identity_map_is_extended_with_gigabyte_pages() {
+        let mut fixture = Fixture::with_frames(0).with_initial_identity_map(true);
+        assert_eq!(fixture.extend_identity_map(7 * GIGABYTE), 7 * GIGABYTE);
+        assert_eq!(fixture.populated_gigabytes(), (0..7).collect::<Vec<_>>());
+        for gigabyte in INITIAL_GIGABYTES..7 {
+            fixture.check_identity_mapped(gigabyte, GIGABYTE);
+        }
+    }
+
+    #[test]
+    fn identity_map_is_extended_with_two_megabyte_pages() {
+        let mut fixture = Fixture::with_frames(3).with_initial_identity_map(false);
+        assert_eq!(fixture.extend_identity_map(7 * GIGABYTE), 7 * GIGABYTE);
+        assert_eq!(fixture.populated_gigabytes(), (0..7).collect::<Vec<_>>());
+        assert_eq!(fixture.free_4k_frames(), 0);
+        for gigabyte in INITIAL_GIGABYTES..7 {
+            fixture.check_identity_mapped(gigabyte, TWO_MEGABYTES);
+        }
+    }
+
+    #[test]
+    fn identity_map_covers_the_whole_last_gigabyte() {
+        let mut fixture = Fixture::with_frames(2).with_initial_identity_map(false);
+        let end = 5 * GIGABYTE + FOUR_KILOBYTES;
+        // The end that's returned is the one asked for even though the map goes further
+        assert_eq!(fixture.extend_identity_map(end), end);
+        assert_eq!(fixture.populated_gigabytes(), (0..6).collect::<Vec<_>>());
+        fixture.check_identity_mapped(5, TWO_MEGABYTES);
+    }
+
+    #[test]
+    fn identity_map_stops_at_the_first_gigabyte_without_a_table() {
+        let mut fixture = Fixture::with_frames(1).with_initial_identity_map(false);
+        assert_eq!(fixture.extend_identity_map(8 * GIGABYTE), 5 * GIGABYTE);
+        assert_eq!(fixture.populated_gigabytes(), (0..5).collect::<Vec<_>>());
+        fixture.check_identity_mapped(4, TWO_MEGABYTES);
+    }
+
+    #[test]
+    fn identity_map_resumes_after_running_out_of_frames() {
+        let mut fixture = Fixture::with_frames(1).with_initial_identity_map(false);
+        assert_eq!(fixture.extend_identity_map(8 * GIGABYTE), 5 * GIGABYTE);
+        let p3_table = unsafe { &*fixture.spare_table(0) };
+        let first_table = p3_table[4].addr();
+
+        let frame = |index| fixture.arena.frame(FOUR_KILOBYTES, index).as_usize();
+        let more_frames = frame(RESERVED_FRAMES + 1)..frame(RESERVED_FRAMES + 4);
+        let allocator = &mut fixture.proc.allocator.four_kilobyte_pages;
+        unsafe { allocator.add_frames(more_frames) };
+        assert_eq!(fixture.extend_identity_map(8 * GIGABYTE), 8 * GIGABYTE);
+        assert_eq!(fixture.populated_gigabytes(), (0..8).collect::<Vec<_>>());
+        // Gigabytes that were already mapped keep their tables
+        assert_eq!(p3_table[4].addr(), first_table);
+        assert_eq!(fixture.free_4k_frames(), 0);
+        for gigabyte in INITIAL_GIGABYTES..8 {
+            fixture.check_identity_mapped(gigabyte, TWO_MEGABYTES);
+        }
+    }
+
+    #[test]
+    fn identity_map_skips_gigabytes_that_are_already_mapped() {
+        let mut fixture = Fixture::with_frames(2).with_initial_identity_map(false);
+        let existing = PhysicalAddress::new(fixture.spare_table(1) as usize);
+        unsafe {
+            set_entry(
+                &mut *fixture.spare_table(0),
+                5,
+                existing,
+                user_accessible_page() | PageTableFlags::WRITABLE,
+            );
+        }
+        assert_eq!(fixture.extend_identity_map(7 * GIGABYTE), 7 * GIGABYTE);
+        assert_eq!(fixture.populated_gigabytes(), (0..7).collect::<Vec<_>>());
+        let p3_table = unsafe { &*fixture.spare_table(0) };
+        assert_eq!(p3_table[5].addr().as_u64(), existing.as_usize() as u64);
+        assert_eq!(fixture.free_4k_frames(), 0);
+    }
+
+    #[test]
+    fn identity_map_is_limited_to_the_first_root_entry() {
+        let mut fixture = Fixture::with_frames(0).with_initial_identity_map(true);
+        let limit = page_size(3);
+        assert_eq!(fixture.extend_identity_map(limit + GIGABYTE), limit);
+        assert_eq!(
+            fixture.populated_gigabytes(),
+            (0..ENTRIES_PER_TABLE).collect::<Vec<_>>()
+        );
+        fixture.check_identity_mapped(ENTRIES_PER_TABLE - 1, GIGABYTE);
+        assert!(unsafe { translate(&*fixture.root(), limit) }.is_none());
+    }
+
+    #[test]
+    fn identity_map_below_the_initial_size_is_left_alone() {
+        let mut fixture = Fixture::with_frames(1).with_initial_identity_map(false);
+        assert_eq!(fixture.extend_identity_map(2 * GIGABYTE), 2 * GIGABYTE);
+        assert_eq!(
+            fixture.populated_gigabytes(),
+            (0..INITIAL_GIGABYTES).collect::<Vec<_>>()
+        );
+        assert_eq!(fixture.free_4k_frames(), 1);
+    }
+
+    #[test]
+    fn processes_reach_the_identity_map_that_the_kernel_keeps_to_itself() {
+        let mut fixture = Fixture::new().with_initial_identity_map(false);
+        unsafe { fixture.proc.boot_page_tables.make_root_supervisor_only() };
+        let (root, _) = unsafe { fixture.proc.initialize_process_page_tables() }.unwrap();
+        let address = 0x1234_5678;
+        let kernel_view = unsafe { translate(&*fixture.root(), address) }.unwrap();
+        let process_view = unsafe { translate(&*root, address) }.unwrap();
+        assert!(!kernel_view.user_accessible);
+        assert!(process_view.user_accessible);
+        assert_eq!(process_view.address, kernel_view.address);
+    }
+
+    #[test]
+    fn memory_after_the_last_gigabyte_page_is_registered() {
+        // Registering a region with a whole gigabyte in it used to drop everything after the last
+        // gigabyte page, because the tail was passed on as an empty range
+        let mut fixture = Fixture::with_frames(0);
+        fixture.proc.allocator.gigabyte_pages = FfiOption::Some(FrameAllocator::new());
+        // Only the first page of each free frame is written, so most of the arena is never touched
+        let arena = Arena::new(2 * GIGABYTE + 2 * TWO_MEGABYTES);
+        let gigabyte_page = arena.range().start.next_multiple_of(GIGABYTE);
+        let region = gigabyte_page..gigabyte_page + GIGABYTE + TWO_MEGABYTES + 2 * FOUR_KILOBYTES;
+        assert!(region.end <= arena.range().end);
+        unsafe { fixture.proc.register_memory_region(region) };
+        let allocator = &fixture.proc.allocator;
+        assert_eq!(
+            allocator.bytes_free_at_granularity(Granularity::Gigabyte),
+            GIGABYTE
+        );
+        assert_eq!(
+            allocator.bytes_free_at_granularity(Granularity::TwoMegabyte),
+            TWO_MEGABYTES
+        );
+        assert_eq!(
+            allocator.bytes_free_at_granularity(Granularity::FourKilobyte),
+            2 * FOUR_KILOBYTES
+        );
+    }
+
+    #[test]
+    fn segments_are_where_entering_user_mode_expects_them() {
+        let mut gdt = GlobalDescriptorTable::new();
+        let tss = Box::leak(Box::new(TaskStateSegment::new()));
+        let selectors = append_segments(&mut gdt, tss);
+        let segments = &selectors.syscall_segments;
+        assert_eq!(selectors.code_selector, KERNEL_CODE_SELECTOR);
+        assert_eq!(segments.kernel_code, KERNEL_CODE_SELECTOR);
+        assert_eq!(segments.user_data, USER_DATA_SELECTOR);
+        assert_eq!(segments.user_code, USER_CODE_SELECTOR);
+        // `sysret` finds the user segments from the kernel's, so they have to be in this order
+        assert_eq!(
+            segments.kernel_data.index(),
+            segments.kernel_code.index() + 1
+        );
+        assert_eq!(segments.user_code.index(), segments.user_data.index() + 1);
+        assert_eq!(selectors.tss_selector.rpl(), PrivilegeLevel::Ring0);
+    }
+
+    #[test]
+    fn user_selectors_request_ring_3() {
+        assert_eq!(KERNEL_CODE_SELECTOR.rpl(), PrivilegeLevel::Ring0);
+        assert_eq!(USER_DATA_SELECTOR.rpl(), PrivilegeLevel::Ring3);
+        assert_eq!(USER_CODE_SELECTOR.rpl(), PrivilegeLevel::Ring3);
+    }
+
+    #[test]
+    fn processes_start_with_only_interrupts_enabled() {
+        let flags = RFlags::from_bits_retain(USER_RFLAGS);
+        assert!(flags.contains(RFlags::INTERRUPT_FLAG));
+        // Bit 1 is reserved and always set
+        assert_eq!(flags.bits() & !RFlags::INTERRUPT_FLAG.bits(), 0b10);
     }
 }
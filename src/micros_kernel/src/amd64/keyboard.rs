@@ -0,0 +1,85 @@
+use super::memory_service;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use micros_abi::{
+    keyboard::KeyEvent,
+    ring::{Message, MESSAGE_DUMP_OWNERS},
+};
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+
+/// Scan codes in scan code set 1 that matter beyond the character they produce
+const LEFT_SHIFT: u8 = 0x2a;
+const RIGHT_SHIFT: u8 = 0x36;
+const LEFT_CONTROL: u8 = 0x1d;
+/// Set on the scan code of a key being released
+const RELEASED: u8 = 0x80;
+
+/// The characters produced by the scan codes in scan code set 1 with and without shift held down.
+/// Zero means the key doesn't produce a character.
+const UNSHIFTED: &[u8; 0x3a] =
+    b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFTED: &[u8; 0x3a] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// Control+O asks the memory manager who owns what
+const DUMP_OWNERS_KEY: u8 = b'o';
+
+static SHIFT_HELD: AtomicBool = AtomicBool::new(false);
+static CONTROL_HELD: AtomicBool = AtomicBool::new(false);
+/// The number of key presses that couldn't be sent because the memory manager wasn't keeping up
+static DROPPED_KEYS: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the scan code that the keyboard interrupted for
+pub unsafe fn read_scancode() -> u8 {
+    Port::new(DATA_PORT).read()
+}
+
+/**
+ * Turns a scan code into a character and sends it to the memory manager. This runs in the
+ * interrupt handler, so key presses are dropped and counted rather than waiting for room in the
+ * ring buffer.
+ */
+pub fn handle_scancode(scancode: u8) {
+    match scancode {
+        LEFT_SHIFT | RIGHT_SHIFT => SHIFT_HELD.store(true, Ordering::Relaxed),
+        _ if scancode == LEFT_SHIFT | RELEASED || scancode == RIGHT_SHIFT | RELEASED => {
+            SHIFT_HELD.store(false, Ordering::Relaxed);
+        }
+        LEFT_CONTROL => CONTROL_HELD.store(true, Ordering::Relaxed),
+        _ if scancode == LEFT_CONTROL | RELEASED => CONTROL_HELD.store(false, Ordering::Relaxed),
+        _ => {
+            let Some(character) = character(scancode) else {
+                return;
+            };
+            let message = if CONTROL_HELD.load(Ordering::Relaxed) {
+                if character.to_ascii_lowercase() != DUMP_OWNERS_KEY {
+                    return;
+                }
+                Message::new(MESSAGE_DUMP_OWNERS)
+            } else {
+                KeyEvent {
+                    character,
+                    dropped: DROPPED_KEYS.load(Ordering::Relaxed),
+                }
+                .to_message()
+            };
+            if !memory_service::send(&message) {
+                DROPPED_KEYS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// The character that a key press produces, taking shift into account
+fn character(scancode: u8) -> Option<u8> {
+    let characters = if SHIFT_HELD.load(Ordering::Relaxed) {
+        SHIFTED
+    } else {
+        UNSHIFTED
+    };
+    characters
+        .get(usize::from(scancode))
+        .copied()
+        .filter(|&character| character != 0)
+}
@@ -0,0 +1,359 @@
+//! Machine check exceptions. The processor reports hardware errors through banks of MSRs, and
+//! raises #MC for the errors it can't correct on its own. Without CR4.MCE a machine check shuts the
+//! processor down, so the kernel turns it on to at least report what went wrong.
+
+use super::{cpu::CpuFeatures, emergency_log};
+use core::{fmt, ptr::addr_of};
+use x86_64::{
+    registers::{
+        control::{Cr4, Cr4Flags},
+        model_specific::Msr,
+    },
+    structures::idt::InterruptStackFrame,
+    VirtAddr,
+};
+
+/// The IST entry that the #MC handler runs on. The double fault handler has entry 0.
+pub const STACK_IST_INDEX: u16 = 1;
+const STACK_SIZE: usize = 0x1000;
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MCG_CTL: u32 = 0x17b;
+const IA32_MC0_CTL: u32 = 0x400;
+/// Each bank has a CTL, STATUS, ADDR, and MISC register in that order
+const BANK_REGISTERS: u32 = 4;
+const STATUS_REGISTER: u32 = 1;
+const ADDR_REGISTER: u32 = 2;
+const MISC_REGISTER: u32 = 3;
+
+/// The number of banks is in the low byte of `IA32_MCG_CAP`
+const BANK_COUNT_MASK: u64 = 0xff;
+/// `IA32_MCG_CTL` exists
+const MCG_CTL_PRESENT: u64 = 1 << 8;
+
+/// The interrupted instruction can be restarted
+const RESTART_IP_VALID: u64 = 1;
+/// The interrupted instruction is the one that caused the error
+const ERROR_IP_VALID: u64 = 1 << 1;
+/// A machine check is being handled. Another machine check while this is set shuts the processor
+/// down.
+const MACHINE_CHECK_IN_PROGRESS: u64 = 1 << 2;
+
+const STATUS_VALID: u64 = 1 << 63;
+/// Another error came in while this one was still valid
+const STATUS_OVERFLOW: u64 = 1 << 62;
+/// The processor didn't correct the error
+const STATUS_UNCORRECTED: u64 = 1 << 61;
+/// The error was enabled in the bank's CTL register
+const STATUS_ENABLED: u64 = 1 << 60;
+const STATUS_MISC_VALID: u64 = 1 << 59;
+const STATUS_ADDR_VALID: u64 = 1 << 58;
+/// The processor's state may be corrupt
+const STATUS_CONTEXT_CORRUPT: u64 = 1 << 57;
+
+/// Part of compound error codes that says whether the error was filtered, which doesn't change
+/// what kind of error it is
+const CORRECTION_REPORT_FILTERING: u16 = 1 << 12;
+
+static mut STACK: Stack = Stack([0; STACK_SIZE]);
+
+#[repr(C, align(0x1000))]
+struct Stack([u8; STACK_SIZE]);
+
+/// The top of the #MC handler's stack. It's in the identity map, like the syscall stack, so that
+/// it's mapped in every address space.
+pub fn stack_top() -> VirtAddr {
+    VirtAddr::new(addr_of!(STACK) as u64 + STACK_SIZE as u64)
+}
+
+/**
+ * Turns on every error reporting bank, clears out errors left over from before boot after logging
+ * them, and enables #MC. Processors without the machine check architecture can still raise #MC,
+ * and so can virtual machines that have no banks. Only the boot processor enables #MC, since the
+ * application processors don't load the TSS that holds the handler's stack.
+ *
+ * # Safety
+ *
+ * The #MC handler must be installed, and its IST stack must be in the loaded TSS.
+ */
+pub unsafe fn init(cpu_features: &CpuFeatures) {
+    if !cpu_features.mce {
+        info!("The processor doesn't have machine check exceptions");
+        return;
+    }
+    let banks = if cpu_features.mca {
+        let capabilities = Msr::new(IA32_MCG_CAP).read();
+        if capabilities & MCG_CTL_PRESENT != 0 {
+            Msr::new(IA32_MCG_CTL).write(u64::MAX);
+        }
+        bank_count(capabilities)
+    } else {
+        0
+    };
+    for bank in 0..banks {
+        let status = BankStatus(bank_register(bank, STATUS_REGISTER).read());
+        if status.valid() {
+            warn!("Machine check bank {bank} held an error from before boot: {status}");
+        }
+        bank_register(bank, 0).write(u64::MAX);
+        bank_register(bank, STATUS_REGISTER).write(0);
+    }
+    Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    info!("Machine checks enabled with {banks} reporting banks");
+}
+
+/**
+ * Reports a machine check and clears the banks that reported it. Returns true if every error was
+ * corrected and the interrupted code can carry on. This writes straight to the serial port, since
+ * the log may be locked by the code that was interrupted.
+ *
+ * # Safety
+ *
+ * This must only be called from the #MC handler.
+ */
+pub unsafe fn report(stack_frame: &InterruptStackFrame) -> bool {
+    let global_status = Msr::new(IA32_MCG_STATUS).read();
+    emergency_log(format_args!(
+        "Machine check at {:#x}: {}",
+        stack_frame.instruction_pointer.as_u64(),
+        GlobalStatus(global_status)
+    ));
+    let mut corrected = true;
+    let banks = if CpuFeatures::detect().mca {
+        bank_count(Msr::new(IA32_MCG_CAP).read())
+    } else {
+        0
+    };
+    for bank in 0..banks {
+        let status = BankStatus(bank_register(bank, STATUS_REGISTER).read());
+        if !status.valid() {
+            continue;
+        }
+        emergency_log(format_args!("  Bank {bank}: {status}"));
+        if status.addr_valid() {
+            let address = bank_register(bank, ADDR_REGISTER).read();
+            emergency_log(format_args!("    Address {address:#x}"));
+        }
+        if status.misc_valid() {
+            let misc = bank_register(bank, MISC_REGISTER).read();
+            emergency_log(format_args!("    Misc {misc:#x}"));
+        }
+        corrected &= !status.uncorrected() && !status.context_corrupt();
+        bank_register(bank, STATUS_REGISTER).write(0);
+    }
+    let recovered = corrected && global_status & RESTART_IP_VALID != 0;
+    if recovered {
+        // Another machine check while MCIP is set would shut the processor down
+        Msr::new(IA32_MCG_STATUS).write(global_status & !MACHINE_CHECK_IN_PROGRESS);
+    }
+    recovered
+}
+
+fn bank_count(capabilities: u64) -> u32 {
+    (capabilities & BANK_COUNT_MASK) as u32
+}
+
+fn bank_register(bank: u32, register: u32) -> Msr {
+    Msr::new(IA32_MC0_CTL + bank * BANK_REGISTERS + register)
+}
+
+/// `IA32_MCG_STATUS`
+struct GlobalStatus(u64);
+
+impl fmt::Display for GlobalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MCG_STATUS {:#x}", self.0)?;
+        for (bit, name) in [
+            (RESTART_IP_VALID, "restartable"),
+            (ERROR_IP_VALID, "at the faulting instruction"),
+            (MACHINE_CHECK_IN_PROGRESS, "in progress"),
+        ] {
+            if self.0 & bit != 0 {
+                write!(f, ", {name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A bank's `IA32_MCi_STATUS` register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BankStatus(u64);
+
+impl BankStatus {
+    fn valid(self) -> bool {
+        self.0 & STATUS_VALID != 0
+    }
+
+    fn overflow(self) -> bool {
+        self.0 & STATUS_OVERFLOW != 0
+    }
+
+    fn uncorrected(self) -> bool {
+        self.0 & STATUS_UNCORRECTED != 0
+    }
+
+    fn enabled(self) -> bool {
+        self.0 & STATUS_ENABLED != 0
+    }
+
+    fn misc_valid(self) -> bool {
+        self.0 & STATUS_MISC_VALID != 0
+    }
+
+    fn addr_valid(self) -> bool {
+        self.0 & STATUS_ADDR_VALID != 0
+    }
+
+    fn context_corrupt(self) -> bool {
+        self.0 & STATUS_CONTEXT_CORRUPT != 0
+    }
+
+    /// The architectural error code, which `error_kind` decodes
+    fn error_code(self) -> u16 {
+        (self.0 & 0xffff) as u16
+    }
+
+    /// The model-specific error code, which only means something with the processor's manual
+    fn model_error_code(self) -> u16 {
+        (self.0 >> 16 & 0xffff) as u16
+    }
+}
+
+impl fmt::Display for BankStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} error ({:#06x}, model code {:#06x})",
+            error_kind(self.error_code()),
+            self.error_code(),
+            self.model_error_code()
+        )?;
+        for (set, name) in [
+            (self.uncorrected(), "uncorrected"),
+            (!self.uncorrected(), "corrected"),
+            (self.overflow(), "overflowed"),
+            (self.context_corrupt(), "processor context corrupt"),
+            (!self.enabled(), "not enabled"),
+        ] {
+            if set {
+                write!(f, ", {name}")?;
+            }
+        }
+        write!(f, " (status {:#018x})", self.0)
+    }
+}
+
+/**
+ * What kind of error an architectural error code is, following the encodings in the Intel SDM
+ * volume 3, section 16.9. Compound error codes carry more detail about the transaction and cache
+ * level, which is left to the raw code in the report.
+ */
+fn error_kind(code: u16) -> &'static str {
+    let compound = code & !CORRECTION_REPORT_FILTERING;
+    if compound & 0xf800 == 0x0800 {
+        "bus or interconnect"
+    } else if code & 0xfc00 == 0x0400 {
+        if code == 0x0400 {
+            "internal timer"
+        } else {
+            "internal unclassified"
+        }
+    } else if compound & 0xff00 == 0x0100 {
+        "cache hierarchy"
+    } else if compound & 0xff80 == 0x0080 {
+        "memory controller"
+    } else if compound & 0xfff0 == 0x0010 {
+        "TLB"
+    } else {
+        match code {
+            0x0000 => "no",
+            0x0001 => "unclassified",
+            0x0002 => "microcode ROM parity",
+            0x0003 => "external",
+            0x0004 => "functional redundancy check",
+            0x0005 => "internal parity",
+            0x0006 => "SMM handler code access violation",
+            _ => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn decodes_status_bits() {
+        // An uncorrected internal timer error, as logged when a core stops responding
+        let timeout = BankStatus(0xfe00_0000_0080_0400);
+        assert!(timeout.valid());
+        assert!(timeout.overflow());
+        assert!(timeout.uncorrected());
+        assert!(timeout.enabled());
+        assert!(timeout.misc_valid());
+        assert!(timeout.addr_valid());
+        assert!(timeout.context_corrupt());
+        assert_eq!(timeout.error_code(), 0x0400);
+        assert_eq!(timeout.model_error_code(), 0x0080);
+
+        // A corrected memory read error with an address and no overflow
+        let corrected = BankStatus(0x8c00_0040_0001_0090);
+        assert!(corrected.valid());
+        assert!(!corrected.overflow());
+        assert!(!corrected.uncorrected());
+        assert!(!corrected.enabled());
+        assert!(corrected.misc_valid());
+        assert!(corrected.addr_valid());
+        assert!(!corrected.context_corrupt());
+        assert_eq!(corrected.error_code(), 0x0090);
+        assert_eq!(corrected.model_error_code(), 0x0001);
+
+        assert!(!BankStatus(0).valid());
+    }
+
+    #[test]
+    fn simple_error_codes() {
+        assert_eq!(error_kind(0x0000), "no");
+        assert_eq!(error_kind(0x0001), "unclassified");
+        assert_eq!(error_kind(0x0005), "internal parity");
+        assert_eq!(error_kind(0x0400), "internal timer");
+        assert_eq!(error_kind(0x0405), "internal unclassified");
+        assert_eq!(error_kind(0x0007), "unknown");
+    }
+
+    #[test]
+    fn compound_error_codes() {
+        // Level 0 data TLB
+        assert_eq!(error_kind(0x0014), "TLB");
+        // Level 1 data cache read
+        assert_eq!(error_kind(0x0135), "cache hierarchy");
+        // Memory controller read on channel 0, with and without filtering
+        assert_eq!(error_kind(0x0090), "memory controller");
+        assert_eq!(error_kind(0x1090), "memory controller");
+        // Bus error from a local processor timing out on a memory read
+        assert_eq!(error_kind(0x0e0f), "bus or interconnect");
+    }
+
+    #[test]
+    fn formats_a_report() {
+        assert_eq!(
+            BankStatus(0x8c00_0040_0001_0090).to_string(),
+            "memory controller error (0x0090, model code 0x0001), corrected, not enabled \
+             (status 0x8c00004000010090)"
+        );
+        assert_eq!(
+            BankStatus(0xfe00_0000_0080_0400).to_string(),
+            "internal timer error (0x0400, model code 0x0080), uncorrected, overflowed, processor \
+             context corrupt (status 0xfe00000000800400)"
+        );
+        assert_eq!(
+            GlobalStatus(0b101).to_string(),
+            "MCG_STATUS 0x5, restartable, in progress"
+        );
+    }
+}
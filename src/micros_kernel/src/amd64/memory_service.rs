@@ -0,0 +1,107 @@
+use super::user_access::UserAccessGuard;
+use core::{
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering},
+};
+use frame_allocation::FfiResult;
+use micros_abi::{
+    frame_service::{AllocFramesRequest, AllocFramesResponse, KERNEL_CALLER},
+    ring::{Message, RingBuffer},
+};
+
+/// The ring buffer that requests and input are sent to the memory manager through. The kernel is
+/// its only producer, and only pushes to it from interrupt handlers once the memory manager is
+/// running. Interrupt handlers don't nest, so there's only ever one push in progress. Both ring
+/// buffers are the memory manager's memory, so they're only touched under a `UserAccessGuard`.
+static EVENTS: AtomicPtr<RingBuffer> = AtomicPtr::new(null_mut());
+/// The ring buffer that the memory manager answers through
+static REPLIES: AtomicPtr<RingBuffer> = AtomicPtr::new(null_mut());
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(0);
+static FIRST_REQUEST_SENT: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Starts talking to the memory manager through `events` and `replies`
+ *
+ * # Safety
+ *
+ * Both ring buffers must stay mapped for as long as the kernel runs, and nothing else in the
+ * kernel may push to `events` or pop from `replies` afterwards.
+ */
+pub unsafe fn init(events: *mut RingBuffer, replies: *mut RingBuffer) {
+    EVENTS.store(events, Ordering::Release);
+    REPLIES.store(replies, Ordering::Release);
+}
+
+/// Sends `message` to the memory manager without waiting. Returns false if it couldn't be sent
+/// because the memory manager isn't running yet or hasn't kept up.
+pub fn send(message: &Message) -> bool {
+    let _access = UserAccessGuard::begin();
+    unsafe { EVENTS.load(Ordering::Acquire).as_ref() }
+        .is_some_and(|events| unsafe { events.try_push(message) })
+}
+
+/// Returns true if the memory manager has events that it hasn't popped yet
+pub fn events_pending() -> bool {
+    let _access = UserAccessGuard::begin();
+    unsafe { EVENTS.load(Ordering::Acquire).as_ref() }.is_some_and(|events| !events.is_empty())
+}
+
+/// Asks the memory manager for `count` contiguous frames on the kernel's behalf. Returns the id
+/// of the request, or `None` if it couldn't be sent.
+pub fn request_frames(count: usize, max_order: u8) -> Option<u32> {
+    let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    let request = AllocFramesRequest {
+        caller: KERNEL_CALLER,
+        request_id,
+        count,
+        max_order,
+    };
+    send(&request.to_message()).then_some(request_id)
+}
+
+/// Logs every answer that the memory manager has sent since the last call
+pub fn handle_replies() {
+    let Some(replies) = (unsafe { REPLIES.load(Ordering::Acquire).as_ref() }) else {
+        return;
+    };
+    while let Some(message) = {
+        let _access = UserAccessGuard::begin();
+        unsafe { replies.try_pop() }
+    } {
+        match AllocFramesResponse::from_message(&message) {
+            Some(AllocFramesResponse {
+                request_id,
+                result: FfiResult::Ok(address),
+                count,
+            }) => info!(
+                "The memory manager allocated {count} frames at {:#x} for request {request_id}",
+                address.as_usize()
+            ),
+            Some(AllocFramesResponse {
+                request_id,
+                result: FfiResult::Err(error),
+                ..
+            }) => warn!(
+                "The memory manager couldn't allocate frames for request {request_id}: {error:?}"
+            ),
+            None => warn!(
+                "Unexpected message {} from the memory manager",
+                message.kind
+            ),
+        }
+    }
+}
+
+/// Called on every timer interrupt. Asks for a frame once the memory manager is running to check
+/// that requests make it there and back.
+pub fn on_timer_tick() {
+    if !EVENTS.load(Ordering::Acquire).is_null()
+        && !FIRST_REQUEST_SENT.swap(true, Ordering::Relaxed)
+    {
+        match request_frames(1, 0) {
+            Some(request_id) => debug!("Sent frame request {request_id} to the memory manager"),
+            None => warn!("Failed to send a frame request to the memory manager"),
+        }
+    }
+    handle_replies();
+}
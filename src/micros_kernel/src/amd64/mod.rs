@@ -1,53 +1,170 @@
 mod apic;
-mod elf;
+mod boot_page_tables;
+mod cmos;
+mod cpu;
 mod init;
+mod keyboard;
+mod mce;
+mod memory_service;
+#[cfg(any(test, feature = "qemu-test"))]
+mod page_walk;
+mod pci;
+mod pit;
+mod power;
+#[cfg(feature = "qemu-test")]
+mod self_checks;
+mod serial;
+mod smp;
+mod syscall;
+mod time;
+mod user_access;
 
+#[cfg(not(test))]
+use crate::panic::{format_panic, PanicReport, SavedRegs};
+use crate::Architecture;
 use apic::end_interrupt;
+#[cfg(not(test))]
+use core::arch::asm;
+use core::fmt::{self, Write};
+#[cfg(not(test))]
 use core::panic::PanicInfo;
-use frame_allocation::amd64::Amd64FrameAllocator;
-pub use init::initialize_operating_system;
-use x86_64::{
-    instructions::hlt,
-    structures::{
-        idt::{InterruptStackFrame, PageFaultErrorCode},
-        paging::PageTable,
-    },
-};
+pub use init::{initialize_operating_system, Amd64};
+pub use power::on_fatal_error;
+use serial::{SerialPort, COM1};
+use x86_64::registers::control::Cr2;
+#[cfg(not(test))]
+use x86_64::registers::{control::Cr3, rflags};
+use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
 
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    halt()
+fn panic(info: &PanicInfo) -> ! {
+    // A panic during a test is reported as that test failing, and ends the test run rather than
+    // leaving QEMU to time out
+    #[cfg(feature = "qemu-test")]
+    if crate::self_test::report_panic(info) {
+        self_checks::exit_qemu(false);
+    }
+    let registers = panic_registers();
+    if !crate::panic::write_panic_to_log(info, &registers) {
+        let mut serial_port = SerialPort::new(COM1);
+        unsafe {
+            serial_port.init();
+        }
+        let message = info.message();
+        let _ = format_panic(
+            &PanicReport::new(info, &message),
+            &SavedRegs {
+                registers: &registers,
+            },
+            &mut serial_port,
+        );
+    }
+    #[cfg(feature = "qemu-test")]
+    self_checks::exit_qemu(false);
+    #[cfg(not(feature = "qemu-test"))]
+    Amd64::halt()
 }
 
-pub fn halt() -> ! {
-    loop {
-        hlt();
+/// The registers that say where the kernel was and what it was looking at when it panicked
+#[cfg(not(test))]
+fn panic_registers() -> [(&'static str, u64); 5] {
+    let (stack_pointer, frame_pointer): (u64, u64);
+    unsafe {
+        asm!(
+            "mov {}, rsp",
+            "mov {}, rbp",
+            out(reg) stack_pointer,
+            out(reg) frame_pointer,
+            options(nomem, nostack, preserves_flags),
+        );
     }
+    [
+        ("rsp", stack_pointer),
+        ("rbp", frame_pointer),
+        ("cr2", Cr2::read_raw()),
+        ("cr3", Cr3::read_raw().0.start_address().as_u64()),
+        ("rflags", rflags::read_raw()),
+    ]
 }
 
-extern "C" {
-    static mut p4_table: PageTable;
-    static mut p2_tables: [PageTable; 2];
-    static mut p1_table_for_stack: PageTable;
-    fn launch_memory_manager(
-        allocator: *mut Amd64FrameAllocator,
-        boot_info_ptr: *const u8,
-        root_page_table_address: usize,
-        entry_point: usize,
-    ) -> !;
+/// Writes a message to the first serial port without going through the log. This is a last resort
+/// for when the log sink can't be used.
+pub fn emergency_log(args: fmt::Arguments) {
+    let mut serial_port = SerialPort::new(COM1);
+    unsafe {
+        serial_port.init();
+    }
+    let _ = writeln!(serial_port, "{args}");
 }
 
 extern "x86-interrupt" fn breakpoint_handler(_stack_frame: InterruptStackFrame) {}
 
 extern "x86-interrupt" fn double_fault_handler(_stack_frame: InterruptStackFrame, _: u64) -> ! {
-    halt();
+    Amd64::halt();
+}
+
+/// Installed with `set_handler_addr` rather than as a diverging handler, since it returns after
+/// errors that the processor corrected
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) {
+    if !unsafe { mce::report(&stack_frame) } {
+        Amd64::halt();
+    }
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    error!(
+        "General protection fault at {:#x} in ring {:?} (error code {error_code:#x})",
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.code_segment.rpl()
+    );
+    Amd64::halt();
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: PageFaultErrorCode,
+    #[cfg_attr(not(feature = "qemu-test"), allow(unused_mut))] mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
 ) {
-    halt();
+    #[cfg(feature = "qemu-test")]
+    if unsafe { self_checks::recover_from_expected_fault(&mut stack_frame, error_code) } {
+        return;
+    }
+    let address = Cr2::read_raw();
+    let supervisor_protection = usize::try_from(address).is_ok_and(|address| unsafe {
+        user_access::blocked_by_supervisor_protection(address, error_code, stack_frame.cpu_flags)
+    });
+    error!(
+        "Page fault at {:#x} accessing {address:#x}: {} (error code {:#x})",
+        stack_frame.instruction_pointer.as_u64(),
+        page_fault_cause(error_code, supervisor_protection),
+        error_code.bits()
+    );
+    Amd64::halt();
+}
+
+/// Says what kind of access caused a page fault. `supervisor_protection` is whether SMEP or SMAP
+/// stopped the kernel from reaching a user page.
+fn page_fault_cause(error_code: PageFaultErrorCode, supervisor_protection: bool) -> &'static str {
+    if error_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+        // The processor checks the reserved bits of every entry on the walk, and the no-execute
+        // bit is reserved while EFER.NXE is off
+        "a page table entry sets a reserved bit, such as no-execute without EFER.NXE"
+    } else if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "access to an unmapped page"
+    } else if supervisor_protection && error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "SMEP: the kernel fetched an instruction from a user page"
+    } else if supervisor_protection {
+        "SMAP: the kernel touched a user page outside a UserAccessGuard"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "write to read-only page"
+    } else if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "instruction fetch from a no-execute page"
+    } else {
+        "access that the page doesn't allow"
+    }
 }
 
 extern "x86-interrupt" fn spurious_interrupt_handler(_: InterruptStackFrame) {
@@ -62,8 +179,64 @@ extern "x86-interrupt" fn error_interrupt_handler(_: InterruptStackFrame) {
     }
 }
 
+extern "x86-interrupt" fn keyboard_interrupt_handler(_: InterruptStackFrame) {
+    user_access::clear_inherited_access();
+    keyboard::handle_scancode(unsafe { keyboard::read_scancode() });
+    unsafe {
+        end_interrupt();
+    }
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(_: InterruptStackFrame) {
+    user_access::clear_inherited_access();
+    memory_service::on_timer_tick();
     unsafe {
         end_interrupt();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_fault_causes() {
+        let write = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+        let fetch =
+            PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::INSTRUCTION_FETCH;
+        assert_eq!(page_fault_cause(write, false), "write to read-only page");
+        assert_eq!(
+            page_fault_cause(PageFaultErrorCode::CAUSED_BY_WRITE, false),
+            "access to an unmapped page"
+        );
+        assert_eq!(
+            page_fault_cause(fetch, false),
+            "instruction fetch from a no-execute page"
+        );
+        assert!(
+            page_fault_cause(write | PageFaultErrorCode::MALFORMED_TABLE, false)
+                .contains("reserved bit")
+        );
+    }
+
+    #[test]
+    fn supervisor_protection_faults_name_smep_or_smap() {
+        let write = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+        let fetch =
+            PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::INSTRUCTION_FETCH;
+        assert!(page_fault_cause(fetch, true).starts_with("SMEP: "));
+        assert!(page_fault_cause(write, true).starts_with("SMAP: "));
+        assert!(
+            page_fault_cause(PageFaultErrorCode::PROTECTION_VIOLATION, true).starts_with("SMAP: ")
+        );
+        // A fault on an unmapped page or a malformed table isn't the protection's doing
+        assert_eq!(
+            page_fault_cause(PageFaultErrorCode::CAUSED_BY_WRITE, true),
+            "access to an unmapped page"
+        );
+        assert!(
+            page_fault_cause(write | PageFaultErrorCode::MALFORMED_TABLE, true)
+                .contains("reserved bit")
+        );
+    }
+}
@@ -0,0 +1,65 @@
+//! A software walk of the page tables, which the kernel tests and the host tests use to see what
+//! the tables actually map rather than what the code that built them meant to map.
+
+use super::init::physical_address;
+use frame_allocation::{
+    amd64::{offset_in_page, page_size, table_index, LEVELS},
+    PhysicalAddress,
+};
+use x86_64::structures::paging::page_table::{PageTable, PageTableFlags};
+
+/// Where a virtual address leads and what the whole walk to it allows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// Each permission is independent, so there's no state machine or enum to replace the bools with
+#[allow(clippy::struct_excessive_bools)]
+pub struct Translation {
+    pub address: PhysicalAddress,
+    /// Every level of the walk allows writes
+    pub writable: bool,
+    /// No level of the walk forbids executing
+    pub executable: bool,
+    /// Every level of the walk allows user mode access
+    pub user_accessible: bool,
+    /// The size of the page that maps the address
+    pub page_size: usize,
+    /// The entry that maps the page disables caching. The MTRRs can make the page uncacheable
+    /// even if it doesn't.
+    pub uncacheable: bool,
+}
+
+/**
+ * Walks the page tables under `root` to find what `address` maps to
+ *
+ * # Safety
+ *
+ * The page tables must be identity mapped.
+ */
+pub unsafe fn translate(root: &PageTable, address: usize) -> Option<Translation> {
+    let mut table = root;
+    let mut writable = true;
+    let mut executable = true;
+    let mut user_accessible = true;
+    for level in (0..LEVELS).rev() {
+        let entry = &table[table_index(level, address)];
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        writable &= flags.contains(PageTableFlags::WRITABLE);
+        executable &= !flags.contains(PageTableFlags::NO_EXECUTE);
+        user_accessible &= flags.contains(PageTableFlags::USER_ACCESSIBLE);
+        let target = physical_address(entry.addr())?.as_usize();
+        if level == 0 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return Some(Translation {
+                address: PhysicalAddress::new(target + offset_in_page(level, address)),
+                writable,
+                executable,
+                user_accessible,
+                page_size: page_size(level),
+                uncacheable: flags.contains(PageTableFlags::NO_CACHE),
+            });
+        }
+        table = &*(target as *const PageTable);
+    }
+    None
+}
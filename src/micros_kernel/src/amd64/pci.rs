@@ -0,0 +1,722 @@
+//! Finds the PCI devices through the legacy configuration mechanism, which reaches configuration
+//! space through an address port and a data port. Every bus, device, and function is tried, since
+//! that doesn't depend on the bridges being set up. What's found is kept for drivers to look up.
+
+use crate::fixed_vec::FixedVec;
+use core::fmt;
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+/// The most devices that are kept. Any beyond this are logged but not kept.
+pub const MAX_DEVICES: usize = 64;
+
+const CONFIG_ADDRESS_PORT: u16 = 0xcf8;
+const CONFIG_DATA_PORT: u16 = 0xcfc;
+/// Set in the configuration address for it to be used
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+
+const VENDOR_ID_OFFSET: u8 = 0x00;
+const COMMAND_OFFSET: u8 = 0x04;
+const CLASS_OFFSET: u8 = 0x08;
+const HEADER_TYPE_OFFSET: u8 = 0x0c;
+const FIRST_BAR_OFFSET: u8 = 0x10;
+
+/// The vendor ID that reads back when there's no function at an address
+const NO_VENDOR: u16 = 0xffff;
+/// Set in the header type if the device has functions other than 0
+const MULTI_FUNCTION: u8 = 1 << 7;
+const HEADER_LAYOUT: u8 = !MULTI_FUNCTION;
+const GENERAL_DEVICE_HEADER: u8 = 0;
+const BRIDGE_HEADER: u8 = 1;
+/// General devices have six BARs and bridges have two
+const MAX_BARS: usize = 6;
+const BRIDGE_BARS: usize = 2;
+
+/// The command register bits that let a device respond to I/O and memory accesses
+const DECODE_ENABLE: u16 = 0b11;
+
+const BAR_IO_SPACE: u32 = 1;
+const BAR_TYPE: u32 = 0b110;
+const BAR_TYPE_64_BIT: u32 = 0b100;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+const BAR_MEMORY_FLAGS: u32 = 0xf;
+const BAR_IO_FLAGS: u32 = 0b11;
+
+static DEVICES: Once<FixedVec<Device, MAX_DEVICES>> = Once::new();
+
+/// Where a function is in configuration space
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// What a base address register maps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// The BAR takes up the next BAR's register too
+        is_64_bit: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Memory { address, size, .. } => write!(f, "{address:#x}/{}", Size(size)),
+            Self::Io { port, size } => write!(f, "io:{port:#x}/{}", Size(size.into())),
+        }
+    }
+}
+
+/// A PCI function
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+// The IDs are named the way the PCI specification names them
+#[allow(clippy::struct_field_names)]
+pub struct Device {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    /// Indexed by register, so the register after a 64 bit BAR is always `None`
+    pub bars: [Option<Bar>; MAX_BARS],
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:02x}{:02x} {} {:04x}:{:04x}",
+            self.address,
+            self.class,
+            self.subclass,
+            class_name(self.class, self.subclass),
+            self.vendor_id,
+            self.device_id
+        )?;
+        for (index, bar) in self.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                write!(f, " BAR{index}={bar}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads and writes configuration space. The legacy mechanism is the only implementation outside
+/// of the tests.
+pub trait ConfigSpace {
+    /// Reads the 32 bit register at `offset`, which must be a multiple of 4
+    fn read(&mut self, address: Address, offset: u8) -> u32;
+
+    /// Writes the 32 bit register at `offset`, which must be a multiple of 4
+    fn write(&mut self, address: Address, offset: u8, value: u32);
+}
+
+/// Configuration mechanism #1, through ports 0xcf8 and 0xcfc
+pub struct PortConfigSpace;
+
+impl ConfigSpace for PortConfigSpace {
+    fn read(&mut self, address: Address, offset: u8) -> u32 {
+        unsafe {
+            Port::new(CONFIG_ADDRESS_PORT).write(config_address(address, offset));
+            Port::new(CONFIG_DATA_PORT).read()
+        }
+    }
+
+    fn write(&mut self, address: Address, offset: u8, value: u32) {
+        unsafe {
+            Port::new(CONFIG_ADDRESS_PORT).write(config_address(address, offset));
+            Port::new(CONFIG_DATA_PORT).write(value);
+        }
+    }
+}
+
+/**
+ * Finds every PCI function, logs them, and keeps them for `devices`.
+ *
+ * # Safety
+ *
+ * Nothing else may use the configuration ports at the same time, and no driver may be using a
+ * device, since its BARs are briefly overwritten to size them.
+ */
+pub unsafe fn init() {
+    DEVICES.call_once(|| {
+        let mut devices = FixedVec::new();
+        scan(&mut PortConfigSpace, |device| {
+            info!("{device}");
+            if devices.push(device).is_err() {
+                warn!("There are more than {MAX_DEVICES} PCI devices, so {device} isn't kept");
+            }
+        });
+        devices
+    });
+    info!("Found {} PCI functions", devices().len());
+}
+
+/// Every PCI function that was found at boot
+pub fn devices() -> &'static [Device] {
+    DEVICES.get().map_or(&[], |devices| devices)
+}
+
+/// Calls `found` with every function in `config` in address order
+pub fn scan(config: &mut impl ConfigSpace, mut found: impl FnMut(Device)) {
+    for bus in 0..=u8::MAX {
+        for device in 0..DEVICES_PER_BUS {
+            let first = Address {
+                bus,
+                device,
+                function: 0,
+            };
+            let Some(header_type) = header_type(config, first) else {
+                continue;
+            };
+            let functions = if header_type & MULTI_FUNCTION == 0 {
+                1
+            } else {
+                FUNCTIONS_PER_DEVICE
+            };
+            for function in 0..functions {
+                let address = Address {
+                    bus,
+                    device,
+                    function,
+                };
+                if let Some(device) = read_device(config, address) {
+                    found(device);
+                }
+            }
+        }
+    }
+}
+
+/// The header type of the function at `address`, or `None` if there isn't one
+fn header_type(config: &mut impl ConfigSpace, address: Address) -> Option<u8> {
+    let [vendor_low, vendor_high, ..] = config.read(address, VENDOR_ID_OFFSET).to_le_bytes();
+    if u16::from_le_bytes([vendor_low, vendor_high]) == NO_VENDOR {
+        return None;
+    }
+    Some(config.read(address, HEADER_TYPE_OFFSET).to_le_bytes()[2])
+}
+
+fn read_device(config: &mut impl ConfigSpace, address: Address) -> Option<Device> {
+    let header_type = header_type(config, address)?;
+    let [vendor_low, vendor_high, device_low, device_high] =
+        config.read(address, VENDOR_ID_OFFSET).to_le_bytes();
+    let [_revision, prog_if, subclass, class] = config.read(address, CLASS_OFFSET).to_le_bytes();
+    let bar_count = match header_type & HEADER_LAYOUT {
+        GENERAL_DEVICE_HEADER => MAX_BARS,
+        BRIDGE_HEADER => BRIDGE_BARS,
+        _ => 0,
+    };
+    Some(Device {
+        address,
+        vendor_id: u16::from_le_bytes([vendor_low, vendor_high]),
+        device_id: u16::from_le_bytes([device_low, device_high]),
+        class,
+        subclass,
+        prog_if,
+        bars: read_bars(config, address, bar_count),
+    })
+}
+
+/**
+ * Reads and sizes the first `count` BARs. Decoding is turned off while each BAR holds all ones so
+ * that the device can't respond at that address, and the BARs and the command register are put
+ * back the way they were.
+ */
+fn read_bars(
+    config: &mut impl ConfigSpace,
+    address: Address,
+    count: usize,
+) -> [Option<Bar>; MAX_BARS] {
+    let mut bars = [None; MAX_BARS];
+    let command = config.read(address, COMMAND_OFFSET);
+    config.write(address, COMMAND_OFFSET, command & !u32::from(DECODE_ENABLE));
+    let mut index = 0;
+    while index < count {
+        let Some(low_offset) = bar_offset(index) else {
+            break;
+        };
+        let low = config.read(address, low_offset);
+        let is_64_bit = low & BAR_IO_SPACE == 0 && low & BAR_TYPE == BAR_TYPE_64_BIT;
+        // A 64 bit BAR in the last register is broken, so it's read as a 32 bit one
+        let high_offset = (is_64_bit && index + 1 < count)
+            .then(|| bar_offset(index + 1))
+            .flatten();
+        let high = high_offset.map(|offset| config.read(address, offset));
+        let low_mask = probe(config, address, low_offset, low);
+        let high_mask = high_offset
+            .zip(high)
+            .map(|(offset, high)| probe(config, address, offset, high));
+        bars[index] = decode_bar(low, high, low_mask, high_mask);
+        index += if high.is_some() { 2 } else { 1 };
+    }
+    config.write(address, COMMAND_OFFSET, command);
+    bars
+}
+
+/// Writes all ones to the register at `offset`, reads back which bits stuck, and puts `original`
+/// back
+fn probe(config: &mut impl ConfigSpace, address: Address, offset: u8, original: u32) -> u32 {
+    config.write(address, offset, u32::MAX);
+    let mask = config.read(address, offset);
+    config.write(address, offset, original);
+    mask
+}
+
+/// The offset of the BAR at `index`, or `None` if it would be past the end of the configuration
+/// space
+fn bar_offset(index: usize) -> Option<u8> {
+    u8::try_from(index)
+        .ok()?
+        .checked_mul(4)?
+        .checked_add(FIRST_BAR_OFFSET)
+}
+
+/**
+ * Decodes a BAR from its value and what it read back as after all ones were written to it. `high`
+ * and `high_mask` are the same for the register after a 64 bit BAR. Returns `None` for a BAR that
+ * isn't implemented.
+ */
+fn decode_bar(low: u32, high: Option<u32>, low_mask: u32, high_mask: Option<u32>) -> Option<Bar> {
+    if low & BAR_IO_SPACE != 0 {
+        // The upper 16 bits of an I/O BAR can be hardwired to 0, since ports only go up to 0xffff
+        let mask = low_mask & !BAR_IO_FLAGS;
+        let mask = if mask & 0xffff_0000 == 0 {
+            mask | 0xffff_0000
+        } else {
+            mask
+        };
+        return (mask != 0xffff_0000 || low_mask & !BAR_IO_FLAGS != 0).then_some(Bar::Io {
+            port: low & !BAR_IO_FLAGS,
+            size: (!mask).wrapping_add(1),
+        });
+    }
+    let address = u64::from(high.unwrap_or(0)) << 32 | u64::from(low & !BAR_MEMORY_FLAGS);
+    let mask = match high_mask {
+        Some(high_mask) => u64::from(high_mask) << 32 | u64::from(low_mask & !BAR_MEMORY_FLAGS),
+        // Only the 32 bits of the register can be sized
+        None => 0xffff_ffff_0000_0000 | u64::from(low_mask & !BAR_MEMORY_FLAGS),
+    };
+    if mask == 0xffff_ffff_0000_0000 || mask == 0 {
+        return None;
+    }
+    Some(Bar::Memory {
+        address,
+        size: (!mask).wrapping_add(1),
+        prefetchable: low & BAR_PREFETCHABLE != 0,
+        is_64_bit: high.is_some(),
+    })
+}
+
+fn config_address(address: Address, offset: u8) -> u32 {
+    CONFIG_ENABLE
+        | u32::from(address.bus) << 16
+        | u32::from(address.device) << 11
+        | u32::from(address.function) << 8
+        | u32::from(offset & 0xfc)
+}
+
+/// A short name for a class and subclass, for the log
+fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE",
+        (0x01, 0x06) => "SATA",
+        (0x01, 0x08) => "NVMe",
+        (0x01, _) => "Storage",
+        (0x02, 0x00) => "Ethernet",
+        (0x02, _) => "Network",
+        (0x03, 0x00) => "VGA",
+        (0x03, _) => "Display",
+        (0x04, _) => "Multimedia",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x0c, 0x03) => "USB",
+        (0x0c, 0x05) => "SMBus",
+        (0x0c, _) => "Serial bus",
+        _ => "Other",
+    }
+}
+
+/// Formats a size with the biggest binary unit that divides it
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+        match units
+            .iter()
+            .find(|(unit, _)| self.0 >= *unit && self.0.is_multiple_of(*unit))
+        {
+            Some((unit, suffix)) => write!(f, "{}{suffix}", self.0 / unit),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{collections::BTreeMap, string::ToString, vec::Vec};
+
+    /// A function's configuration header and which bits of each BAR can be written. Writes to
+    /// BARs only change the bits that can be written, like they do in hardware.
+    struct Function {
+        registers: [u32; 16],
+        bar_masks: [u32; MAX_BARS],
+    }
+
+    #[derive(Default)]
+    struct FakeConfigSpace {
+        functions: BTreeMap<(u8, u8, u8), Function>,
+        /// Writes that were made, so that probing can be checked for leaving things as they were
+        writes: usize,
+    }
+
+    impl FakeConfigSpace {
+        fn add(&mut self, bus: u8, device: u8, function: u8, dump: [u32; 16], masks: [u32; 6]) {
+            self.functions.insert(
+                (bus, device, function),
+                Function {
+                    registers: dump,
+                    bar_masks: masks,
+                },
+            );
+        }
+    }
+
+    impl ConfigSpace for FakeConfigSpace {
+        fn read(&mut self, address: Address, offset: u8) -> u32 {
+            self.functions
+                .get(&(address.bus, address.device, address.function))
+                .map_or(u32::MAX, |function| {
+                    function.registers[usize::from(offset / 4)]
+                })
+        }
+
+        fn write(&mut self, address: Address, offset: u8, value: u32) {
+            self.writes += 1;
+            let function = self
+                .functions
+                .get_mut(&(address.bus, address.device, address.function))
+                .unwrap();
+            let register = usize::from(offset / 4);
+            function.registers[register] = match register.checked_sub(4) {
+                Some(bar) if bar < MAX_BARS => {
+                    let mask = function.bar_masks[bar];
+                    value & mask | function.registers[register] & !mask
+                }
+                _ => value,
+            };
+        }
+    }
+
+    // Captured from QEMU's i440FX machine with a standard VGA card and an e1000
+    const HOST_BRIDGE: [u32; 16] = [
+        0x1237_8086,
+        0x0000_0106,
+        0x0600_0002,
+        0x0000_0000,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0x1100_1af4,
+        0,
+        0,
+        0,
+        0,
+    ];
+    const ISA_BRIDGE: [u32; 16] = [
+        0x7000_8086,
+        0x0200_0103,
+        0x0601_0000,
+        0x0080_0000,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0x1100_1af4,
+        0,
+        0,
+        0,
+        0,
+    ];
+    const IDE: [u32; 16] = [
+        0x7010_8086,
+        0x0280_0107,
+        0x0101_8000,
+        0x0000_0000,
+        0,
+        0,
+        0,
+        0,
+        0x0000_c041,
+        0,
+        0,
+        0x1100_1af4,
+        0,
+        0,
+        0,
+        0,
+    ];
+    const VGA: [u32; 16] = [
+        0x1111_1234,
+        0x0000_0107,
+        0x0300_0002,
+        0x0000_0000,
+        0xfd00_0008,
+        0,
+        0xfebf_0000,
+        0,
+        0,
+        0,
+        0,
+        0x1100_1af4,
+        0xfebe_0000,
+        0,
+        0,
+        0,
+    ];
+    const E1000: [u32; 16] = [
+        0x100e_8086,
+        0x0000_0107,
+        0x0200_0003,
+        0x0000_0000,
+        0xfebc_0000,
+        0x0000_c001,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0x1100_1af4,
+        0xfeb8_0000,
+        0,
+        0,
+        0x0000_010b,
+    ];
+    /// A virtio device with a 64 bit prefetchable BAR in BARs 4 and 5
+    const VIRTIO: [u32; 16] = [
+        0x1041_1af4,
+        0x0010_0107,
+        0x0200_0001,
+        0x0000_0000,
+        0,
+        0xfebd_1000,
+        0,
+        0,
+        0xfe00_000c,
+        0x0000_0000,
+        0,
+        0x1100_1af4,
+        0,
+        0x0000_0040,
+        0,
+        0x0000_010b,
+    ];
+
+    const NO_BARS: [u32; 6] = [0; 6];
+    const IDE_MASKS: [u32; 6] = [0, 0, 0, 0, 0xffff_fff0, 0];
+    const VGA_MASKS: [u32; 6] = [0xff00_0000, 0, 0xffff_f000, 0, 0, 0];
+    const E1000_MASKS: [u32; 6] = [0xfffe_0000, 0xffff_ffc0, 0, 0, 0, 0];
+    const VIRTIO_MASKS: [u32; 6] = [0, 0xffff_f000, 0, 0, 0xffff_c000, 0xffff_ffff];
+
+    fn qemu_machine() -> FakeConfigSpace {
+        let mut config = FakeConfigSpace::default();
+        config.add(0, 0, 0, HOST_BRIDGE, NO_BARS);
+        config.add(0, 1, 0, ISA_BRIDGE, NO_BARS);
+        config.add(0, 1, 1, IDE, IDE_MASKS);
+        config.add(0, 2, 0, VGA, VGA_MASKS);
+        config.add(0, 3, 0, E1000, E1000_MASKS);
+        config.add(0, 4, 0, VIRTIO, VIRTIO_MASKS);
+        config
+    }
+
+    fn scan_all(config: &mut FakeConfigSpace) -> Vec<Device> {
+        let mut devices = Vec::new();
+        scan(config, |device| devices.push(device));
+        devices
+    }
+
+    #[test]
+    fn finds_every_function() {
+        let devices = scan_all(&mut qemu_machine());
+        let found: Vec<_> = devices
+            .iter()
+            .map(|device| device.address.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            ["00:00.0", "00:01.0", "00:01.1", "00:02.0", "00:03.0", "00:04.0"]
+        );
+    }
+
+    #[test]
+    fn skips_functions_of_single_function_devices() {
+        let mut config = qemu_machine();
+        // The multi-function bit is clear on the ISA bridge, so its IDE function isn't looked at
+        config.functions.get_mut(&(0, 1, 0)).unwrap().registers[3] = 0;
+        let devices = scan_all(&mut config);
+        assert!(devices.iter().all(|device| device.address.function == 0));
+    }
+
+    #[test]
+    fn formats_like_the_boot_log() {
+        let devices = scan_all(&mut qemu_machine());
+        let lines: Vec<_> = devices.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "00:00.0 0600 Host bridge 8086:1237",
+                "00:01.0 0601 ISA bridge 8086:7000",
+                "00:01.1 0101 IDE 8086:7010 BAR4=io:0xc040/16",
+                "00:02.0 0300 VGA 1234:1111 BAR0=0xfd000000/16M BAR2=0xfebf0000/4K",
+                "00:03.0 0200 Ethernet 8086:100e BAR0=0xfebc0000/128K BAR1=io:0xc000/64",
+                "00:04.0 0200 Ethernet 1af4:1041 BAR1=0xfebd1000/4K BAR4=0xfe000000/16K",
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_bar_types() {
+        let devices = scan_all(&mut qemu_machine());
+        assert_eq!(
+            devices[3].bars[0],
+            Some(Bar::Memory {
+                address: 0xfd00_0000,
+                size: 16 << 20,
+                prefetchable: true,
+                is_64_bit: false,
+            })
+        );
+        assert_eq!(
+            devices[5].bars[4],
+            Some(Bar::Memory {
+                address: 0xfe00_0000,
+                size: 16 << 10,
+                prefetchable: true,
+                is_64_bit: true,
+            })
+        );
+        assert_eq!(devices[5].bars[5], None);
+        assert_eq!(
+            devices[4].bars[1],
+            Some(Bar::Io {
+                port: 0xc000,
+                size: 64,
+            })
+        );
+    }
+
+    #[test]
+    fn probing_leaves_the_registers_as_they_were() {
+        let mut config = qemu_machine();
+        scan_all(&mut config);
+        assert!(config.writes > 0);
+        for (dump, address) in [
+            (HOST_BRIDGE, (0, 0, 0)),
+            (ISA_BRIDGE, (0, 1, 0)),
+            (IDE, (0, 1, 1)),
+            (VGA, (0, 2, 0)),
+            (E1000, (0, 3, 0)),
+            (VIRTIO, (0, 4, 0)),
+        ] {
+            assert_eq!(config.functions[&address].registers, dump, "{address:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_a_64_bit_bar_above_4_gigabytes() {
+        let bar = decode_bar(
+            0x0000_000c,
+            Some(0x0000_0008),
+            0xf000_000c,
+            Some(0xffff_ffff),
+        );
+        assert_eq!(
+            bar,
+            Some(Bar::Memory {
+                address: 0x8_0000_0000,
+                size: 0x1000_0000,
+                prefetchable: true,
+                is_64_bit: true,
+            })
+        );
+    }
+
+    #[test]
+    fn io_bars_with_hardwired_upper_bits() {
+        // The upper 16 bits of the mask read back as 0
+        assert_eq!(
+            decode_bar(0xc001, None, 0x0000_ffe1, None),
+            Some(Bar::Io {
+                port: 0xc000,
+                size: 32,
+            })
+        );
+        assert_eq!(
+            decode_bar(0xc001, None, 0xffff_ffe1, None),
+            Some(Bar::Io {
+                port: 0xc000,
+                size: 32,
+            })
+        );
+    }
+
+    #[test]
+    fn unimplemented_bars_are_none() {
+        assert_eq!(decode_bar(0, None, 0, None), None);
+        assert_eq!(decode_bar(0, Some(0), 0, Some(0)), None);
+    }
+
+    #[test]
+    fn config_addresses() {
+        let address = Address {
+            bus: 0x12,
+            device: 0x1f,
+            function: 7,
+        };
+        assert_eq!(config_address(address, 0x3c), 0x8012_ff3c);
+        assert_eq!(config_address(address, 0x3f), 0x8012_ff3c);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(Size(16 << 20).to_string(), "16M");
+        assert_eq!(Size(1 << 30).to_string(), "1G");
+        assert_eq!(Size(4096).to_string(), "4K");
+        assert_eq!(Size(64).to_string(), "64");
+        assert_eq!(Size(1536).to_string(), "1536");
+    }
+}
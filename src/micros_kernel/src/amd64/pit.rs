@@ -0,0 +1,165 @@
+//! Busy waits timed by channel 2 of the 8254 programmable interval timer. The PIT runs at a fixed
+//! frequency on every PC, so it can time short waits before anything else has been calibrated.
+//! Channel 2 is the one wired to the speaker, which is the only channel whose output can be read
+//! back, through the system control port.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// How many times a second the PIT counts down
+const PIT_FREQUENCY: u64 = 1_193_182;
+const MICROSECONDS_PER_SECOND: u64 = 1_000_000;
+/// The longest wait that fits in the 16 bit counter, which is about 55 ms
+const MAX_TICKS: u16 = 0xffff;
+
+const CHANNEL_2_DATA_PORT: u16 = 0x42;
+const MODE_COMMAND_PORT: u16 = 0x43;
+/// Channel 2, low byte then high byte, mode 0 (interrupt on terminal count), binary
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+const SYSTEM_CONTROL_PORT: u16 = 0x61;
+/// Counting on channel 2 only happens while its gate is high
+const CHANNEL_2_GATE: u8 = 1;
+/// Sends channel 2's output to the speaker, which has to stay off
+const SPEAKER_ENABLE: u8 = 1 << 1;
+/// Channel 2's output, which goes high once the count reaches 0
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+/// How long the wait is that's checked against the TSC during boot
+const TSC_CHECK_MICROSECONDS: u64 = 10_000;
+/// The CPUID leaf that reports the processor's base frequency in MHz
+const FREQUENCY_LEAF: u32 = 0x16;
+
+/// Only one wait can use channel 2 at a time
+static CHANNEL_2: Mutex<()> = Mutex::new(());
+
+/// Waits for `microseconds` microseconds, to within a few microseconds
+pub fn busy_wait_us(microseconds: u64) {
+    wait_for(microseconds, || false);
+}
+
+/**
+ * Waits until `done` returns true or `microseconds` microseconds have passed, whichever is first.
+ * Returns what `done` returned last. Waits longer than the counter can time are split into several
+ * countdowns.
+ */
+pub fn wait_for(microseconds: u64, done: impl Fn() -> bool) -> bool {
+    let _channel = CHANNEL_2.lock();
+    let mut ticks = ticks_for_microseconds(microseconds);
+    while ticks > 0 {
+        let countdown = u16::try_from(ticks).unwrap_or(MAX_TICKS);
+        let finished = unsafe { count_down(countdown, &done) };
+        if finished {
+            return true;
+        }
+        ticks -= u64::from(countdown);
+    }
+    done()
+}
+
+/**
+ * Checks that a 10 ms wait takes about as long as the TSC says it does, and logs a warning if it
+ * doesn't. Without the processor's base frequency this can only check that the TSC moved forward.
+ */
+pub fn check_against_tsc() {
+    let start = unsafe { _rdtsc() };
+    busy_wait_us(TSC_CHECK_MICROSECONDS);
+    let elapsed = unsafe { _rdtsc() }.wrapping_sub(start);
+    let base_frequency_mhz = if __cpuid(0).eax >= FREQUENCY_LEAF {
+        u64::from(__cpuid(FREQUENCY_LEAF).eax)
+    } else {
+        0
+    };
+    if !tsc_agrees(elapsed, TSC_CHECK_MICROSECONDS, base_frequency_mhz) {
+        warn!(
+            "A {TSC_CHECK_MICROSECONDS} us PIT wait took {elapsed} TSC ticks at a base frequency of \
+             {base_frequency_mhz} MHz"
+        );
+    }
+}
+
+/// The number of PIT ticks in `microseconds` microseconds, rounded up so that waits are never
+/// shorter than asked for
+fn ticks_for_microseconds(microseconds: u64) -> u64 {
+    microseconds
+        .saturating_mul(PIT_FREQUENCY)
+        .div_ceil(MICROSECONDS_PER_SECOND)
+}
+
+/**
+ * Whether `elapsed` TSC ticks is within an order of magnitude of `microseconds` at
+ * `base_frequency_mhz`. A frequency of 0 means that it's unknown, so any forward progress passes.
+ */
+fn tsc_agrees(elapsed: u64, microseconds: u64, base_frequency_mhz: u64) -> bool {
+    if base_frequency_mhz == 0 {
+        return elapsed > 0;
+    }
+    let expected = microseconds * base_frequency_mhz;
+    (expected / 10..=expected.saturating_mul(10)).contains(&elapsed)
+}
+
+/**
+ * Counts channel 2 down from `ticks` and waits for it to reach 0 or for `done` to return true.
+ * Returns true if `done` did.
+ *
+ * # Safety
+ *
+ * Nothing else may use channel 2 or the speaker at the same time.
+ */
+unsafe fn count_down(ticks: u16, done: impl Fn() -> bool) -> bool {
+    let mut control = Port::<u8>::new(SYSTEM_CONTROL_PORT);
+    let mut command = Port::<u8>::new(MODE_COMMAND_PORT);
+    let mut data = Port::<u8>::new(CHANNEL_2_DATA_PORT);
+    // The gate is held low while the count is loaded so that it can't start counting from a half
+    // written count
+    let gate_low = control.read() & !(CHANNEL_2_GATE | SPEAKER_ENABLE);
+    control.write(gate_low);
+    // Setting the mode drives the output low, so a high output left over from the last wait can't
+    // be mistaken for this one finishing
+    command.write(CHANNEL_2_ONE_SHOT);
+    let [low, high] = ticks.to_le_bytes();
+    data.write(low);
+    data.write(high);
+    control.write(gate_low | CHANNEL_2_GATE);
+    while control.read() & CHANNEL_2_OUTPUT == 0 {
+        if done() {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_round_up() {
+        assert_eq!(ticks_for_microseconds(0), 0);
+        assert_eq!(ticks_for_microseconds(1), 2);
+        assert_eq!(ticks_for_microseconds(1_000_000), PIT_FREQUENCY);
+        // 50 ms still fits in one countdown
+        assert!(ticks_for_microseconds(50_000) <= u64::from(MAX_TICKS));
+        assert_eq!(
+            ticks_for_microseconds(u64::MAX),
+            u64::MAX / MICROSECONDS_PER_SECOND + 1
+        );
+    }
+
+    #[test]
+    fn tsc_within_an_order_of_magnitude() {
+        // 10 ms at 2 GHz is 20 million ticks
+        assert!(tsc_agrees(20_000_000, 10_000, 2000));
+        assert!(tsc_agrees(2_000_000, 10_000, 2000));
+        assert!(tsc_agrees(200_000_000, 10_000, 2000));
+        assert!(!tsc_agrees(1_999_999, 10_000, 2000));
+        assert!(!tsc_agrees(200_000_001, 10_000, 2000));
+    }
+
+    #[test]
+    fn unknown_frequency_only_needs_progress() {
+        assert!(tsc_agrees(1, 10_000, 0));
+        assert!(!tsc_agrees(0, 10_000, 0));
+    }
+}
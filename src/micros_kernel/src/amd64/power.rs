@@ -0,0 +1,209 @@
+//! Turns the machine off or restarts it after an error that the kernel can't recover from. Which
+//! way works depends on the machine, so each is tried in turn and logged: ACPI S5, then the power
+//! management ports that QEMU and Bochs have, then resetting the processor through the keyboard
+//! controller, which is also how the machine is restarted. The processor halts if nothing works.
+
+use super::{pit, Amd64};
+use crate::{
+    acpi::{self, SoftOff},
+    boot_options::{BootOptions, OnFatal},
+    Architecture,
+};
+use multiboot2::BootInformation;
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+/// The port that QEMU's isa-debug-exit device listens on when it's started with `iobase=0xf4`
+pub const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+/// QEMU exits with 37 when this is written to isa-debug-exit, which is neither of the codes that
+/// the kernel tests exit with
+const FATAL_ERROR_CODE: u32 = 0x12;
+
+/// Set in the PM1 control register once the firmware has handed power management to the OS
+const SCI_ENABLED: u16 = 1;
+const SLEEP_TYPE_SHIFT: u16 = 10;
+const SLEEP_TYPE_BITS: u16 = 0b111 << SLEEP_TYPE_SHIFT;
+const SLEEP_ENABLE: u16 = 1 << 13;
+/// What QEMU's and Bochs's `PM1a` control registers turn the machine off with
+const EMULATOR_SOFT_OFF: u16 = SLEEP_ENABLE;
+const QEMU_PM1A_CONTROL: u16 = 0x604;
+const BOCHS_PM1A_CONTROL: u16 = 0xb004;
+
+/// QEMU's firmware configuration device, which holds "QEMU" in its first item
+const FW_CFG_SELECTOR_PORT: u16 = 0x510;
+const FW_CFG_DATA_PORT: u16 = 0x511;
+const FW_CFG_SIGNATURE: u16 = 0;
+/// Bochs's debug port reads back its own number
+const BOCHS_DEBUG_PORT: u16 = 0xe9;
+
+const KEYBOARD_CONTROLLER_PORT: u16 = 0x64;
+/// Set in the keyboard controller's status while it hasn't taken the last command yet
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+/// Pulses the processor's reset line
+const PULSE_RESET_LINE: u8 = 0xfe;
+/// What reading a port with nothing behind it returns
+const NO_DEVICE: u8 = 0xff;
+
+/// How long the firmware gets to switch to ACPI mode. ACPI allows it up to 3 seconds, but it's
+/// usually immediate.
+const ACPI_ENABLE_TIMEOUT_MICROSECONDS: u64 = 300_000;
+const KEYBOARD_CONTROLLER_TIMEOUT_MICROSECONDS: u64 = 100_000;
+/// How long each way of turning the machine off or restarting it gets before the next is tried
+const POWER_OFF_WAIT_MICROSECONDS: u64 = 100_000;
+
+static ON_FATAL: Once<OnFatal> = Once::new();
+/// Read from the FADT and the DSDT while booting, since the memory that holds the ACPI tables is
+/// given to the frame allocator afterwards
+static SOFT_OFF: Once<SoftOff> = Once::new();
+
+/// An emulator with its own way of being turned off
+#[derive(Clone, Copy)]
+enum Emulator {
+    Qemu,
+    Bochs,
+}
+
+/**
+ * Remembers what to do after a fatal error, reads how to turn the machine off from ACPI, and logs
+ * which ways of turning the machine off there are.
+ *
+ * # Safety
+ *
+ * `boot_info_ptr` must point to valid multiboot2 boot information.
+ */
+pub unsafe fn init(boot_info_ptr: *const u8, on_fatal: OnFatal) {
+    ON_FATAL.call_once(|| on_fatal);
+    match acpi::soft_off(
+        BootInformation::new(boot_info_ptr),
+        Amd64::INITIAL_VIRTUAL_MEMORY_SIZE,
+    ) {
+        Some(soft_off) => {
+            info!(
+                "ACPI S5 is SLP_TYP {} through the PM1a control register at {:#x}",
+                soft_off.sleep_type_a, soft_off.pm1a_control
+            );
+            SOFT_OFF.call_once(|| soft_off);
+        }
+        None => warn!("Couldn't read how to turn the machine off from ACPI"),
+    }
+    match detect_emulator() {
+        Some(Emulator::Qemu) => debug!("QEMU can also be turned off through its own ports"),
+        Some(Emulator::Bochs) => debug!("Bochs can also be turned off through its own port"),
+        None => {}
+    }
+}
+
+/// Does what the `on-fatal` boot option says after an error that the kernel can't recover from
+pub fn on_fatal_error() -> ! {
+    let on_fatal = ON_FATAL
+        .get()
+        .copied()
+        .unwrap_or_else(|| BootOptions::default().on_fatal);
+    match on_fatal {
+        OnFatal::Halt => Amd64::halt(),
+        OnFatal::Shutdown => shutdown(),
+        OnFatal::Reboot => reboot(),
+    }
+}
+
+/// Turns the machine off, or restarts it if it can't be turned off
+pub fn shutdown() -> ! {
+    if let Some(soft_off) = SOFT_OFF.get() {
+        info!("Turning the machine off through ACPI");
+        unsafe {
+            enter_s5(soft_off);
+        }
+        warn!("The machine is still on after entering ACPI S5");
+    }
+    match detect_emulator() {
+        Some(Emulator::Qemu) => unsafe {
+            info!("Turning QEMU off through its power management port");
+            Port::<u16>::new(QEMU_PM1A_CONTROL).write(EMULATOR_SOFT_OFF);
+            pit::busy_wait_us(POWER_OFF_WAIT_MICROSECONDS);
+            info!("Ending QEMU through the isa-debug-exit device");
+            Port::<u32>::new(ISA_DEBUG_EXIT_PORT).write(FATAL_ERROR_CODE);
+            pit::busy_wait_us(POWER_OFF_WAIT_MICROSECONDS);
+            warn!("QEMU is still running");
+        },
+        Some(Emulator::Bochs) => unsafe {
+            info!("Turning Bochs off through its power management port");
+            Port::<u16>::new(BOCHS_PM1A_CONTROL).write(EMULATOR_SOFT_OFF);
+            pit::busy_wait_us(POWER_OFF_WAIT_MICROSECONDS);
+            warn!("Bochs is still running");
+        },
+        None => {}
+    }
+    warn!("Couldn't turn the machine off, so restarting it instead");
+    reboot()
+}
+
+/// Restarts the machine through the keyboard controller, or halts if it can't
+pub fn reboot() -> ! {
+    let status = || unsafe { Port::<u8>::new(KEYBOARD_CONTROLLER_PORT).read() };
+    if status() == NO_DEVICE {
+        warn!("There's no keyboard controller to restart the machine with");
+    } else {
+        info!("Restarting the machine through the keyboard controller");
+        // The command is sent even if the controller is stuck, since there's nothing else to try
+        pit::wait_for(KEYBOARD_CONTROLLER_TIMEOUT_MICROSECONDS, || {
+            status() & INPUT_BUFFER_FULL == 0
+        });
+        unsafe {
+            Port::<u8>::new(KEYBOARD_CONTROLLER_PORT).write(PULSE_RESET_LINE);
+        }
+        pit::busy_wait_us(POWER_OFF_WAIT_MICROSECONDS);
+        warn!("The machine didn't restart");
+    }
+    error!("Halting");
+    Amd64::halt()
+}
+
+/**
+ * Puts the machine in the S5 sleep state, switching it to ACPI mode first if it isn't already.
+ * Returns if the machine is still on after a while.
+ *
+ * # Safety
+ *
+ * `soft_off` must have come from the machine's ACPI tables.
+ */
+unsafe fn enter_s5(soft_off: &SoftOff) {
+    let control = |port| unsafe { Port::<u16>::new(port).read() };
+    if control(soft_off.pm1a_control) & SCI_ENABLED == 0 {
+        if let Some((smi_command, acpi_enable)) = soft_off.acpi_enable {
+            Port::<u8>::new(smi_command).write(acpi_enable);
+            if !pit::wait_for(ACPI_ENABLE_TIMEOUT_MICROSECONDS, || {
+                control(soft_off.pm1a_control) & SCI_ENABLED != 0
+            }) {
+                warn!("The firmware didn't switch to ACPI mode");
+            }
+        }
+    }
+    let sleep = |port, sleep_type: u8| unsafe {
+        let value = control(port) & !SLEEP_TYPE_BITS
+            | u16::from(sleep_type) << SLEEP_TYPE_SHIFT
+            | SLEEP_ENABLE;
+        Port::<u16>::new(port).write(value);
+    };
+    // Writing PM1a is what turns the machine off on most machines, so PM1b is set up first
+    if let Some(pm1b_control) = soft_off.pm1b_control {
+        sleep(pm1b_control, soft_off.sleep_type_b);
+    }
+    sleep(soft_off.pm1a_control, soft_off.sleep_type_a);
+    pit::busy_wait_us(POWER_OFF_WAIT_MICROSECONDS);
+}
+
+/// Works out whether the kernel is running in QEMU or Bochs from the ports that only they have
+fn detect_emulator() -> Option<Emulator> {
+    unsafe {
+        Port::<u16>::new(FW_CFG_SELECTOR_PORT).write(FW_CFG_SIGNATURE);
+        let mut data = Port::<u8>::new(FW_CFG_DATA_PORT);
+        let signature = [data.read(), data.read(), data.read(), data.read()];
+        if &signature == b"QEMU" {
+            return Some(Emulator::Qemu);
+        }
+        if Port::<u8>::new(BOCHS_DEBUG_PORT).read() == 0xe9 {
+            return Some(Emulator::Bochs);
+        }
+    }
+    None
+}
@@ -0,0 +1,591 @@
+//! Kernel tests for the AMD64 frame allocator and page tables, and the isa-debug-exit device that
+//! tells the QEMU test runner how they went.
+
+use super::{
+    apic::DEFAULT_IO_APIC_ADDRESS,
+    cpu::CpuFeatures,
+    init::physical_address,
+    page_fault_cause,
+    page_walk::{translate, Translation},
+    pci::{self, PortConfigSpace},
+    power::{self, ISA_DEBUG_EXIT_PORT},
+    syscall,
+    user_access::{blocked_by_supervisor_protection, UserAccessGuard},
+    Amd64,
+};
+use crate::{
+    covers_kernel_image, intersect, usable_memory_areas, Architecture, BootReport, SegmentFlags,
+    ELF_WRITABLE_SEGMENT, SANITIZED_MEMORY_MAP,
+};
+use core::{
+    arch::asm,
+    fmt,
+    ops::Range,
+    ptr::addr_of,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use frame_allocation::{
+    amd64::{offset_in_page, page_size, GIGABYTE},
+    PhysicalAddress,
+};
+use multiboot2::{phys_to_usize, MemoryMapEntry};
+use x2apic::lapic::xapic_base;
+use x86_64::{
+    instructions::port::Port,
+    registers::{
+        control::{Cr0, Cr0Flags, Cr3, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags, Msr},
+        rflags,
+    },
+    structures::{
+        idt::{InterruptStackFrame, PageFaultErrorCode},
+        paging::{page_table::PageTable, PhysFrame},
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// QEMU exits with `(code << 1) | 1` when a code is written to the device, so these make it exit
+/// with 33 and 35. Neither can be confused with QEMU's own exit statuses.
+const SUCCESS_CODE: u32 = 0x10;
+const FAILURE_CODE: u32 = 0x11;
+
+/**
+ * Makes QEMU exit with a status that says whether the kernel tests passed. Without the
+ * isa-debug-exit device the write does nothing, and the processor halts if the tests passed or
+ * does what the `on-fatal` boot option says if they didn't.
+ */
+pub fn exit_qemu(passed: bool) -> ! {
+    let mut port = Port::<u32>::new(ISA_DEBUG_EXIT_PORT);
+    unsafe {
+        port.write(if passed { SUCCESS_CODE } else { FAILURE_CODE });
+    }
+    if passed {
+        Amd64::halt()
+    } else {
+        power::on_fatal_error()
+    }
+}
+
+/// Frames from the allocator have to be free memory that the kernel can write to
+unsafe fn frame_allocator_hands_out_usable_frames(
+    proc: &mut Amd64,
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    let (Some(first), Some(second)) =
+        (proc.allocator.get_4k_frame(), proc.allocator.get_4k_frame())
+    else {
+        return Err("the allocator ran out of 4 KB frames");
+    };
+    let result = check_frames(&[first, second], boot_report);
+    proc.allocator.four_kilobyte_pages.add_frame(second);
+    proc.allocator.four_kilobyte_pages.add_frame(first);
+    result
+}
+ktest!(frame_allocator_hands_out_usable_frames);
+
+unsafe fn check_frames(
+    frames: &[PhysicalAddress; 2],
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    if frames[0] == frames[1] {
+        return Err("the same frame was handed out twice");
+    }
+    for &frame in frames {
+        let range = frame.as_usize()..frame.as_usize() + page_size(0);
+        if offset_in_page(0, frame.as_usize()) != 0 {
+            return Err("a frame isn't aligned to 4 KB");
+        }
+        if covers_kernel_image(&range) {
+            return Err("a frame overlaps the kernel image");
+        }
+        if boot_report
+            .modules
+            .iter()
+            .any(|module| !intersect(range.clone(), module.range.clone()).is_empty())
+        {
+            return Err("a frame overlaps a boot module");
+        }
+        let memory = frame.as_usize() as *mut u64;
+        memory.write_volatile(0x5555_aaaa_5555_aaaa);
+        if memory.read_volatile() != 0x5555_aaaa_5555_aaaa {
+            return Err("a frame didn't hold what was written to it");
+        }
+    }
+    Ok(())
+}
+
+/// Code, the stack, and the boot modules all have to be identity mapped
+unsafe fn translate_spot_checks(
+    proc: &mut Amd64,
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    let root = proc.boot_page_tables.root();
+    let stack_variable = 0u8;
+    let addresses = [
+        exit_qemu as fn(bool) -> ! as usize,
+        addr_of!(stack_variable) as usize,
+    ]
+    .into_iter()
+    .chain(boot_report.modules.iter().map(|module| module.range.start));
+    for address in addresses {
+        if translate(root, address).map(|translation| translation.address)
+            != Some(PhysicalAddress::new(address))
+        {
+            return Err("an address isn't identity mapped");
+        }
+    }
+    Ok(())
+}
+ktest!(translate_spot_checks);
+
+/**
+ * EFER.NXE has to be on exactly when the processor has a no-execute bit, and a new address space
+ * may only keep its stack from being executed when it is. QEMU runs this with and without NX.
+ */
+unsafe fn no_execute_matches_the_processor(
+    proc: &mut Amd64,
+    _: &BootReport,
+) -> Result<(), &'static str> {
+    let nx = CpuFeatures::detect().nx;
+    if Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) != nx {
+        return Err("EFER.NXE doesn't match whether the processor supports it");
+    }
+    if proc.no_execute.is_empty() == nx {
+        return Err("the no-execute flag doesn't match whether the processor supports it");
+    }
+    let Some((root, _)) = proc.initialize_process_page_tables() else {
+        return Err("no frames were free for an address space");
+    };
+    // The stack is at the very top of the address space
+    let stack = translate(&*root, usize::MAX);
+    proc.abandon_address_space(root);
+    match stack {
+        None => Err("the stack of a new address space isn't mapped"),
+        Some(translation) if translation.executable == nx => {
+            Err("the stack's no-execute bit doesn't match the processor")
+        }
+        Some(_) => Ok(()),
+    }
+}
+ktest!(no_execute_matches_the_processor);
+
+/// Where `writes_to_read_only_pages_fault` maps its page. It's past the identity map, which takes up
+/// the first entry of the root page table.
+const READ_ONLY_PAGE: usize = 0x80_0000_0000;
+
+/**
+ * With CR0.WP set, a write from the kernel to a read-only page has to fault rather than quietly
+ * succeed. Every page of the identity map is writable, so the page is mapped read-only in a new
+ * address space.
+ */
+unsafe fn writes_to_read_only_pages_fault(
+    proc: &mut Amd64,
+    _: &BootReport,
+) -> Result<(), &'static str> {
+    const VALUE: u8 = 0x5a;
+    if !Cr0::read().contains(Cr0Flags::WRITE_PROTECT) {
+        return Err("CR0.WP is clear");
+    }
+    let Some((root, _)) = proc.initialize_process_page_tables() else {
+        return Err("no frames were free for an address space");
+    };
+    if proc
+        .copy_into_address_space(&mut *root, READ_ONLY_PAGE, &[VALUE], 1, SegmentFlags(0))
+        .is_none()
+    {
+        proc.abandon_address_space(root);
+        return Err("no frames were free for the read-only page");
+    }
+    let (boot_root, cr3_flags) = Cr3::read();
+    Cr3::write(
+        PhysFrame::containing_address(PhysAddr::new(root as u64)),
+        cr3_flags,
+    );
+    // The page is a user page, which SMAP would fault on before CR0.WP got a say
+    let user_access = UserAccessGuard::begin();
+    let fault = probe_write(READ_ONLY_PAGE);
+    let value = (READ_ONLY_PAGE as *const u8).read_volatile();
+    drop(user_access);
+    Cr3::write(boot_root, cr3_flags);
+    proc.abandon_address_space(root);
+    match fault {
+        None => Err("a write to a read-only page succeeded"),
+        Some(error_code) if page_fault_cause(error_code, false) != "write to read-only page" => {
+            Err("the page fault wasn't reported as a write to a read-only page")
+        }
+        Some(_) if value != VALUE => Err("the read-only page was changed"),
+        Some(_) => Ok(()),
+    }
+}
+ktest!(writes_to_read_only_pages_fault);
+
+/**
+ * CR4.SMEP and CR4.SMAP have to be on exactly when the processor has them, and in a process's
+ * address space the kernel's code, data and local APIC have to be out of user mode's reach while
+ * the rest of the identity map isn't. QEMU runs this with and without SMEP and SMAP.
+ */
+unsafe fn kernel_memory_is_supervisor_only(
+    proc: &mut Amd64,
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    let cpu_features = CpuFeatures::detect();
+    let cr4 = Cr4::read();
+    if cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION) != cpu_features.smep
+        || cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION) != cpu_features.smap
+    {
+        return Err("CR4.SMEP or CR4.SMAP doesn't match whether the processor supports it");
+    }
+    let Some(module) = boot_report.modules.first() else {
+        return Err("there are no boot modules");
+    };
+    let kernel_code = exit_qemu as fn(bool) -> ! as usize;
+    let kernel_data = FAULT_RECOVERY.as_ptr() as usize;
+    let local_apic = phys_to_usize(xapic_base()).ok_or("the local APIC is out of reach")?;
+    if translate(proc.boot_page_tables.root(), module.range.start)
+        .is_none_or(|translation| translation.user_accessible)
+    {
+        return Err("the kernel's own view of the identity map is user accessible");
+    }
+    let Some((root, _)) = proc.initialize_process_page_tables() else {
+        return Err("no frames were free for an address space");
+    };
+    let user_accessible =
+        |address| translate(&*root, address).map(|translation| translation.user_accessible);
+    let result = if [kernel_code, kernel_data, local_apic]
+        .into_iter()
+        .any(|address| user_accessible(address) != Some(false))
+    {
+        Err("the kernel's memory is user accessible in a process's address space")
+    } else if user_accessible(module.range.start) != Some(true) {
+        Err("a boot module isn't user accessible in a process's address space")
+    } else {
+        Ok(())
+    };
+    proc.abandon_address_space(root);
+    result
+}
+ktest!(kernel_memory_is_supervisor_only);
+
+/// Where `smap_guards_user_memory` maps its page, past the identity map like `READ_ONLY_PAGE`
+const USER_PAGE: usize = 0x80_0000_0000;
+
+/**
+ * With SMAP on, the kernel has to fault when it touches a user page without a `UserAccessGuard`,
+ * and the fault has to be put down to SMAP. `write_console` has to be able to read the same page,
+ * since it holds a guard. Processors without SMAP pass trivially.
+ */
+unsafe fn smap_guards_user_memory(proc: &mut Amd64, _: &BootReport) -> Result<(), &'static str> {
+    const MESSAGE: &[u8] = b"Logged from a user page";
+    if !CpuFeatures::detect().smap {
+        return Ok(());
+    }
+    let Some((root, _)) = proc.initialize_process_page_tables() else {
+        return Err("no frames were free for an address space");
+    };
+    let writable = SegmentFlags(ELF_WRITABLE_SEGMENT);
+    if proc
+        .copy_into_address_space(&mut *root, USER_PAGE, MESSAGE, MESSAGE.len(), writable)
+        .is_none()
+    {
+        proc.abandon_address_space(root);
+        return Err("no frames were free for the user page");
+    }
+    let (boot_root, cr3_flags) = Cr3::read();
+    Cr3::write(
+        PhysFrame::containing_address(PhysAddr::new(root as u64)),
+        cr3_flags,
+    );
+    let fault = probe_write(USER_PAGE);
+    let cause = fault.map(|error_code| {
+        let blocked = blocked_by_supervisor_protection(USER_PAGE, error_code, rflags::read());
+        page_fault_cause(error_code, blocked)
+    });
+    let logged = syscall::write_console([USER_PAGE, MESSAGE.len(), 0, 0, 0]);
+    Cr3::write(boot_root, cr3_flags);
+    proc.abandon_address_space(root);
+    match cause {
+        None => Err("an unguarded write to a user page succeeded"),
+        Some(cause) if !cause.starts_with("SMAP: ") => {
+            Err("the page fault wasn't put down to SMAP")
+        }
+        Some(_) if logged != 0 => Err("write_console couldn't read a user page"),
+        Some(_) => Ok(()),
+    }
+}
+ktest!(smap_guards_user_memory);
+
+/**
+ * Every PC has a host bridge at 00:00.0. Scanning again has to find the same devices with the same
+ * BARs, which it wouldn't if sizing the BARs at boot had left any of them changed.
+ */
+unsafe fn pci_scan_finds_the_host_bridge(
+    _: &mut Amd64,
+    _: &BootReport,
+) -> Result<(), &'static str> {
+    let devices = pci::devices();
+    let host_bridge = devices
+        .iter()
+        .find(|device| device.class == 0x06 && device.subclass == 0x00);
+    match host_bridge {
+        Some(bridge) if bridge.address == pci::Address::default() => {}
+        Some(_) => return Err("the host bridge isn't at 00:00.0"),
+        None => return Err("there's no host bridge"),
+    }
+    let mut index = 0;
+    let mut matches = true;
+    pci::scan(&mut PortConfigSpace, |device| {
+        matches &= devices.get(index) == Some(&device);
+        index += 1;
+    });
+    if !matches || index != devices.len() {
+        return Err("scanning again found different devices or BARs");
+    }
+    Ok(())
+}
+ktest!(pci_scan_finds_the_host_bridge);
+
+/// Where the page fault handler resumes when a test expects a fault, or 0 if none is expected
+static FAULT_RECOVERY: AtomicUsize = AtomicUsize::new(0);
+/// The error code of the last page fault that a test expected
+static EXPECTED_FAULT_ERROR_CODE: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * Resumes at the recovery address if a test is expecting a page fault. Returns false if none is
+ * expected, in which case the fault is reported as usual.
+ *
+ * # Safety
+ *
+ * This must only be called by the page fault handler with the frame that it was given.
+ */
+pub(super) unsafe fn recover_from_expected_fault(
+    stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) -> bool {
+    let recovery = FAULT_RECOVERY.swap(0, Ordering::SeqCst);
+    if recovery == 0 {
+        return false;
+    }
+    EXPECTED_FAULT_ERROR_CODE.store(error_code.bits(), Ordering::SeqCst);
+    stack_frame
+        .as_mut()
+        .update(|frame| frame.instruction_pointer = VirtAddr::new(recovery as u64));
+    true
+}
+
+/// Writes to `address` and returns the error code of the page fault that the write causes, or
+/// `None` if it succeeds
+unsafe fn probe_write(address: usize) -> Option<PageFaultErrorCode> {
+    // The handler clears the recovery address when it resumes there, so it's still set if
+    // nothing faulted
+    asm!(
+        "lea {recovery}, [rip + 2f]",
+        "mov [{slot}], {recovery}",
+        "mov byte ptr [{address}], 0",
+        "2:",
+        slot = in(reg) FAULT_RECOVERY.as_ptr(),
+        address = in(reg) address,
+        recovery = out(reg) _,
+    );
+    if FAULT_RECOVERY.swap(0, Ordering::SeqCst) == 0 {
+        Some(PageFaultErrorCode::from_bits_truncate(
+            EXPECTED_FAULT_ERROR_CODE.load(Ordering::SeqCst),
+        ))
+    } else {
+        None
+    }
+}
+
+/**
+ * Every byte of available memory has to be identity mapped for the kernel to write to, nothing past
+ * the end of the identity map may be mapped in its part of the address space, the kernel's code
+ * has to be executable, and the APICs mustn't be cached. Each mismatch is logged with what was
+ * expected and what the page tables hold.
+ */
+unsafe fn identity_map_matches_memory_map(
+    _: &mut Amd64,
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    // The tables that the processor is using, rather than the ones the kernel thinks it set up
+    let root = physical_address(Cr3::read().0.start_address())
+        .ok_or("CR3 points past the end of the address space")?;
+    let root = &*(root.as_usize() as *const PageTable);
+    let mut mismatches = Mismatches(0);
+
+    let mut sanitized_memory_map = SANITIZED_MEMORY_MAP.lock();
+    let memory_map = sanitized_memory_map
+        .sanitize(boot_report.memory_map)
+        .ok_or("the memory map is too large")?;
+    let identity_map = 0..boot_report.identity_map_end;
+    for area in usable_memory_areas(memory_map).filter_map(MemoryMapEntry::address_range) {
+        check_identity_mapped(root, intersect(area, identity_map.clone()), &mut mismatches);
+    }
+    check_nothing_mapped_past(root, boot_report.identity_map_end, &mut mismatches);
+
+    let kernel_code = exit_qemu as fn(bool) -> ! as usize;
+    match translate(root, kernel_code) {
+        Some(translation) if translation.executable => {}
+        found => mismatches.report(
+            kernel_code,
+            format_args!("executable kernel code"),
+            format_args!("{}", Found(found)),
+        ),
+    }
+
+    let features = CpuFeatures::detect();
+    let apics = [xapic_base(), u64::from(DEFAULT_IO_APIC_ADDRESS)];
+    for apic in apics.into_iter().filter_map(phys_to_usize) {
+        let Some(translation) = translate(root, apic) else {
+            continue;
+        };
+        if translation.address != PhysicalAddress::new(apic)
+            || !(translation.uncacheable || features.mtrr && mtrrs_make_uncacheable(apic))
+        {
+            mismatches.report(
+                apic,
+                format_args!("an APIC that's unmapped or identity mapped and uncacheable"),
+                format_args!("{}", Found(Some(translation))),
+            );
+        }
+    }
+
+    if mismatches.0 > 0 {
+        error!("Found {} identity map mismatches", mismatches.0);
+        return Err("the identity map doesn't match the memory map");
+    }
+    Ok(())
+}
+ktest!(identity_map_matches_memory_map);
+
+/// The most mismatches that are logged by one test, so that a broken map doesn't flood the log
+const MAX_REPORTED_MISMATCHES: usize = 16;
+
+/// Counts the mismatches that have been found and logs the first few of them
+struct Mismatches(usize);
+
+impl Mismatches {
+    fn report(&mut self, address: usize, expected: fmt::Arguments, found: fmt::Arguments) {
+        if self.0 < MAX_REPORTED_MISMATCHES {
+            error!("Identity map mismatch at {address:#x}: expected {expected}, found {found}");
+        }
+        self.0 += 1;
+    }
+}
+
+/// Describes what a walk of the page tables found
+struct Found(Option<Translation>);
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(translation) = self.0 else {
+            return f.write_str("nothing mapped");
+        };
+        write!(
+            f,
+            "{:#x} in a {:#x} byte page (writable: {}, executable: {}, uncacheable: {})",
+            translation.address.as_usize(),
+            translation.page_size,
+            translation.writable,
+            translation.executable,
+            translation.uncacheable
+        )
+    }
+}
+
+/// Checks that every page of `region` is identity mapped and writable
+unsafe fn check_identity_mapped(
+    root: &PageTable,
+    region: Range<usize>,
+    mismatches: &mut Mismatches,
+) {
+    let mut address = region.start;
+    while address < region.end {
+        let translation = translate(root, address);
+        let page_size = match translation {
+            Some(translation)
+                if translation.address == PhysicalAddress::new(address) && translation.writable =>
+            {
+                translation.page_size
+            }
+            found => {
+                mismatches.report(
+                    address,
+                    format_args!("a writable identity mapping"),
+                    format_args!("{}", Found(found)),
+                );
+                page_size(0)
+            }
+        };
+        address = (address / page_size + 1) * page_size;
+    }
+}
+
+/// Checks that the identity map's part of the address space is empty past the gigabyte that
+/// `end` is in
+unsafe fn check_nothing_mapped_past(root: &PageTable, end: usize, mismatches: &mut Mismatches) {
+    let entry = &root[0];
+    if entry.is_unused() {
+        mismatches.report(
+            0,
+            format_args!("an identity map"),
+            format_args!("nothing mapped"),
+        );
+        return;
+    }
+    let Some(p3_table) = physical_address(entry.addr()) else {
+        mismatches.report(
+            0,
+            format_args!("an identity map"),
+            format_args!("a table past the end of the address space"),
+        );
+        return;
+    };
+    let p3_table = &*(p3_table.as_usize() as *const PageTable);
+    for gigabyte in end.div_ceil(GIGABYTE)..page_size(3) / GIGABYTE {
+        if !p3_table[gigabyte].is_unused() {
+            let address = gigabyte * GIGABYTE;
+            mismatches.report(
+                address,
+                format_args!("nothing mapped past the end of the identity map"),
+                format_args!("{}", Found(translate(root, address))),
+            );
+        }
+    }
+}
+
+const IA32_MTRRCAP: u32 = 0xfe;
+const IA32_MTRR_PHYSBASE0: u32 = 0x200;
+const IA32_MTRR_DEF_TYPE: u32 = 0x2ff;
+/// Set in `IA32_MTRR_DEF_TYPE` when the MTRRs are in use, and in a `IA32_MTRR_PHYSMASK` register
+/// when its range is
+const MTRR_ENABLED: u64 = 1 << 11;
+const MTRR_TYPE_MASK: u64 = 0xff;
+const MTRR_UNCACHEABLE: u64 = 0;
+
+/**
+ * Returns true if the variable range MTRRs make the memory at `address` uncacheable regardless of
+ * the page tables. The fixed range MTRRs only cover the first megabyte, so they're ignored.
+ *
+ * # Safety
+ *
+ * The processor must support MTRRs.
+ */
+unsafe fn mtrrs_make_uncacheable(address: usize) -> bool {
+    let default_type = Msr::new(IA32_MTRR_DEF_TYPE).read();
+    if default_type & MTRR_ENABLED == 0 {
+        return true;
+    }
+    let mut in_a_range = false;
+    for index in 0..(Msr::new(IA32_MTRRCAP).read() & 0xff) as u32 {
+        let base = Msr::new(IA32_MTRR_PHYSBASE0 + 2 * index).read();
+        let mask = Msr::new(IA32_MTRR_PHYSBASE0 + 2 * index + 1).read();
+        let address_mask = mask & !0xfff;
+        if mask & MTRR_ENABLED == 0 || address as u64 & address_mask != base & address_mask {
+            continue;
+        }
+        if base & MTRR_TYPE_MASK == MTRR_UNCACHEABLE {
+            return true;
+        }
+        in_a_range = true;
+    }
+    !in_a_range && default_type & MTRR_TYPE_MASK == MTRR_UNCACHEABLE
+}
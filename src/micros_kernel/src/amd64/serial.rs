@@ -0,0 +1,73 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+/// The I/O port of the first serial port on PC compatible machines
+pub const COM1: u16 = 0x3f8;
+
+/// A 16550 compatible UART serial port
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    #[must_use]
+    pub const fn new(base: u16) -> Self {
+        Self { base }
+    }
+
+    /**
+     * Configures the serial port for 38400 baud with 8 data bits, no parity, and one stop bit.
+     *
+     * # Safety
+     *
+     * `self` must refer to a serial port that isn't being used by anything else.
+     */
+    pub unsafe fn init(&mut self) {
+        self.port(INTERRUPT_ENABLE).write(0x00);
+        self.port(LINE_CONTROL).write(DIVISOR_LATCH_ACCESS);
+        self.port(DATA).write(BAUD_RATE_DIVISOR);
+        self.port(INTERRUPT_ENABLE).write(0x00);
+        self.port(LINE_CONTROL)
+            .write(EIGHT_DATA_BITS_NO_PARITY_ONE_STOP_BIT);
+        self.port(FIFO_CONTROL).write(ENABLE_AND_CLEAR_FIFOS);
+        self.port(MODEM_CONTROL)
+            .write(DATA_TERMINAL_READY | REQUEST_TO_SEND | AUXILIARY_OUTPUT_2);
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.port(LINE_STATUS).read() & TRANSMITTER_EMPTY == 0 {}
+            self.port(DATA).write(byte);
+        }
+    }
+
+    fn port(&self, register: u16) -> Port<u8> {
+        Port::new(self.base + register)
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const DIVISOR_LATCH_ACCESS: u8 = 0x80;
+// 115200 / 3 = 38400 baud
+const BAUD_RATE_DIVISOR: u8 = 3;
+const EIGHT_DATA_BITS_NO_PARITY_ONE_STOP_BIT: u8 = 0x03;
+const ENABLE_AND_CLEAR_FIFOS: u8 = 0xc7;
+const DATA_TERMINAL_READY: u8 = 0x01;
+const REQUEST_TO_SEND: u8 = 0x02;
+const AUXILIARY_OUTPUT_2: u8 = 0x08;
+const TRANSMITTER_EMPTY: u8 = 0x20;
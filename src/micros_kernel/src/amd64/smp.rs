@@ -0,0 +1,182 @@
+//! Starting the application processors. Nothing is scheduled on them yet, so each one just sets up
+//! its local APIC, marks itself online, and halts with interrupts disabled.
+
+use super::{
+    apic,
+    init::{enable_write_protection, load_descriptor_tables},
+    pit, user_access, Amd64,
+};
+use crate::{
+    acpi::{PlatformInfo, MAX_PROCESSORS},
+    Architecture,
+};
+use core::{
+    ops::Range,
+    ptr::{self, addr_of, addr_of_mut},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
+use frame_allocation::{
+    amd64::{Amd64FrameAllocator, FOUR_KILOBYTES},
+    range_math::intersect,
+};
+use x86_64::registers::control::Cr3;
+
+/// The page number that startup IPIs give for the trampoline. Startup IPIs can only start
+/// processors at the beginning of a page below 1 MB.
+const START_PAGE: u8 = 8;
+
+/// Where the trampoline that application processors start in is copied to. This must match
+/// `AP_TRAMPOLINE_ADDRESS` in `ap_trampoline.asm`.
+const TRAMPOLINE_ADDRESS: usize = START_PAGE as usize * FOUR_KILOBYTES;
+
+/// The page that the trampoline is copied to, which has to be kept out of the frame allocator
+pub const TRAMPOLINE_PAGE: Range<usize> = TRAMPOLINE_ADDRESS..TRAMPOLINE_ADDRESS + FOUR_KILOBYTES;
+
+/// How long to wait for a processor to come online after starting it
+const STARTUP_TIMEOUT_MICROSECONDS: u64 = 100_000;
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static mut ap_trampoline_page_table: u64;
+    static mut ap_trampoline_stack_top: u64;
+    static mut ap_trampoline_entry: u64;
+    static mut ap_trampoline_argument: u64;
+}
+
+/// What the kernel keeps track of for each processor. Processors are numbered by their position in
+/// the MADT.
+pub struct PerCpu {
+    pub apic_id: AtomicU32,
+    /// Set by the processor itself once it's running kernel code
+    pub online: AtomicBool,
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            apic_id: AtomicU32::new(0),
+            online: AtomicBool::new(false),
+        }
+    }
+}
+
+pub static PER_CPU: [PerCpu; MAX_PROCESSORS] = [const { PerCpu::new() }; MAX_PROCESSORS];
+
+/// The number of processors that are running, including the boot processor
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/**
+ * Starts every enabled processor in `platform` other than the boot processor, one at a time, and
+ * returns the number of processors that are online. Each processor's stack is taken from
+ * `allocator`.
+ *
+ * # Safety
+ *
+ * `TRAMPOLINE_PAGE` must be free memory that nothing else uses, the kernel's root page table must
+ * be below 4 GB, and the boot processor's descriptor tables and local APIC must be set up.
+ */
+pub unsafe fn start_application_processors(
+    platform: &PlatformInfo,
+    boot_processor_apic_id: u32,
+    allocator: &mut Amd64FrameAllocator,
+) -> usize {
+    let Ok(root_page_table) = u32::try_from(Cr3::read().0.start_address().as_u64()) else {
+        warn!("The root page table is above 4 GB, so the other processors can't be started");
+        return CPUS_ONLINE.load(Ordering::Acquire);
+    };
+    copy_trampoline();
+    relocated(addr_of_mut!(ap_trampoline_page_table)).write_volatile(root_page_table.into());
+    relocated(addr_of_mut!(ap_trampoline_entry))
+        .write_volatile(application_processor_main as extern "C" fn(usize) -> ! as usize as u64);
+
+    for (cpu, &apic_id) in platform.local_apic_ids.iter().enumerate() {
+        PER_CPU[cpu].apic_id.store(apic_id, Ordering::Relaxed);
+        if apic_id == boot_processor_apic_id {
+            PER_CPU[cpu].online.store(true, Ordering::Release);
+            continue;
+        }
+        let Some(stack) = allocator.get_4k_frame() else {
+            warn!("There's no memory left for the stacks of the other processors");
+            break;
+        };
+        relocated(addr_of_mut!(ap_trampoline_stack_top))
+            .write_volatile((stack + FOUR_KILOBYTES).as_usize() as u64);
+        relocated(addr_of_mut!(ap_trampoline_argument)).write_volatile(cpu as u64);
+
+        let online = || PER_CPU[cpu].online.load(Ordering::Acquire);
+        apic::start_processor(apic_id, START_PAGE, online);
+        if !pit::wait_for(STARTUP_TIMEOUT_MICROSECONDS, online) {
+            warn!("The processor with local APIC ID {apic_id} didn't start");
+            // It never got as far as the stack, so nothing will touch it
+            allocator.four_kilobyte_pages.add_frame(stack);
+        }
+    }
+    CPUS_ONLINE.load(Ordering::Acquire)
+}
+
+/// Where the application processors go once they're in long mode
+extern "C" fn application_processor_main(cpu: usize) -> ! {
+    unsafe {
+        enable_write_protection();
+        user_access::enable_on_this_processor();
+        load_descriptor_tables();
+        if apic::init_application_processor().is_none() {
+            warn!("Processor {cpu} couldn't set up its local APIC");
+        }
+    }
+    PER_CPU[cpu].online.store(true, Ordering::Release);
+    CPUS_ONLINE.fetch_add(1, Ordering::AcqRel);
+    Amd64::halt()
+}
+
+unsafe fn copy_trampoline() {
+    let start = addr_of!(ap_trampoline_start);
+    let size = addr_of!(ap_trampoline_end) as usize - start as usize;
+    ptr::copy_nonoverlapping(start, TRAMPOLINE_ADDRESS as *mut u8, size);
+}
+
+/// The address that a variable in the trampoline ends up at once it's been copied
+fn relocated<T>(variable: *mut T) -> *mut T {
+    let offset = variable as usize - addr_of!(ap_trampoline_start) as usize;
+    (TRAMPOLINE_ADDRESS + offset) as *mut T
+}
+
+/// Returns true if the trampoline can be copied without overwriting anything in `in_use`
+pub fn trampoline_page_is_free(in_use: impl IntoIterator<Item = Range<usize>>) -> bool {
+    in_use
+        .into_iter()
+        .all(|range| intersect(range, TRAMPOLINE_PAGE).is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_trampoline_page_can_be_reached_by_a_startup_ipi() {
+        // A startup IPI starts a processor in real mode at the page whose number is its vector
+        assert_eq!(
+            TRAMPOLINE_PAGE.start,
+            usize::from(START_PAGE) * FOUR_KILOBYTES
+        );
+        assert_eq!(TRAMPOLINE_PAGE.len(), FOUR_KILOBYTES);
+        const { assert!(TRAMPOLINE_PAGE.end <= 0x10_0000) };
+    }
+
+    #[test]
+    fn the_trampoline_page_is_free_unless_something_overlaps_it() {
+        let just_before = 0..TRAMPOLINE_PAGE.start;
+        let just_after = TRAMPOLINE_PAGE.end..0x10_0000;
+        assert!(trampoline_page_is_free([]));
+        assert!(trampoline_page_is_free([just_before, just_after]));
+        let first_byte = 0..TRAMPOLINE_PAGE.start + 1;
+        assert!(!trampoline_page_is_free([first_byte]));
+        let last_byte = TRAMPOLINE_PAGE.end - 1..TRAMPOLINE_PAGE.end;
+        assert!(!trampoline_page_is_free([last_byte]));
+        assert!(!trampoline_page_is_free([0x1000..0x2000, 0..0x10_0000]));
+        // An empty range doesn't use any memory even if it starts in the page
+        let empty = TRAMPOLINE_PAGE.start + 1..TRAMPOLINE_PAGE.start + 1;
+        assert!(trampoline_page_is_free([empty]));
+    }
+}
@@ -0,0 +1,150 @@
+use super::{
+    init::{is_user_accessible, Amd64},
+    memory_service, power,
+    user_access::UserAccessGuard,
+};
+use crate::idle_until;
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    slice, str,
+};
+use layout_assert::layout_assert;
+use micros_abi::syscall::{ERROR_INVALID_ADDRESS, ERROR_INVALID_ARGUMENT, ERROR_UNKNOWN_SYSCALL};
+use x86_64::{
+    registers::{
+        model_specific::{Efer, EferFlags, KernelGsBase, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    structures::gdt::SegmentSelector,
+    VirtAddr,
+};
+
+/// The segments that `syscall` and `sysret` switch between
+pub struct SyscallSegments {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub user_data: SegmentSelector,
+}
+
+/**
+ * Enables the `syscall` instruction and points it at `syscall_entry`. Returns `None` if the
+ * segments aren't laid out the way `sysret` needs them to be.
+ *
+ * # Safety
+ *
+ * The segments must be loaded in the current global descriptor table.
+ */
+pub unsafe fn init(segments: &SyscallSegments) -> Option<()> {
+    Star::write(
+        segments.user_code,
+        segments.user_data,
+        segments.kernel_code,
+        segments.kernel_data,
+    )
+    .ok()?;
+    LStar::write(VirtAddr::from_ptr(syscall_entry as *const ()));
+    // Interrupts stay disabled until `syscall_entry` is on the kernel stack, the Rust code that
+    // handles the call expects the direction flag to be clear, and user mode can set the alignment
+    // check flag to get around SMAP
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::ALIGNMENT_CHECK);
+    let scratch = &mut *addr_of_mut!(SYSCALL_SCRATCH);
+    scratch.kernel_stack_top = addr_of!(SYSCALL_STACK) as usize + SYSCALL_STACK_SIZE;
+    KernelGsBase::write(VirtAddr::from_ptr(addr_of!(SYSCALL_SCRATCH)));
+    Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    Some(())
+}
+
+/// Called by `syscall_entry` with the system call number moved after the arguments
+#[no_mangle]
+unsafe extern "C" fn handle_syscall(
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+    arg4: usize,
+    number: usize,
+) -> isize {
+    SYSCALL_HANDLERS
+        .get(number)
+        .map_or(ERROR_UNKNOWN_SYSCALL, |handler| {
+            handler([arg0, arg1, arg2, arg3, arg4])
+        })
+}
+
+type SyscallHandler = unsafe fn([usize; 5]) -> isize;
+
+/// The handlers for each system call, indexed by system call number
+static SYSCALL_HANDLERS: [SyscallHandler; 5] = [
+    write_console,
+    // The frame service calls are only made by the kernel itself so far
+    unknown_syscall,
+    unknown_syscall,
+    yield_until_event,
+    fatal_error,
+];
+
+unsafe fn unknown_syscall(_: [usize; 5]) -> isize {
+    ERROR_UNKNOWN_SYSCALL
+}
+
+pub(super) unsafe fn write_console([address, len, ..]: [usize; 5]) -> isize {
+    // The message is logged straight out of the caller's memory
+    let _access = UserAccessGuard::begin();
+    let Some(end) = address.checked_add(len) else {
+        return ERROR_INVALID_ADDRESS;
+    };
+    if !is_user_accessible(address..end) {
+        return ERROR_INVALID_ADDRESS;
+    }
+    let Ok(message) = str::from_utf8(slice::from_raw_parts(address as *const u8, len)) else {
+        return ERROR_INVALID_ARGUMENT;
+    };
+    info!("{message}");
+    0
+}
+
+/// Halts until an interrupt handler sends the memory manager an event. The process can't halt the
+/// processor itself from ring 3, and spinning instead keeps a core busy.
+unsafe fn yield_until_event(_: [usize; 5]) -> isize {
+    // `syscall` masks interrupts, which `idle_until` needs
+    idle_until::<Amd64>(memory_service::events_pending);
+    0
+}
+
+/// The memory manager can't go on, and nothing else can run without it
+unsafe fn fatal_error(_: [usize; 5]) -> isize {
+    error!("The memory manager hit a fatal error");
+    power::on_fatal_error()
+}
+
+/// What `syscall_entry` finds through the GS segment after `swapgs`
+#[repr(C)]
+struct SyscallScratch {
+    kernel_stack_top: usize,
+    /// Where `syscall_entry` saves the caller's stack pointer while it switches stacks
+    user_stack_pointer: usize,
+}
+
+// `syscall_entry` reaches the fields at fixed offsets from GS, and the fields are private, so this
+// is checked here rather than with the rest of the kernel's layouts
+layout_assert!(SyscallScratch, size = 16, align = 8, {
+    kernel_stack_top: 0,
+    user_stack_pointer: 8,
+});
+
+static mut SYSCALL_SCRATCH: SyscallScratch = SyscallScratch {
+    kernel_stack_top: 0,
+    user_stack_pointer: 0,
+};
+
+const SYSCALL_STACK_SIZE: usize = 0x4000;
+
+#[repr(C, align(16))]
+struct SyscallStack([u8; SYSCALL_STACK_SIZE]);
+
+static mut SYSCALL_STACK: SyscallStack = SyscallStack([0; SYSCALL_STACK_SIZE]);
+
+extern "C" {
+    fn syscall_entry();
+}
@@ -0,0 +1,240 @@
+//! A monotonic clock built on the TSC. Its frequency comes from CPUID when the processor reports it
+//! and is measured against the PIT when it doesn't. Time 0 is when the clock started.
+
+use super::pit;
+use core::{
+    arch::x86_64::{__cpuid, _rdtsc, CpuidResult},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+const HERTZ_PER_MEGAHERTZ: u64 = 1_000_000;
+/// The number of fractional bits in `NANOSECONDS_PER_CYCLE`
+const SHIFT: u32 = 32;
+/// How long the TSC is timed against the PIT when CPUID doesn't report its frequency
+const MEASUREMENT_MICROSECONDS: u64 = 50_000;
+
+/// The CPUID leaf that reports the TSC's frequency as a ratio of the core crystal clock
+const TSC_LEAF: u32 = 0x15;
+/// The CPUID leaf that reports the processor's base frequency in MHz
+const FREQUENCY_LEAF: u32 = 0x16;
+const EXTENDED_LEAF_BASE: u32 = 0x8000_0000;
+/// The extended CPUID leaf that reports whether the TSC is invariant
+const POWER_MANAGEMENT_LEAF: u32 = EXTENDED_LEAF_BASE + 7;
+/// The TSC runs at the same rate in every power state
+const INVARIANT_TSC_BIT: u32 = 1 << 8;
+
+/// Nanoseconds per TSC cycle as a fixed point number with `SHIFT` fractional bits, or 0 while the
+/// clock isn't running
+static NANOSECONDS_PER_CYCLE: AtomicU64 = AtomicU64::new(0);
+/// The TSC when the clock started
+static START: AtomicU64 = AtomicU64::new(0);
+/// The number of nanoseconds from the Unix epoch to when the clock started, or 0 if the wall clock
+/// hasn't been set
+static WALL_CLOCK_AT_START: AtomicU64 = AtomicU64::new(0);
+
+/// A point in time, counted from when the clock started
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    #[must_use]
+    pub fn now() -> Self {
+        Self(nanoseconds())
+    }
+
+    /// How long after `earlier` this is, or no time at all if it's before `earlier`
+    #[must_use]
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    #[must_use]
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+}
+
+/**
+ * Works out how fast the TSC runs and starts the clock. The PIT is used if CPUID doesn't report
+ * the frequency. The clock isn't started if neither gives a frequency.
+ */
+pub fn init() {
+    let max_leaf = __cpuid(0).eax;
+    let leaf = |leaf| (max_leaf >= leaf).then(|| __cpuid(leaf));
+    let frequency = frequency_from_cpuid(leaf(TSC_LEAF), leaf(FREQUENCY_LEAF))
+        .unwrap_or_else(measure_frequency);
+    if frequency == 0 {
+        warn!("The TSC didn't move while the PIT counted, so there's no clock");
+        return;
+    }
+    // There's no periodic timer to count time with instead, so a TSC that isn't invariant is still
+    // used, and time drifts whenever the processor changes its frequency
+    let invariant = __cpuid(EXTENDED_LEAF_BASE).eax >= POWER_MANAGEMENT_LEAF
+        && __cpuid(POWER_MANAGEMENT_LEAF).edx & INVARIANT_TSC_BIT != 0;
+    if !invariant {
+        warn!("The TSC isn't invariant, so the clock may drift");
+    }
+    START.store(unsafe { _rdtsc() }, Ordering::Relaxed);
+    NANOSECONDS_PER_CYCLE.store(nanoseconds_per_cycle(frequency), Ordering::Release);
+    info!("The TSC runs at {} kHz", frequency / 1000);
+}
+
+/// The number of nanoseconds since the clock started, or 0 if it hasn't
+#[must_use]
+pub fn nanoseconds() -> u64 {
+    let nanoseconds_per_cycle = NANOSECONDS_PER_CYCLE.load(Ordering::Acquire);
+    let cycles = unsafe { _rdtsc() }.wrapping_sub(START.load(Ordering::Relaxed));
+    cycles_to_nanoseconds(cycles, nanoseconds_per_cycle)
+}
+
+/// Sets the wall clock to `unix_seconds` seconds after the Unix epoch
+pub fn set_wall_clock(unix_seconds: u64) {
+    let now = unix_seconds.saturating_mul(NANOSECONDS_PER_SECOND);
+    WALL_CLOCK_AT_START.store(now.saturating_sub(nanoseconds()), Ordering::Relaxed);
+}
+
+/// How long it's been since the Unix epoch, or `None` if the wall clock hasn't been set. The wall
+/// clock is set from the RTC, so it's only as accurate as the RTC's whole seconds, and it stands
+/// still if the TSC clock isn't running.
+#[must_use]
+pub fn wall_clock() -> Option<Duration> {
+    match WALL_CLOCK_AT_START.load(Ordering::Relaxed) {
+        0 => None,
+        at_start => Some(Duration::from_nanos(at_start + nanoseconds())),
+    }
+}
+
+/**
+ * The TSC's frequency in Hz according to CPUID leaves 0x15 and 0x16, if the processor has them.
+ * Leaf 0x15 gives the TSC's frequency as a ratio of the crystal clock, whose frequency it can leave
+ * out. In that case the base frequency from leaf 0x16 is what the TSC runs at.
+ */
+fn frequency_from_cpuid(
+    tsc_leaf: Option<CpuidResult>,
+    frequency_leaf: Option<CpuidResult>,
+) -> Option<u64> {
+    if let Some(CpuidResult {
+        eax: denominator,
+        ebx: numerator,
+        ecx: crystal,
+        ..
+    }) = tsc_leaf
+    {
+        if denominator != 0 && numerator != 0 && crystal != 0 {
+            return Some(u64::from(crystal) * u64::from(numerator) / u64::from(denominator));
+        }
+    }
+    frequency_leaf
+        .filter(|leaf| leaf.eax != 0)
+        .map(|leaf| u64::from(leaf.eax) * HERTZ_PER_MEGAHERTZ)
+}
+
+/// Counts TSC cycles during a PIT wait to work out the TSC's frequency in Hz
+fn measure_frequency() -> u64 {
+    let start = unsafe { _rdtsc() };
+    pit::busy_wait_us(MEASUREMENT_MICROSECONDS);
+    let cycles = unsafe { _rdtsc() }.wrapping_sub(start);
+    cycles * (1_000_000 / MEASUREMENT_MICROSECONDS)
+}
+
+/// Nanoseconds per cycle at `frequency` Hz as a fixed point number with `SHIFT` fractional bits
+fn nanoseconds_per_cycle(frequency: u64) -> u64 {
+    let scaled = (u128::from(NANOSECONDS_PER_SECOND) << SHIFT) / u128::from(frequency);
+    u64::try_from(scaled).unwrap_or(u64::MAX)
+}
+
+/// Converts TSC cycles to nanoseconds. The product can't overflow 128 bits, so this is exact to
+/// within the rounding of `nanoseconds_per_cycle` however long the clock has been running.
+fn cycles_to_nanoseconds(cycles: u64, nanoseconds_per_cycle: u64) -> u64 {
+    let nanoseconds = (u128::from(cycles) * u128::from(nanoseconds_per_cycle)) >> SHIFT;
+    u64::try_from(nanoseconds).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3600 * NANOSECONDS_PER_SECOND;
+
+    /// Converts `nanoseconds` worth of cycles at `frequency` Hz and checks that the result is
+    /// within a part per billion of the truth
+    fn check_conversion(frequency: u64, nanoseconds: u64) {
+        let cycles = u64::try_from(
+            u128::from(nanoseconds) * u128::from(frequency) / u128::from(NANOSECONDS_PER_SECOND),
+        )
+        .unwrap();
+        let converted = cycles_to_nanoseconds(cycles, nanoseconds_per_cycle(frequency));
+        let error = converted.abs_diff(nanoseconds);
+        assert!(
+            error <= nanoseconds / NANOSECONDS_PER_SECOND + 1,
+            "{frequency} Hz: {converted} ns instead of {nanoseconds} ns"
+        );
+    }
+
+    #[test]
+    fn converts_at_common_frequencies() {
+        for frequency in [1_000_000_000, 2_500_000_000, 3_000_000_000, 3_192_000_000] {
+            check_conversion(frequency, NANOSECONDS_PER_SECOND);
+            check_conversion(frequency, 1000);
+        }
+        check_conversion(1_193_182, NANOSECONDS_PER_SECOND);
+    }
+
+    #[test]
+    fn long_uptimes_dont_overflow() {
+        check_conversion(3_000_000_000, 10 * HOUR);
+        check_conversion(5_000_000_000, 24 * 365 * HOUR);
+        assert_eq!(
+            cycles_to_nanoseconds(u64::MAX, nanoseconds_per_cycle(1)),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn exact_at_one_gigahertz() {
+        assert_eq!(nanoseconds_per_cycle(1_000_000_000), 1 << SHIFT);
+        assert_eq!(cycles_to_nanoseconds(12_345, 1 << SHIFT), 12_345);
+    }
+
+    fn cpuid(eax: u32, ebx: u32, ecx: u32) -> CpuidResult {
+        CpuidResult {
+            eax,
+            ebx,
+            ecx,
+            edx: 0,
+        }
+    }
+
+    #[test]
+    fn frequency_from_the_crystal_ratio() {
+        // A 24 MHz crystal and a ratio of 2 / 250
+        assert_eq!(
+            frequency_from_cpuid(Some(cpuid(2, 250, 24_000_000)), Some(cpuid(2900, 0, 0))),
+            Some(3_000_000_000)
+        );
+    }
+
+    #[test]
+    fn frequency_from_the_base_frequency() {
+        // The crystal frequency is left out
+        assert_eq!(
+            frequency_from_cpuid(Some(cpuid(2, 250, 0)), Some(cpuid(2900, 0, 0))),
+            Some(2_900_000_000)
+        );
+        assert_eq!(
+            frequency_from_cpuid(None, Some(cpuid(2900, 0, 0))),
+            Some(2_900_000_000)
+        );
+    }
+
+    #[test]
+    fn no_frequency_without_the_leaves() {
+        assert_eq!(frequency_from_cpuid(None, None), None);
+        assert_eq!(
+            frequency_from_cpuid(Some(cpuid(0, 0, 0)), Some(cpuid(0, 0, 0))),
+            None
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Supervisor mode execution prevention (SMEP) and supervisor mode access prevention (SMAP), which
+//! keep the kernel from running user code and from touching user memory by accident.
+
+use super::{cpu::CpuFeatures, init::is_user_accessible};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    registers::{
+        control::{Cr4, Cr4Flags},
+        rflags::{self, RFlags},
+    },
+    structures::idt::PageFaultErrorCode,
+};
+
+static SMEP_ENABLED: AtomicBool = AtomicBool::new(false);
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Turns SMEP and SMAP on for the boot processor if it supports them
+ *
+ * # Safety
+ *
+ * The kernel's code and data must be supervisor only in every address space, and the kernel
+ * mustn't touch user memory outside a `UserAccessGuard` afterwards.
+ */
+pub unsafe fn enable(cpu_features: &CpuFeatures) {
+    SMEP_ENABLED.store(cpu_features.smep, Ordering::Relaxed);
+    SMAP_ENABLED.store(cpu_features.smap, Ordering::Relaxed);
+    enable_on_this_processor();
+    let on_or_off = |enabled| if enabled { "on" } else { "off" };
+    info!(
+        "SMEP is {} and SMAP is {}",
+        on_or_off(cpu_features.smep),
+        on_or_off(cpu_features.smap)
+    );
+}
+
+/**
+ * Turns on whatever `enable` turned on for the boot processor. Application processors call this
+ * while they start.
+ *
+ * # Safety
+ *
+ * The same as for `enable`
+ */
+pub unsafe fn enable_on_this_processor() {
+    Cr4::update(|flags| {
+        flags.set(
+            Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION,
+            SMEP_ENABLED.load(Ordering::Relaxed),
+        );
+        flags.set(
+            Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION,
+            SMAP_ENABLED.load(Ordering::Relaxed),
+        );
+    });
+}
+
+/// Lets the kernel touch user memory for as long as it's alive. SMAP steps aside while RFLAGS.AC
+/// is set, and the flag goes back to how it was when the guard is dropped, so guards can nest.
+pub struct UserAccessGuard {
+    was_allowed: bool,
+}
+
+impl UserAccessGuard {
+    /// Sets RFLAGS.AC. `stac` is an invalid opcode without SMAP, so nothing is done then.
+    #[must_use]
+    pub fn begin() -> Self {
+        let was_allowed = rflags::read().contains(RFlags::ALIGNMENT_CHECK);
+        if SMAP_ENABLED.load(Ordering::Relaxed) && !was_allowed {
+            // Not `nomem`, since memory accesses mustn't be moved out from under the guard
+            unsafe { asm!("stac", options(nostack)) };
+        }
+        Self { was_allowed }
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        if SMAP_ENABLED.load(Ordering::Relaxed) && !self.was_allowed {
+            unsafe { asm!("clac", options(nostack)) };
+        }
+    }
+}
+
+/// Clears RFLAGS.AC, which the processor leaves alone when it delivers an interrupt. Handlers for
+/// interrupts that can arrive during a `UserAccessGuard` or from user mode, which can set the flag
+/// too, call this before they do anything else. Returning from the interrupt restores the flag.
+pub fn clear_inherited_access() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+}
+
+/**
+ * Returns true if SMEP or SMAP caused a page fault at `address`: the kernel fetched an instruction
+ * from a user page, or touched one without a `UserAccessGuard`. `flags` are the ones the faulting
+ * code ran with.
+ *
+ * # Safety
+ *
+ * The current page tables must be identity mapped.
+ */
+pub unsafe fn blocked_by_supervisor_protection(
+    address: usize,
+    error_code: PageFaultErrorCode,
+    flags: RFlags,
+) -> bool {
+    let enabled = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        SMEP_ENABLED.load(Ordering::Relaxed)
+    } else {
+        SMAP_ENABLED.load(Ordering::Relaxed) && !flags.contains(RFlags::ALIGNMENT_CHECK)
+    };
+    if !enabled
+        || error_code.contains(PageFaultErrorCode::USER_MODE)
+        || !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    {
+        return false;
+    }
+    // The walk goes through the identity map, which is user memory in a process's address space
+    let _access = UserAccessGuard::begin();
+    is_user_accessible(address..address.saturating_add(1))
+}
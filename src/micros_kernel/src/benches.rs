@@ -0,0 +1,279 @@
+//! Benchmarks for the region math that registering memory does at boot, run on the host with
+//! `make bench`. The memory maps are generated to look like what firmware reports for machines of
+//! a few sizes, with a few dozen entries each.
+//!
+//! Registering frames writes to every frame that isn't part of a bigger one, so it can only be
+//! measured on memory that the host can hand out. It's reported as a rate, which carries over to
+//! bigger machines because the work grows with the amount of memory.
+//!
+//! Baseline numbers from a single core cloud VM, to compare later changes against:
+//!
+//! ```text
+//! sanitize_4gb                3,400 ns/iter
+//! sanitize_64gb               3,400 ns/iter
+//! sanitize_1tb                8,400 ns/iter
+//! unused_regions_4gb            310 ns/iter
+//! unused_regions_64gb           310 ns/iter
+//! unused_regions_1tb            420 ns/iter
+//! add_memory_region_256mb    37,000 ns/iter
+//! add_4k_frames_256mb       920,000 ns/iter
+//! ```
+
+extern crate std;
+extern crate test;
+
+use crate::{
+    unused_memory_regions, unused_regions_of_type, usable_memory_areas, MAX_MEMORY_MAP_ENTRIES,
+};
+use core::{cmp::min, hint::black_box, ops::Range};
+use multiboot2::{
+    MemoryMapEntry, MemoryMapTag, SanitizedMemoryMap, ACPI_MEMORY, AVAILABLE_MEMORY,
+    DEFECTIVE_MEMORY,
+};
+use std::vec::Vec;
+use test::Bencher;
+
+const RESERVED_MEMORY: u32 = 2;
+const ACPI_NVS_MEMORY: u32 = 4;
+const MEGABYTE: u64 = 0x10_0000;
+const GIGABYTE: u64 = 0x4000_0000;
+
+/// A xorshift generator with a fixed seed so that every run benchmarks the same memory maps
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        usize::try_from(self.between(0, bound as u64)).unwrap()
+    }
+}
+
+/**
+ * Generates the memory map of a PC with `memory_size` bytes of memory. Low memory has the usual
+ * legacy holes, the memory below the PCI hole is broken up by firmware and ACPI regions, the
+ * memory above 4 GB is split between NUMA nodes, and the entries come in no particular order with
+ * one duplicated, like some firmware reports them.
+ */
+fn memory_map(memory_size: u64) -> Vec<MemoryMapEntry> {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15 ^ memory_size);
+    let mut entries = std::vec![
+        MemoryMapEntry::new(0, 0x9_fc00, AVAILABLE_MEMORY),
+        MemoryMapEntry::new(0x9_fc00, 0x400, RESERVED_MEMORY),
+        MemoryMapEntry::new(0xe_0000, 0x2_0000, RESERVED_MEMORY),
+    ];
+
+    // Firmware, ACPI tables, and the odd defective page are scattered below the PCI hole
+    let low_memory_end = min(memory_size, 3 * GIGABYTE);
+    let mut address = MEGABYTE;
+    while address < low_memory_end {
+        let length = rng.between(low_memory_end / 16, low_memory_end / 6) & !0xfff;
+        let length = min(length, low_memory_end - address);
+        entries.push(MemoryMapEntry::new(address, length, AVAILABLE_MEMORY));
+        address += length;
+        if address < low_memory_end {
+            let hole_type = [
+                RESERVED_MEMORY,
+                ACPI_MEMORY,
+                ACPI_NVS_MEMORY,
+                DEFECTIVE_MEMORY,
+            ][rng.below(4)];
+            let hole = min(rng.between(1, 512) * 0x1000, low_memory_end - address);
+            entries.push(MemoryMapEntry::new(address, hole, hole_type));
+            address += hole;
+        }
+    }
+
+    // Configuration space, the APICs, and the firmware flash in the PCI hole
+    for (start, length) in [
+        (0xe000_0000, 0x1000_0000),
+        (0xfec0_0000, 0x1000),
+        (0xfee0_0000, 0x1000),
+        (0xff00_0000, 0x100_0000),
+    ] {
+        entries.push(MemoryMapEntry::new(start, length, RESERVED_MEMORY));
+    }
+
+    // Each NUMA node's memory ends with a little that the firmware keeps for itself
+    let high_memory = memory_size - low_memory_end;
+    let nodes = (memory_size / (64 * GIGABYTE)).clamp(1, 8);
+    let mut address = 4 * GIGABYTE;
+    for _ in 0..nodes {
+        let node_size = high_memory / nodes;
+        if node_size == 0 {
+            break;
+        }
+        let reserved = rng.between(1, 64) * MEGABYTE;
+        entries.push(MemoryMapEntry::new(
+            address,
+            node_size - reserved,
+            AVAILABLE_MEMORY,
+        ));
+        entries.push(MemoryMapEntry::new(
+            address + node_size - reserved,
+            reserved,
+            RESERVED_MEMORY,
+        ));
+        address += node_size;
+    }
+
+    let duplicate = &entries[rng.below(entries.len())];
+    entries.push(MemoryMapEntry::new(
+        duplicate.base_addr,
+        duplicate.length,
+        duplicate.region_type,
+    ));
+    for index in (1..entries.len()).rev() {
+        entries.swap(index, rng.below(index + 1));
+    }
+    entries
+}
+
+/// The memory that a typical boot keeps out of the frame allocator: the AP trampoline, the boot
+/// information, the kernel image, the boot modules, and the framebuffer
+fn memory_regions_in_use() -> [Range<usize>; 6] {
+    [
+        0x8000..0x9000,
+        0x1_0000..0x1_2000,
+        0x10_0000..0x30_0000,
+        0x80_0000..0x98_0000,
+        0x98_0000..0xa4_0000,
+        0xfd00_0000..0xfd80_0000,
+    ]
+}
+
+fn sanitize(b: &mut Bencher, memory_size: u64) {
+    let entries = memory_map(memory_size);
+    let mut sanitized = SanitizedMemoryMap::<MAX_MEMORY_MAP_ENTRIES>::new();
+    b.iter(|| {
+        let memory_map = MemoryMapTag {
+            entries: black_box(&entries),
+        };
+        sanitized.sanitize(memory_map).map(|map| map.entries.len())
+    });
+}
+
+/// Finds the available memory that isn't in use, the way that booting does before registering it
+fn unused_regions(b: &mut Bencher, memory_size: u64) {
+    let entries = memory_map(memory_size);
+    let mut sanitized = SanitizedMemoryMap::<MAX_MEMORY_MAP_ENTRIES>::new();
+    let memory_map = sanitized
+        .sanitize(MemoryMapTag { entries: &entries })
+        .unwrap();
+    let physical_memory_size = usable_memory_areas(memory_map)
+        .filter_map(MemoryMapEntry::address_range)
+        .map(|area| area.end)
+        .max()
+        .unwrap();
+    let window = 0..usize::MAX;
+    b.iter(|| {
+        let mut in_use = memory_regions_in_use();
+        let gaps = unused_memory_regions(black_box(&mut in_use), physical_memory_size);
+        unused_regions_of_type(black_box(memory_map), AVAILABLE_MEMORY, &gaps, &window)
+            .map(|region| region.len())
+            .sum::<usize>()
+    });
+}
+
+#[bench]
+fn sanitize_4gb(b: &mut Bencher) {
+    sanitize(b, 4 * GIGABYTE);
+}
+
+#[bench]
+fn sanitize_64gb(b: &mut Bencher) {
+    sanitize(b, 64 * GIGABYTE);
+}
+
+#[bench]
+fn sanitize_1tb(b: &mut Bencher) {
+    sanitize(b, 1024 * GIGABYTE);
+}
+
+#[bench]
+fn unused_regions_4gb(b: &mut Bencher) {
+    unused_regions(b, 4 * GIGABYTE);
+}
+
+#[bench]
+fn unused_regions_64gb(b: &mut Bencher) {
+    unused_regions(b, 64 * GIGABYTE);
+}
+
+#[bench]
+fn unused_regions_1tb(b: &mut Bencher) {
+    unused_regions(b, 1024 * GIGABYTE);
+}
+
+#[cfg(target_arch = "x86_64")]
+mod registration {
+    use super::*;
+    use frame_allocation::{
+        amd64::{Amd64FrameAllocator, FOUR_KILOBYTES},
+        end_of_last_full_page, first_full_page_address, FfiOption, FrameAllocator,
+    };
+    use test_arena::Arena;
+
+    /// How much memory the registration benchmarks give the allocator
+    const MEMORY_SIZE: u64 = 256 * MEGABYTE;
+
+    /// The available regions of a generated memory map, moved into `arena`
+    fn arena_regions(arena: &Arena, memory_size: u64) -> Vec<Range<usize>> {
+        let entries = memory_map(memory_size);
+        let mut sanitized = SanitizedMemoryMap::<MAX_MEMORY_MAP_ENTRIES>::new();
+        let memory_map = sanitized
+            .sanitize(MemoryMapTag { entries: &entries })
+            .unwrap();
+        let mut in_use = memory_regions_in_use();
+        let gaps = unused_memory_regions(&mut in_use, arena.range().len());
+        let window = 0..arena.range().len();
+        unused_regions_of_type(memory_map, AVAILABLE_MEMORY, &gaps, &window)
+            .map(|region| region.start + arena.range().start..region.end + arena.range().start)
+            .collect()
+    }
+
+    fn register(b: &mut Bencher, add: fn(&mut Amd64FrameAllocator, Range<usize>)) {
+        let arena = Arena::new(usize::try_from(MEMORY_SIZE).unwrap());
+        let regions = arena_regions(&arena, MEMORY_SIZE);
+        b.bytes = regions.iter().map(|region| region.len() as u64).sum();
+        b.iter(|| {
+            let mut allocator = Amd64FrameAllocator {
+                four_kilobyte_pages: FrameAllocator::new(),
+                two_megabyte_pages: FrameAllocator::new(),
+                gigabyte_pages: FfiOption::Some(FrameAllocator::new()),
+            };
+            for region in &regions {
+                add(&mut allocator, region.clone());
+            }
+            black_box(&allocator);
+        });
+    }
+
+    #[bench]
+    fn add_memory_region_256mb(b: &mut Bencher) {
+        register(b, |allocator, region| unsafe {
+            allocator.add_memory_region(region);
+        });
+    }
+
+    /// The worst case, where every frame is a 4 KB frame and has to be written to
+    #[bench]
+    fn add_4k_frames_256mb(b: &mut Bencher) {
+        register(b, |allocator, region| {
+            let frames = first_full_page_address(region.start, FOUR_KILOBYTES)
+                ..end_of_last_full_page(region.end, FOUR_KILOBYTES);
+            if !frames.is_empty() {
+                unsafe { allocator.four_kilobyte_pages.add_frames(frames) };
+            }
+        });
+    }
+}
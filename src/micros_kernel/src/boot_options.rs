@@ -0,0 +1,106 @@
+use crate::log::Level;
+use multiboot2::{BootInformation, CommandLineTag};
+
+/// Settings that can be changed from the kernel command line
+#[derive(Clone, Copy)]
+// Each option is an independent switch
+#[allow(clippy::struct_excessive_bools)]
+pub struct BootOptions {
+    /// Use gigabyte pages if the processor supports them. Disabled by `no-gbpages`.
+    pub gigabyte_pages: bool,
+    /// The least important log messages to keep. Set by `loglevel=error|warn|info|debug`.
+    pub log_level: Level,
+    /// Log to the serial port. Disabled by `serial=off`.
+    pub serial: bool,
+    /// Draw the physical memory map before launching the memory manager. Enabled by
+    /// `memmap-view`, but only if the kernel was built with the `memmap-view` feature.
+    pub memmap_view: bool,
+    /// Keep the memory below 1 MB out of the frame allocators, since firmware and option ROMs may
+    /// still use it. Disabled by `reserve-low-memory=off`.
+    pub reserve_low_memory: bool,
+    /// What to do when booting fails. Set by `on-fatal=halt|shutdown|reboot`.
+    pub on_fatal: OnFatal,
+}
+
+/// What the kernel does after an error that it can't recover from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnFatal {
+    /// Stop the processor, which leaves the error on the screen and in the log
+    Halt,
+    /// Turn the machine off, which ends an unattended virtual machine
+    Shutdown,
+    /// Restart the machine
+    Reboot,
+}
+
+impl BootOptions {
+    /// Reads the options from the command line tag. Returns the defaults if there isn't one.
+    #[must_use]
+    pub fn from_boot_info(boot_info: BootInformation) -> Self {
+        boot_info
+            .tags_of_type::<CommandLineTag>()
+            .next()
+            .map_or_else(Self::default, |tag| Self::parse(tag.string))
+    }
+
+    /**
+     * Parses a command line made up of whitespace separated `key` and `key=value` tokens.
+     *
+     * Unknown keys and invalid values are ignored so that a typo on the command line can't stop
+     * the operating system from booting.
+     */
+    #[must_use]
+    pub fn parse(command_line: &str) -> Self {
+        let mut options = Self::default();
+        for token in command_line.split_ascii_whitespace() {
+            let (key, value) = match token.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (token, None),
+            };
+            match (key, value) {
+                ("no-gbpages", None) => options.gigabyte_pages = false,
+                ("loglevel", Some(value)) => {
+                    options.log_level = parse_log_level(value).unwrap_or(options.log_level);
+                }
+                ("serial", Some("off")) => options.serial = false,
+                ("serial", Some("on")) => options.serial = true,
+                ("memmap-view", None) => options.memmap_view = true,
+                ("reserve-low-memory", Some("off")) => options.reserve_low_memory = false,
+                ("reserve-low-memory", Some("on")) => options.reserve_low_memory = true,
+                ("on-fatal", Some("halt")) => options.on_fatal = OnFatal::Halt,
+                ("on-fatal", Some("shutdown")) => options.on_fatal = OnFatal::Shutdown,
+                ("on-fatal", Some("reboot")) => options.on_fatal = OnFatal::Reboot,
+                _ => {}
+            }
+        }
+        options
+    }
+}
+
+impl Default for BootOptions {
+    fn default() -> Self {
+        Self {
+            gigabyte_pages: true,
+            log_level: Level::Info,
+            serial: true,
+            memmap_view: false,
+            reserve_low_memory: true,
+            // A test run in QEMU has nobody watching it, so it shouldn't hang until it times out
+            on_fatal: if cfg!(feature = "qemu-test") {
+                OnFatal::Shutdown
+            } else {
+                OnFatal::Halt
+            },
+        }
+    }
+}
+
+fn parse_log_level(value: &str) -> Option<Level> {
+    match value {
+        "error" => Some(Level::Error),
+        "warn" => Some(Level::Warn),
+        "info" => Some(Level::Info),
+        "debug" => Some(Level::Debug),
+        _ => None,
+    }
+}
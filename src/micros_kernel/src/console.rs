@@ -0,0 +1,71 @@
+//! The console on the screen that the kernel logs to while it boots. The memory manager takes the
+//! screen over once it starts, so the console is only used until then.
+
+use core::ptr::addr_of_mut;
+use framebuffer::{BootConsole, TextBuffer};
+use multiboot2::{BootInformation, FramebufferTag};
+
+static mut TEXT: TextBuffer = TextBuffer::new();
+static mut CONSOLE: Option<BootConsole<'static>> = None;
+
+/**
+ * Picks the best console for the framebuffers that the bootloader set up and makes it the log's
+ * primary sink. Framebuffers that aren't entirely below `mapped_memory_size` are skipped. Returns
+ * false if there's no console.
+ *
+ * # Safety
+ *
+ * `boot_info` must be valid, memory below `mapped_memory_size` must be identity mapped, nothing
+ * else may be drawing on the screen, and this may only be called once.
+ */
+pub unsafe fn init(boot_info: BootInformation<'static>, mapped_memory_size: usize) -> bool {
+    let framebuffers = boot_info
+        .tags_of_type::<FramebufferTag>()
+        .filter(|tag| is_mapped(tag, mapped_memory_size));
+    let Some(console) = BootConsole::select(framebuffers, &mut *addr_of_mut!(TEXT)) else {
+        return false;
+    };
+    crate::log::set_console((*addr_of_mut!(CONSOLE)).insert(console));
+    true
+}
+
+/// Hands the screen over to whatever runs next. Nothing is logged to the console after this.
+pub fn detach() {
+    crate::log::take_console();
+}
+
+fn is_mapped(tag: &FramebufferTag, mapped_memory_size: usize) -> bool {
+    (tag.pitch as usize)
+        .checked_mul(tag.height as usize)
+        .and_then(|size| (tag.framebuffer as usize).checked_add(size))
+        .is_some_and(|end| end <= mapped_memory_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    fn tag(address: usize, pitch: u32, height: u32) -> FramebufferTag<'static> {
+        FramebufferTag {
+            framebuffer: ptr::without_provenance_mut(address),
+            pitch,
+            width: pitch / 4,
+            height,
+            bits_per_pixel: 32,
+            framebuffer_type: 1,
+            color_data: &[],
+        }
+    }
+
+    #[test]
+    fn framebuffers_must_be_entirely_mapped() {
+        const MAPPED: usize = 0x1_0000_0000;
+        assert!(is_mapped(&tag(0xfd00_0000, 4096, 768), MAPPED));
+        // Ending exactly at the end of the identity map is fine
+        assert!(is_mapped(&tag(MAPPED - 4096 * 768, 4096, 768), MAPPED));
+        assert!(!is_mapped(&tag(MAPPED - 4096 * 767, 4096, 768), MAPPED));
+        assert!(!is_mapped(&tag(MAPPED, 4096, 0), MAPPED - 1));
+        assert!(!is_mapped(&tag(usize::MAX - 4095, 4096, 2), usize::MAX));
+    }
+}
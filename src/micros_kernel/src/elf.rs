@@ -0,0 +1,170 @@
+//! 64 bit little endian ELF executables. The header is parameterized by the machine it was built
+//! for so that each architecture only accepts its own executables.
+
+use crate::{ExecutableHeader, SegmentFlags, SegmentHeader, ELF_MAGIC_NUMBER};
+use core::mem::size_of;
+use multiboot2::phys_to_usize;
+
+/// The header of an executable for the machine `MACHINE`, which is one of the `EM_` constants
+#[repr(C)]
+pub struct Header<const MACHINE: u16> {
+    pub(crate) ident_magic: u32,
+    pub(crate) ident_width_class: u8,
+    pub(crate) ident_data_endianness: u8,
+    pub(crate) ident_version: u8,
+    pub(crate) ident_os_abi: u8,
+    pub(crate) ident_abi_version: u8,
+    pub(crate) ident_padding_0: u8,
+    pub(crate) ident_padding_1: u8,
+    pub(crate) ident_padding_2: u8,
+    pub(crate) ident_padding_3: u8,
+    pub(crate) ident_padding_4: u8,
+    pub(crate) ident_padding_5: u8,
+    pub(crate) ident_padding_6: u8,
+    pub(crate) file_type: u16,
+    pub(crate) machine: u16,
+    pub(crate) version: u32,
+    pub(crate) entry: u64,
+    pub(crate) program_header_offset: u64,
+    pub(crate) section_header_offset: u64,
+    pub(crate) flags: u32,
+    pub(crate) size: u16,
+    pub(crate) program_header_entry_size: u16,
+    pub(crate) program_header_num: u16,
+    pub(crate) section_header_entry_size: u16,
+    pub(crate) section_header_num: u16,
+    pub(crate) shstrndx: u16,
+}
+
+impl<const MACHINE: u16> ExecutableHeader for Header<MACHINE> {
+    fn is_valid(&self, file_size: usize) -> bool {
+        size_of::<Self>() <= file_size
+            && self.ident_magic == ELF_MAGIC_NUMBER
+            && self.ident_width_class == ELF_64_BIT
+            && self.ident_data_endianness == ELF_LITTLE_ENDIAN
+            && self.ident_version == 1
+            && self.file_type == ELF_EXECUTABLE
+            && self.machine == MACHINE
+            && usize::from(self.program_header_num)
+                .checked_mul(size_of::<ProgramHeader>())
+                .zip(phys_to_usize(self.program_header_offset))
+                .and_then(|(size, offset)| size.checked_add(offset))
+                .is_some_and(|end| end <= file_size)
+    }
+
+    fn num_segments(&self) -> usize {
+        usize::from(self.program_header_num)
+    }
+
+    fn segment_header_table_offset(&self) -> Option<usize> {
+        phys_to_usize(self.program_header_offset)
+    }
+
+    fn entry(&self) -> Option<usize> {
+        phys_to_usize(self.entry)
+    }
+}
+
+#[repr(C)]
+pub struct ProgramHeader {
+    pub(crate) segment_type: u32,
+    pub(crate) flags: u32,
+    pub(crate) offset: u64,
+    pub(crate) virtual_address: u64,
+    pub(crate) physical_address: u64,
+    pub(crate) file_size: u64,
+    pub(crate) memory_size: u64,
+    pub(crate) align: u64,
+}
+
+impl SegmentHeader for ProgramHeader {
+    fn offset(&self) -> Option<usize> {
+        phys_to_usize(self.offset)
+    }
+
+    fn segment_type(&self) -> u32 {
+        self.segment_type
+    }
+
+    fn file_size(&self) -> Option<usize> {
+        phys_to_usize(self.file_size)
+    }
+
+    fn memory_size(&self) -> Option<usize> {
+        phys_to_usize(self.memory_size)
+    }
+
+    fn address(&self) -> Option<usize> {
+        phys_to_usize(self.virtual_address)
+    }
+
+    fn flags(&self) -> SegmentFlags {
+        SegmentFlags(self.flags)
+    }
+}
+
+const ELF_64_BIT: u8 = 2;
+const ELF_LITTLE_ENDIAN: u8 = 1;
+const ELF_EXECUTABLE: u16 = 2;
+
+#[cfg(target_arch = "x86_64")]
+pub const EM_X86_64: u16 = 0x3e;
+#[cfg(target_arch = "aarch64")]
+pub const EM_AARCH64: u16 = 0xb7;
+#[cfg(target_arch = "riscv64")]
+pub const EM_RISCV: u16 = 0xf3;
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_boot_info::{elf_executable, TestSegment};
+    use core::mem::size_of_val;
+    use std::vec::Vec;
+
+    fn header(words: &mut [u64]) -> &mut Header<EM_X86_64> {
+        unsafe { &mut *words.as_mut_ptr().cast() }
+    }
+
+    fn segment_header(words: &mut [u64], index: usize) -> &mut ProgramHeader {
+        let offset = size_of::<Header<EM_X86_64>>() + index * size_of::<ProgramHeader>();
+        unsafe { &mut *words.as_mut_ptr().cast::<u8>().add(offset).cast() }
+    }
+
+    fn executable() -> Vec<u64> {
+        elf_executable(
+            0x40_1000,
+            &[TestSegment {
+                address: 0x40_0000,
+                data: &[0x90; 16],
+                memory_size: 0x1000,
+                flags: 0,
+            }],
+        )
+    }
+
+    #[test]
+    fn program_header_table_that_wraps_around_is_invalid() {
+        let mut words = executable();
+        let file_size = size_of_val(&words[..]);
+        assert!(header(&mut words).is_valid(file_size));
+
+        header(&mut words).program_header_offset = u64::MAX - 8;
+        assert!(!header(&mut words).is_valid(file_size));
+        header(&mut words).program_header_offset = file_size as u64;
+        assert!(!header(&mut words).is_valid(file_size));
+    }
+
+    #[test]
+    fn fields_are_read_without_truncation() {
+        let mut words = executable();
+        header(&mut words).entry = 0xffff_8000_0040_1000;
+        assert_eq!(header(&mut words).entry(), Some(0xffff_8000_0040_1000));
+        let segment = segment_header(&mut words, 0);
+        segment.memory_size = 0x1_0000_1000;
+        segment.virtual_address = 0x7f_0000_0000;
+        assert_eq!(segment.memory_size(), Some(0x1_0000_1000));
+        assert_eq!(segment.address(), Some(0x7f_0000_0000));
+    }
+}
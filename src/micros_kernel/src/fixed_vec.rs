@@ -0,0 +1,58 @@
+use core::ops::{Deref, DerefMut};
+
+/// A vector with a fixed capacity that lives entirely on the stack
+pub struct FixedVec<T, const N: usize> {
+    items: [T; N],
+    len: usize,
+}
+
+impl<T: Default, const N: usize> FixedVec<T, N> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            items: core::array::from_fn(|_| T::default()),
+            len: 0,
+        }
+    }
+}
+
+impl<T, const N: usize> FixedVec<T, N> {
+    /// Creates an empty vector that keeps its items in `storage`. Unlike `new`, this can be used
+    /// to initialize a static.
+    pub const fn with_storage(storage: [T; N]) -> Self {
+        Self {
+            items: storage,
+            len: 0,
+        }
+    }
+
+    /// Appends `item` to the vector. If the vector is full then `item` is handed back.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.len < N {
+            self.items[self.len] = item;
+            self.len += 1;
+            Ok(())
+        } else {
+            Err(item)
+        }
+    }
+
+    /// Removes every item from the vector. The storage isn't dropped until the vector is.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<T, const N: usize> Deref for FixedVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.items[..self.len]
+    }
+}
+
+impl<T, const N: usize> DerefMut for FixedVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.items[..self.len]
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{Arch, Architecture};
+use core::fmt;
+
+/**
+ * Reports a failed `kernel_assert!` and halts. The message goes to the log sink if there is one.
+ * Otherwise it's written straight to the architecture's emergency output, since assertions can
+ * fail before logging has been set up or while the sink is in use.
+ */
+#[cold]
+pub fn assertion_failed(file: &str, line: u32, args: fmt::Arguments) -> ! {
+    let message = format_args!("Assertion failed at {file}:{line}: {args}");
+    if !crate::log::log_unconditionally(crate::log::Level::Error, message) {
+        #[cfg(target_arch = "x86_64")]
+        crate::amd64::emergency_log(message);
+    }
+    Arch::halt()
+}
+
+/// Halts the kernel with a message giving the file and line if `cond` is false. Unlike `assert!`
+/// the message is logged instead of silently halting in the panic handler.
+macro_rules! kernel_assert {
+    ($cond:expr $(,)?) => {
+        kernel_assert!($cond, "{}", stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::kernel_assert::assertion_failed(file!(), line!(), format_args!($($arg)+))
+        }
+    };
+}
+
+/// Like `kernel_assert!` but checks that two values are equal and logs both if they aren't.
+macro_rules! kernel_assert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => kernel_assert!(
+                *left == *right,
+                "{} == {} ({:?} != {:?})",
+                stringify!($left),
+                stringify!($right),
+                left,
+                right
+            ),
+        }
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        match (&$left, &$right) {
+            (left, right) => kernel_assert!(
+                *left == *right,
+                "{} ({:?} != {:?})",
+                format_args!($($arg)+),
+                left,
+                right
+            ),
+        }
+    };
+}
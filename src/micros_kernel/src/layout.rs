@@ -0,0 +1,44 @@
+//! The layouts of the structures that the kernel reads from executables or shares with assembly.
+//! The ELF numbers come from the System V ABI's description of 64 bit ELF files.
+
+use crate::{
+    elf::{Header, ProgramHeader, EM_X86_64},
+    ProcessLaunchInfo, MAX_PROCESS_ARGUMENTS,
+};
+use core::mem::size_of;
+use layout_assert::layout_assert;
+
+layout_assert!(Header<EM_X86_64>, size = 64, align = 8, {
+    ident_magic: 0,
+    file_type: 16,
+    machine: 18,
+    version: 20,
+    entry: 24,
+    program_header_offset: 32,
+    section_header_offset: 40,
+    flags: 48,
+    size: 52,
+    program_header_entry_size: 54,
+    program_header_num: 56,
+    section_header_entry_size: 58,
+    section_header_num: 60,
+    shstrndx: 62,
+});
+
+layout_assert!(ProgramHeader, size = 56, align = 8, {
+    segment_type: 0,
+    flags: 4,
+    offset: 8,
+    virtual_address: 16,
+    physical_address: 24,
+    file_size: 32,
+    memory_size: 40,
+    align: 48,
+});
+
+layout_assert!(ProcessLaunchInfo, size = (3 + MAX_PROCESS_ARGUMENTS) * size_of::<usize>(), {
+    root_page_table_address: 0,
+    entry_point: 8,
+    stack_pointer: 16,
+    arguments: 24,
+});
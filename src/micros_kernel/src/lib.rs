@@ -1,32 +1,89 @@
 #![no_std]
 #![deny(clippy::all)]
 #![deny(clippy::pedantic)]
-#![feature(abi_x86_interrupt)]
+#![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
+#![cfg_attr(test, feature(test))]
+// The aarch64 and RISC-V skeletons can't boot yet, so nothing calls the portable boot code there
+#![cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
 
+// The architecture code lays out page tables, process stacks, and the boot handoff for a 64 bit
+// address space, so a 32 bit build has to stop here
+#[cfg(not(target_pointer_width = "64"))]
+compile_error!("the kernel only supports 64 bit processors");
+
+// Declared first so that the logging macros are available in every other module
+#[macro_use]
+#[allow(unused_macros)]
+mod log;
+#[macro_use]
+#[allow(unused_macros)]
+mod kernel_assert;
+#[cfg(feature = "qemu-test")]
+#[macro_use]
+mod self_test;
+
+#[cfg(any(test, target_arch = "aarch64"))]
+mod aarch64;
+mod acpi;
 #[cfg(target_arch = "x86_64")]
 mod amd64;
+#[cfg(test)]
+mod benches;
+mod boot_options;
+mod console;
+mod elf;
+mod fixed_vec;
+mod layout;
+#[cfg(feature = "memmap-view")]
+mod memmap_view;
+mod memory_stats;
+#[cfg(test)]
+mod mock_architecture;
+mod panic;
+#[cfg(any(test, target_arch = "riscv64"))]
+mod riscv64;
+#[cfg(any(test, target_arch = "aarch64", target_arch = "riscv64"))]
+mod table_walk;
+#[cfg(test)]
+mod test_boot_info;
 
-use core::{
-    cmp::{max, min},
-    iter::once,
-    ops::Range,
-    ptr::addr_of,
-    slice,
+use boot_options::BootOptions;
+#[cfg(not(test))]
+use core::ptr::addr_of;
+use core::{fmt, iter::once, mem::size_of, ops::Range, slice};
+use fixed_vec::FixedVec;
+use frame_allocation::{
+    range_math::{align_outward, intersect, is_aligned, merge_sorted},
+    PhysicalAddress, VirtualAddress,
 };
+use micros_abi::{MemoryStats, STATS_FRAME_SIZES};
 use multiboot2::{
-    BootInformation, BootModuleTag, FramebufferTag, MemoryMapEntry, MemoryMapTag, ACPI_MEMORY,
+    aligned_pointer_cast, BasicMemoryInfoTag, BootInformation, BootInformationHeader,
+    BootModuleTag, FramebufferTag, MemoryMapEntry, MemoryMapTag, SanitizedMemoryMap, ACPI_MEMORY,
     AVAILABLE_MEMORY,
 };
 
+// The boot code still passes the result of CPUID as `_cpu_info`, but the kernel now queries the
+// processor's features itself
+// Host tests build it too, without the symbol, so that the code it boots with isn't dead
 #[cfg(target_arch = "x86_64")]
-#[no_mangle]
-pub extern "C" fn main(multiboot_info_ptr: u32, cpu_info: u32) -> ! {
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn main(multiboot_info_ptr: u32, _cpu_info: u32) -> ! {
     unsafe {
-        amd64::initialize_operating_system(multiboot_info_ptr, cpu_info);
+        amd64::initialize_operating_system(multiboot_info_ptr);
     }
-    amd64::halt()
+    // Booting only comes back here if it failed
+    amd64::on_fatal_error()
 }
 
+/// The architecture that the kernel is being built for
+#[cfg(target_arch = "x86_64")]
+type Arch = amd64::Amd64;
+#[cfg(target_arch = "aarch64")]
+type Arch = aarch64::Aarch64;
+#[cfg(target_arch = "riscv64")]
+type Arch = riscv64::Riscv64;
+
 trait Architecture: Sized {
     const INITIAL_VIRTUAL_MEMORY_SIZE: usize;
 
@@ -36,10 +93,45 @@ trait Architecture: Sized {
 
     type SegmentHeader: SegmentHeader;
 
-    unsafe fn initialize_memory_manager_page_tables(&mut self) -> Option<*mut Self::PageTable>;
+    /// Creates an address space for a new process with a stack mapped into it. Returns the root
+    /// page table and the initial stack pointer.
+    unsafe fn initialize_process_page_tables(
+        &mut self,
+    ) -> Option<(*mut Self::PageTable, VirtualAddress)>;
 
     unsafe fn register_memory_region(&mut self, memory_region: Range<usize>);
 
+    /// The number of bytes in the frame allocator's free frames of each of `STATS_FRAME_SIZES`.
+    /// Larger frames aren't counted in the smaller sizes that they'd be split into.
+    fn free_memory_by_frame_size(&self) -> [usize; STATS_FRAME_SIZES.len()];
+
+    /**
+     * Identity maps physical memory from `INITIAL_VIRTUAL_MEMORY_SIZE` up to `end` and returns
+     * the end of the identity mapped memory, which may be less than `end` if the architecture
+     * can't map that much or runs out of frames for page tables.
+     *
+     * The map grows in whatever units the architecture maps at once, so memory a little past the
+     * returned end may be mapped too. Running out of frames leaves everything mapped so far in
+     * place, and the end returned is where the first unit that couldn't be mapped starts. Calling
+     * this again once there are more frames picks up from there, since units that are already
+     * mapped are skipped rather than mapped again. Nothing below `INITIAL_VIRTUAL_MEMORY_SIZE` is
+     * touched, so an `end` below it is returned as is.
+     *
+     * The page tables come from the frame allocator, so only the memory that's already identity
+     * mapped may have been registered when this is called.
+     */
+    unsafe fn extend_identity_map(&mut self, end: usize) -> usize;
+
+    /// Checks that the page tables set up while booting are consistent. This is only meant as a
+    /// debugging aid, so architectures that don't implement it always report success.
+    unsafe fn verify_page_table_integrity(&self) -> bool {
+        true
+    }
+
+    /// Frees an address space created by `initialize_process_page_tables` along with all of the
+    /// memory mapped into it that isn't shared with the kernel.
+    unsafe fn abandon_address_space(&mut self, root_page_table: *mut Self::PageTable);
+
     unsafe fn copy_into_address_space(
         &mut self,
         root_page_table: &mut Self::PageTable,
@@ -48,6 +140,33 @@ trait Architecture: Sized {
         size: usize,
         flags: SegmentFlags,
     ) -> Option<()>;
+
+    /// Stops the processor for good. Interrupts are still handled if they're enabled.
+    fn halt() -> !;
+
+    /**
+     * Sleeps until an interrupt has been handled. Interrupts are enabled while sleeping and
+     * disabled again before this returns, so an interrupt that arrives between checking for work
+     * and calling this still wakes it.
+     */
+    fn wait_for_interrupt();
+
+    /**
+     * Lets the processor deliver interrupts
+     *
+     * # Safety
+     *
+     * The handlers for every interrupt that can arrive must be set up.
+     */
+    unsafe fn enable_interrupts();
+}
+
+/// Handles interrupts until `has_work` returns true. Interrupts must be disabled when this is
+/// called and stay disabled while `has_work` runs.
+fn idle_until<Proc: Architecture>(has_work: impl Fn() -> bool) {
+    while !has_work() {
+        Proc::wait_for_interrupt();
+    }
 }
 
 trait ExecutableHeader {
@@ -55,17 +174,20 @@ trait ExecutableHeader {
 
     fn num_segments(&self) -> usize;
 
-    fn segment_header_table_offset(&self) -> usize;
+    /// Returns `None` if the offset doesn't fit in a `usize`
+    fn segment_header_table_offset(&self) -> Option<usize>;
 
-    fn entry(&self) -> usize;
+    /// Returns `None` if the address doesn't fit in a `usize`
+    fn entry(&self) -> Option<usize>;
 }
 
 trait SegmentHeader {
     fn segment_type(&self) -> u32;
-    fn offset(&self) -> usize;
-    fn address(&self) -> usize;
-    fn file_size(&self) -> usize;
-    fn memory_size(&self) -> usize;
+    // These return `None` if the value in the header doesn't fit in a `usize`
+    fn offset(&self) -> Option<usize>;
+    fn address(&self) -> Option<usize>;
+    fn file_size(&self) -> Option<usize>;
+    fn memory_size(&self) -> Option<usize>;
     fn flags(&self) -> SegmentFlags;
 }
 
@@ -84,52 +206,504 @@ impl SegmentFlags {
     }
 }
 
+/// Everything needed to start running a process
+#[repr(C)]
 struct ProcessLaunchInfo {
-    root_page_table_address: usize,
-    entry_point: usize,
+    root_page_table_address: PhysicalAddress,
+    entry_point: VirtualAddress,
+    stack_pointer: VirtualAddress,
+    /// The values of the first few arguments to the process's entry point
+    arguments: [usize; MAX_PROCESS_ARGUMENTS],
 }
 
-unsafe fn boot_os<Proc: Architecture>(
+/// The number of arguments that can be passed to a process when it starts
+const MAX_PROCESS_ARGUMENTS: usize = 4;
+
+/// An error that prevents the operating system from booting
+#[derive(Clone, Copy, Debug)]
+enum Error {
+    /// There's no boot module for the memory manager
+    NoMemoryManager,
+    /// More than one boot module looks like the memory manager
+    MultipleMemoryManagers,
+    /// More than one boot module looks like the init process
+    MultipleInitModules,
+    /// The boot information has neither a memory map nor basic memory information
+    NoMemoryInformation,
+    /// The memory map has more than `MAX_MEMORY_MAP_ENTRIES` entries after sanitizing it
+    MemoryMapTooLarge,
+    /// There weren't enough free frames to set up the memory manager's address space
+    OutOfMemory,
+    /// The memory manager boot module isn't an ELF file
+    InvalidMemoryManagerModule,
+    /// The memory manager boot module isn't a valid executable
+    InvalidMemoryManagerExecutable,
+    /// The init boot module isn't a valid ELF executable
+    InvalidInitExecutable,
+    /// There are more boot modules and memory exclusions than `MAX_MEMORY_REGIONS_IN_USE`
+    TooManyMemoryRegionsInUse,
+    /// The bootloader passed a null pointer to the boot information
+    NullBootInformation,
+    /// The pointer to the boot information isn't aligned to 8 bytes
+    MisalignedBootInformation,
+    /// The boot information overlaps the kernel image
+    BootInformationOverlapsKernel,
+    /// The boot information extends past the memory that's mapped while booting
+    BootInformationOutOfRange,
+    /// The kernel image bounds from the linker script are out of order, too large, or outside of
+    /// the memory that's mapped while booting
+    InvalidKernelImage,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoMemoryManager => "no boot module is named memory_manager",
+            Self::MultipleMemoryManagers => "more than one boot module is named memory_manager",
+            Self::MultipleInitModules => "more than one boot module is named init",
+            Self::NoMemoryInformation => "the bootloader didn't say where memory is",
+            Self::MemoryMapTooLarge => "the memory map has too many entries",
+            Self::OutOfMemory => "ran out of memory while loading the memory manager",
+            Self::InvalidMemoryManagerModule => "the memory manager module isn't an ELF file",
+            Self::InvalidMemoryManagerExecutable => {
+                "the memory manager module isn't a valid executable"
+            }
+            Self::InvalidInitExecutable => "the init module isn't a valid ELF executable",
+            Self::TooManyMemoryRegionsInUse => "there are too many boot modules to keep track of",
+            Self::NullBootInformation => "the boot information pointer is null",
+            Self::MisalignedBootInformation => {
+                "the boot information pointer isn't aligned to 8 bytes"
+            }
+            Self::BootInformationOverlapsKernel => "the boot information overlaps the kernel image",
+            Self::BootInformationOutOfRange => {
+                "the boot information extends past the initially mapped memory"
+            }
+            Self::InvalidKernelImage => "the kernel image bounds are invalid",
+        })
+    }
+}
+
+/// A module that the bootloader loaded into memory
+#[derive(Clone, Default)]
+struct BootModule<'a> {
+    /// The command line that the module was loaded with
+    name: &'a str,
+    /// The physical memory that the module occupies
+    range: Range<usize>,
+}
+
+/// The result of a successful boot
+struct BootReport<'a> {
+    /// How to launch the memory manager
+    memory_manager: ProcessLaunchInfo,
+    /// How to launch the init process if there's an init module. The memory manager is
+    /// responsible for starting it.
+    init: Option<ProcessLaunchInfo>,
+    /// Every module that the bootloader loaded, including the memory manager. None of their memory
+    /// is given to the frame allocator.
+    modules: FixedVec<BootModule<'a>, MAX_MEMORY_REGIONS_IN_USE>,
+    /// Where physical memory went while booting
+    memory_stats: MemoryStats,
+    /// Free ACPI memory that is kept out of the frame allocator until the ACPI tables have been
+    /// read. See `reclaim_acpi_memory`.
+    acpi_memory: FixedVec<Range<usize>, MAX_DEFERRED_ACPI_REGIONS>,
+    /// The memory that was given to the frame allocator, in the order it was registered
+    registered_memory: spin::MutexGuard<'static, RegisteredMemory>,
+    /// The memory map as the bootloader reported it
+    #[cfg_attr(not(feature = "qemu-test"), allow(dead_code))]
+    memory_map: MemoryMapTag<'a>,
+    /// The end of the identity map. All of physical memory is below it unless there weren't enough
+    /// frames to extend it.
+    #[cfg_attr(not(feature = "qemu-test"), allow(dead_code))]
+    identity_map_end: usize,
+}
+
+/// The number of memory regions other than boot modules that `boot_os` always keeps out of the
+/// frame allocator (the kernel image, the boot information, and the framebuffer)
+const STANDARD_MEMORY_REGIONS_IN_USE: usize = 3;
+
+/// The maximum number of memory regions that can be kept out of the frame allocator while booting
+const MAX_MEMORY_REGIONS_IN_USE: usize = 16;
+
+/// The end of the memory that real mode code can address. The interrupt vector table, the BIOS
+/// data area, and the extended BIOS data area live below this.
+const LOW_MEMORY_END: usize = 0x10_0000;
+
+/// The maximum number of entries in the memory map after overlapping entries have been split
+const MAX_MEMORY_MAP_ENTRIES: usize = 128;
+
+// This is too big to keep on the boot stack
+static SANITIZED_MEMORY_MAP: spin::Mutex<SanitizedMemoryMap<MAX_MEMORY_MAP_ENTRIES>> =
+    spin::Mutex::new(SanitizedMemoryMap::new());
+
+/// The memory map that's made up from the basic memory information when there's no memory map
+static BASIC_MEMORY_MAP: spin::Once<[MemoryMapEntry; 2]> = spin::Once::new();
+
+/// The maximum number of memory regions that `boot_os` records as it registers them. Each region
+/// that's in use can split a memory map entry in two, and so can the end of the initially mapped
+/// memory.
+const MAX_REGISTERED_MEMORY_REGIONS: usize = MAX_MEMORY_MAP_ENTRIES + MAX_MEMORY_REGIONS_IN_USE + 1;
+
+type RegisteredMemory = FixedVec<Range<usize>, MAX_REGISTERED_MEMORY_REGIONS>;
+
+// This is too big to keep on the boot stack
+static REGISTERED_MEMORY: spin::Mutex<RegisteredMemory> = spin::Mutex::new(FixedVec::with_storage(
+    [const { 0..0 }; MAX_REGISTERED_MEMORY_REGIONS],
+));
+
+/// The maximum number of ACPI memory regions that can be reclaimed after booting. Any beyond this
+/// are never given to the frame allocator.
+const MAX_DEFERRED_ACPI_REGIONS: usize = 16;
+
+/**
+ * Returns the memory map that the bootloader passed along. If there isn't one, a memory map is made
+ * up from the basic memory information, which says how much memory there is below 1 MB and above
+ * it.
+ */
+fn raw_memory_map(boot_info: BootInformation) -> Result<MemoryMapTag, Error> {
+    if let Some(memory_map) = boot_info.tags_of_type::<MemoryMapTag>().next() {
+        return Ok(memory_map);
+    }
+    let basic_memory_info = boot_info
+        .tags_of_type::<BasicMemoryInfoTag>()
+        .next()
+        .ok_or(Error::NoMemoryInformation)?;
+    warn!("There's no memory map, so only the basic memory information is used");
+    // The boot report keeps the memory map for as long as the boot information, so the made up
+    // one can't live on the stack
+    Ok(MemoryMapTag {
+        entries: BASIC_MEMORY_MAP.call_once(|| memory_map_from_basic_info(basic_memory_info)),
+    })
+}
+
+/// The available memory that the basic memory information describes. Lower memory starts at 0 and
+/// upper memory starts at 1 MB, and both are given in kilobytes.
+fn memory_map_from_basic_info(info: BasicMemoryInfoTag) -> [MemoryMapEntry; 2] {
+    [
+        MemoryMapEntry::new(0, u64::from(info.mem_lower) * 1024, AVAILABLE_MEMORY),
+        MemoryMapEntry::new(
+            LOW_MEMORY_END as u64,
+            u64::from(info.mem_upper) * 1024,
+            AVAILABLE_MEMORY,
+        ),
+    ]
+}
+
+/// Logs which bootloader booted the kernel, since bootloaders differ in how they set things like
+/// the framebuffer up, which disk it booted from, and whether it booted through EFI
+fn log_boot_loader(boot_info: BootInformation) {
+    if let Some(name) = boot_info.boot_loader_name() {
+        info!("Booted by {name}");
+    }
+    if let Some(device) = boot_info.boot_device() {
+        match (device.partition, device.sub_partition) {
+            (None, _) => info!("Booted from BIOS disk {:#x}", device.biosdev),
+            (Some(partition), None) => info!(
+                "Booted from BIOS disk {:#x} partition {partition}",
+                device.biosdev
+            ),
+            (Some(partition), Some(sub_partition)) => info!(
+                "Booted from BIOS disk {:#x} partition {partition}.{sub_partition}",
+                device.biosdev
+            ),
+        }
+    }
+    if boot_info.is_efi_boot() {
+        info!("Booted through EFI");
+    }
+}
+
+/**
+ * Boots the operating system from `boot_info` and keeps the memory in `extra_exclusions` out of
+ * the frame allocator. This is useful for memory that firmware has reserved without reporting it
+ * in the memory map and for memory that the architecture needs at a fixed address.
+ *
+ * # Safety
+ *
+ * `boot_info` must describe the machine that the kernel is running on.
+ */
+#[must_use = "boot failures need to be reported"]
+unsafe fn boot_os<'a, Proc: Architecture, const N: usize>(
     proc: &mut Proc,
-    multiboot_info_ptr: *const u8,
-) -> Option<ProcessLaunchInfo> {
-    // Initialize available memory and set up page tables
-    let boot_info = BootInformation::new(multiboot_info_ptr);
-
-    let mut physical_memory_size = 0;
-
-    // Add free frames from first 4 GB to available frame list
-    let memory_manager_bounds = memory_manager_executable(boot_info)?;
-
-    let mut memory_regions_in_use_arr = [
-        addr_of!(header_start) as usize..addr_of!(kernel_end) as usize,
-        boot_info.address_range(),
-        memory_manager_bounds.clone(),
-        0..0,
-    ];
-    let memory_regions_in_use = if let Some(framebuffer_tag) =
-        boot_info.tags_of_type::<FramebufferTag>().next()
-    {
-        let framebuffer_addr = framebuffer_tag.framebuffer as usize;
-        memory_regions_in_use_arr[3] = framebuffer_addr
-            ..framebuffer_addr + (framebuffer_tag.height as usize * framebuffer_tag.pitch as usize);
-        &mut memory_regions_in_use_arr
-    } else {
-        &mut memory_regions_in_use_arr[0..3]
-    };
-    let available_memory_regions =
-        unused_memory_regions(memory_regions_in_use, Proc::INITIAL_VIRTUAL_MEMORY_SIZE)?;
+    boot_info: BootInformation<'a>,
+    options: BootOptions,
+    extra_exclusions: [Range<usize>; N],
+) -> Result<BootReport<'a>, Error> {
+    log_boot_loader(boot_info);
+    validate_kernel_image(Proc::INITIAL_VIRTUAL_MEMORY_SIZE)?;
+
+    let modules = boot_modules(boot_info)?;
+    let memory_manager_bounds = only_module_named(&modules, MEMORY_MANAGER_MODULE_NAME)
+        .map_err(|()| Error::MultipleMemoryManagers)?
+        .ok_or(Error::NoMemoryManager)?;
+    let init_bounds =
+        only_module_named(&modules, INIT_MODULE_NAME).map_err(|()| Error::MultipleInitModules)?;
+
+    let memory = register_boot_memory(proc, boot_info, options, &modules, extra_exclusions)?;
 
-    for memory_area in available_memory_areas(boot_info.tags_of_type::<MemoryMapTag>().next()?) {
-        physical_memory_size = max(physical_memory_size, memory_area_end(memory_area));
-        for memory_region in
-            unused_memory_regions_from_area(memory_area, available_memory_regions.clone())
+    if options.memmap_view {
+        #[cfg(feature = "memmap-view")]
         {
-            proc.register_memory_region(memory_region);
+            console::detach();
+            memmap_view::show(boot_info, memory.physical_memory_size);
         }
+        #[cfg(not(feature = "memmap-view"))]
+        warn!("The memmap-view option needs a kernel built with the memmap-view feature");
+    }
+
+    let (memory_manager, init) = load_processes(proc, memory_manager_bounds, init_bounds)?;
+    Ok(BootReport {
+        memory_manager,
+        init,
+        modules,
+        memory_stats: memory.stats,
+        acpi_memory: memory.acpi_memory,
+        registered_memory: memory.registered_memory,
+        memory_map: memory.memory_map,
+        identity_map_end: memory.identity_map_end,
+    })
+}
+
+/// The memory that `register_boot_memory` found while booting
+struct BootMemory<'a> {
+    /// Where physical memory went
+    stats: MemoryStats,
+    /// Free ACPI memory that was kept out of the frame allocator
+    acpi_memory: FixedVec<Range<usize>, MAX_DEFERRED_ACPI_REGIONS>,
+    /// The memory that was given to the frame allocator, in the order it was registered
+    registered_memory: spin::MutexGuard<'static, RegisteredMemory>,
+    /// The memory map as the bootloader reported it
+    memory_map: MemoryMapTag<'a>,
+    /// The end of the identity map
+    identity_map_end: usize,
+    /// The end of the highest usable memory map area
+    #[cfg_attr(not(feature = "memmap-view"), allow(dead_code))]
+    physical_memory_size: usize,
+}
+
+/**
+ * Gives the available memory in the memory map to the frame allocator, except for the memory that
+ * the kernel image, the boot information, the framebuffer, `modules`, and `extra_exclusions` take
+ * up. The identity map is extended as far as the memory goes.
+ *
+ * # Safety
+ *
+ * `boot_info` must describe the machine that the kernel is running on.
+ */
+unsafe fn register_boot_memory<'a, Proc: Architecture, const N: usize>(
+    proc: &mut Proc,
+    boot_info: BootInformation<'a>,
+    options: BootOptions,
+    modules: &[BootModule],
+    extra_exclusions: [Range<usize>; N],
+) -> Result<BootMemory<'a>, Error> {
+    const {
+        assert!(
+            STANDARD_MEMORY_REGIONS_IN_USE + N <= MAX_MEMORY_REGIONS_IN_USE,
+            "too many extra memory exclusions"
+        );
+    };
+
+    let mut memory_regions_in_use = memory_regions_in_use(boot_info, modules, extra_exclusions)?;
+    let raw_memory_map = raw_memory_map(boot_info)?;
+    let mut sanitized_memory_map = SANITIZED_MEMORY_MAP.lock();
+    let memory_map = sanitized_memory_map
+        .sanitize(raw_memory_map)
+        .ok_or(Error::MemoryMapTooLarge)?;
+    let physical_memory_size = usable_memory_areas(memory_map)
+        .filter_map(MemoryMapEntry::address_range)
+        .map(|area| area.end)
+        .max()
+        .unwrap_or(0);
+    if usable_memory_areas(memory_map).any(MemoryMapEntry::exceeds_address_space) {
+        warn!("Some memory is beyond the end of the address space and won't be used");
+    }
+    let available_memory_regions =
+        unused_memory_regions(&mut memory_regions_in_use, physical_memory_size);
+
+    // Only the first few gigabytes of memory are identity mapped at first. The rest can be
+    // registered once the identity map has been extended using frames from the first part.
+    let usable_start = if options.reserve_low_memory {
+        LOW_MEMORY_END
+    } else {
+        0
+    };
+    let initially_mapped = usable_start..Proc::INITIAL_VIRTUAL_MEMORY_SIZE;
+    let mut stats = memory_stats::from_memory_map(memory_map);
+    stats.low_memory = unused_regions_of_type(
+        memory_map,
+        AVAILABLE_MEMORY,
+        &available_memory_regions,
+        &(0..usable_start),
+    )
+    .map(|region| region.len())
+    .sum();
+    let mut registered_memory = REGISTERED_MEMORY.lock();
+    registered_memory.clear();
+    stats.free = register_available_memory(
+        proc,
+        memory_map,
+        &available_memory_regions,
+        &initially_mapped,
+        &mut registered_memory,
+    );
+    let mut identity_map_end = initially_mapped.end;
+    if physical_memory_size > identity_map_end {
+        identity_map_end = proc.extend_identity_map(physical_memory_size);
+        stats.free += register_available_memory(
+            proc,
+            memory_map,
+            &available_memory_regions,
+            &(initially_mapped.end..identity_map_end),
+            &mut registered_memory,
+        );
+    }
+    stats.free_by_frame_size = proc.free_memory_by_frame_size();
+
+    let acpi_memory = deferred_acpi_memory(
+        memory_map,
+        &available_memory_regions,
+        &(usable_start..identity_map_end),
+    );
+
+    stats.kernel_image = kernel_image().len();
+    stats.boot_information = boot_info.address_range().len();
+    stats.modules = modules.iter().map(|module| module.range.len()).sum();
+    kernel_assert!(
+        stats.free <= stats.available,
+        "registered {:#x} bytes of free memory but only {:#x} bytes are available",
+        stats.free,
+        stats.available
+    );
+    Ok(BootMemory {
+        stats,
+        acpi_memory,
+        registered_memory,
+        memory_map: raw_memory_map,
+        identity_map_end,
+        physical_memory_size,
+    })
+}
+
+/**
+ * Gives the ACPI memory that was held back while booting to the frame allocator and logs how much
+ * there was. Returns the number of bytes registered.
+ *
+ * # Safety
+ *
+ * Nothing may read the ACPI tables after this is called.
+ */
+unsafe fn reclaim_acpi_memory<Proc: Architecture>(proc: &mut Proc, report: &BootReport) -> usize {
+    let mut reclaimed = 0;
+    for region in report.acpi_memory.iter() {
+        reclaimed += region.len();
+        proc.register_memory_region(region.clone());
     }
+    if reclaimed > 0 {
+        info!("Reclaimed {} KiB of ACPI memory", reclaimed / 1024);
+    }
+    reclaimed
+}
+
+/**
+ * Checks that `multiboot_info_ptr` plausibly points to a multiboot2 boot information structure
+ * inside the first `mapped_size` bytes of memory. Some bootloaders have been seen passing null
+ * pointers or pointers into the kernel image, so this should be called before the boot
+ * information is read.
+ *
+ * # Safety
+ *
+ * The first `mapped_size` bytes of memory must be mapped.
+ */
+unsafe fn validate_boot_information(
+    multiboot_info_ptr: *const u8,
+    mapped_size: usize,
+) -> Result<(), Error> {
+    let header_size = size_of::<BootInformationHeader>();
+    let address = multiboot_info_ptr as usize;
+    if multiboot_info_ptr.is_null() {
+        return Err(Error::NullBootInformation);
+    }
+    let header = aligned_pointer_cast::<BootInformationHeader>(multiboot_info_ptr)
+        .ok_or(Error::MisalignedBootInformation)?;
+    if address.saturating_add(header_size) > mapped_size {
+        return Err(Error::BootInformationOutOfRange);
+    }
+    let total_size = (*header).total_size as usize;
+    let end = address
+        .checked_add(total_size.max(header_size))
+        .ok_or(Error::BootInformationOutOfRange)?;
+    if covers_kernel_image(&(address..end)) {
+        return Err(Error::BootInformationOverlapsKernel);
+    }
+    if end > mapped_size {
+        return Err(Error::BootInformationOutOfRange);
+    }
+    Ok(())
+}
+
+/// Returns true if `region` overlaps the memory occupied by the kernel image.
+#[must_use]
+pub fn covers_kernel_image(region: &Range<usize>) -> bool {
+    !intersect(region.clone(), kernel_image()).is_empty()
+}
+
+/**
+ * Returns true if `region` can be handed to the frame allocator. The in-use region math should
+ * never produce a region containing the kernel, but handing out the kernel's own frames would be
+ * catastrophic, so debug builds stop there and release builds leave the region out.
+ */
+#[must_use]
+fn may_register_memory_region(region: &Range<usize>) -> bool {
+    debug_assert!(
+        !covers_kernel_image(region),
+        "attempted to register memory containing the kernel image"
+    );
+    if covers_kernel_image(region) {
+        warn!("Skipped memory at {region:#x?} because it contains the kernel image");
+        return false;
+    }
+    true
+}
 
-    load_memory_manager(proc, memory_manager_bounds)
+/// Returns the memory occupied by the kernel image, rounded out to whole pages.
+fn kernel_image() -> Range<usize> {
+    align_outward(unaligned_kernel_image(), KERNEL_IMAGE_ALIGNMENT)
+}
+
+#[cfg(not(test))]
+fn unaligned_kernel_image() -> Range<usize> {
+    addr_of!(header_start) as usize..addr_of!(kernel_end) as usize
+}
+
+// Host tests aren't linked with the kernel's linker script, so they say where the image is
+#[cfg(test)]
+fn unaligned_kernel_image() -> Range<usize> {
+    test_boot_info::kernel_image()
+}
+
+/**
+ * Checks that the kernel image bounds from the linker script are sane. A linker script that
+ * reorders sections could otherwise make the kernel image region empty or bogus, which would
+ * quietly let the frame allocator hand out memory that the kernel is using.
+ */
+fn validate_kernel_image(mapped_size: usize) -> Result<(), Error> {
+    let image = unaligned_kernel_image();
+    if image.start >= image.end || image.end > mapped_size || image.len() > MAX_KERNEL_IMAGE_SIZE {
+        error!(
+            "Kernel image bounds {:#x}..{:#x} are invalid (mapped memory ends at {mapped_size:#x})",
+            image.start, image.end
+        );
+        return Err(Error::InvalidKernelImage);
+    }
+    if !is_aligned(&image, KERNEL_IMAGE_ALIGNMENT) {
+        let aligned = kernel_image();
+        warn!(
+            "Kernel image bounds {:#x}..{:#x} aren't page aligned; treating {:#x}..{:#x} as in use",
+            image.start, image.end, aligned.start, aligned.end
+        );
+    }
+    Ok(())
 }
 
 fn copy_and_zero_fill(dest: &mut [u8], src: &[u8]) {
@@ -137,116 +711,861 @@ fn copy_and_zero_fill(dest: &mut [u8], src: &[u8]) {
     dest[src.len()..].fill(0);
 }
 
+/// Returns the part of `src[index..index + len]` that is inside `src`, which may be empty.
 #[must_use]
 fn slice_with_bounds_check(src: &[u8], index: usize, len: usize) -> &[u8] {
-    &src[index.min(src.len())..(index + len).min(src.len())]
+    &src[index.min(src.len())..index.saturating_add(len).min(src.len())]
 }
 
+#[cfg(not(test))]
 extern "C" {
     // These aren't real variables. We just need the address of the start and end of the kernel
     static header_start: u8;
     static kernel_end: u8;
 }
 
+/// The alignment that the linker script is expected to give the start and end of the kernel image
+const KERNEL_IMAGE_ALIGNMENT: usize = 0x1000;
+
+/// The largest kernel image that's considered plausible
+const MAX_KERNEL_IMAGE_SIZE: usize = 64 * 0x10_0000;
+
+const MEMORY_MANAGER_MODULE_NAME: &str = "memory_manager";
+const INIT_MODULE_NAME: &str = "init";
+
+const ELF_MAGIC_NUMBER: u32 = 0x464c_457f;
 const ELF_LOADABLE_SEGMENT: u32 = 1;
 const ELF_WRITABLE_SEGMENT: u32 = 2;
 const ELF_EXECUTABLE_SEGMENT: u32 = 1;
 
-unsafe fn load_memory_manager<Proc: Architecture>(
+/**
+ * Checks whether the module in `module_range` starts with the ELF magic number. This only looks at
+ * the first four bytes, so it's a cheap way to tell executables apart from other kinds of modules
+ * before trying to parse them.
+ *
+ * # Safety
+ *
+ * `module_range` must be a range of readable memory.
+ */
+unsafe fn is_elf(module_range: Range<usize>) -> bool {
+    let magic_size = size_of::<u32>();
+    if module_range.len() < magic_size {
+        return false;
+    }
+    let magic = slice::from_raw_parts(module_range.start as *const u8, magic_size);
+    magic == ELF_MAGIC_NUMBER.to_le_bytes()
+}
+
+fn boot_modules(
+    boot_info: BootInformation,
+) -> Result<FixedVec<BootModule, MAX_MEMORY_REGIONS_IN_USE>, Error> {
+    let mut modules = FixedVec::new();
+    for module in boot_info.tags_of_type::<BootModuleTag>() {
+        let module = BootModule {
+            name: module.string,
+            range: module.mod_start as usize..module.mod_end as usize,
+        };
+        debug!("Boot module {} at {:#x?}", module.name, module.range);
+        modules
+            .push(module)
+            .map_err(|_| Error::TooManyMemoryRegionsInUse)?;
+    }
+    Ok(modules)
+}
+
+/// Lists the memory that must be kept out of the frame allocator
+fn memory_regions_in_use<const N: usize>(
+    boot_info: BootInformation,
+    modules: &[BootModule],
+    extra_exclusions: [Range<usize>; N],
+) -> Result<FixedVec<Range<usize>, MAX_MEMORY_REGIONS_IN_USE>, Error> {
+    let mut memory_regions_in_use = FixedVec::new();
+    let framebuffer = boot_info
+        .tags_of_type::<FramebufferTag>()
+        .next()
+        .map(|framebuffer_tag| {
+            let framebuffer_addr = framebuffer_tag.framebuffer as usize;
+            framebuffer_addr
+                ..framebuffer_addr
+                    + (framebuffer_tag.height as usize * framebuffer_tag.pitch as usize)
+        });
+    for region in [kernel_image(), boot_info.address_range()]
+        .into_iter()
+        .chain(framebuffer)
+        .chain(modules.iter().map(|module| module.range.clone()))
+        .chain(extra_exclusions)
+    {
+        memory_regions_in_use
+            .push(region)
+            .map_err(|_| Error::TooManyMemoryRegionsInUse)?;
+    }
+    Ok(memory_regions_in_use)
+}
+
+/**
+ * Loads the memory manager and the init process if there is one.
+ *
+ * # Safety
+ *
+ * The bounds must be ranges of readable memory.
+ */
+unsafe fn load_processes<Proc: Architecture>(
+    proc: &mut Proc,
+    memory_manager_bounds: Range<usize>,
+    init_bounds: Option<Range<usize>>,
+) -> Result<(ProcessLaunchInfo, Option<ProcessLaunchInfo>), Error> {
+    if !is_elf(memory_manager_bounds.clone()) {
+        return Err(Error::InvalidMemoryManagerModule);
+    }
+    let memory_manager = load_elf_executable(
+        proc,
+        memory_manager_bounds,
+        Error::InvalidMemoryManagerExecutable,
+    )?;
+    let init = match init_bounds {
+        Some(init_bounds) if is_elf(init_bounds.clone()) => Some(load_elf_executable(
+            proc,
+            init_bounds,
+            Error::InvalidInitExecutable,
+        )?),
+        Some(_) => return Err(Error::InvalidInitExecutable),
+        None => None,
+    };
+    Ok((memory_manager, init))
+}
+
+/**
+ * Loads the ELF executable in `exectuable_location` into a new address space. `invalid_executable`
+ * is returned if the executable is malformed.
+ *
+ * # Safety
+ *
+ * `exectuable_location` must be a range of readable memory that starts with an ELF header.
+ */
+#[must_use = "the process can't be launched if loading it failed"]
+unsafe fn load_elf_executable<Proc: Architecture>(
     proc: &mut Proc,
     exectuable_location: Range<usize>,
-) -> Option<ProcessLaunchInfo> {
-    let memory_manager_root_page_table = proc.initialize_memory_manager_page_tables()?;
+    invalid_executable: Error,
+) -> Result<ProcessLaunchInfo, Error> {
+    let (root_page_table, stack_pointer) = proc
+        .initialize_process_page_tables()
+        .ok_or(Error::OutOfMemory)?;
+    let entry_point = load_elf_segments(
+        proc,
+        &mut *root_page_table,
+        exectuable_location,
+        invalid_executable,
+    )
+    .inspect_err(|_| proc.abandon_address_space(root_page_table))?;
 
-    let memory_manager_elf_header = &*(exectuable_location.start as *const Proc::ExecutableHeader);
+    // Page tables are identity mapped while booting, so the pointer is also the physical address
+    Ok(ProcessLaunchInfo {
+        root_page_table_address: PhysicalAddress::new(root_page_table as usize),
+        entry_point,
+        stack_pointer,
+        arguments: [0; MAX_PROCESS_ARGUMENTS],
+    })
+}
 
-    if !memory_manager_elf_header.is_valid(exectuable_location.len()) {
-        return None;
+/// Copies the loadable segments of an ELF executable into the address space with the root page
+/// table `root_page_table` and returns the executable's entry point.
+unsafe fn load_elf_segments<Proc: Architecture>(
+    proc: &mut Proc,
+    root_page_table: &mut Proc::PageTable,
+    exectuable_location: Range<usize>,
+    invalid_executable: Error,
+) -> Result<VirtualAddress, Error> {
+    let elf_header = &*(exectuable_location.start as *const Proc::ExecutableHeader);
+
+    if !elf_header.is_valid(exectuable_location.len()) {
+        return Err(invalid_executable);
     }
 
+    let segment_header_table_offset = elf_header
+        .segment_header_table_offset()
+        .ok_or(invalid_executable)?;
+    let entry = elf_header.entry().ok_or(invalid_executable)?;
+
     for segment_header in slice::from_raw_parts(
-        (exectuable_location.start + memory_manager_elf_header.segment_header_table_offset())
-            as *const Proc::SegmentHeader,
-        memory_manager_elf_header.num_segments(),
+        (exectuable_location.start + segment_header_table_offset) as *const Proc::SegmentHeader,
+        elf_header.num_segments(),
     )
     .iter()
     .filter(|header| header.segment_type() == ELF_LOADABLE_SEGMENT)
     {
-        if segment_header.offset() + segment_header.file_size() > exectuable_location.len()
-            || segment_header.file_size() > segment_header.memory_size()
+        let (Some(offset), Some(address), Some(file_size), Some(memory_size)) = (
+            segment_header.offset(),
+            segment_header.address(),
+            segment_header.file_size(),
+            segment_header.memory_size(),
+        ) else {
+            return Err(invalid_executable);
+        };
+        if offset
+            .checked_add(file_size)
+            .is_none_or(|end| end > exectuable_location.len())
+            || file_size > memory_size
         {
-            return None;
+            return Err(invalid_executable);
         }
         proc.copy_into_address_space(
-            &mut *memory_manager_root_page_table,
-            segment_header.address(),
-            slice::from_raw_parts(
-                (exectuable_location.start + segment_header.offset()) as *const u8,
-                segment_header.file_size(),
-            ),
-            segment_header.memory_size(),
+            root_page_table,
+            address,
+            slice::from_raw_parts((exectuable_location.start + offset) as *const u8, file_size),
+            memory_size,
             segment_header.flags(),
-        );
+        )
+        .ok_or(Error::OutOfMemory)?;
     }
 
-    Some(ProcessLaunchInfo {
-        root_page_table_address: memory_manager_root_page_table as usize,
-        entry_point: memory_manager_elf_header.entry(),
-    })
-}
-
-// I'm only supporting 64 bit machines as of now so casting from u64 to usize shouldn't result
-// in any truncation. Will need to revisit if I ever add support for 32 bit machines.
-#[allow(clippy::cast_possible_truncation)]
-fn memory_area_start(area: &MemoryMapEntry) -> usize {
-    area.base_addr as usize
-}
-
-#[allow(clippy::cast_possible_truncation)]
-fn memory_area_end(area: &MemoryMapEntry) -> usize {
-    (area.base_addr + area.length) as usize
-}
-
-fn memory_manager_executable(boot_info: BootInformation) -> Option<Range<usize>> {
-    let memory_manager = boot_info
-        .tags_of_type::<BootModuleTag>()
-        .find(|module| module.string.contains("memory_manager"))?;
-    Some(memory_manager.mod_start as usize..memory_manager.mod_end as usize)
+    Ok(VirtualAddress::new(entry))
 }
 
-fn intersect(a: Range<usize>, b: Range<usize>) -> Range<usize> {
-    max(a.start, b.start)..min(a.end, b.end)
+/**
+ * Finds the boot module called `name`. The first word of a module's command line is the path that
+ * it was loaded from, and the module's name is the file name at the end of that path. Returns
+ * `Err` if more than one module has the name.
+ */
+fn only_module_named(modules: &[BootModule], name: &str) -> Result<Option<Range<usize>>, ()> {
+    let mut candidates = modules.iter().filter(|module| {
+        let path = module.name.split_whitespace().next().unwrap_or("");
+        path.rsplit('/').next() == Some(name)
+    });
+    let Some(module) = candidates.next() else {
+        return Ok(None);
+    };
+    if candidates.next().is_some() {
+        return Err(());
+    }
+    info!("Using {} at {:#x?} as {name}", module.name, module.range);
+    Ok(Some(module.range.clone()))
 }
 
 fn unused_memory_regions_from_area<'a, RangeIter: Iterator<Item = Range<usize>> + 'a>(
     memory_area: &'a MemoryMapEntry,
     unused_memory_regions: RangeIter,
 ) -> impl Iterator<Item = Range<usize>> + 'a {
-    let area = memory_area_start(memory_area)..memory_area_end(memory_area);
+    // Areas that can't be addressed are skipped
+    let area = memory_area.address_range().unwrap_or(0..0);
     unused_memory_regions
         .map(move |region| intersect(area.clone(), region.clone()))
         .filter(|region| !region.is_empty())
 }
 
+/// Computes the gaps between the regions in `memory_regions_in_use` below `max_address`.
 fn unused_memory_regions(
     memory_regions_in_use: &mut [Range<usize>],
     max_address: usize,
-) -> Option<impl Iterator<Item = Range<usize>> + Clone + '_> {
-    memory_regions_in_use.sort_unstable_by(|a, b| a.start.cmp(&b.start));
-    Some(
-        once(0..memory_regions_in_use.first()?.start)
-            .chain(
-                memory_regions_in_use
-                    .windows(2)
-                    .map(|window| window[0].end..window[1].start),
+) -> impl Iterator<Item = Range<usize>> + Clone + '_ {
+    // The bootloader may place modules or the boot information inside other regions that are in
+    // use, and the gaps between overlapping regions would be inverted
+    let memory_regions_in_use = merge_sorted(memory_regions_in_use);
+    let first_start = memory_regions_in_use
+        .first()
+        .map_or(max_address, |r| r.start);
+    let last_end = memory_regions_in_use.last().map_or(max_address, |r| r.end);
+    let gaps = once(0..first_start)
+        .chain(
+            memory_regions_in_use
+                .windows(2)
+                .map(|window| window[0].end..window[1].start),
+        )
+        .chain(once(last_end..max_address));
+    kernel_assert!(
+        gaps.clone()
+            .all(|gap| memory_regions_in_use.iter().all(|region| intersect(
+                gap.clone(),
+                region.clone()
             )
-            .chain(once(memory_regions_in_use.last()?.end..max_address)),
-    )
+            .is_empty())),
+        "the gaps between memory regions overlap regions that are in use"
+    );
+    gaps
+}
+
+/// Registers the available memory in `memory_map` that lies inside `window` and isn't in use, in
+/// order of address, and appends it to `registered_memory`. Returns the number of bytes registered.
+unsafe fn register_available_memory<Proc: Architecture>(
+    proc: &mut Proc,
+    memory_map: MemoryMapTag,
+    unused_regions: &(impl Iterator<Item = Range<usize>> + Clone),
+    window: &Range<usize>,
+    registered_memory: &mut RegisteredMemory,
+) -> usize {
+    let mut registered = 0;
+    let mut unrecorded_regions = 0;
+    let first_new_region = registered_memory.len();
+    for memory_region in
+        unused_regions_of_type(memory_map, AVAILABLE_MEMORY, unused_regions, window)
+    {
+        // Memory that can't be recorded is still registered so that it isn't lost
+        if let Err(memory_region) = registered_memory.push(memory_region) {
+            unrecorded_regions += 1;
+            registered += memory_region.len();
+            proc.register_memory_region(memory_region);
+        }
+    }
+    if unrecorded_regions > 0 {
+        warn!("Registered {unrecorded_regions} memory regions without recording them");
+    }
+
+    // The firmware's memory map can be in any order, so sort the regions to make registration
+    // predictable
+    let new_regions = &mut registered_memory[first_new_region..];
+    new_regions.sort_unstable_by_key(|memory_region| memory_region.start);
+    for memory_region in new_regions.iter() {
+        debug!(
+            "Registering {:#x}..{:#x} ({} KiB)",
+            memory_region.start,
+            memory_region.end,
+            memory_region.len() / 1024
+        );
+        registered += memory_region.len();
+        proc.register_memory_region(memory_region.clone());
+    }
+    registered
 }
 
-fn available_memory_areas(memory_map: MemoryMapTag) -> impl Iterator<Item = &MemoryMapEntry> {
+/// Collects the ACPI memory inside `window` that isn't in use. ACPI memory holds the ACPI tables,
+/// so it can't be handed out until they've been read.
+fn deferred_acpi_memory(
+    memory_map: MemoryMapTag,
+    unused_regions: &(impl Iterator<Item = Range<usize>> + Clone),
+    window: &Range<usize>,
+) -> FixedVec<Range<usize>, MAX_DEFERRED_ACPI_REGIONS> {
+    let mut acpi_memory = FixedVec::new();
+    for region in unused_regions_of_type(memory_map, ACPI_MEMORY, unused_regions, window) {
+        if acpi_memory.push(region).is_err() {
+            warn!("There are too many ACPI memory regions to reclaim all of them");
+            break;
+        }
+    }
+    acpi_memory
+}
+
+/// Finds the parts of the memory map areas of type `region_type` that lie inside `window` and
+/// aren't in use.
+fn unused_regions_of_type<'a>(
+    memory_map: MemoryMapTag<'a>,
+    region_type: u32,
+    unused_regions: &'a (impl Iterator<Item = Range<usize>> + Clone),
+    window: &'a Range<usize>,
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    memory_map
+        .entries
+        .iter()
+        .filter(move |area| area.region_type == region_type)
+        .flat_map(|memory_area| {
+            unused_memory_regions_from_area(memory_area, unused_regions.clone())
+        })
+        .map(|memory_region| intersect(memory_region, window.clone()))
+        .filter(|memory_region| !memory_region.is_empty())
+}
+
+/// Finds the memory map areas that can eventually be given to the frame allocator
+fn usable_memory_areas(memory_map: MemoryMapTag) -> impl Iterator<Item = &MemoryMapEntry> {
     memory_map
         .entries
         .iter()
         .filter(|area| area.region_type == AVAILABLE_MEMORY || area.region_type == ACPI_MEMORY)
 }
+
+#[cfg(test)]
+// Memory is often registered or excluded in a single region
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{
+        mock_architecture::{interrupt_waits, MockArchitecture, STACK_TOP},
+        test_boot_info::{
+            elf_executable, module_range, set_kernel_image, BootInfoBuilder, TestSegment,
+            DEFAULT_KERNEL_IMAGE,
+        },
+    };
+    use frame_allocation::page_tables::TWO_MEGABYTES;
+    use multiboot2::DEFECTIVE_MEMORY;
+    use std::{vec, vec::Vec};
+    use test_arena::Arena;
+
+    const RESERVED_MEMORY: u32 = 2;
+
+    /// What `register_boot_memory` did with a mock architecture
+    struct Registration {
+        proc: MockArchitecture,
+        acpi_memory: Vec<Range<usize>>,
+        stats: MemoryStats,
+    }
+
+    fn register(boot_info: BootInformation, options: BootOptions) -> Registration {
+        let mut proc = MockArchitecture::new(0);
+        let modules = boot_modules(boot_info).unwrap();
+        let memory =
+            unsafe { register_boot_memory(&mut proc, boot_info, options, &modules, []) }.unwrap();
+        assert_eq!(&memory.registered_memory[..], &proc.registered[..]);
+        Registration {
+            acpi_memory: memory.acpi_memory.to_vec(),
+            stats: memory.stats,
+            proc,
+        }
+    }
+
+    fn without_low_memory_reserved() -> BootOptions {
+        BootOptions {
+            reserve_low_memory: false,
+            ..BootOptions::default()
+        }
+    }
+
+    fn to_u64(range: Range<usize>) -> Range<u64> {
+        range.start as u64..range.end as u64
+    }
+
+    #[test]
+    fn module_at_the_top_of_memory() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[
+                (0..0x9_fc00, AVAILABLE_MEMORY),
+                (0xf_0000..0x10_0000, RESERVED_MEMORY),
+                (0x10_0000..0x800_0000, AVAILABLE_MEMORY),
+            ])
+            .module(0x7f0_0000..0x800_0000, "/boot/memory_manager")
+            .build();
+        let registration = register(blob.boot_info(), BootOptions::default());
+        assert_eq!(
+            registration.proc.registered,
+            [DEFAULT_KERNEL_IMAGE.end..0x7f0_0000]
+        );
+        assert!(registration.proc.identity_map_requests.is_empty());
+
+        let registration = register(blob.boot_info(), without_low_memory_reserved());
+        assert_eq!(
+            registration.proc.registered,
+            [0..0x9_fc00, DEFAULT_KERNEL_IMAGE.end..0x7f0_0000]
+        );
+    }
+
+    #[test]
+    fn memory_stats_account_for_the_memory_map() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[
+                (0..0x9_fc00, AVAILABLE_MEMORY),
+                (0x9_fc00..0x10_0000, RESERVED_MEMORY),
+                (0x10_0000..0x800_0000, AVAILABLE_MEMORY),
+                (0x800_0000..0x801_0000, ACPI_MEMORY),
+                (0x801_0000..0x802_0000, DEFECTIVE_MEMORY),
+            ])
+            .module(0x40_0000..0x50_0000, "/boot/memory_manager")
+            .module(0x60_0000..0x61_0000, "/boot/init")
+            .build();
+        let registration = register(blob.boot_info(), BootOptions::default());
+        assert_eq!(
+            registration.proc.registered,
+            [
+                0x20_0000..0x40_0000,
+                0x50_0000..0x60_0000,
+                0x61_0000..0x800_0000
+            ]
+        );
+        let stats = registration.stats;
+        assert_eq!(stats.total, 0x802_0000);
+        assert_eq!(stats.available, 0x9_fc00 + 0x7f0_0000);
+        assert_eq!(stats.reserved, 0x6_0400);
+        assert_eq!(stats.acpi, 0x1_0000);
+        assert_eq!(stats.defective, 0x1_0000);
+        assert_eq!(stats.free, 0x20_0000 + 0x10_0000 + 0x79f_0000);
+        // The 2 MB frames between 0x20_0000 and 0x40_0000 and between 0x80_0000 and 0x800_0000
+        assert_eq!(
+            stats.free_by_frame_size,
+            [0x10_0000 + 0x1f_0000, 0x20_0000 + 0x780_0000, 0]
+        );
+        assert_eq!(stats.kernel_image, DEFAULT_KERNEL_IMAGE.len());
+        assert_eq!(
+            stats.boot_information,
+            blob.boot_info().address_range().len()
+        );
+        assert_eq!(stats.modules, 0x10_0000 + 0x1_0000);
+        assert_eq!(stats.low_memory, 0x9_fc00);
+        assert_eq!(stats.unaccounted(), 0);
+    }
+
+    #[test]
+    fn boot_info_below_the_kernel() {
+        let arena = Arena::new(4 * TWO_MEGABYTES);
+        let memory = arena.range();
+        let kernel_image = memory.start + TWO_MEGABYTES..memory.start + 2 * TWO_MEGABYTES;
+        set_kernel_image(kernel_image.clone());
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[(to_u64(memory.clone()), AVAILABLE_MEMORY)])
+            .build();
+        let boot_info = unsafe { blob.copy_to(memory.start + 0x1000) };
+        let boot_info_range = boot_info.address_range();
+        assert!(boot_info_range.end < kernel_image.start);
+        let registration = register(boot_info, BootOptions::default());
+        assert_eq!(
+            registration.proc.registered,
+            [
+                memory.start..boot_info_range.start,
+                boot_info_range.end..kernel_image.start,
+                kernel_image.end..memory.end,
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_map_with_a_hole_at_0xa0000() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[
+                (0..0xa_0000, AVAILABLE_MEMORY),
+                (0xa_0000..0x10_0000, RESERVED_MEMORY),
+                (0x10_0000..0x3ff_0000, AVAILABLE_MEMORY),
+                (0x3ff_0000..0x400_0000, ACPI_MEMORY),
+            ])
+            .module(0x30_0000..0x40_0000, "/boot/memory_manager")
+            .build();
+        let registration = register(blob.boot_info(), without_low_memory_reserved());
+        assert_eq!(
+            registration.proc.registered,
+            [
+                0..0xa_0000,
+                DEFAULT_KERNEL_IMAGE.end..0x30_0000,
+                0x40_0000..0x3ff_0000
+            ]
+        );
+        assert_eq!(registration.acpi_memory, [0x3ff_0000..0x400_0000]);
+    }
+
+    #[test]
+    fn every_available_area_is_registered() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[
+                (0..0x9_fc00, AVAILABLE_MEMORY),
+                (0x9_fc00..0x10_0000, RESERVED_MEMORY),
+                (0x10_0000..0x80_0000, AVAILABLE_MEMORY),
+                (0x80_0000..0x80_8000, ACPI_MEMORY),
+                (0x80_8000..0x100_0000, AVAILABLE_MEMORY),
+                (0x100_0000..0x110_0000, RESERVED_MEMORY),
+                (0x110_0000..0x200_0000, AVAILABLE_MEMORY),
+                (0x200_0000..0x210_0000, RESERVED_MEMORY),
+                (0x210_0000..0x400_0000, AVAILABLE_MEMORY),
+            ])
+            .build();
+        let registration = register(blob.boot_info(), without_low_memory_reserved());
+        assert_eq!(
+            registration.proc.registered,
+            [
+                0..0x9_fc00,
+                DEFAULT_KERNEL_IMAGE.end..0x80_0000,
+                0x80_8000..0x100_0000,
+                0x110_0000..0x200_0000,
+                0x210_0000..0x400_0000,
+            ]
+        );
+    }
+
+    #[test]
+    fn basic_memory_info_stands_in_for_a_memory_map() {
+        let blob = BootInfoBuilder::new()
+            .basic_memory_info(639, 0x1_fc00)
+            .build();
+        let registration = register(blob.boot_info(), without_low_memory_reserved());
+        assert_eq!(
+            registration.proc.registered,
+            [0..639 * 1024, DEFAULT_KERNEL_IMAGE.end..0x800_0000]
+        );
+    }
+
+    #[test]
+    fn memory_past_the_initial_identity_map_is_registered_once_it_is_mapped() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[(0x10_0000..0x1_8000_0000, AVAILABLE_MEMORY)])
+            .build();
+        let mut proc = MockArchitecture::new(0);
+        proc.identity_map_limit = 0x1_4000_0000;
+        let modules = boot_modules(blob.boot_info()).unwrap();
+        let memory = unsafe {
+            register_boot_memory(
+                &mut proc,
+                blob.boot_info(),
+                BootOptions::default(),
+                &modules,
+                [],
+            )
+        }
+        .unwrap();
+        assert_eq!(memory.identity_map_end, 0x1_4000_0000);
+        drop(memory);
+        assert_eq!(proc.identity_map_requests, [0x1_8000_0000]);
+        assert_eq!(
+            proc.registered,
+            [
+                DEFAULT_KERNEL_IMAGE.end..0x1_0000_0000,
+                0x1_0000_0000..0x1_4000_0000
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_exclusions_are_not_registered() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[(0x10_0000..0x100_0000, AVAILABLE_MEMORY)])
+            .build();
+        let mut proc = MockArchitecture::new(0);
+        let memory = unsafe {
+            register_boot_memory(
+                &mut proc,
+                blob.boot_info(),
+                BootOptions::default(),
+                &[],
+                [0x80_0000..0x90_0000],
+            )
+        }
+        .unwrap();
+        drop(memory);
+        assert_eq!(
+            proc.registered,
+            [DEFAULT_KERNEL_IMAGE.end..0x80_0000, 0x90_0000..0x100_0000]
+        );
+    }
+
+    #[test]
+    fn regions_overlapping_the_kernel_image_cover_it() {
+        set_kernel_image(0x20_0000..0x30_0000);
+        assert!(covers_kernel_image(&(0x1f_f000..0x20_1000)));
+        assert!(covers_kernel_image(&(0x2f_f000..0x40_0000)));
+        assert!(covers_kernel_image(&(0x24_0000..0x25_0000)));
+        assert!(covers_kernel_image(&(0..0x100_0000)));
+        assert!(!covers_kernel_image(&(0x10_0000..0x20_0000)));
+        assert!(!covers_kernel_image(&(0x30_0000..0x40_0000)));
+    }
+
+    #[test]
+    fn memory_beside_the_kernel_image_may_be_registered() {
+        assert!(may_register_memory_region(&(0..DEFAULT_KERNEL_IMAGE.start)));
+        assert!(may_register_memory_region(
+            &(DEFAULT_KERNEL_IMAGE.end..0x100_0000)
+        ));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "attempted to register memory containing the kernel image")]
+    fn registering_the_kernel_image_panics_in_debug_builds() {
+        let _ = may_register_memory_region(&(0x8_0000..DEFAULT_KERNEL_IMAGE.start + 0x1000));
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn registering_the_kernel_image_is_skipped_in_release_builds() {
+        assert!(!may_register_memory_region(
+            &(0x8_0000..DEFAULT_KERNEL_IMAGE.start + 0x1000)
+        ));
+    }
+
+    #[test]
+    fn memory_manager_module_is_found_by_its_file_name() {
+        let blob = BootInfoBuilder::new()
+            .module(0x30_0000..0x31_0000, "/boot/memory_manager_test_corpus")
+            .module(0x40_0000..0x41_0000, "/boot/memory_manager --verbose")
+            .module(0x50_0000..0x51_0000, "/boot/not_the_memory_manager.bin")
+            .build();
+        let modules = boot_modules(blob.boot_info()).unwrap();
+        assert_eq!(
+            only_module_named(&modules, MEMORY_MANAGER_MODULE_NAME),
+            Ok(Some(0x40_0000..0x41_0000))
+        );
+        assert_eq!(only_module_named(&modules, INIT_MODULE_NAME), Ok(None));
+    }
+
+    #[test]
+    fn modules_that_only_resemble_the_memory_manager_are_ignored() {
+        let blob = BootInfoBuilder::new()
+            .module(0x30_0000..0x31_0000, "memory_manager_test_corpus")
+            .module(0x40_0000..0x41_0000, "/boot/not_the_memory_manager.bin")
+            .build();
+        let modules = boot_modules(blob.boot_info()).unwrap();
+        assert_eq!(
+            only_module_named(&modules, MEMORY_MANAGER_MODULE_NAME),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn two_memory_managers_fail_the_boot() {
+        let blob = BootInfoBuilder::new()
+            .memory_map(&[(0x10_0000..0x800_0000, AVAILABLE_MEMORY)])
+            .module(0x30_0000..0x31_0000, "/boot/memory_manager")
+            .module(0x40_0000..0x41_0000, "/modules/memory_manager")
+            .build();
+        let modules = boot_modules(blob.boot_info()).unwrap();
+        assert_eq!(
+            only_module_named(&modules, MEMORY_MANAGER_MODULE_NAME),
+            Err(())
+        );
+        let mut proc = MockArchitecture::new(1);
+        let result = unsafe { boot_os(&mut proc, blob.boot_info(), BootOptions::default(), []) };
+        assert!(matches!(result, Err(Error::MultipleMemoryManagers)));
+        assert!(proc.registered.is_empty());
+    }
+
+    #[test]
+    fn segments_are_copied_into_a_new_address_space() {
+        let code = [0x90; 100];
+        let data = [1, 2, 3];
+        let executable = elf_executable(
+            0x40_1000,
+            &[
+                TestSegment {
+                    address: 0x40_1000,
+                    data: &code,
+                    memory_size: 100,
+                    flags: ELF_EXECUTABLE_SEGMENT,
+                },
+                TestSegment {
+                    address: 0x60_0000,
+                    data: &data,
+                    memory_size: 0x2000,
+                    flags: ELF_WRITABLE_SEGMENT,
+                },
+            ],
+        );
+        let mut proc = MockArchitecture::new(1);
+        let (memory_manager, init) =
+            unsafe { load_processes(&mut proc, module_range(&executable), None) }.unwrap();
+        assert!(init.is_none());
+        assert_eq!(memory_manager.entry_point, VirtualAddress::new(0x40_1000));
+        assert_eq!(memory_manager.stack_pointer, VirtualAddress::new(STACK_TOP));
+        assert!(proc.abandoned.is_empty());
+        let copies: Vec<_> = proc
+            .copies
+            .iter()
+            .map(|copy| {
+                assert_eq!(
+                    copy.root_page_table as usize,
+                    memory_manager.root_page_table_address.as_usize()
+                );
+                (copy.address, copy.data.clone(), copy.size, copy.flags)
+            })
+            .collect();
+        assert_eq!(
+            copies,
+            [
+                (0x40_1000, code.to_vec(), 100, ELF_EXECUTABLE_SEGMENT),
+                (0x60_0000, data.to_vec(), 0x2000, ELF_WRITABLE_SEGMENT),
+            ]
+        );
+    }
+
+    #[test]
+    fn segment_past_the_end_of_the_module_abandons_the_address_space() {
+        let data = [7; 32];
+        let executable = elf_executable(
+            0x40_0000,
+            &[TestSegment {
+                address: 0x40_0000,
+                data: &data,
+                memory_size: 32,
+                flags: 0,
+            }],
+        );
+        let truncated = &executable[..executable.len() - 2];
+        let mut proc = MockArchitecture::new(1);
+        let result = unsafe { load_processes(&mut proc, module_range(truncated), None) };
+        assert!(matches!(result, Err(Error::InvalidMemoryManagerExecutable)));
+        assert_eq!(proc.abandoned.len(), 1);
+        assert!(proc.copies.is_empty());
+    }
+
+    #[test]
+    fn init_is_loaded_into_its_own_address_space() {
+        let memory_manager = elf_executable(0x40_1000, &[]);
+        let code = [0xc3; 16];
+        let init = elf_executable(
+            0x50_2000,
+            &[TestSegment {
+                address: 0x50_2000,
+                data: &code,
+                memory_size: 16,
+                flags: ELF_EXECUTABLE_SEGMENT,
+            }],
+        );
+        let mut proc = MockArchitecture::new(2);
+        let (memory_manager, init) = unsafe {
+            load_processes(
+                &mut proc,
+                module_range(&memory_manager),
+                Some(module_range(&init)),
+            )
+        }
+        .unwrap();
+        let init = init.unwrap();
+        assert_eq!(memory_manager.entry_point, VirtualAddress::new(0x40_1000));
+        assert_eq!(init.entry_point, VirtualAddress::new(0x50_2000));
+        assert_eq!(init.stack_pointer, VirtualAddress::new(STACK_TOP));
+        assert_ne!(
+            init.root_page_table_address,
+            memory_manager.root_page_table_address
+        );
+        assert!(proc.abandoned.is_empty());
+        assert_eq!(proc.copies.len(), 1);
+        let copy = &proc.copies[0];
+        assert_eq!(
+            copy.root_page_table as usize,
+            init.root_page_table_address.as_usize()
+        );
+        assert_eq!(
+            (copy.address, copy.data.clone(), copy.size, copy.flags),
+            (0x50_2000, code.to_vec(), 16, ELF_EXECUTABLE_SEGMENT)
+        );
+    }
+
+    #[test]
+    fn init_that_is_not_an_executable_is_rejected() {
+        let memory_manager = elf_executable(0x40_0000, &[]);
+        let init = vec![0u64; 8];
+        let mut proc = MockArchitecture::new(2);
+        let result = unsafe {
+            load_processes(
+                &mut proc,
+                module_range(&memory_manager),
+                Some(module_range(&init)),
+            )
+        };
+        assert!(matches!(result, Err(Error::InvalidInitExecutable)));
+    }
+
+    #[test]
+    fn running_out_of_address_spaces_fails() {
+        let memory_manager = elf_executable(0x40_0000, &[]);
+        let init = elf_executable(0x40_0000, &[]);
+        let mut proc = MockArchitecture::new(1);
+        let result = unsafe {
+            load_processes(
+                &mut proc,
+                module_range(&memory_manager),
+                Some(module_range(&init)),
+            )
+        };
+        assert!(matches!(result, Err(Error::OutOfMemory)));
+    }
+
+    #[test]
+    fn idling_with_work_to_do_does_not_wait() {
+        idle_until::<MockArchitecture>(|| true);
+        assert_eq!(interrupt_waits(), 0);
+    }
+
+    #[test]
+    fn idling_waits_for_an_interrupt_each_time_there_is_no_work() {
+        let checks = core::cell::Cell::new(0);
+        idle_until::<MockArchitecture>(|| {
+            checks.set(checks.get() + 1);
+            // Work turns up after the third interrupt
+            interrupt_waits() == 3
+        });
+        assert_eq!(interrupt_waits(), 3);
+        assert_eq!(checks.get(), 4);
+    }
+}
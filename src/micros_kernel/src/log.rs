@@ -0,0 +1,162 @@
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+use spin::{Mutex, Once};
+
+/// How important a log message is. Messages less important than the maximum level are dropped.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// How long it's been since the Unix epoch, or `None` if that isn't known yet
+static CLOCK: Once<fn() -> Option<Duration>> = Once::new();
+
+type Sink = Mutex<Option<&'static mut (dyn Write + Send)>>;
+
+/// The console on the screen, which gets every message first
+static CONSOLE: Sink = Mutex::new(None);
+static SINK: Sink = Mutex::new(None);
+
+/// Sends all future log messages to `sink` as well as the console. Messages logged before a sink
+/// is set are dropped.
+pub fn set_sink(sink: &'static mut (dyn Write + Send)) {
+    *SINK.lock() = Some(sink);
+}
+
+/// Makes `console` the primary log sink. Every message is written to it before the other sink.
+pub fn set_console(console: &'static mut (dyn Write + Send)) {
+    *CONSOLE.lock() = Some(console);
+}
+
+/// Stops logging to the console and hands it back, so that something else can draw on the screen
+pub fn take_console() -> Option<&'static mut (dyn Write + Send)> {
+    CONSOLE.lock().take()
+}
+
+/// Starts every future log message with the time that `clock` gives, so that the log can be lined
+/// up with logs from outside the machine. Messages are left without a time while it gives `None`.
+pub fn set_clock(clock: fn() -> Option<Duration>) {
+    CLOCK.call_once(|| clock);
+}
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a message to the console and the log sink. Use the `error!`, `warn!`, `info!`, and
+/// `debug!` macros rather than calling this directly.
+pub fn log(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    for sink in [&CONSOLE, &SINK] {
+        if let Some(sink) = sink.lock().as_mut() {
+            // There's nowhere to report a failure to log, so errors are ignored
+            let _ = writeln!(sink, "{} {}", Prefix(level), args);
+        }
+    }
+}
+
+/// Writes a message to the console and the log sink regardless of the maximum level. Returns false
+/// without waiting if neither is set or free, such as when a message is logged while another is
+/// being written.
+pub fn log_unconditionally(level: Level, args: fmt::Arguments) -> bool {
+    write_to_sink(|sink| writeln!(sink, "{} {}", Prefix(level), args))
+}
+
+/// What goes in front of a log message: its level, after the time in seconds since the Unix epoch
+/// once that's known
+struct Prefix(Level);
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match CLOCK.get().and_then(|clock| clock()) {
+            Some(now) => write!(
+                f,
+                "[{}.{:06} {}]",
+                now.as_secs(),
+                now.subsec_micros(),
+                self.0.label()
+            ),
+            None => write!(f, "[{}]", self.0.label()),
+        }
+    }
+}
+
+/// Lets `write` write whatever it wants to the console and the log sink, for output that doesn't
+/// fit in a log message. Sinks that are already in use are skipped rather than waited for. Returns
+/// false if nothing could be written to.
+pub fn write_to_sink(mut write: impl FnMut(&mut dyn Write) -> fmt::Result) -> bool {
+    let mut written = false;
+    for sink in [&CONSOLE, &SINK] {
+        if let Some(sink) = sink.try_lock().as_mut().and_then(|sink| sink.as_mut()) {
+            let _ = write(&mut **sink);
+            written = true;
+        }
+    }
+    written
+}
+
+/// Writes a line to the log sink without a level label, for output that another program reads.
+/// The console is left out since nothing reads it. Returns false without waiting if there's no
+/// sink or it's already in use.
+#[cfg(feature = "qemu-test")]
+pub fn write_line(args: fmt::Arguments) -> bool {
+    let Some(mut sink) = SINK.try_lock() else {
+        return false;
+    };
+    let Some(sink) = sink.as_mut() else {
+        return false;
+    };
+    let _ = writeln!(sink, "{args}");
+    true
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
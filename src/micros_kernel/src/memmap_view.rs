@@ -0,0 +1,200 @@
+use crate::kernel_image;
+use core::{
+    fmt::{self, Write},
+    iter::once,
+    ops::Range,
+};
+use framebuffer::{
+    font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH},
+    Rgb, StandardRgbFramebuffer,
+};
+use multiboot2::{
+    BootInformation, BootModuleTag, FramebufferTag, MemoryMapTag, ACPI_MEMORY, AVAILABLE_MEMORY,
+};
+
+/// The height of the memory map bar in pixels
+const BAR_HEIGHT: u32 = 32;
+
+/// The narrowest that a segment of the bar is drawn so that small regions stay visible
+const MIN_SEGMENT_WIDTH: u32 = 2;
+
+const MEGABYTE: u64 = 0x10_0000;
+
+#[derive(Clone, Copy)]
+enum Category {
+    Available,
+    Acpi,
+    Reserved,
+    KernelImage,
+    BootModules,
+    BootInformation,
+}
+
+impl Category {
+    const ALL: [Self; 6] = [
+        Self::Available,
+        Self::Acpi,
+        Self::Reserved,
+        Self::KernelImage,
+        Self::BootModules,
+        Self::BootInformation,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Available => "Available         ",
+            Self::Acpi => "ACPI              ",
+            Self::Reserved => "Reserved          ",
+            Self::KernelImage => "Kernel image      ",
+            Self::BootModules => "Boot modules      ",
+            Self::BootInformation => "Boot information  ",
+        }
+    }
+
+    fn color(self) -> Rgb {
+        match self {
+            Self::Available => Rgb::new(0x40, 0xc0, 0x40),
+            Self::Acpi => Rgb::new(0x40, 0x80, 0xff),
+            Self::Reserved => Rgb::new(0x80, 0x80, 0x80),
+            Self::KernelImage => Rgb::new(0xff, 0x60, 0x40),
+            Self::BootModules => Rgb::new(0xff, 0xc0, 0x40),
+            Self::BootInformation => Rgb::new(0xc0, 0x60, 0xff),
+        }
+    }
+}
+
+/// Draws text onto a framebuffer one row of glyphs at a time. This is used instead of
+/// `FramebufferConsole` because the console's text buffer doesn't fit on the boot stack.
+struct Label<'a, 'b> {
+    framebuffer: &'a mut StandardRgbFramebuffer<'b>,
+    row: u32,
+    column: u32,
+    color: [u8; 8],
+}
+
+impl Label<'_, '_> {
+    fn next_line(&mut self) {
+        self.row += GLYPH_HEIGHT;
+        self.column = 0;
+    }
+}
+
+impl Write for Label<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.bytes() {
+            for (glyph_row, bits) in (0..).zip(glyph(character)) {
+                for glyph_column in 0..GLYPH_WIDTH {
+                    if bits & (0x80 >> glyph_column) != 0 {
+                        self.framebuffer.draw_pixel(
+                            self.row + glyph_row,
+                            self.column + glyph_column,
+                            self.color,
+                        );
+                    }
+                }
+            }
+            self.column += GLYPH_WIDTH;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Draws the physical memory map on the screen as a bar with one colored segment per memory
+ * region, along with a legend giving the total size of each kind of region in megabytes.
+ *
+ * # Safety
+ *
+ * The framebuffer described by the boot information must be mapped and not in use.
+ */
+pub unsafe fn show(boot_info: BootInformation, physical_memory_size: usize) {
+    let memory_size = physical_memory_size as u64;
+    let Some(mut framebuffer) = boot_info
+        .tags_of_type::<FramebufferTag>()
+        .next()
+        .and_then(|tag| StandardRgbFramebuffer::from_tag(tag))
+    else {
+        return;
+    };
+    if memory_size == 0 {
+        return;
+    }
+    let (width, height) = framebuffer.dimensions();
+    framebuffer.fill_rect(0, 0, width, height, StandardRgbFramebuffer::BLACK);
+
+    let mut sizes = [0; Category::ALL.len()];
+    for (category, region) in regions(boot_info) {
+        sizes[category as usize] += region.end.saturating_sub(region.start);
+    }
+    let mut label = Label {
+        framebuffer: &mut framebuffer,
+        row: 0,
+        column: 0,
+        color: StandardRgbFramebuffer::WHITE,
+    };
+    let _ = label.write_str("Physical memory map");
+    label.next_line();
+    for (category, size) in Category::ALL.into_iter().zip(sizes) {
+        label.next_line();
+        label.color = label.framebuffer.pack_color(category.color());
+        let _ = write!(label, "{}{} MB", category.label(), size / MEGABYTE);
+    }
+    label.next_line();
+    let top = label.row + GLYPH_HEIGHT;
+
+    for (category, region) in regions(boot_info) {
+        if region.start >= memory_size || region.is_empty() {
+            continue;
+        }
+        let columns = segment_columns(&region, memory_size, width);
+        let color = framebuffer.pack_color(category.color());
+        for row in top..top + BAR_HEIGHT {
+            framebuffer.fill_span(row, columns.start, columns.end, color);
+        }
+    }
+}
+
+// Memory map entries come first so that the regions that are in use are drawn over them.
+fn regions(boot_info: BootInformation<'_>) -> impl Iterator<Item = (Category, Range<u64>)> + '_ {
+    let memory_map = boot_info
+        .tags_of_type::<MemoryMapTag>()
+        .next()
+        .into_iter()
+        .flat_map(|memory_map| memory_map.entries.iter())
+        .map(|entry| {
+            let category = match entry.region_type {
+                AVAILABLE_MEMORY => Category::Available,
+                ACPI_MEMORY => Category::Acpi,
+                _ => Category::Reserved,
+            };
+            (category, entry.base_addr..entry.base_addr + entry.length)
+        });
+    let modules = boot_info.tags_of_type::<BootModuleTag>().map(|module| {
+        let range = u64::from(module.mod_start)..u64::from(module.mod_end);
+        (Category::BootModules, range)
+    });
+    memory_map
+        .chain(once((Category::KernelImage, to_u64_range(kernel_image()))))
+        .chain(modules)
+        .chain(once((
+            Category::BootInformation,
+            to_u64_range(boot_info.address_range()),
+        )))
+}
+
+fn to_u64_range(range: Range<usize>) -> Range<u64> {
+    range.start as u64..range.end as u64
+}
+
+/// Computes which columns of a bar `width` pixels wide represent `region` when the bar covers
+/// `memory_size` bytes. Segments are widened to at least `MIN_SEGMENT_WIDTH` columns.
+fn segment_columns(region: &Range<u64>, memory_size: u64, width: u32) -> Range<u32> {
+    let column = |address: u64| {
+        let column =
+            u128::from(address.min(memory_size)) * u128::from(width) / u128::from(memory_size);
+        u32::try_from(column).unwrap_or(width)
+    };
+    let start = column(region.start);
+    let end = column(region.end).max(start + MIN_SEGMENT_WIDTH).min(width);
+    start.min(end.saturating_sub(MIN_SEGMENT_WIDTH))..end
+}
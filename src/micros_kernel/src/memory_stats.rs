@@ -0,0 +1,56 @@
+use crate::BootModule;
+use micros_abi::{MemoryStats, STATS_FRAME_SIZES};
+use multiboot2::{MemoryMapTag, ACPI_MEMORY, AVAILABLE_MEMORY, DEFECTIVE_MEMORY};
+
+/// If more than this much available memory is neither free nor in use then something has probably
+/// gone wrong while computing which regions are free
+const UNACCOUNTED_MEMORY_WARNING_THRESHOLD: usize = 64 * 0x10_0000;
+
+/// Sums up the regions of each type in `memory_map`. The remaining fields are left at zero.
+pub fn from_memory_map(memory_map: MemoryMapTag) -> MemoryStats {
+    let mut stats = MemoryStats::default();
+    for area in memory_map.entries {
+        let Some(range) = area.address_range() else {
+            continue;
+        };
+        let size = range.len();
+        stats.total += size;
+        match area.region_type {
+            AVAILABLE_MEMORY => stats.available += size,
+            ACPI_MEMORY => stats.acpi += size,
+            DEFECTIVE_MEMORY => stats.defective += size,
+            _ => stats.reserved += size,
+        }
+    }
+    stats
+}
+
+/// Logs `stats`, with the size of each of `modules` listed under the boot modules
+pub fn log(stats: &MemoryStats, modules: &[BootModule]) {
+    info!("Physical memory: {} KiB", stats.total / 1024);
+    info!("  Free:             {} KiB", stats.free / 1024);
+    for (frame_size, free) in STATS_FRAME_SIZES.iter().zip(stats.free_by_frame_size) {
+        info!(
+            "    In {} KiB frames: {} KiB",
+            frame_size / 1024,
+            free / 1024
+        );
+    }
+    info!("  Kernel image:     {} KiB", stats.kernel_image / 1024);
+    info!("  Boot information: {} KiB", stats.boot_information / 1024);
+    info!("  Boot modules:     {} KiB", stats.modules / 1024);
+    for module in modules {
+        info!("    {}: {} KiB", module.name, module.range.len() / 1024);
+    }
+    info!("  Low memory:       {} KiB", stats.low_memory / 1024);
+    info!("  ACPI:             {} KiB", stats.acpi / 1024);
+    info!("  Reserved:         {} KiB", stats.reserved / 1024);
+    info!("  Defective:        {} KiB", stats.defective / 1024);
+    let unaccounted = stats.unaccounted();
+    if unaccounted > UNACCOUNTED_MEMORY_WARNING_THRESHOLD {
+        warn!(
+            "{} KiB of available memory is neither free nor in use",
+            unaccounted / 1024
+        );
+    }
+}
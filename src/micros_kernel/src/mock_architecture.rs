@@ -0,0 +1,154 @@
+//! An `Architecture` for host tests of the portable boot code. It records what the boot code asks
+//! of it instead of managing memory, and its page tables are frames in a test arena.
+
+extern crate std;
+
+use crate::{
+    elf::{self, ProgramHeader, EM_X86_64},
+    Architecture, SegmentFlags,
+};
+use core::{cell::Cell, ops::Range};
+use frame_allocation::{
+    page_tables::{FOUR_KILOBYTES, TWO_MEGABYTES},
+    VirtualAddress,
+};
+use micros_abi::STATS_FRAME_SIZES;
+use std::vec::Vec;
+use test_arena::Arena;
+
+/// Where the stack of every process ends
+pub const STACK_TOP: usize = 0x8000_0000_0000;
+
+std::thread_local! {
+    // Each test runs on its own thread, so tests can't see each other's waits
+    static INTERRUPT_WAITS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The number of times that the current test has waited for an interrupt
+pub fn interrupt_waits() -> usize {
+    INTERRUPT_WAITS.get()
+}
+
+/// A page table that nothing walks
+pub type PageTable = [u64; 512];
+
+/// A call to `copy_into_address_space`
+pub struct SegmentCopy {
+    pub root_page_table: *mut PageTable,
+    pub address: usize,
+    pub data: Vec<u8>,
+    pub size: usize,
+    pub flags: u32,
+}
+
+pub struct MockArchitecture {
+    /// Every region passed to `register_memory_region`, in order
+    pub registered: Vec<Range<usize>>,
+    /// Every call to `copy_into_address_space`, in order
+    pub copies: Vec<SegmentCopy>,
+    /// The root page tables passed to `abandon_address_space`
+    pub abandoned: Vec<*mut PageTable>,
+    /// The `end` passed to each call to `extend_identity_map`
+    pub identity_map_requests: Vec<usize>,
+    /// How far the identity map can be extended, as if the frames for page tables ran out there
+    pub identity_map_limit: usize,
+    arena: Arena,
+    /// The number of address spaces that can be created
+    page_tables: usize,
+    /// The number of root page tables that have been handed out of the arena
+    page_tables_used: usize,
+}
+
+impl MockArchitecture {
+    /// A mock with room for `page_tables` address spaces
+    pub fn new(page_tables: usize) -> Self {
+        Self {
+            registered: Vec::new(),
+            copies: Vec::new(),
+            abandoned: Vec::new(),
+            identity_map_requests: Vec::new(),
+            identity_map_limit: usize::MAX,
+            arena: Arena::new(page_tables.max(1) * FOUR_KILOBYTES),
+            page_tables,
+            page_tables_used: 0,
+        }
+    }
+}
+
+impl Architecture for MockArchitecture {
+    // Like AMD64, the first 4 GB are identity mapped when booting starts
+    const INITIAL_VIRTUAL_MEMORY_SIZE: usize = 0x1_0000_0000;
+
+    type PageTable = PageTable;
+
+    type ExecutableHeader = elf::Header<EM_X86_64>;
+
+    type SegmentHeader = ProgramHeader;
+
+    unsafe fn initialize_process_page_tables(
+        &mut self,
+    ) -> Option<(*mut Self::PageTable, VirtualAddress)> {
+        if self.page_tables_used == self.page_tables {
+            return None;
+        }
+        let root_page_table = self
+            .arena
+            .frame(FOUR_KILOBYTES, self.page_tables_used)
+            .as_usize() as *mut PageTable;
+        self.page_tables_used += 1;
+        Some((root_page_table, VirtualAddress::new(STACK_TOP)))
+    }
+
+    unsafe fn register_memory_region(&mut self, memory_region: Range<usize>) {
+        self.registered.push(memory_region);
+    }
+
+    // Like the frame allocators, whole 2 MB frames are kept as 2 MB frames
+    fn free_memory_by_frame_size(&self) -> [usize; STATS_FRAME_SIZES.len()] {
+        let mut free = [0; STATS_FRAME_SIZES.len()];
+        for region in &self.registered {
+            let big_frames = region.start.next_multiple_of(TWO_MEGABYTES)
+                ..region.end / TWO_MEGABYTES * TWO_MEGABYTES;
+            free[0] += region.len() - big_frames.len();
+            free[1] += big_frames.len();
+        }
+        free
+    }
+
+    unsafe fn extend_identity_map(&mut self, end: usize) -> usize {
+        self.identity_map_requests.push(end);
+        end.min(self.identity_map_limit)
+    }
+
+    unsafe fn abandon_address_space(&mut self, root_page_table: *mut Self::PageTable) {
+        self.abandoned.push(root_page_table);
+    }
+
+    unsafe fn copy_into_address_space(
+        &mut self,
+        root_page_table: &mut Self::PageTable,
+        address: usize,
+        data: &[u8],
+        size: usize,
+        flags: SegmentFlags,
+    ) -> Option<()> {
+        self.copies.push(SegmentCopy {
+            root_page_table,
+            address,
+            data: data.to_vec(),
+            size,
+            flags: flags.0,
+        });
+        Some(())
+    }
+
+    fn halt() -> ! {
+        panic!("the mock architecture was halted");
+    }
+
+    fn wait_for_interrupt() {
+        INTERRUPT_WAITS.set(INTERRUPT_WAITS.get() + 1);
+    }
+
+    unsafe fn enable_interrupts() {}
+}
@@ -0,0 +1,230 @@
+//! What the kernel writes when it panics. The handlers only gather what they can about the panic
+//! and pick somewhere to write it, so that the formatting can be tested on the host.
+//!
+//! ```text
+//! Kernel panicked at src/micros_kernel/src/lib.rs:120:9:
+//! Boot information is missing its memory map
+//! rsp=0x0000000000207e48 rbp=0x0000000000207f10 cr2=0x0000000000000000
+//! cr3=0x0000000000101000 rflags=0x0000000000000046
+//! ```
+
+use core::fmt::{self, Display, Write};
+#[cfg(not(test))]
+use core::panic::PanicInfo;
+
+/// The width of the text console. Lines longer than this are cut short so that a long message
+/// doesn't wrap over the registers.
+pub const PANIC_LINE_WIDTH: usize = 80;
+
+/// Where a panic happened
+#[derive(Clone, Copy)]
+pub struct PanicLocation<'a> {
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// The parts of a `PanicInfo` that get reported. `PanicInfo` can't be constructed outside of the
+/// standard library, so the tests build these directly instead.
+pub struct PanicReport<'a> {
+    pub location: Option<PanicLocation<'a>>,
+    pub message: &'a dyn Display,
+}
+
+// Only the panic handlers make reports out of a real `PanicInfo`
+#[cfg(not(test))]
+impl<'a> PanicReport<'a> {
+    pub fn new(info: &'a PanicInfo, message: &'a dyn Display) -> Self {
+        Self {
+            location: info.location().map(|location| PanicLocation {
+                file: location.file(),
+                line: location.line(),
+                column: location.column(),
+            }),
+            message,
+        }
+    }
+}
+
+/// The registers that a panic handler read when it started, named the way its architecture names
+/// them
+pub struct SavedRegs<'a> {
+    pub registers: &'a [(&'static str, u64)],
+}
+
+/**
+ * Writes a report of a panic to `out`. No line is longer than `PANIC_LINE_WIDTH` characters, so
+ * the end of a long line is dropped, and the registers are wrapped onto as many lines as they need.
+ */
+pub fn format_panic(info: &PanicReport, regs: &SavedRegs, out: &mut impl Write) -> fmt::Result {
+    let mut out = Truncated { out, column: 0 };
+    out.write_str("Kernel panicked")?;
+    if let Some(location) = info.location {
+        write!(
+            out,
+            " at {}:{}:{}",
+            location.file, location.line, location.column
+        )?;
+    }
+    write!(out, ":\n{}\n", info.message)?;
+    for (index, (name, value)) in regs.registers.iter().enumerate() {
+        // The `=`, the `0x`, and 16 digits
+        let width = name.len() + 19;
+        if index > 0 {
+            let separator = if out.column + 1 + width > PANIC_LINE_WIDTH {
+                '\n'
+            } else {
+                ' '
+            };
+            out.write_char(separator)?;
+        }
+        write!(out, "{name}={value:#018x}")?;
+    }
+    if !regs.registers.is_empty() {
+        out.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Writes a report of `info` to the log sink. Returns false if the sink couldn't be used, such as
+/// when the panic happened while logging.
+#[cfg(not(test))]
+pub fn write_panic_to_log(info: &PanicInfo, registers: &[(&'static str, u64)]) -> bool {
+    let message = info.message();
+    let report = PanicReport::new(info, &message);
+    crate::log::write_to_sink(|mut sink| format_panic(&report, &SavedRegs { registers }, &mut sink))
+}
+
+/// Drops whatever would go past `PANIC_LINE_WIDTH` on each line
+struct Truncated<'a, W: Write> {
+    out: &'a mut W,
+    column: usize,
+}
+
+impl<W: Write> Write for Truncated<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.chars() {
+            if character == '\n' {
+                self.column = 0;
+            } else if self.column < PANIC_LINE_WIDTH {
+                self.column += 1;
+            } else {
+                continue;
+            }
+            self.out.write_char(character)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+
+    const LOCATION: PanicLocation = PanicLocation {
+        file: "src/micros_kernel/src/lib.rs",
+        line: 120,
+        column: 9,
+    };
+
+    const REGISTERS: [(&str, u64); 5] = [
+        ("rsp", 0x20_7e48),
+        ("rbp", 0x20_7f10),
+        ("cr2", 0),
+        ("cr3", 0x10_1000),
+        ("rflags", 0x46),
+    ];
+
+    fn format(
+        location: Option<PanicLocation>,
+        message: fmt::Arguments,
+        regs: &[(&'static str, u64)],
+    ) -> String {
+        let mut out = String::new();
+        let report = PanicReport {
+            location,
+            message: &message,
+        };
+        format_panic(&report, &SavedRegs { registers: regs }, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn formats_location_message_and_registers() {
+        let out = format(
+            Some(LOCATION),
+            format_args!("Boot information is missing its memory map"),
+            &REGISTERS,
+        );
+        assert_eq!(
+            out,
+            "Kernel panicked at src/micros_kernel/src/lib.rs:120:9:\n\
+             Boot information is missing its memory map\n\
+             rsp=0x0000000000207e48 rbp=0x0000000000207f10 cr2=0x0000000000000000\n\
+             cr3=0x0000000000101000 rflags=0x0000000000000046\n"
+        );
+    }
+
+    #[test]
+    fn formats_without_location_or_registers() {
+        let out = format(None, format_args!("out of memory"), &[]);
+        assert_eq!(out, "Kernel panicked:\nout of memory\n");
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let long = "x".repeat(200);
+        let out = format(
+            Some(LOCATION),
+            format_args!("first {long}\nsecond line"),
+            &REGISTERS[..1],
+        );
+        let expected_first = std::format!("first {}", "x".repeat(PANIC_LINE_WIDTH - 6));
+        assert_eq!(
+            out,
+            std::format!(
+                "Kernel panicked at src/micros_kernel/src/lib.rs:120:9:\n\
+                 {expected_first}\n\
+                 second line\n\
+                 rsp=0x0000000000207e48\n"
+            )
+        );
+        assert!(out
+            .lines()
+            .all(|line| line.chars().count() <= PANIC_LINE_WIDTH));
+    }
+
+    #[test]
+    fn truncates_long_locations() {
+        let file = std::format!("src/{}.rs", "a".repeat(100));
+        let location = PanicLocation {
+            file: &file,
+            ..LOCATION
+        };
+        let out = format(Some(location), format_args!("message"), &[]);
+        let first_line = out.lines().next().unwrap();
+        assert_eq!(first_line.len(), PANIC_LINE_WIDTH);
+        assert!(first_line.starts_with("Kernel panicked at src/aaa"));
+        assert_eq!(out.lines().nth(1), Some("message"));
+    }
+
+    #[test]
+    fn keeps_braces_in_messages() {
+        let out = format(
+            None,
+            format_args!("{} {{}}", "unexpected {} in {name}"),
+            &[],
+        );
+        assert_eq!(out, "Kernel panicked:\nunexpected {} in {name} {}\n");
+    }
+
+    #[test]
+    fn counts_characters_rather_than_bytes() {
+        let out = format(None, format_args!("{}", "é".repeat(100)), &[]);
+        let message = out.lines().nth(1).unwrap();
+        assert_eq!(message.chars().count(), PANIC_LINE_WIDTH);
+    }
+}
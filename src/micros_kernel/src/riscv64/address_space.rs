@@ -0,0 +1,131 @@
+use crate::{
+    elf::{self, ProgramHeader, EM_RISCV},
+    may_register_memory_region,
+    riscv64::paging::{PageTable, ROOT_TABLE_LEVEL},
+    table_walk::{self, identity_mapped},
+    Architecture, SegmentFlags, ELF_WRITABLE_SEGMENT,
+};
+use core::{arch::asm, ops::Range};
+use frame_allocation::{
+    riscv64::{Riscv64FrameAllocator, FOUR_KILOBYTES, TWO_MEGABYTES},
+    PhysicalAddress, VirtualAddress,
+};
+use micros_abi::STATS_FRAME_SIZES;
+
+/// Process stacks end at the top of the lower half of the Sv39 address space. The kernel's own
+/// mappings will go in the upper half of each root table once something builds them.
+const STACK_TOP: usize = 0x40_0000_0000;
+const STACK_SIZE: usize = 4 * FOUR_KILOBYTES;
+
+pub struct Riscv64 {
+    allocator: Riscv64FrameAllocator,
+}
+
+impl Riscv64 {
+    pub const fn new() -> Self {
+        Self {
+            allocator: Riscv64FrameAllocator::new(),
+        }
+    }
+}
+
+impl Architecture for Riscv64 {
+    // The boot code is expected to identity map the first 4 GB like it does on AMD64
+    const INITIAL_VIRTUAL_MEMORY_SIZE: usize = 0x1_0000_0000;
+
+    type PageTable = PageTable;
+
+    type ExecutableHeader = elf::Header<EM_RISCV>;
+
+    type SegmentHeader = ProgramHeader;
+
+    unsafe fn initialize_process_page_tables(
+        &mut self,
+    ) -> Option<(*mut Self::PageTable, VirtualAddress)> {
+        let root_table_pointer = identity_mapped::<PageTable>(self.allocator.get_4k_frame()?);
+        let root_table = &mut *root_table_pointer;
+        root_table.zero();
+        table_walk::copy_into_address_space(
+            &mut self.allocator,
+            ROOT_TABLE_LEVEL,
+            root_table,
+            STACK_TOP - STACK_SIZE,
+            &[],
+            STACK_SIZE,
+            SegmentFlags(ELF_WRITABLE_SEGMENT),
+        )?;
+        Some((root_table_pointer, VirtualAddress::new(STACK_TOP)))
+    }
+
+    unsafe fn abandon_address_space(&mut self, root_page_table: *mut Self::PageTable) {
+        table_walk::free_page_table(&mut self.allocator, ROOT_TABLE_LEVEL, &mut *root_page_table);
+        self.allocator
+            .four_kilobyte_pages
+            .add_frame(PhysicalAddress::new(root_page_table as usize));
+    }
+
+    unsafe fn register_memory_region(&mut self, memory_region: Range<usize>) {
+        if may_register_memory_region(&memory_region) {
+            self.allocator.add_memory_region(memory_region);
+        }
+    }
+
+    // 1 GB blocks aren't used
+    fn free_memory_by_frame_size(&self) -> [usize; STATS_FRAME_SIZES.len()] {
+        [
+            self.allocator.four_kilobyte_pages.frame_count() * FOUR_KILOBYTES,
+            self.allocator.two_megabyte_pages.frame_count() * TWO_MEGABYTES,
+            0,
+        ]
+    }
+
+    // Nothing builds the kernel's page tables on RISC-V yet, so only what the boot code
+    // mapped is available
+    unsafe fn extend_identity_map(&mut self, end: usize) -> usize {
+        end.min(Self::INITIAL_VIRTUAL_MEMORY_SIZE)
+    }
+
+    unsafe fn copy_into_address_space(
+        &mut self,
+        root_page_table: &mut Self::PageTable,
+        address: usize,
+        data: &[u8],
+        size: usize,
+        flags: SegmentFlags,
+    ) -> Option<()> {
+        table_walk::copy_into_address_space(
+            &mut self.allocator,
+            ROOT_TABLE_LEVEL,
+            root_page_table,
+            address,
+            data,
+            size,
+            flags,
+        )
+    }
+
+    fn halt() -> ! {
+        loop {
+            unsafe {
+                asm!("wfi", options(nomem, nostack));
+            }
+        }
+    }
+
+    fn wait_for_interrupt() {
+        // An interrupt that's already pending wakes `wfi` even while it's masked, and is taken as
+        // soon as it's unmasked
+        unsafe {
+            asm!(
+                "wfi",
+                "csrsi sstatus, 2",
+                "csrci sstatus, 2",
+                options(nostack)
+            );
+        }
+    }
+
+    unsafe fn enable_interrupts() {
+        asm!("csrsi sstatus, 2", options(nostack));
+    }
+}
@@ -0,0 +1,31 @@
+// Host tests only build the page table encodings
+#[cfg(target_arch = "riscv64")]
+mod address_space;
+mod paging;
+
+#[cfg(target_arch = "riscv64")]
+use crate::Architecture;
+#[cfg(target_arch = "riscv64")]
+pub use address_space::Riscv64;
+#[cfg(target_arch = "riscv64")]
+use core::{arch::asm, panic::PanicInfo};
+
+#[cfg(target_arch = "riscv64")]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut registers = [("sp", 0), ("s0", 0), ("ra", 0)];
+    unsafe {
+        asm!(
+            "mv {}, sp",
+            "mv {}, s0",
+            "mv {}, ra",
+            out(reg) registers[0].1,
+            out(reg) registers[1].1,
+            out(reg) registers[2].1,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    // There's nowhere else to write the report
+    crate::panic::write_panic_to_log(info, &registers);
+    Riscv64::halt()
+}
@@ -0,0 +1,177 @@
+//! RISC-V Sv39 page tables, which translate 39 bit virtual addresses through three levels. Level 0
+//! holds the entries that map 4 KB pages and level 2 is the root.
+
+use crate::{table_walk::TableEntry, SegmentFlags};
+use frame_allocation::{riscv64::LEVELS, PhysicalAddress};
+use multiboot2::phys_to_usize;
+
+/// The level of the root page table
+pub const ROOT_TABLE_LEVEL: u8 = LEVELS - 1;
+
+/// The entry maps something
+const VALID: u64 = 1;
+const READABLE: u64 = 1 << 1;
+const WRITABLE: u64 = 1 << 2;
+const EXECUTABLE: u64 = 1 << 3;
+/// The memory can be accessed from user mode
+const USER_ACCESSIBLE: u64 = 1 << 4;
+/// The hardware may fault instead of setting the accessed and dirty bits, so they're set up front
+const ACCESSED: u64 = 1 << 6;
+const DIRTY: u64 = 1 << 7;
+/// Entries that have any of these set map memory. Entries that have none of them point to another
+/// page table.
+const LEAF: u64 = READABLE | WRITABLE | EXECUTABLE;
+/// Where the physical page number starts in an entry
+const PHYSICAL_PAGE_NUMBER_SHIFT: u32 = 10;
+/// Sv39 physical page numbers are 44 bits
+const PHYSICAL_PAGE_NUMBER_MASK: u64 = (1 << 44) - 1;
+const PAGE_SHIFT: u32 = 12;
+
+/// An entry in an Sv39 page table
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct PageTableEntry(u64);
+
+#[cfg(target_arch = "riscv64")]
+pub type PageTable = crate::table_walk::PageTable<PageTableEntry>;
+
+impl PageTableEntry {
+    fn new(address: PhysicalAddress, flags: u64) -> Self {
+        Self(((address.as_usize() as u64) >> PAGE_SHIFT) << PHYSICAL_PAGE_NUMBER_SHIFT | flags)
+    }
+}
+
+impl TableEntry for PageTableEntry {
+    const INVALID: Self = Self(0);
+
+    fn table(address: PhysicalAddress) -> Self {
+        Self::new(address, VALID)
+    }
+
+    /// Pages are always readable because writable pages that can't be read are reserved
+    fn process_page(address: PhysicalAddress, flags: SegmentFlags) -> Self {
+        Self::new(address, VALID | READABLE | USER_ACCESSIBLE | ACCESSED).allow(flags)
+    }
+
+    fn allow(self, flags: SegmentFlags) -> Self {
+        let mut bits = self.0;
+        if flags.writable() {
+            bits |= WRITABLE | DIRTY;
+        }
+        if flags.executable() {
+            bits |= EXECUTABLE;
+        }
+        Self(bits)
+    }
+
+    fn is_valid(self) -> bool {
+        self.0 & VALID != 0
+    }
+
+    fn points_to_table(self, page_table_level: u8) -> bool {
+        page_table_level > 0 && self.is_valid() && self.0 & LEAF == 0
+    }
+
+    fn address(self) -> Option<PhysicalAddress> {
+        phys_to_usize(
+            ((self.0 >> PHYSICAL_PAGE_NUMBER_SHIFT) & PHYSICAL_PAGE_NUMBER_MASK) << PAGE_SHIFT,
+        )
+        .map(PhysicalAddress::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{table_walk::tests::load_segment, ELF_EXECUTABLE_SEGMENT, ELF_WRITABLE_SEGMENT};
+
+    const READ_ONLY: SegmentFlags = SegmentFlags(4);
+    const READ_WRITE: SegmentFlags = SegmentFlags(4 | ELF_WRITABLE_SEGMENT);
+    const READ_EXECUTE: SegmentFlags = SegmentFlags(4 | ELF_EXECUTABLE_SEGMENT);
+
+    /// The bits that every page mapped into a process has
+    const PROCESS_PAGE: u64 = VALID | READABLE | USER_ACCESSIBLE | ACCESSED;
+
+    fn flags(entry: PageTableEntry) -> u64 {
+        entry.0 & ((1 << PHYSICAL_PAGE_NUMBER_SHIFT) - 1)
+    }
+
+    #[test]
+    fn table_entries_hold_the_physical_page_number() {
+        let entry = PageTableEntry::table(PhysicalAddress::new(0x8020_3000));
+        assert_eq!(entry.0, 0x8_0203 << PHYSICAL_PAGE_NUMBER_SHIFT | VALID);
+        assert!(entry.points_to_table(ROOT_TABLE_LEVEL));
+        assert!(!entry.points_to_table(0));
+        assert_eq!(entry.address(), Some(PhysicalAddress::new(0x8020_3000)));
+    }
+
+    #[test]
+    fn process_pages_are_only_as_permissive_as_their_segment() {
+        let address = PhysicalAddress::new(0x8040_1000);
+        let read_only = PageTableEntry::process_page(address, READ_ONLY);
+        assert_eq!(flags(read_only), PROCESS_PAGE);
+        let writable = PageTableEntry::process_page(address, READ_WRITE);
+        assert_eq!(flags(writable), PROCESS_PAGE | WRITABLE | DIRTY);
+        let executable = PageTableEntry::process_page(address, READ_EXECUTE);
+        assert_eq!(flags(executable), PROCESS_PAGE | EXECUTABLE);
+        for entry in [read_only, writable, executable] {
+            assert_eq!(entry.address(), Some(address));
+        }
+        assert_eq!(
+            flags(executable.allow(READ_WRITE)),
+            PROCESS_PAGE | WRITABLE | DIRTY | EXECUTABLE
+        );
+    }
+
+    #[test]
+    fn leaves_above_level_0_are_not_tables() {
+        let gigapage = PageTableEntry::new(PhysicalAddress::new(0x4000_0000), VALID | READABLE);
+        assert!(gigapage.is_valid());
+        assert!(!gigapage.points_to_table(ROOT_TABLE_LEVEL));
+        assert!(!PageTableEntry::INVALID.is_valid());
+        assert!(!PageTableEntry::INVALID.points_to_table(1));
+    }
+
+    #[test]
+    fn the_physical_page_number_is_decoded_without_the_bits_above_it() {
+        let address = PhysicalAddress::new(0xff_ffff_ffff_f000);
+        let entry = PageTableEntry::table(address);
+        assert_eq!(entry.address(), Some(address));
+        // Svpbmt and Svnapot use the top bits of an entry
+        let entry = PageTableEntry(entry.0 | 0b111 << 61);
+        assert_eq!(entry.address(), Some(address));
+    }
+
+    #[test]
+    fn a_segment_is_loaded_through_three_levels() {
+        let data: [u8; 0x20] = core::array::from_fn(|index| u8::try_from(index + 1).unwrap());
+        let segment =
+            load_segment::<PageTableEntry>(ROOT_TABLE_LEVEL, 0x40_0ff0, &data, 0x2000, READ_WRITE);
+        // The segment starts 16 bytes before the end of its first page
+        assert_eq!(segment.pages.len(), 3);
+        assert_eq!(segment.tables, 2);
+        assert_eq!(segment.bytes[..data.len()], data);
+        assert!(segment.bytes[data.len()..].iter().all(|&byte| byte == 0));
+        for page in segment.pages {
+            assert_eq!(flags(page), PROCESS_PAGE | WRITABLE | DIRTY);
+        }
+    }
+
+    #[test]
+    fn a_segment_across_two_root_entries_gets_tables_on_both_sides() {
+        let segment = load_segment::<PageTableEntry>(
+            ROOT_TABLE_LEVEL,
+            0x3fff_f000,
+            b"code",
+            0x2000,
+            READ_EXECUTE,
+        );
+        // The segment crosses from the first gigabyte into the next, so each half has its own
+        // table at every level below the root
+        assert_eq!(segment.tables, 4);
+        assert_eq!(segment.bytes[..4], *b"code");
+        for page in segment.pages {
+            assert_eq!(flags(page), PROCESS_PAGE | EXECUTABLE);
+        }
+    }
+}
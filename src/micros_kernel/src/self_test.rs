@@ -0,0 +1,217 @@
+//! Tests that a kernel built with the `qemu-test` feature runs once it has booted, before it
+//! launches the memory manager. Any module can register a test with `ktest!`. The tests end up next
+//! to each other in the `ktests` section, so nothing has to list them all.
+//!
+//! The results are written to the log sink as lines that the QEMU test runner parses:
+//!
+//! ```text
+//! TEST name=<name> result=ok
+//! TEST name=<name> result=fail detail=<why it failed>
+//! SUMMARY passed=<count> failed=<count>
+//! ```
+//!
+//! The detail is the rest of the line, so it's always the last field.
+
+use crate::{Arch, BootReport};
+#[cfg(not(test))]
+use core::panic::PanicInfo;
+use core::{
+    fmt::{self, Write},
+    mem::size_of,
+    ptr::addr_of,
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A test that runs after booting. It returns why it failed if it does.
+pub struct KernelTest {
+    pub name: &'static str,
+    pub run: unsafe fn(&mut Arch, &BootReport) -> Result<(), &'static str>,
+}
+
+/// Registers `$test`, an `unsafe fn(&mut Arch, &BootReport) -> Result<(), &'static str>`, to run
+/// when the kernel is tested in QEMU. The test is named after the function.
+macro_rules! ktest {
+    ($test:ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = "ktests"]
+            static TEST: $crate::self_test::KernelTest = $crate::self_test::KernelTest {
+                name: stringify!($test),
+                run: $test,
+            };
+        };
+    };
+}
+
+extern "C" {
+    // Defined by the linker script around the `ktests` section
+    static ktests_start: u8;
+    static ktests_end: u8;
+}
+
+/// The index of the test that's running, or `NOT_RUNNING`. The panic handler uses it to work out
+/// which test to blame.
+static CURRENT_TEST: AtomicUsize = AtomicUsize::new(NOT_RUNNING);
+const NOT_RUNNING: usize = usize::MAX;
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * Runs every registered test and reports how each one went. Returns true if they all passed. A
+ * kernel without any tests fails, since that means the linker script lost them.
+ *
+ * # Safety
+ *
+ * The kernel must have finished booting, and the tests may change `proc` as long as they put it
+ * back the way it was.
+ */
+pub unsafe fn run_kernel_tests(proc: &mut Arch, boot_report: &BootReport) -> bool {
+    let tests = registered_tests();
+    if tests.is_empty() {
+        error!("No kernel tests were registered");
+        report_summary();
+        return false;
+    }
+    for (index, test) in tests.iter().enumerate() {
+        CURRENT_TEST.store(index, Ordering::SeqCst);
+        let result = (test.run)(proc, boot_report);
+        CURRENT_TEST.store(NOT_RUNNING, Ordering::SeqCst);
+        match result {
+            Ok(()) => report_passed(test),
+            Err(reason) => report_failed(test, format_args!("{reason}")),
+        }
+    }
+    report_summary()
+}
+
+/**
+ * Reports a panic during a test as that test failing. The kernel can't unwind, so there's no way
+ * to go on to the next test. The tests that haven't run yet are reported as failures too so that
+ * the runner doesn't mistake them for passing, and then the summary is written. Returns false if
+ * no test was running, in which case nothing is reported.
+ */
+#[cfg(not(test))]
+pub fn report_panic(info: &PanicInfo) -> bool {
+    let index = CURRENT_TEST.swap(NOT_RUNNING, Ordering::SeqCst);
+    let tests = registered_tests();
+    let Some(test) = tests.get(index) else {
+        return false;
+    };
+    report_failed(test, format_args!("panicked: {info}"));
+    for skipped in &tests[index + 1..] {
+        report_failed(
+            skipped,
+            format_args!("not run because {} panicked", test.name),
+        );
+    }
+    report_summary();
+    true
+}
+
+fn report_passed(test: &KernelTest) {
+    PASSED.fetch_add(1, Ordering::SeqCst);
+    report(format_args!("TEST name={} result=ok", test.name));
+}
+
+fn report_failed(test: &KernelTest, detail: fmt::Arguments) {
+    FAILED.fetch_add(1, Ordering::SeqCst);
+    report(format_args!(
+        "TEST name={} result=fail detail={}",
+        test.name,
+        OneLine(detail)
+    ));
+}
+
+/// Writes the summary line and returns true if every test passed
+fn report_summary() -> bool {
+    let passed = PASSED.load(Ordering::SeqCst);
+    let failed = FAILED.load(Ordering::SeqCst);
+    report(format_args!("SUMMARY passed={passed} failed={failed}"));
+    passed > 0 && failed == 0
+}
+
+/// Writes a line of the protocol. It goes straight to the serial port if the log sink is in use,
+/// which happens when a test panics while logging.
+fn report(line: fmt::Arguments) {
+    if !crate::log::write_line(line) {
+        #[cfg(target_arch = "x86_64")]
+        crate::amd64::emergency_log(line);
+    }
+}
+
+/// Formats its contents with line breaks replaced by spaces, since each result has to fit on one
+/// line
+struct OneLine<'a>(fmt::Arguments<'a>);
+
+impl fmt::Display for OneLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Flatten<'a, 'b>(&'a mut fmt::Formatter<'b>);
+        impl Write for Flatten<'_, '_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for (index, line) in s.split(['\n', '\r']).enumerate() {
+                    if index > 0 {
+                        self.0.write_char(' ')?;
+                    }
+                    self.0.write_str(line)?;
+                }
+                Ok(())
+            }
+        }
+        Flatten(f).write_fmt(self.0)
+    }
+}
+
+// The linker script aligns the section
+#[allow(clippy::cast_ptr_alignment)]
+fn registered_tests() -> &'static [KernelTest] {
+    let start = addr_of!(ktests_start);
+    let end = addr_of!(ktests_end);
+    let count = (end as usize).saturating_sub(start as usize) / size_of::<KernelTest>();
+    unsafe { slice::from_raw_parts(start.cast::<KernelTest>(), count) }
+}
+
+/// Booting has to account for the memory that it finds
+unsafe fn memory_stats_are_nonzero(
+    _: &mut Arch,
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    let stats = &boot_report.memory_stats;
+    if stats.total == 0 || stats.available == 0 {
+        return Err("the memory map was empty");
+    }
+    if stats.free == 0 || boot_report.registered_memory.is_empty() {
+        return Err("no memory was given to the frame allocator");
+    }
+    let left_in_allocator: usize = stats.free_by_frame_size.iter().sum();
+    if left_in_allocator == 0 || left_in_allocator > stats.free {
+        return Err("the frame allocator's free lists don't match the memory given to it");
+    }
+    if stats.kernel_image == 0 {
+        return Err("the kernel image wasn't accounted for");
+    }
+    Ok(())
+}
+ktest!(memory_stats_are_nonzero);
+
+/// Booting has to get as far as loading the memory manager and registering memory in order
+unsafe fn boot_reached_the_memory_manager(
+    _: &mut Arch,
+    boot_report: &BootReport,
+) -> Result<(), &'static str> {
+    let memory_manager = &boot_report.memory_manager;
+    if memory_manager.entry_point.as_usize() == 0
+        || memory_manager.root_page_table_address.as_usize() == 0
+    {
+        return Err("the memory manager wasn't loaded");
+    }
+    let registered = &boot_report.registered_memory;
+    if registered
+        .windows(2)
+        .any(|pair| pair[0].end > pair[1].start)
+    {
+        return Err("memory was registered out of order or twice");
+    }
+    Ok(())
+}
+ktest!(boot_reached_the_memory_manager);
@@ -0,0 +1,254 @@
+//! Page table walks shared by aarch64 with the 4 KB granule and RISC-V with Sv39. The walks take
+//! the level to start at, so they work for hierarchies of any depth, and the arithmetic comes from
+//! `frame_allocation::page_tables`. Level 0 holds the entries that map 4 KB pages.
+
+use crate::{copy_and_zero_fill, slice_with_bounds_check, SegmentFlags};
+use core::slice;
+use frame_allocation::{
+    page_tables::{
+        entry_indices, number_of_bytes_for_page, offset_in_page, ENTRIES_PER_TABLE, FOUR_KILOBYTES,
+    },
+    two_tier::TwoTierFrameAllocator,
+    PhysicalAddress,
+};
+
+/// An entry in a page table
+pub trait TableEntry: Copy {
+    /// An entry that doesn't map anything
+    const INVALID: Self;
+
+    /// An entry that points to the page table at `address`
+    fn table(address: PhysicalAddress) -> Self;
+
+    /// A level 0 entry that maps the 4 KB page at `address` into a process with the permissions
+    /// in `flags`
+    fn process_page(address: PhysicalAddress, flags: SegmentFlags) -> Self;
+
+    /// Loosens the permissions of an entry that maps memory to also allow what `flags` allows
+    #[must_use]
+    fn allow(self, flags: SegmentFlags) -> Self;
+
+    fn is_valid(self) -> bool;
+
+    /// Returns true if this is a valid entry in a table at `page_table_level` that points to
+    /// another table rather than mapping memory
+    fn points_to_table(self, page_table_level: u8) -> bool;
+
+    /// The physical address of the table or memory that the entry points to, or `None` if it's
+    /// past the end of the address space
+    fn address(self) -> Option<PhysicalAddress>;
+}
+
+#[repr(C, align(0x1000))]
+pub struct PageTable<Entry> {
+    pub entries: [Entry; ENTRIES_PER_TABLE],
+}
+
+impl<Entry: TableEntry> PageTable<Entry> {
+    pub fn zero(&mut self) {
+        self.entries = [Entry::INVALID; ENTRIES_PER_TABLE];
+    }
+}
+
+/**
+ * Copies `data` to `address` in the address space under `page_table` and zero fills the rest of
+ * the `size` bytes there. Pages and page tables that don't exist yet are taken from `allocator`.
+ *
+ * # Safety
+ *
+ * The page tables and the memory that they map must be identity mapped.
+ */
+pub unsafe fn copy_into_address_space<Entry: TableEntry>(
+    allocator: &mut TwoTierFrameAllocator,
+    page_table_level: u8,
+    page_table: &mut PageTable<Entry>,
+    mut address: usize,
+    data: &[u8],
+    size: usize,
+    flags: SegmentFlags,
+) -> Option<()> {
+    let mut data_offset = 0;
+    for entry in table_entries(page_table, page_table_level, address, size) {
+        if !entry.is_valid() {
+            let frame = allocator.get_4k_frame()?;
+            identity_mapped::<u8>(frame).write_bytes(0, FOUR_KILOBYTES);
+            *entry = if page_table_level == 0 {
+                Entry::process_page(frame, flags)
+            } else {
+                Entry::table(frame)
+            };
+        } else if !entry.points_to_table(page_table_level) {
+            *entry = entry.allow(flags);
+        }
+        let page_offset = offset_in_page(page_table_level, address);
+        let bytes_for_page =
+            number_of_bytes_for_page(page_table_level, page_offset, size, data_offset);
+        let data_for_entry = slice_with_bounds_check(data, data_offset, bytes_for_page);
+
+        if entry.points_to_table(page_table_level) {
+            copy_into_address_space::<Entry>(
+                allocator,
+                page_table_level - 1,
+                &mut *identity_mapped(entry.address()?),
+                address,
+                data_for_entry,
+                bytes_for_page,
+                flags,
+            )?;
+        } else {
+            copy_and_zero_fill(
+                slice::from_raw_parts_mut(
+                    identity_mapped(entry.address()? + page_offset),
+                    bytes_for_page,
+                ),
+                data_for_entry,
+            );
+        }
+        data_offset += bytes_for_page;
+        address += bytes_for_page;
+    }
+    Some(())
+}
+
+/**
+ * Returns every frame mapped by `page_table`, including the frames of its sub tables, to
+ * `allocator` and clears its entries.
+ *
+ * # Safety
+ *
+ * Nothing may use the memory mapped by `page_table` afterwards.
+ */
+pub unsafe fn free_page_table<Entry: TableEntry>(
+    allocator: &mut TwoTierFrameAllocator,
+    page_table_level: u8,
+    page_table: &mut PageTable<Entry>,
+) {
+    for entry in &mut page_table.entries {
+        if !entry.is_valid() {
+            continue;
+        }
+        // A frame past the end of the address space can't have come from the allocator
+        if let Some(frame) = entry.address() {
+            if entry.points_to_table(page_table_level) {
+                free_page_table::<Entry>(
+                    allocator,
+                    page_table_level - 1,
+                    &mut *identity_mapped(frame),
+                );
+                allocator.four_kilobyte_pages.add_frame(frame);
+            } else if page_table_level == 0 {
+                allocator.four_kilobyte_pages.add_frame(frame);
+            } else if page_table_level == 1 {
+                allocator.two_megabyte_pages.add_frame(frame);
+            }
+        }
+        // Bigger blocks are never mapped into processes
+        *entry = Entry::INVALID;
+    }
+}
+
+/// Converts a physical address to a pointer. This relies on the low physical memory being identity
+/// mapped while the operating system boots.
+pub fn identity_mapped<T>(address: PhysicalAddress) -> *mut T {
+    address.as_usize() as *mut T
+}
+
+fn table_entries<Entry>(
+    page_table: &mut PageTable<Entry>,
+    page_table_level: u8,
+    base_address: usize,
+    size: usize,
+) -> impl Iterator<Item = &mut Entry> {
+    page_table.entries[entry_indices(page_table_level, base_address, size)].iter_mut()
+}
+
+/// Loads segments through the shared walk so that each architecture's tests can check the entries
+/// that its encoding produces
+#[cfg(test)]
+pub mod tests {
+    extern crate std;
+
+    use super::*;
+    use frame_allocation::page_tables::{table_index, TWO_MEGABYTES};
+    use std::vec::Vec;
+    use test_arena::Arena;
+
+    /// What the page tables held after `load_segment` loaded a segment
+    pub struct LoadedSegment<Entry> {
+        /// The level 0 entry of each page of the segment, in order
+        pub pages: Vec<Entry>,
+        /// The segment's bytes, read back through the page tables
+        pub bytes: Vec<u8>,
+        /// The number of page tables below the root
+        pub tables: usize,
+    }
+
+    /**
+     * Copies `data` to `address` in a new address space with `root_level` as the root's level,
+     * zero filling the rest of the `size` bytes, and reads the segment back by walking the tables.
+     * The address space is then freed, which has to give back every frame that it took.
+     */
+    pub fn load_segment<Entry: TableEntry>(
+        root_level: u8,
+        address: usize,
+        data: &[u8],
+        size: usize,
+        flags: SegmentFlags,
+    ) -> LoadedSegment<Entry> {
+        let arena = Arena::new(4 * TWO_MEGABYTES);
+        let mut allocator = TwoTierFrameAllocator::new();
+        unsafe { allocator.add_memory_region(arena.range()) };
+        let free_before = allocator.total_bytes_free();
+        let root_frame = unsafe { allocator.get_4k_frame() }.unwrap();
+        let root = unsafe { &mut *identity_mapped::<PageTable<Entry>>(root_frame) };
+        root.zero();
+        unsafe {
+            copy_into_address_space(&mut allocator, root_level, root, address, data, size, flags)
+                .unwrap();
+        }
+        let first_page = address - offset_in_page(0, address);
+        let pages: Vec<Entry> = (first_page..address + size)
+            .step_by(FOUR_KILOBYTES)
+            .map(|page| page_entry(root, root_level, page))
+            .collect();
+        let bytes = (address..address + size)
+            .map(|byte| {
+                let entry = pages[(byte - first_page) / FOUR_KILOBYTES];
+                let frame = entry.address().unwrap();
+                assert!(arena.contains(frame, FOUR_KILOBYTES));
+                unsafe { *identity_mapped::<u8>(frame + offset_in_page(0, byte)) }
+            })
+            .collect();
+        let frames_used = (free_before - allocator.total_bytes_free()) / FOUR_KILOBYTES;
+        unsafe {
+            free_page_table(&mut allocator, root_level, root);
+            allocator.four_kilobyte_pages.add_frame(root_frame);
+        }
+        assert_eq!(allocator.total_bytes_free(), free_before);
+        LoadedSegment {
+            tables: frames_used - 1 - pages.len(),
+            pages,
+            bytes,
+        }
+    }
+
+    /// Walks down from `root` to the level 0 entry that maps `address`
+    fn page_entry<Entry: TableEntry>(
+        root: &PageTable<Entry>,
+        root_level: u8,
+        address: usize,
+    ) -> Entry {
+        let mut table = root;
+        for level in (1..=root_level).rev() {
+            let entry = table.entries[table_index(level, address)];
+            assert!(
+                entry.points_to_table(level),
+                "{address:#x} at level {level}"
+            );
+            table = unsafe { &*identity_mapped(entry.address().unwrap()) };
+        }
+        let entry = table.entries[table_index(0, address)];
+        assert!(entry.is_valid() && !entry.points_to_table(0));
+        entry
+    }
+}
@@ -0,0 +1,196 @@
+//! Boot information and boot modules for tests, laid out the way a multiboot2 bootloader and a
+//! linker lay them out, and where the kernel image is while a test runs.
+
+extern crate std;
+
+use core::{cell::Cell, mem::size_of, ops::Range, slice};
+use multiboot2::{BootInformation, MemoryMapEntry};
+use std::{vec, vec::Vec};
+
+/// Where the kernel image is unless a test says otherwise. GRUB loads it at 1 MB.
+pub const DEFAULT_KERNEL_IMAGE: Range<usize> = 0x10_0000..0x20_0000;
+
+std::thread_local! {
+    // Each test runs on its own thread, so tests can't see each other's kernel images
+    static KERNEL_IMAGE: Cell<(usize, usize)> =
+        const { Cell::new((DEFAULT_KERNEL_IMAGE.start, DEFAULT_KERNEL_IMAGE.end)) };
+}
+
+/// The memory that the kernel image takes up in the current test
+pub fn kernel_image() -> Range<usize> {
+    let (start, end) = KERNEL_IMAGE.get();
+    start..end
+}
+
+/// Moves the kernel image for the rest of the current test
+pub fn set_kernel_image(image: Range<usize>) {
+    KERNEL_IMAGE.set((image.start, image.end));
+}
+
+const MODULE_TAG: u32 = 3;
+const BASIC_MEMORY_INFO_TAG: u32 = 4;
+const MEMORY_MAP_TAG: u32 = 6;
+const END_TAG: u32 = 0;
+
+/// Builds boot information one tag at a time
+pub struct BootInfoBuilder {
+    bytes: Vec<u8>,
+}
+
+impl BootInfoBuilder {
+    pub fn new() -> Self {
+        // The total size is filled in by `build`
+        Self { bytes: vec![0; 8] }
+    }
+
+    /// Adds a memory map with an area of `region_type` for each range
+    pub fn memory_map(self, areas: &[(Range<u64>, u32)]) -> Self {
+        let entry_size = u32::try_from(size_of::<MemoryMapEntry>()).unwrap();
+        let mut data = Vec::new();
+        data.extend(entry_size.to_ne_bytes());
+        // The entry version
+        data.extend(0u32.to_ne_bytes());
+        for (area, region_type) in areas {
+            data.extend(area.start.to_ne_bytes());
+            data.extend((area.end - area.start).to_ne_bytes());
+            data.extend(region_type.to_ne_bytes());
+            data.extend(0u32.to_ne_bytes());
+        }
+        self.tag(MEMORY_MAP_TAG, &data)
+    }
+
+    /// Adds the basic memory information, which is given in kilobytes
+    pub fn basic_memory_info(self, lower: u32, upper: u32) -> Self {
+        let mut data = Vec::new();
+        data.extend(lower.to_ne_bytes());
+        data.extend(upper.to_ne_bytes());
+        self.tag(BASIC_MEMORY_INFO_TAG, &data)
+    }
+
+    /// Adds a module that was loaded at `range` with `command_line`
+    pub fn module(self, range: Range<u32>, command_line: &str) -> Self {
+        let mut data = Vec::new();
+        data.extend(range.start.to_ne_bytes());
+        data.extend(range.end.to_ne_bytes());
+        data.extend(command_line.as_bytes());
+        data.push(0);
+        self.tag(MODULE_TAG, &data)
+    }
+
+    fn tag(mut self, tag_type: u32, data: &[u8]) -> Self {
+        let size = u32::try_from(8 + data.len()).unwrap();
+        self.bytes.extend(tag_type.to_ne_bytes());
+        self.bytes.extend(size.to_ne_bytes());
+        self.bytes.extend(data);
+        self.bytes.resize(self.bytes.len().next_multiple_of(8), 0);
+        self
+    }
+
+    pub fn build(self) -> BootInfoBlob {
+        let mut bytes = self.tag(END_TAG, &[]).bytes;
+        let total_size = u32::try_from(bytes.len()).unwrap();
+        bytes[..4].copy_from_slice(&total_size.to_ne_bytes());
+        BootInfoBlob {
+            words: words(&bytes),
+        }
+    }
+}
+
+/// Boot information in memory. It's kept in `u64`s because the bootloader aligns it to 8 bytes.
+pub struct BootInfoBlob {
+    words: Vec<u64>,
+}
+
+impl BootInfoBlob {
+    pub fn boot_info(&self) -> BootInformation<'_> {
+        unsafe { BootInformation::new(self.words.as_ptr().cast()) }
+    }
+
+    /**
+     * Copies the boot information to `address`, as if the bootloader had put it there
+     *
+     * # Safety
+     *
+     * `address` must be aligned to 8 bytes, and the memory there must be writable and stay
+     * allocated for as long as the boot information is used.
+     */
+    pub unsafe fn copy_to<'a>(&self, address: usize) -> BootInformation<'a> {
+        let memory = slice::from_raw_parts_mut(address as *mut u64, self.words.len());
+        memory.copy_from_slice(&self.words);
+        BootInformation::new(memory.as_ptr().cast())
+    }
+}
+
+/// A loadable segment of an executable built by `elf_executable`
+pub struct TestSegment<'a> {
+    pub address: u64,
+    pub data: &'a [u8],
+    pub memory_size: u64,
+    pub flags: u32,
+}
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/**
+ * Lays out an x86-64 executable that starts at `entry` and has `segments`. The program headers come
+ * right after the ELF header, and the segments' data follows them in order. Like a module, it's
+ * aligned to 8 bytes.
+ */
+pub fn elf_executable(entry: u64, segments: &[TestSegment]) -> Vec<u64> {
+    let headers_size = ELF_HEADER_SIZE + segments.len() * PROGRAM_HEADER_SIZE;
+    let mut bytes = Vec::new();
+    bytes.extend(b"\x7fELF");
+    // 64 bit, little endian, version 1, and padding out to the file type
+    bytes.extend([2, 1, 1]);
+    bytes.resize(16, 0);
+    // An executable for x86-64
+    bytes.extend(2u16.to_le_bytes());
+    bytes.extend(0x3eu16.to_le_bytes());
+    bytes.extend(1u32.to_le_bytes());
+    bytes.extend(entry.to_le_bytes());
+    // The program headers come right after the ELF header, and there are no section headers
+    bytes.extend(u64::try_from(ELF_HEADER_SIZE).unwrap().to_le_bytes());
+    bytes.extend(0u64.to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(u16::try_from(ELF_HEADER_SIZE).unwrap().to_le_bytes());
+    bytes.extend(u16::try_from(PROGRAM_HEADER_SIZE).unwrap().to_le_bytes());
+    bytes.extend(u16::try_from(segments.len()).unwrap().to_le_bytes());
+    bytes.extend([0; 6]);
+    let mut offset = headers_size;
+    for segment in segments {
+        // A loadable segment
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(segment.flags.to_le_bytes());
+        bytes.extend(u64::try_from(offset).unwrap().to_le_bytes());
+        bytes.extend(segment.address.to_le_bytes());
+        bytes.extend(segment.address.to_le_bytes());
+        bytes.extend(u64::try_from(segment.data.len()).unwrap().to_le_bytes());
+        bytes.extend(segment.memory_size.to_le_bytes());
+        bytes.extend(0x1000u64.to_le_bytes());
+        offset += segment.data.len();
+    }
+    for segment in segments {
+        bytes.extend(segment.data);
+    }
+    words(&bytes)
+}
+
+/// The memory that an executable built by `elf_executable` takes up, as a module would be passed
+/// to the kernel
+pub fn module_range(words: &[u64]) -> Range<usize> {
+    let start = words.as_ptr() as usize;
+    start..start + size_of_val(words)
+}
+
+/// Packs `bytes` into 8 byte aligned words, padding the end with zeroes
+fn words(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks(8)
+        .map(|chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_ne_bytes(word)
+        })
+        .collect()
+}
@@ -0,0 +1,31 @@
+use core::ops::Range;
+use frame_allocation::{amd64::Amd64FrameAllocator, PhysicalAddress};
+use micros_memory_manager_core::{frame_service::FrameSource, heap::ChunkSource};
+
+/// Gets frames from the frame allocator that the kernel handed off. Memory is identity mapped, so
+/// a frame's physical address can be used as a pointer. Nothing else may use the allocator while
+/// this does.
+pub struct HandoffFrames(pub *mut Amd64FrameAllocator);
+
+impl ChunkSource for HandoffFrames {
+    fn get_chunk(&mut self) -> Option<usize> {
+        let allocator = unsafe { self.0.as_mut()? };
+        unsafe { allocator.get_2mb_frame() }.map(usize::from)
+    }
+}
+
+impl FrameSource for HandoffFrames {
+    fn get_4k_frame(&mut self) -> Option<PhysicalAddress> {
+        unsafe { self.0.as_mut()?.get_4k_frame() }
+    }
+
+    fn get_2mb_frame(&mut self) -> Option<PhysicalAddress> {
+        unsafe { self.0.as_mut()?.get_2mb_frame() }
+    }
+
+    fn add_4k_frames(&mut self, frames: Range<usize>) {
+        if let Some(allocator) = unsafe { self.0.as_mut() } {
+            unsafe { allocator.four_kilobyte_pages.add_frames(frames) };
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::frames::HandoffFrames;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{self, null_mut},
+};
+use frame_allocation::amd64::Amd64FrameAllocator;
+use micros_memory_manager_core::heap::Heap;
+
+/// A heap that can be shared with the rest of the memory manager through `GlobalAlloc`
+pub struct LockedHeap(spin::Mutex<Heap<HandoffFrames>>);
+
+impl LockedHeap {
+    /// Creates a heap that fails every allocation until `init` is called
+    pub const fn empty() -> Self {
+        Self(spin::Mutex::new(Heap::new(HandoffFrames(null_mut()))))
+    }
+
+    /// Makes the heap grow using frames from `allocator`
+    pub fn init(&self, allocator: *mut Amd64FrameAllocator) {
+        *self.0.lock() = Heap::new(HandoffFrames(allocator));
+    }
+}
+
+// The heap's pointers only ever refer to memory that the heap owns, and the lock serializes access
+// to them
+unsafe impl Sync for LockedHeap {}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        self.0.lock().free(block, layout);
+    }
+
+    unsafe fn realloc(&self, block: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if Heap::<HandoffFrames>::fits_in_place(layout, new_layout) {
+            return block;
+        }
+        let mut heap = self.0.lock();
+        let new_block = heap.allocate(new_layout);
+        if !new_block.is_null() {
+            ptr::copy_nonoverlapping(block, new_block, layout.size().min(new_size));
+            heap.free(block, layout);
+        }
+        new_block
+    }
+}
@@ -5,30 +5,283 @@
 #![allow(clippy::empty_loop)]
 #![allow(clippy::missing_safety_doc)]
 
+extern crate alloc;
+
+#[cfg(target_arch = "x86_64")]
+mod frames;
+#[cfg(target_arch = "x86_64")]
+mod heap;
+#[cfg(target_arch = "x86_64")]
+mod page_tables;
+#[cfg(target_arch = "x86_64")]
+mod panic;
+#[cfg(target_arch = "x86_64")]
+mod screen;
+#[cfg(target_arch = "x86_64")]
+mod syscall;
+#[cfg(target_arch = "x86_64")]
+mod text;
+
+#[cfg(target_arch = "x86_64")]
+use alloc::format;
+#[cfg(not(target_arch = "x86_64"))]
 use core::panic::PanicInfo;
-use framebuffer::StandardRgbFramebuffer;
-use multiboot2::{BootInformation, FramebufferTag};
+#[cfg(target_arch = "x86_64")]
+use core::{ptr::addr_of_mut, slice};
+#[cfg(target_arch = "x86_64")]
+use frame_allocation::amd64::{Amd64FrameAllocator, FOUR_KILOBYTES};
+#[cfg(target_arch = "x86_64")]
+use framebuffer::{BootConsole, Console, TextBuffer};
+#[cfg(target_arch = "x86_64")]
+use frames::HandoffFrames;
+#[cfg(target_arch = "x86_64")]
+use heap::LockedHeap;
+#[cfg(target_arch = "x86_64")]
+use micros_abi::{
+    keyboard::KeyEvent,
+    paging::{no_execute, WRITABLE},
+    ring::{
+        Message, RingBuffer, MESSAGE_ALLOC_FRAMES, MESSAGE_BOOT_COMPLETE, MESSAGE_DUMP_OWNERS,
+        MESSAGE_FREE_FRAMES, MESSAGE_KEY,
+    },
+    BootHandoff, MemoryManagerEntry,
+};
+#[cfg(target_arch = "x86_64")]
+use micros_memory_manager_core::{
+    frame_service::FrameService,
+    line_editor::LineEditor,
+    memory_report::{memory_regions, print_memory_report},
+    virtual_space::VirtualSpace,
+};
+#[cfg(target_arch = "x86_64")]
+use multiboot2::{BootInformation, MemoryMapTag};
+#[cfg(target_arch = "x86_64")]
+use page_tables::{IdentityMappedPageTables, MANAGED_ADDRESSES};
+#[cfg(target_arch = "x86_64")]
+use screen::{Screen, SCREEN};
+#[cfg(target_arch = "x86_64")]
+use syscall::SyscallConsole;
+#[cfg(target_arch = "x86_64")]
+use text::{draw_input_line, BACKGROUND_COLOR, TEXT_COLOR};
+
+#[cfg(target_arch = "x86_64")]
+#[global_allocator]
+static HEAP: LockedHeap = LockedHeap::empty();
+
+/// The copy of the text on the screen that the console keeps, which is too big for the stack
+#[cfg(target_arch = "x86_64")]
+static mut TEXT_BUFFER: TextBuffer = TextBuffer::new();
+
+// The kernel calls `main` without knowing its type, so at least make sure it matches the ABI
+#[cfg(target_arch = "x86_64")]
+const _: MemoryManagerEntry = main;
 
 #[cfg(target_arch = "x86_64")]
 #[no_mangle]
-pub unsafe extern "C" fn main(
-    _: *mut frame_allocation::amd64::Amd64FrameAllocator,
-    boot_info_ptr: *const u8,
-) -> ! {
-    if let Some(mut framebuffer) = get_framebuffer(boot_info_ptr) {
-        framebuffer.paint_the_screen_white();
+pub unsafe extern "C" fn main(handoff: *const BootHandoff) -> ! {
+    // A kernel built against a different version of the handoff could have put anything here, so
+    // nothing else in it can be trusted
+    // There's nothing to report a failure to if the console doesn't work
+    let Some(handoff) = handoff.as_ref().filter(|handoff| handoff.is_valid()) else {
+        let _ = syscall::write_console("The kernel passed an invalid boot handoff");
+        syscall::fatal_error();
+    };
+    if let Some(mut console) = BootConsole::from_boot_info(
+        BootInformation::new(handoff.boot_info),
+        &mut *addr_of_mut!(TEXT_BUFFER),
+    ) {
+        console.set_color(TEXT_COLOR, BACKGROUND_COLOR);
+        console.clear();
+        *SCREEN.lock() = Some(Screen::new(console));
     }
-    loop {}
+    let _ = syscall::write_console("Memory manager started");
+    if cfg!(feature = "panic-test") {
+        test_panic();
+    }
+    let Some(allocator) = handoff
+        .allocator
+        .as_mut()
+        .filter(|allocator| allocator.is_valid())
+    else {
+        let _ = syscall::write_console("The frame allocator's layout doesn't match the kernel's");
+        syscall::fatal_error();
+    };
+    let allocator = &raw mut allocator.allocator;
+    HEAP.init(allocator);
+    let mut virtual_space = VirtualSpace::new(
+        IdentityMappedPageTables {
+            root_page_table: handoff.root_page_table,
+            allocator,
+        },
+        MANAGED_ADDRESSES,
+    );
+    let _ = syscall::write_console(check_virtual_space(&mut virtual_space, allocator));
+    draw_off_screen(&mut virtual_space, allocator);
+    if let Some(memory_map) = BootInformation::new(handoff.boot_info)
+        .tags_of_type::<MemoryMapTag>()
+        .next()
+    {
+        print_memory_report(&memory_regions(memory_map), &mut SyscallConsole);
+    } else {
+        let _ = syscall::write_console("The boot information doesn't have a valid memory map");
+    }
+    let (Some(events), Some(replies)) = (handoff.events.as_ref(), handoff.replies.as_ref()) else {
+        let _ = syscall::write_console("The kernel didn't pass the ring buffers");
+        syscall::fatal_error();
+    };
+    let mut frame_service = FrameService::new(HandoffFrames(allocator));
+    let mut line_editor = LineEditor::new();
+    loop {
+        while let Some(message) = events.try_pop() {
+            handle_event(&message, &mut frame_service, &mut line_editor, replies);
+        }
+        syscall::yield_until_event();
+    }
+}
+
+/// Handles a message that the kernel sent through the event ring buffer
+#[cfg(target_arch = "x86_64")]
+unsafe fn handle_event(
+    message: &Message,
+    frame_service: &mut FrameService<HandoffFrames>,
+    line_editor: &mut LineEditor,
+    replies: &RingBuffer,
+) {
+    match message.kind {
+        MESSAGE_BOOT_COMPLETE => {
+            let _ = syscall::write_console("Event: boot complete");
+        }
+        MESSAGE_ALLOC_FRAMES | MESSAGE_FREE_FRAMES => {
+            match frame_service.handle(message, &mut SyscallConsole) {
+                Ok(Some(reply)) => {
+                    if !replies.try_push(&reply) {
+                        let _ = syscall::write_console("Dropped a reply because the ring is full");
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    let _ = syscall::write_console(&format!("Frame request failed: {err}"));
+                }
+            }
+        }
+        MESSAGE_DUMP_OWNERS => frame_service.dump_owners(&mut SyscallConsole),
+        MESSAGE_KEY => {
+            if let Some(event) = KeyEvent::from_message(message) {
+                line_editor.handle_key(&event, &mut SyscallConsole);
+                if let Some(screen) = SCREEN.lock().as_mut() {
+                    draw_input_line(&mut screen.console, line_editor.line());
+                    present(screen);
+                }
+            }
+        }
+        _ => {
+            let _ = syscall::write_console("Event: unknown message");
+        }
+    }
+}
+
+/// Maps a fresh frame into a fresh region and checks that it can be written to and read back
+#[cfg(target_arch = "x86_64")]
+unsafe fn check_virtual_space(
+    virtual_space: &mut VirtualSpace<IdentityMappedPageTables>,
+    allocator: *mut Amd64FrameAllocator,
+) -> &'static str {
+    const PATTERN: u64 = 0x5a5a_a5a5_0123_4567;
+    let Some(region) = virtual_space.allocate_region(FOUR_KILOBYTES, FOUR_KILOBYTES) else {
+        return "Virtual space check failed: no region is free";
+    };
+    let Some(frame) = (*allocator).get_4k_frame() else {
+        return "Virtual space check failed: no frame is free";
+    };
+    if virtual_space
+        .map(region, frame, WRITABLE | no_execute())
+        .is_err()
+    {
+        return "Virtual space check failed: the region couldn't be mapped";
+    }
+    let words = region.as_usize() as *mut u64;
+    for index in 0..FOUR_KILOBYTES / 8 {
+        words.add(index).write_volatile(PATTERN ^ index as u64);
+    }
+    if (0..FOUR_KILOBYTES / 8)
+        .all(|index| words.add(index).read_volatile() == PATTERN ^ index as u64)
+    {
+        "Virtual space check passed"
+    } else {
+        "Virtual space check failed: the pattern didn't read back"
+    }
+}
+
+/**
+ * Moves the console off screen into fresh memory so that only what changes is copied onto the
+ * screen. Screens that can't be drawn off screen are left as they are.
+ */
+#[cfg(target_arch = "x86_64")]
+unsafe fn draw_off_screen(
+    virtual_space: &mut VirtualSpace<IdentityMappedPageTables>,
+    allocator: *mut Amd64FrameAllocator,
+) {
+    let mut screen = SCREEN.lock();
+    let Some(size) = screen.as_mut().and_then(Screen::back_buffer_size) else {
+        return;
+    };
+    if (*allocator).total_bytes_free() < size {
+        let _ = syscall::write_console("There isn't enough memory to draw off screen");
+        return;
+    }
+    let Some(memory) = map_fresh_memory(virtual_space, allocator, size) else {
+        let _ = syscall::write_console("Couldn't map memory to draw off screen in");
+        return;
+    };
+    let Some(mut double_buffered) = screen.take().map(|screen| screen.double_buffered(memory))
+    else {
+        return;
+    };
+    double_buffered
+        .console
+        .set_color(TEXT_COLOR, BACKGROUND_COLOR);
+    double_buffered.console.clear();
+    present(screen.insert(double_buffered));
+}
+
+/// Copies what's been drawn off screen onto the screen and logs how many pixels that took
+#[cfg(target_arch = "x86_64")]
+fn present(screen: &mut Screen) {
+    let copied = screen.present();
+    if copied > 0 {
+        let _ = syscall::write_console(&format!("Presented {copied} pixels"));
+    }
+}
+
+/**
+ * Maps `size` bytes of fresh frames into a fresh region. Pages that were mapped before a failure
+ * stay mapped, since `VirtualSpace` can't unmap them.
+ */
+#[cfg(target_arch = "x86_64")]
+unsafe fn map_fresh_memory(
+    virtual_space: &mut VirtualSpace<IdentityMappedPageTables>,
+    allocator: *mut Amd64FrameAllocator,
+    size: usize,
+) -> Option<&'static mut [u8]> {
+    let region = virtual_space.allocate_region(size, FOUR_KILOBYTES)?;
+    for offset in (0..size).step_by(FOUR_KILOBYTES) {
+        let frame = (*allocator).get_4k_frame()?;
+        virtual_space
+            .map(region + offset, frame, WRITABLE | no_execute())
+            .ok()?;
+    }
+    Some(slice::from_raw_parts_mut(
+        region.as_usize() as *mut u8,
+        size,
+    ))
 }
 
-unsafe fn get_framebuffer(boot_info_ptr: *const u8) -> Option<StandardRgbFramebuffer<'static>> {
-    StandardRgbFramebuffer::from_tag(
-        BootInformation::new(boot_info_ptr)
-            .tags_of_type::<FramebufferTag>()
-            .next()?,
-    )
+/// Checks that panics are visible in builds with the `panic-test` feature
+fn test_panic() {
+    panic!("test");
 }
 
+#[cfg(not(target_arch = "x86_64"))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
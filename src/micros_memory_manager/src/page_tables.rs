@@ -0,0 +1,60 @@
+use core::{ops::Range, ptr};
+use frame_allocation::{
+    amd64::{table_index, Amd64FrameAllocator, ENTRIES_PER_TABLE},
+    PhysicalAddress, VirtualAddress,
+};
+use micros_abi::paging::{
+    ENTRY_ADDRESS_MASK, HUGE_PAGE, PRESENT, ROOT_PAGE_TABLE_LEVEL, USER_ACCESSIBLE, WRITABLE,
+};
+use micros_memory_manager_core::virtual_space::{MapError, PageTableEditor};
+use multiboot2::phys_to_usize;
+
+/// The part of the address space that the memory manager hands out regions from. The first root
+/// page table entry is the identity map that the kernel shares with every process, and the top of
+/// the lower half is left alone so that an overflowing pointer can't wrap into it.
+pub const MANAGED_ADDRESSES: Range<usize> = 0x80_0000_0000..0x7fff_ffff_f000;
+
+/**
+ * Edits the memory manager's page tables through the identity map, taking any page tables that
+ * don't exist yet from the frame allocator. `allocator` must stay valid for as long as this is
+ * used.
+ */
+pub struct IdentityMappedPageTables {
+    pub root_page_table: PhysicalAddress,
+    pub allocator: *mut Amd64FrameAllocator,
+}
+
+impl PageTableEditor for IdentityMappedPageTables {
+    unsafe fn map_4k_page(
+        &mut self,
+        virt: VirtualAddress,
+        frame: PhysicalAddress,
+        flags: u64,
+    ) -> Result<(), MapError> {
+        let address = virt.as_usize();
+        let mut page_table = self.root_page_table.as_usize() as *mut u64;
+        for level in (1..=ROOT_PAGE_TABLE_LEVEL).rev() {
+            let entry = page_table.add(table_index(level, address));
+            if *entry & PRESENT == 0 {
+                let new_page_table = (*self.allocator)
+                    .get_4k_frame()
+                    .ok_or(MapError::OutOfMemory)?
+                    .as_usize() as *mut u64;
+                ptr::write_bytes(new_page_table, 0, ENTRIES_PER_TABLE);
+                *entry = new_page_table as u64 | PRESENT | WRITABLE | USER_ACCESSIBLE;
+            } else if *entry & HUGE_PAGE != 0 {
+                return Err(MapError::AlreadyMapped);
+            }
+            // A table past the end of the address space can't be edited, so whatever it maps is
+            // left alone
+            page_table = phys_to_usize(*entry & ENTRY_ADDRESS_MASK)
+                .ok_or(MapError::AlreadyMapped)? as *mut u64;
+        }
+        let entry = page_table.add(table_index(0, address));
+        if *entry & PRESENT != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+        *entry = frame.as_usize() as u64 | flags | PRESENT;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{screen::SCREEN, syscall};
+use core::{
+    fmt::{self, Write},
+    hint::spin_loop,
+    panic::PanicInfo,
+};
+use framebuffer::{BootConsole, Console, Rgb};
+
+/// The height of the red band at the top of the screen in rows of text
+const BANNER_ROWS: u32 = 4;
+const BANNER_COLOR: Rgb = Rgb::new(0xc0, 0, 0);
+const BANNER_TEXT_COLOR: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+/// The longest panic message that's sent to the console when there's no framebuffer
+const CONSOLE_MESSAGE_SIZE: usize = 256;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // A panic while something is being drawn would deadlock if this waited for the lock
+    let mut screen = SCREEN.try_lock();
+    if let Some(screen) = screen.as_mut().and_then(|guard| guard.as_mut()) {
+        draw_panic(&mut screen.console, info);
+        screen.present();
+    } else {
+        let mut message = ConsoleMessage {
+            bytes: [0; CONSOLE_MESSAGE_SIZE],
+            len: 0,
+        };
+        let _ = write_panic(&mut message, info);
+        let _ = syscall::write_console(message.as_str());
+    }
+    // Processes can't disable interrupts, so this just spins between them
+    loop {
+        spin_loop();
+    }
+}
+
+fn write_panic(writer: &mut impl Write, info: &PanicInfo) -> fmt::Result {
+    writer.write_str("Memory manager panicked")?;
+    if let Some(location) = info.location() {
+        write!(writer, " at {location}")?;
+    }
+    write!(writer, ":\n{}", info.message())
+}
+
+/// Draws a red band across the top of the screen with the panic message written over it
+fn draw_panic(console: &mut BootConsole, info: &PanicInfo) {
+    console.set_color(BANNER_TEXT_COLOR, BANNER_COLOR);
+    match console {
+        BootConsole::Framebuffer(console) => console.draw_panic_banner(BANNER_ROWS),
+        // Text mode can't blend, so the whole screen turns red rather than a band across the top
+        BootConsole::TextMode(console) => console.clear(),
+    }
+    let _ = write_panic(console, info);
+}
+
+/// A panic message formatted without the heap, which might be what panicked. Anything past
+/// `CONSOLE_MESSAGE_SIZE` bytes is dropped.
+struct ConsoleMessage {
+    bytes: [u8; CONSOLE_MESSAGE_SIZE],
+    len: usize,
+}
+
+impl ConsoleMessage {
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever copied in
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for ConsoleMessage {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.chars() {
+            let end = self.len + character.len_utf8();
+            if end > CONSOLE_MESSAGE_SIZE {
+                break;
+            }
+            character.encode_utf8(&mut self.bytes[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use framebuffer::{BootConsole, FramebufferConsole, StandardRgbFramebuffer};
+
+/// What the memory manager draws on, including when it panics. This is set at the top of `main` so
+/// that panics in the rest of the memory manager are visible.
+pub static SCREEN: spin::Mutex<Option<Screen>> = spin::Mutex::new(None);
+
+/// A console and, once it draws off screen, the framebuffer that it's presented on
+pub struct Screen {
+    pub console: BootConsole<'static>,
+    front: Option<StandardRgbFramebuffer<'static>>,
+}
+
+impl Screen {
+    /// A screen that `console` draws on directly
+    pub fn new(console: BootConsole<'static>) -> Self {
+        Self {
+            console,
+            front: None,
+        }
+    }
+
+    /// The number of bytes that `double_buffered` needs, or `None` if the console doesn't draw on
+    /// a framebuffer or already draws off screen
+    pub fn back_buffer_size(&mut self) -> Option<usize> {
+        match (&mut self.console, &self.front) {
+            (BootConsole::Framebuffer(console), None) => {
+                Some(console.framebuffer().size_in_bytes())
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * Moves the console off screen into `memory` so that `present` only has to copy what changed
+     * onto the screen. The console starts out blank. Screens that `back_buffer_size` returns
+     * `None` for, or that `memory` is too small for, are left as they are.
+     */
+    pub fn double_buffered(self, memory: &'static mut [u8]) -> Self {
+        match self.console {
+            BootConsole::Framebuffer(console) if self.front.is_none() => {
+                let (front, text) = console.into_parts();
+                match front.back_buffer(memory) {
+                    Some(back) => Self {
+                        console: BootConsole::Framebuffer(FramebufferConsole::new(back, text)),
+                        front: Some(front),
+                    },
+                    None => Self::new(BootConsole::Framebuffer(FramebufferConsole::new(
+                        front, text,
+                    ))),
+                }
+            }
+            console => Self {
+                console,
+                front: self.front,
+            },
+        }
+    }
+
+    /// Copies whatever the console has drawn off screen since the last call onto the screen.
+    /// Returns the number of pixels copied.
+    pub fn present(&mut self) -> usize {
+        match (&mut self.console, &mut self.front) {
+            (BootConsole::Framebuffer(console), Some(front)) => {
+                console.framebuffer().present(front)
+            }
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use core::arch::asm;
+use micros_abi::syscall::{FATAL_ERROR, WRITE_CONSOLE, YIELD_UNTIL_EVENT};
+use micros_memory_manager_core::console::Console;
+
+/// Makes the system call `number` without any arguments and returns the kernel's result.
+unsafe fn syscall0(number: usize) -> isize {
+    let result;
+    asm!(
+        "syscall",
+        in("rax") number,
+        lateout("rax") result,
+        clobber_abi("C"),
+        options(nostack),
+    );
+    result
+}
+
+/// Makes the system call `number` with two arguments and returns the kernel's result.
+unsafe fn syscall2(number: usize, arg0: usize, arg1: usize) -> isize {
+    let result;
+    asm!(
+        "syscall",
+        in("rax") number,
+        lateout("rax") result,
+        in("rdi") arg0,
+        in("rsi") arg1,
+        clobber_abi("C"),
+        options(nostack),
+    );
+    result
+}
+
+/// Writes `message` to the kernel's console. On failure, returns the error code from the kernel.
+pub fn write_console(message: &str) -> Result<(), isize> {
+    let result = unsafe { syscall2(WRITE_CONSOLE, message.as_ptr() as usize, message.len()) };
+    if result < 0 {
+        Err(result)
+    } else {
+        Ok(())
+    }
+}
+
+/// Sleeps until the kernel sends an event through the event ring buffer
+pub fn yield_until_event() {
+    unsafe {
+        syscall0(YIELD_UNTIL_EVENT);
+    }
+}
+
+/// Asks the kernel to deal with an error that the memory manager can't recover from
+pub fn fatal_error() -> ! {
+    unsafe {
+        syscall0(FATAL_ERROR);
+    }
+    // The kernel never returns from the call
+    loop {}
+}
+
+/// Writes to the kernel's console through `write_console`
+pub struct SyscallConsole;
+
+impl Console for SyscallConsole {
+    fn write_line(&mut self, line: &str) {
+        // There's nothing to report a failure to if the console doesn't work
+        let _ = write_console(line);
+    }
+}
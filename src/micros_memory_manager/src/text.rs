@@ -0,0 +1,27 @@
+use framebuffer::{Console, Rgb};
+
+/// The color of the memory manager's text
+pub const TEXT_COLOR: Rgb = Rgb::new(0, 0, 0);
+/// The color of the memory manager's screen
+pub const BACKGROUND_COLOR: Rgb = Rgb::new(0xff, 0xff, 0xff);
+
+/// Draws `line` along the bottom of the screen as the line that's being typed. Only the end of a
+/// line that's too long for the screen is drawn.
+pub fn draw_input_line(console: &mut impl Console, line: &str) {
+    const PROMPT: &str = "> ";
+    const CURSOR: &str = "_";
+    let (columns, rows) = console.dimensions();
+    let columns = usize::try_from(columns).unwrap_or(usize::MAX);
+    let room = columns.saturating_sub(PROMPT.len() + CURSOR.len());
+    let length = line.chars().count();
+    console.set_cursor(rows.saturating_sub(1), 0);
+    let _ = console.write_str(PROMPT);
+    for character in line.chars().skip(length.saturating_sub(room)) {
+        let _ = console.write_char(character);
+    }
+    let _ = console.write_str(CURSOR);
+    // Padding the rest of the row erases anything longer that was there before
+    for _ in length.min(room)..room {
+        let _ = console.write_char(' ');
+    }
+}
@@ -0,0 +1,14 @@
+/// Somewhere to write lines of text for whoever is watching the system
+pub trait Console {
+    /// Writes `line` followed by a line break. Failures are ignored because there's nothing to
+    /// report them to.
+    fn write_line(&mut self, line: &str);
+}
+
+/// Tests collect the lines that are written so that they can check them
+#[cfg(test)]
+impl Console for alloc::vec::Vec<alloc::string::String> {
+    fn write_line(&mut self, line: &str) {
+        self.push(line.into());
+    }
+}
@@ -0,0 +1,464 @@
+use crate::{
+    console::Console,
+    owners::{dump_owners, Owner, Ownership, OwnershipError, OwnershipTable},
+};
+use alloc::format;
+use core::{fmt, ops::Range};
+use frame_allocation::{amd64::FOUR_KILOBYTES, PhysicalAddress};
+use micros_abi::{
+    frame_service::{AllocFramesError, AllocFramesRequest, AllocFramesResponse, FreeFramesRequest},
+    ring::Message,
+};
+
+/// The order of a 2 MB frame, which holds 2^9 4 KB frames
+const TWO_MEGABYTE_ORDER: u8 = 9;
+const FOUR_KILOBYTE_FRAMES_PER_TWO_MEGABYTES: usize = 1 << TWO_MEGABYTE_ORDER;
+
+#[derive(Debug)]
+pub enum FrameServiceError {
+    /// The message isn't a well formed frame request
+    MalformedRequest,
+    /// The frames being freed aren't exactly the frames of an allocation
+    WrongSize {
+        allocated: usize,
+        freed: usize,
+    },
+    Ownership(OwnershipError),
+}
+
+impl fmt::Display for FrameServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MalformedRequest => f.write_str("malformed frame request"),
+            Self::WrongSize { allocated, freed } => write!(
+                f,
+                "tried to free {freed} bytes of an allocation of {allocated} bytes"
+            ),
+            Self::Ownership(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Somewhere the frame service can get physical frames from and give them back to
+pub trait FrameSource {
+    fn get_4k_frame(&mut self) -> Option<PhysicalAddress>;
+    fn get_2mb_frame(&mut self) -> Option<PhysicalAddress>;
+    /// Makes the 4 KB frames in `frames` available again
+    fn add_4k_frames(&mut self, frames: Range<usize>);
+}
+
+/// Hands out frames from a `FrameSource` on behalf of other parts of the system and keeps track
+/// of who owns them
+pub struct FrameService<Frames> {
+    frames: Frames,
+    owners: OwnershipTable,
+}
+
+impl<Frames: FrameSource> FrameService<Frames> {
+    pub const fn new(frames: Frames) -> Self {
+        Self {
+            frames,
+            owners: OwnershipTable::new(),
+        }
+    }
+
+    /**
+     * Allocates `count` physically contiguous 4 KB frames for `caller`, labelled with `tag`. A
+     * single frame comes from the 4 KB pool. Anything bigger is carved out of a 2 MB frame if
+     * `max_order` allows it, and the rest of the 2 MB frame goes back to the 4 KB pool. Problems
+     * with the frame source are written to `console`, and the caller is told that there's no
+     * memory.
+     */
+    pub fn allocate(
+        &mut self,
+        caller: Owner,
+        tag: u32,
+        count: usize,
+        max_order: u8,
+        console: &mut impl Console,
+    ) -> Result<PhysicalAddress, AllocFramesError> {
+        let start = match count {
+            0 => return Err(AllocFramesError::InvalidCount),
+            1 => self
+                .frames
+                .get_4k_frame()
+                .ok_or(AllocFramesError::OutOfMemory)?
+                .as_usize(),
+            _ if count <= FOUR_KILOBYTE_FRAMES_PER_TWO_MEGABYTES
+                && max_order >= TWO_MEGABYTE_ORDER =>
+            {
+                let start = self
+                    .frames
+                    .get_2mb_frame()
+                    .ok_or(AllocFramesError::OutOfMemory)?
+                    .as_usize();
+                self.frames.add_4k_frames(
+                    start + count * FOUR_KILOBYTES
+                        ..start + FOUR_KILOBYTE_FRAMES_PER_TWO_MEGABYTES * FOUR_KILOBYTES,
+                );
+                start
+            }
+            _ => return Err(AllocFramesError::InvalidCount),
+        };
+        let ownership = Ownership {
+            owner: caller,
+            tag,
+            size: count * FOUR_KILOBYTES,
+        };
+        if let Err(err) = self.owners.insert(start, ownership) {
+            // The frame source handed out frames that are in use, so it can't be trusted with them
+            // either
+            console.write_line(&format!(
+                "The frame allocator handed out {start:#x} but {err}"
+            ));
+            return Err(AllocFramesError::OutOfMemory);
+        }
+        Ok(PhysicalAddress::new(start))
+    }
+
+    /**
+     * Gives back the frames of an earlier allocation by `caller`. Nothing is given back if
+     * `address` and `count` don't match an allocation owned by `caller`.
+     */
+    pub fn free(
+        &mut self,
+        caller: Owner,
+        address: PhysicalAddress,
+        count: usize,
+    ) -> Result<(), FrameServiceError> {
+        let start = address.as_usize();
+        let size = count.saturating_mul(FOUR_KILOBYTES);
+        if let Some(ownership) = self.owners.get(start) {
+            if ownership.owner == caller && ownership.size != size {
+                return Err(FrameServiceError::WrongSize {
+                    allocated: ownership.size,
+                    freed: size,
+                });
+            }
+        }
+        self.owners
+            .remove(start, caller)
+            .map_err(FrameServiceError::Ownership)?;
+        self.frames.add_4k_frames(start..start + size);
+        Ok(())
+    }
+
+    /// Writes who owns how much memory to `console`
+    pub fn dump_owners(&self, console: &mut impl Console) {
+        dump_owners(&self.owners, console);
+    }
+
+    /**
+     * Services a frame request that arrived through the event ring buffer. Returns the message to
+     * answer it with if it needs an answer, or a description of what was wrong with it.
+     */
+    pub fn handle(
+        &mut self,
+        message: &Message,
+        console: &mut impl Console,
+    ) -> Result<Option<Message>, FrameServiceError> {
+        if let Some(request) = AllocFramesRequest::from_message(message) {
+            let result = self.allocate(
+                Owner(request.caller),
+                request.request_id,
+                request.count,
+                request.max_order,
+                console,
+            );
+            Ok(Some(
+                AllocFramesResponse {
+                    request_id: request.request_id,
+                    result: result.into(),
+                    count: request.count,
+                }
+                .to_message(),
+            ))
+        } else if let Some(request) = FreeFramesRequest::from_message(message) {
+            self.free(Owner(request.caller), request.address, request.count)?;
+            Ok(None)
+        } else {
+            Err(FrameServiceError::MalformedRequest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::String, vec, vec::Vec};
+    use frame_allocation::FfiResult;
+    use micros_abi::{frame_service::KERNEL_CALLER, ring::MESSAGE_KEY};
+
+    const TWO_MEGABYTES: usize = 0x20_0000;
+    const KERNEL: Owner = Owner(KERNEL_CALLER);
+    const INIT: Owner = Owner(1);
+
+    /// Hands out the frames that it's given, last first, and remembers what it gets back
+    #[derive(Default)]
+    struct TestFrames {
+        four_kilobytes: Vec<usize>,
+        two_megabytes: Vec<usize>,
+        returned: Vec<Range<usize>>,
+    }
+
+    impl FrameSource for TestFrames {
+        fn get_4k_frame(&mut self) -> Option<PhysicalAddress> {
+            self.four_kilobytes.pop().map(PhysicalAddress::new)
+        }
+
+        fn get_2mb_frame(&mut self) -> Option<PhysicalAddress> {
+            self.two_megabytes.pop().map(PhysicalAddress::new)
+        }
+
+        fn add_4k_frames(&mut self, frames: Range<usize>) {
+            self.returned.push(frames);
+        }
+    }
+
+    fn service(four_kilobytes: &[usize], two_megabytes: &[usize]) -> FrameService<TestFrames> {
+        FrameService::new(TestFrames {
+            four_kilobytes: four_kilobytes.to_vec(),
+            two_megabytes: two_megabytes.to_vec(),
+            returned: Vec::new(),
+        })
+    }
+
+    fn allocate(
+        service: &mut FrameService<TestFrames>,
+        caller: Owner,
+        count: usize,
+        max_order: u8,
+    ) -> Result<usize, AllocFramesError> {
+        let mut console: Vec<String> = Vec::new();
+        let address = service.allocate(caller, 0, count, max_order, &mut console);
+        assert_eq!(console, Vec::<String>::new());
+        address.map(PhysicalAddress::as_usize)
+    }
+
+    fn alloc_request(caller: u32, request_id: u32, count: usize) -> Message {
+        AllocFramesRequest {
+            caller,
+            request_id,
+            count,
+            max_order: TWO_MEGABYTE_ORDER,
+        }
+        .to_message()
+    }
+
+    fn free_request(caller: u32, address: usize, count: usize) -> Message {
+        FreeFramesRequest {
+            caller,
+            address: PhysicalAddress::new(address),
+            count,
+        }
+        .to_message()
+    }
+
+    #[test]
+    fn a_single_frame_comes_from_the_four_kilobyte_pool() {
+        let mut service = service(&[0x5000], &[TWO_MEGABYTES]);
+        assert_eq!(allocate(&mut service, KERNEL, 1, 0), Ok(0x5000));
+        assert_eq!(service.frames.two_megabytes, [TWO_MEGABYTES]);
+        assert_eq!(service.owners.usage_of(KERNEL), FOUR_KILOBYTES);
+    }
+
+    #[test]
+    fn several_frames_are_carved_out_of_a_two_megabyte_frame() {
+        let mut service = service(&[], &[TWO_MEGABYTES]);
+        assert_eq!(
+            allocate(&mut service, INIT, 3, TWO_MEGABYTE_ORDER),
+            Ok(TWO_MEGABYTES)
+        );
+        let rest_of_the_frame = TWO_MEGABYTES + 0x3000..2 * TWO_MEGABYTES;
+        assert_eq!(service.frames.returned, [rest_of_the_frame]);
+        assert_eq!(service.owners.usage_of(INIT), 0x3000);
+    }
+
+    #[test]
+    fn requests_that_cannot_be_satisfied_are_refused() {
+        let mut service = service(&[0x5000], &[TWO_MEGABYTES]);
+        assert_eq!(
+            allocate(&mut service, KERNEL, 0, TWO_MEGABYTE_ORDER),
+            Err(AllocFramesError::InvalidCount)
+        );
+        assert_eq!(
+            allocate(&mut service, KERNEL, 2, TWO_MEGABYTE_ORDER - 1),
+            Err(AllocFramesError::InvalidCount)
+        );
+        let too_many = FOUR_KILOBYTE_FRAMES_PER_TWO_MEGABYTES + 1;
+        assert_eq!(
+            allocate(&mut service, KERNEL, too_many, TWO_MEGABYTE_ORDER),
+            Err(AllocFramesError::InvalidCount)
+        );
+        assert_eq!(service.frames.four_kilobytes, [0x5000]);
+        assert_eq!(service.frames.two_megabytes, [TWO_MEGABYTES]);
+        assert_eq!(service.owners.iter().count(), 0);
+    }
+
+    #[test]
+    fn frames_handed_out_twice_are_not_given_to_anyone() {
+        let mut service = service(&[0x5000, 0x5000], &[]);
+        assert_eq!(allocate(&mut service, KERNEL, 1, 0), Ok(0x5000));
+        let mut console: Vec<String> = Vec::new();
+        assert_eq!(
+            service.allocate(INIT, 0, 1, 0, &mut console),
+            Err(AllocFramesError::OutOfMemory)
+        );
+        assert_eq!(
+            console,
+            ["The frame allocator handed out 0x5000 but the frames already belong to owner 0"]
+        );
+        assert_eq!(service.owners.usage_of(INIT), 0);
+    }
+
+    #[test]
+    fn freed_frames_go_back_to_the_frame_source() {
+        let mut service = service(&[], &[TWO_MEGABYTES]);
+        let address = allocate(&mut service, INIT, 4, TWO_MEGABYTE_ORDER).unwrap();
+        service.frames.returned.clear();
+        service
+            .free(INIT, PhysicalAddress::new(address), 4)
+            .unwrap();
+        let allocation = address..address + 0x4000;
+        assert_eq!(service.frames.returned, [allocation]);
+        assert_eq!(service.owners.usage_of(INIT), 0);
+    }
+
+    #[test]
+    fn bad_frees_give_nothing_back() {
+        let mut service = service(&[0x5000], &[]);
+        allocate(&mut service, INIT, 1, 0).unwrap();
+        let address = PhysicalAddress::new(0x5000);
+        assert!(matches!(
+            service.free(INIT, address, 2),
+            Err(FrameServiceError::WrongSize {
+                allocated: FOUR_KILOBYTES,
+                freed: 0x2000
+            })
+        ));
+        assert!(matches!(
+            service.free(KERNEL, address, 1),
+            Err(FrameServiceError::Ownership(OwnershipError::WrongOwner(
+                INIT
+            )))
+        ));
+        assert!(matches!(
+            service.free(INIT, PhysicalAddress::new(0x6000), 1),
+            Err(FrameServiceError::Ownership(OwnershipError::NotOwned))
+        ));
+        assert!(service.frames.returned.is_empty());
+        service.free(INIT, address, 1).unwrap();
+        assert!(matches!(
+            service.free(INIT, address, 1),
+            Err(FrameServiceError::Ownership(OwnershipError::DoubleFree))
+        ));
+        let frame = 0x5000..0x6000;
+        assert_eq!(service.frames.returned, [frame]);
+    }
+
+    #[test]
+    fn allocation_requests_are_answered() {
+        let mut service = service(&[0x5000], &[]);
+        let mut console: Vec<String> = Vec::new();
+        let reply = service
+            .handle(&alloc_request(KERNEL_CALLER, 9, 1), &mut console)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            AllocFramesResponse::from_message(&reply),
+            Some(AllocFramesResponse {
+                request_id: 9,
+                result: FfiResult::Ok(PhysicalAddress::new(0x5000)),
+                count: 1,
+            })
+        );
+        // Failures are answered too, so that the caller isn't left waiting
+        let reply = service
+            .handle(&alloc_request(KERNEL_CALLER, 10, 1), &mut console)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            AllocFramesResponse::from_message(&reply),
+            Some(AllocFramesResponse {
+                request_id: 10,
+                result: FfiResult::Err(AllocFramesError::OutOfMemory),
+                count: 1,
+            })
+        );
+        assert_eq!(service.owners.get(0x5000).unwrap().tag, 9);
+    }
+
+    #[test]
+    fn free_requests_are_not_answered() {
+        let mut service = service(&[0x5000], &[]);
+        let mut console: Vec<String> = Vec::new();
+        service
+            .handle(&alloc_request(KERNEL_CALLER, 1, 1), &mut console)
+            .unwrap();
+        let free = free_request(KERNEL_CALLER, 0x5000, 1);
+        assert!(matches!(service.handle(&free, &mut console), Ok(None)));
+        assert!(matches!(
+            service.handle(&free, &mut console),
+            Err(FrameServiceError::Ownership(OwnershipError::DoubleFree))
+        ));
+    }
+
+    #[test]
+    fn other_messages_are_malformed_requests() {
+        let mut service = service(&[0x5000], &[]);
+        let mut console: Vec<String> = Vec::new();
+        for message in [
+            Message::new(MESSAGE_KEY),
+            Message::with_fields(micros_abi::ring::MESSAGE_ALLOC_FRAMES, &[0, 1]),
+        ] {
+            assert!(matches!(
+                service.handle(&message, &mut console),
+                Err(FrameServiceError::MalformedRequest)
+            ));
+        }
+        assert_eq!(service.frames.four_kilobytes, [0x5000]);
+    }
+
+    #[test]
+    fn many_requests_and_frees_keep_the_books_balanced() {
+        let frames: Vec<usize> = (1..=256).map(|index| index * FOUR_KILOBYTES).collect();
+        let mut service = service(&frames, &[]);
+        let mut console: Vec<String> = Vec::new();
+        let mut held = vec![Vec::new(); 4];
+        for request_id in 0..256 {
+            let caller = request_id % 4;
+            let reply = service
+                .handle(&alloc_request(caller, request_id, 1), &mut console)
+                .unwrap()
+                .unwrap();
+            let Some(AllocFramesResponse {
+                result: FfiResult::Ok(address),
+                ..
+            }) = AllocFramesResponse::from_message(&reply)
+            else {
+                panic!("request {request_id} wasn't satisfied");
+            };
+            held[caller as usize].push(address.as_usize());
+            // Every third frame is given straight back
+            if request_id % 3 == 0 {
+                let address = held[caller as usize].pop().unwrap();
+                let free = free_request(caller, address, 1);
+                assert!(matches!(service.handle(&free, &mut console), Ok(None)));
+            }
+        }
+        for (caller, frames) in held.iter().enumerate() {
+            let owner = Owner(u32::try_from(caller).unwrap());
+            assert_eq!(
+                service.owners.usage_of(owner),
+                frames.len() * FOUR_KILOBYTES
+            );
+        }
+        let given_back: usize = service.frames.returned.iter().map(Range::len).sum();
+        let still_held: usize = held.iter().map(Vec::len).sum();
+        assert_eq!(
+            given_back + still_held * FOUR_KILOBYTES,
+            256 * FOUR_KILOBYTES
+        );
+        assert!(console.is_empty());
+    }
+}
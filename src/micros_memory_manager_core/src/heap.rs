@@ -0,0 +1,290 @@
+use core::{alloc::Layout, ops::Range, ptr::null_mut};
+
+/// The size of the chunks of memory that the heap grows by
+pub const CHUNK_SIZE: usize = 0x20_0000;
+
+/// Small allocations are rounded up to one of these sizes so that freed blocks can be reused by any
+/// allocation of the same size class
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Allocations that are too big for a size class are rounded up to a multiple of this
+const LARGE_BLOCK_GRANULARITY: usize = 0x1000;
+
+/// Somewhere the heap can get more memory from
+pub trait ChunkSource {
+    /// Returns the address of `CHUNK_SIZE` bytes of unused memory aligned to `CHUNK_SIZE`
+    fn get_chunk(&mut self) -> Option<usize>;
+}
+
+/// A free block that's too big for a size class
+struct LargeBlock {
+    size: usize,
+    next: *mut LargeBlock,
+}
+
+/**
+ * A heap that hands out memory from chunks that it gets from a `ChunkSource`. Small allocations
+ * come from free lists for each size class, and large ones from a first-fit list of freed blocks.
+ * Anything that can't be satisfied from a free list is carved off the end of the current chunk.
+ */
+pub struct Heap<Source> {
+    source: Source,
+    /// The part of the current chunk that hasn't been handed out
+    unused: Range<usize>,
+    /// The head of the free list for each size class, or null
+    free_lists: [*mut u8; SIZE_CLASSES.len()],
+    large_blocks: *mut LargeBlock,
+}
+
+impl<Source: ChunkSource> Heap<Source> {
+    pub const fn new(source: Source) -> Self {
+        Self {
+            source,
+            unused: 0..0,
+            free_lists: [null_mut(); SIZE_CLASSES.len()],
+            large_blocks: null_mut(),
+        }
+    }
+
+    /// Allocates memory for `layout`. Returns null if there isn't enough memory.
+    // Every block is aligned to at least the smallest size class
+    #[allow(clippy::cast_ptr_alignment)]
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        if let Some(class) = size_class(layout) {
+            let block = self.free_lists[class];
+            if block.is_null() {
+                self.carve(SIZE_CLASSES[class], SIZE_CLASSES[class])
+            } else {
+                self.free_lists[class] = unsafe { block.cast::<*mut u8>().read() };
+                block
+            }
+        } else {
+            let (size, align) = large_block_layout(layout);
+            self.take_large_block(size, align)
+                .unwrap_or_else(|| self.carve(size, align))
+        }
+    }
+
+    /**
+     * Returns the memory at `block` to the heap.
+     *
+     * # Safety
+     *
+     * `block` must have been returned by `allocate` on this heap with the same `layout`, and must
+     * not be used afterwards.
+     */
+    // Every block is aligned to at least the smallest size class
+    #[allow(clippy::cast_ptr_alignment)]
+    pub unsafe fn free(&mut self, block: *mut u8, layout: Layout) {
+        if let Some(class) = size_class(layout) {
+            block.cast::<*mut u8>().write(self.free_lists[class]);
+            self.free_lists[class] = block;
+        } else {
+            let large_block = block.cast::<LargeBlock>();
+            large_block.write(LargeBlock {
+                size: large_block_layout(layout).0,
+                next: self.large_blocks,
+            });
+            self.large_blocks = large_block;
+        }
+    }
+
+    /// Returns true if memory allocated for `old` can be used as is for `new`.
+    #[must_use]
+    pub fn fits_in_place(old: Layout, new: Layout) -> bool {
+        match (size_class(old), size_class(new)) {
+            (Some(old_class), Some(new_class)) => old_class == new_class,
+            (None, None) => large_block_layout(old) == large_block_layout(new),
+            _ => false,
+        }
+    }
+
+    /// Removes the first freed large block of exactly `size` bytes that's aligned to `align` from
+    /// the free list.
+    fn take_large_block(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut link = &raw mut self.large_blocks;
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                // Blocks aren't split, so they must be exactly the right size to be able to free
+                // them with the layout they were allocated with
+                if (*block).size == size && (block as usize).is_multiple_of(align) {
+                    *link = (*block).next;
+                    return Some(block.cast());
+                }
+                link = &raw mut (*block).next;
+            }
+        }
+        None
+    }
+
+    /// Hands out memory from the current chunk, moving on to a new chunk if the current one is
+    /// too small. Whatever is left of the old chunk is abandoned.
+    fn carve(&mut self, size: usize, align: usize) -> *mut u8 {
+        if size > CHUNK_SIZE || align > CHUNK_SIZE {
+            return null_mut();
+        }
+        let start = self.unused.start.next_multiple_of(align);
+        if start
+            .checked_add(size)
+            .is_none_or(|end| end > self.unused.end)
+        {
+            let Some(chunk) = self.source.get_chunk() else {
+                return null_mut();
+            };
+            self.unused = chunk..chunk + CHUNK_SIZE;
+            return self.carve(size, align);
+        }
+        self.unused.start = start + size;
+        start as *mut u8
+    }
+}
+
+/// Returns the index of the smallest size class that can hold `layout`, if there is one.
+fn size_class(layout: Layout) -> Option<usize> {
+    // Blocks in a size class are aligned to their size, so they satisfy any smaller alignment
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| size <= class)
+}
+
+/// The size and alignment of the block used for a large allocation
+fn large_block_layout(layout: Layout) -> (usize, usize) {
+    (
+        layout.size().next_multiple_of(LARGE_BLOCK_GRANULARITY),
+        layout.align().max(LARGE_BLOCK_GRANULARITY),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{
+        alloc::{alloc, dealloc},
+        vec::Vec,
+    };
+
+    /// Hands out up to `limit` chunks of host memory and frees them when it's dropped
+    struct TestChunks {
+        chunks: Vec<*mut u8>,
+        limit: usize,
+    }
+
+    impl TestChunks {
+        fn new(limit: usize) -> Self {
+            Self {
+                chunks: Vec::new(),
+                limit,
+            }
+        }
+
+        fn chunk_layout() -> Layout {
+            Layout::from_size_align(CHUNK_SIZE, CHUNK_SIZE).unwrap()
+        }
+    }
+
+    impl ChunkSource for TestChunks {
+        fn get_chunk(&mut self) -> Option<usize> {
+            if self.chunks.len() == self.limit {
+                return None;
+            }
+            let chunk = unsafe { alloc(Self::chunk_layout()) };
+            assert!(!chunk.is_null());
+            self.chunks.push(chunk);
+            Some(chunk as usize)
+        }
+    }
+
+    impl Drop for TestChunks {
+        fn drop(&mut self) {
+            for &chunk in &self.chunks {
+                unsafe { dealloc(chunk, Self::chunk_layout()) };
+            }
+        }
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn freed_blocks_are_reused_by_the_same_size_class() {
+        let mut heap = Heap::new(TestChunks::new(1));
+        let first = heap.allocate(layout(20, 4));
+        let second = heap.allocate(layout(24, 8));
+        assert!(!first.is_null() && !second.is_null());
+        assert_ne!(first, second);
+        unsafe { heap.free(first, layout(20, 4)) };
+        // 30 bytes is in the same 32 byte class as 20
+        assert_eq!(heap.allocate(layout(30, 1)), first);
+        // A different size class doesn't take the freed block
+        unsafe { heap.free(second, layout(24, 8)) };
+        assert_ne!(heap.allocate(layout(64, 8)), second);
+        assert_eq!(heap.source.chunks.len(), 1);
+    }
+
+    #[test]
+    fn allocations_are_aligned() {
+        let mut heap = Heap::new(TestChunks::new(2));
+        for (size, align) in [(1, 1), (3, 2), (16, 16), (40, 64), (100, 8), (5000, 0x2000)] {
+            let block = heap.allocate(layout(size, align));
+            assert!(!block.is_null());
+            assert!(
+                (block as usize).is_multiple_of(align),
+                "{size} bytes at {block:?} aren't aligned to {align}"
+            );
+        }
+    }
+
+    #[test]
+    fn large_blocks_are_only_reused_at_the_same_size() {
+        let mut heap = Heap::new(TestChunks::new(1));
+        let block = heap.allocate(layout(5000, 8));
+        unsafe { heap.free(block, layout(5000, 8)) };
+        // 12 KB rounds up past the freed 8 KB block
+        let bigger = heap.allocate(layout(12_000, 8));
+        assert_ne!(bigger, block);
+        // 6000 bytes rounds up to the same 8 KB
+        assert_eq!(heap.allocate(layout(6000, 8)), block);
+    }
+
+    #[test]
+    fn heap_grows_by_a_chunk_when_the_current_one_is_full() {
+        let mut heap = Heap::new(TestChunks::new(2));
+        let first = heap.allocate(layout(CHUNK_SIZE - 0x1000, 0x1000));
+        assert!(!first.is_null());
+        let second = heap.allocate(layout(0x2000, 0x1000));
+        assert!(!second.is_null());
+        assert_eq!(heap.source.chunks.len(), 2);
+        assert_eq!(second as usize, heap.source.chunks[1] as usize);
+    }
+
+    #[test]
+    fn allocation_fails_when_the_source_runs_out() {
+        let mut heap = Heap::new(TestChunks::new(1));
+        assert!(!heap.allocate(layout(CHUNK_SIZE, 8)).is_null());
+        assert!(heap.allocate(layout(16, 8)).is_null());
+        assert!(Heap::new(TestChunks::new(0))
+            .allocate(layout(16, 8))
+            .is_null());
+    }
+
+    #[test]
+    fn allocations_bigger_than_a_chunk_fail() {
+        let mut heap = Heap::new(TestChunks::new(4));
+        assert!(heap.allocate(layout(CHUNK_SIZE + 1, 8)).is_null());
+        assert!(heap.allocate(layout(16, 2 * CHUNK_SIZE)).is_null());
+        assert!(heap.source.chunks.is_empty());
+    }
+
+    #[test]
+    fn reallocation_in_place_stays_in_the_block() {
+        type TestHeap = Heap<TestChunks>;
+        assert!(TestHeap::fits_in_place(layout(17, 8), layout(32, 8)));
+        assert!(!TestHeap::fits_in_place(layout(32, 8), layout(33, 8)));
+        assert!(TestHeap::fits_in_place(layout(5000, 8), layout(8000, 8)));
+        assert!(!TestHeap::fits_in_place(layout(5000, 8), layout(9000, 8)));
+        assert!(!TestHeap::fits_in_place(layout(2048, 8), layout(2049, 8)));
+    }
+}
@@ -0,0 +1,24 @@
+#![no_std]
+#![deny(clippy::all)]
+#![deny(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc)]
+
+//! The memory manager's bookkeeping and policy, kept apart from the system calls and page table
+//! writes that it drives
+//!
+//! Everything that touches hardware or the kernel goes through a trait: `Console` for output,
+//! `ChunkSource` and `FrameSource` for physical memory and `PageTableEditor` for mappings. The
+//! memory manager binary implements them with system calls and the frame allocator that the kernel
+//! handed off, so nothing in this crate depends on running inside micros.
+
+extern crate alloc;
+
+pub mod console;
+#[cfg(target_arch = "x86_64")]
+pub mod frame_service;
+pub mod heap;
+pub mod line_editor;
+pub mod memory_report;
+pub mod owners;
+#[cfg(target_arch = "x86_64")]
+pub mod virtual_space;
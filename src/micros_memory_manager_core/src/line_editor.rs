@@ -0,0 +1,63 @@
+use crate::console::Console;
+use alloc::{format, string::String};
+use core::mem;
+use micros_abi::keyboard::KeyEvent;
+
+const BACKSPACE: u8 = 0x08;
+
+/// Collects key presses into a line of text that can be edited until enter is pressed
+pub struct LineEditor {
+    line: String,
+    /// The number of dropped key presses that have already been reported
+    reported_dropped: u64,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            reported_dropped: 0,
+        }
+    }
+
+    /// Edits the line with `character`. Returns the finished line when `character` is enter.
+    pub fn edit(&mut self, character: u8) -> Option<String> {
+        match character {
+            b'\n' => return Some(mem::take(&mut self.line)),
+            BACKSPACE => {
+                self.line.pop();
+            }
+            b' '..=b'~' => self.line.push(char::from(character)),
+            _ => {}
+        }
+        None
+    }
+
+    /// The line being edited
+    #[must_use]
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Applies a key press that the kernel sent. Finished lines and key presses that the kernel
+    /// dropped are written to `console`.
+    pub fn handle_key(&mut self, event: &KeyEvent, console: &mut impl Console) {
+        if event.dropped > self.reported_dropped {
+            console.write_line(&format!(
+                "Dropped {} key presses",
+                event.dropped - self.reported_dropped
+            ));
+            self.reported_dropped = event.dropped;
+        }
+        if let Some(line) = self.edit(event.character) {
+            console.write_line(&format!("you typed: {line}"));
+        }
+    }
+}
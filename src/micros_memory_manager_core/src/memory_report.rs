@@ -0,0 +1,115 @@
+use crate::console::Console;
+use alloc::{format, vec::Vec};
+use core::ops::Range;
+use multiboot2::{MemoryMapTag, ACPI_MEMORY, AVAILABLE_MEMORY, DEFECTIVE_MEMORY};
+
+/// A region from the firmware's memory map
+pub struct MemoryRegion {
+    pub range: Range<usize>,
+    pub region_type: u32,
+}
+
+/// Lists the regions in `memory_map`, skipping any that start outside of the address space and
+/// clamping any that end outside of it
+#[must_use]
+pub fn memory_regions(memory_map: MemoryMapTag) -> Vec<MemoryRegion> {
+    memory_map
+        .entries
+        .iter()
+        .filter_map(|area| {
+            Some(MemoryRegion {
+                range: area.address_range()?,
+                region_type: area.region_type,
+            })
+        })
+        .collect()
+}
+
+/// Writes a line for each region followed by the totals to `console`.
+pub fn print_memory_report(regions: &[MemoryRegion], console: &mut impl Console) {
+    let mut total = 0;
+    let mut available = 0;
+    for region in regions {
+        let size = region.range.len();
+        total += size;
+        if region.region_type == AVAILABLE_MEMORY {
+            available += size;
+        }
+        console.write_line(&format!(
+            "{:#014x}..{:#014x} {:>10} KiB {}",
+            region.range.start,
+            region.range.end,
+            size / 1024,
+            region_type_name(region.region_type)
+        ));
+    }
+    console.write_line(&format!(
+        "Total: {} KiB, available: {} KiB, reserved: {} KiB",
+        total / 1024,
+        available / 1024,
+        (total - available) / 1024
+    ));
+}
+
+fn region_type_name(region_type: u32) -> &'static str {
+    match region_type {
+        AVAILABLE_MEMORY => "available",
+        ACPI_MEMORY => "ACPI",
+        DEFECTIVE_MEMORY => "defective",
+        _ => "reserved",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::String, vec};
+    /// Any type that the memory manager doesn't know is reserved
+    const RESERVED_MEMORY: u32 = 2;
+
+    #[test]
+    fn each_region_is_listed_before_the_totals() {
+        let regions = vec![
+            MemoryRegion {
+                range: 0..0x9_f000,
+                region_type: AVAILABLE_MEMORY,
+            },
+            MemoryRegion {
+                range: 0xf_0000..0x10_0000,
+                region_type: RESERVED_MEMORY,
+            },
+            MemoryRegion {
+                range: 0x10_0000..0x800_0000,
+                region_type: AVAILABLE_MEMORY,
+            },
+            MemoryRegion {
+                range: 0x800_0000..0x801_0000,
+                region_type: ACPI_MEMORY,
+            },
+            MemoryRegion {
+                range: 0x801_0000..0x801_1000,
+                region_type: DEFECTIVE_MEMORY,
+            },
+        ];
+        let mut lines: Vec<String> = Vec::new();
+        print_memory_report(&regions, &mut lines);
+        assert_eq!(
+            lines,
+            [
+                "0x000000000000..0x00000009f000        636 KiB available",
+                "0x0000000f0000..0x000000100000         64 KiB reserved",
+                "0x000000100000..0x000008000000     130048 KiB available",
+                "0x000008000000..0x000008010000         64 KiB ACPI",
+                "0x000008010000..0x000008011000          4 KiB defective",
+                "Total: 130816 KiB, available: 130684 KiB, reserved: 132 KiB",
+            ]
+        );
+    }
+
+    #[test]
+    fn an_empty_memory_map_adds_up_to_nothing() {
+        let mut lines: Vec<String> = Vec::new();
+        print_memory_report(&[], &mut lines);
+        assert_eq!(lines, ["Total: 0 KiB, available: 0 KiB, reserved: 0 KiB"]);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::console::Console;
+use alloc::{format, vec, vec::Vec};
+use core::fmt;
+
+/// The smallest number of slots that the table allocates. Always a power of two.
+const MIN_CAPACITY: usize = 64;
+
+/// The table is rebuilt once more than this percentage of its slots are owned or freed
+const MAX_LOAD_PERCENT: usize = 70;
+
+/// Frames are at least 4 KB aligned, so the low bits of their addresses don't help the hash
+const FRAME_ADDRESS_SHIFT: u32 = 12;
+
+/// 2^64 divided by the golden ratio, which spreads out keys that only differ in their high bits
+const FIBONACCI_MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Something that can own frames. The kernel supplies these, so they can be trusted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Owner(pub u32);
+
+/// Who owns an allocation and how big it is
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ownership {
+    pub owner: Owner,
+    /// A label chosen by the owner when it made the allocation
+    pub tag: u32,
+    /// The size of the allocation in bytes
+    pub size: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OwnershipError {
+    /// The address already belongs to an allocation
+    AlreadyOwned(Ownership),
+    /// The address was freed and hasn't been allocated again since
+    DoubleFree,
+    /// The address doesn't belong to any allocation that the table knows about
+    NotOwned,
+    /// The address belongs to someone else
+    WrongOwner(Owner),
+}
+
+impl fmt::Display for OwnershipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AlreadyOwned(ownership) => {
+                write!(
+                    f,
+                    "the frames already belong to owner {}",
+                    ownership.owner.0
+                )
+            }
+            Self::DoubleFree => f.write_str("the frames were already freed"),
+            Self::NotOwned => f.write_str("the frames don't belong to anyone"),
+            Self::WrongOwner(owner) => write!(f, "the frames belong to owner {}", owner.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Empty,
+    /// A tombstone that remembers which address was freed so that double frees can be told apart
+    /// from frees of frames that were never allocated
+    Freed(usize),
+    Owned(usize, Ownership),
+}
+
+/**
+ * A hash table from the base address of each allocation to who owns it. Collisions are resolved
+ * with linear probing. Freed entries become tombstones until the table is next rebuilt, so a
+ * double free is only reported as such while its tombstone survives.
+ */
+pub struct OwnershipTable {
+    /// Always empty or a power of two long
+    slots: Vec<Slot>,
+    owned: usize,
+    /// The number of slots that aren't empty, including tombstones
+    used: usize,
+}
+
+impl Default for OwnershipTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OwnershipTable {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            owned: 0,
+            used: 0,
+        }
+    }
+
+    /// Records that the allocation at `address` belongs to `ownership.owner`.
+    #[must_use = "the allocation isn't recorded if it's already owned"]
+    pub fn insert(&mut self, address: usize, ownership: Ownership) -> Result<(), OwnershipError> {
+        if let Some(Slot::Owned(_, existing)) = self.find(address).map(|index| self.slots[index]) {
+            return Err(OwnershipError::AlreadyOwned(existing));
+        }
+        if (self.used + 1) * 100 > self.slots.len() * MAX_LOAD_PERCENT {
+            self.rebuild();
+        }
+        let mut index = self.home(address);
+        loop {
+            match self.slots[index] {
+                Slot::Empty => {
+                    self.used += 1;
+                    break;
+                }
+                Slot::Freed(_) => break,
+                Slot::Owned(..) => index = (index + 1) & (self.slots.len() - 1),
+            }
+        }
+        self.slots[index] = Slot::Owned(address, ownership);
+        self.owned += 1;
+        Ok(())
+    }
+
+    /// Returns who owns the allocation at `address`, if anyone does.
+    #[must_use]
+    pub fn get(&self, address: usize) -> Option<Ownership> {
+        match self.slots[self.find(address)?] {
+            Slot::Owned(_, ownership) => Some(ownership),
+            _ => None,
+        }
+    }
+
+    /// Forgets the allocation at `address` if it belongs to `owner`. The table isn't changed if
+    /// it doesn't.
+    pub fn remove(&mut self, address: usize, owner: Owner) -> Result<Ownership, OwnershipError> {
+        let index = self.find(address).ok_or(OwnershipError::NotOwned)?;
+        match self.slots[index] {
+            Slot::Owned(_, ownership) if ownership.owner == owner => {
+                self.slots[index] = Slot::Freed(address);
+                self.owned -= 1;
+                Ok(ownership)
+            }
+            Slot::Owned(_, ownership) => Err(OwnershipError::WrongOwner(ownership.owner)),
+            _ => Err(OwnershipError::DoubleFree),
+        }
+    }
+
+    /// The total size in bytes of the allocations that belong to `owner`
+    #[must_use]
+    pub fn usage_of(&self, owner: Owner) -> usize {
+        self.iter()
+            .filter(|(_, ownership)| ownership.owner == owner)
+            .map(|(_, ownership)| ownership.size)
+            .sum()
+    }
+
+    /// Lists the base address and ownership of every allocation in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Ownership)> + '_ {
+        self.slots.iter().filter_map(|slot| match *slot {
+            Slot::Owned(address, ownership) => Some((address, ownership)),
+            _ => None,
+        })
+    }
+
+    /// Returns the index of the owned or freed slot for `address`.
+    fn find(&self, address: usize) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut index = self.home(address);
+        loop {
+            match self.slots[index] {
+                Slot::Empty => return None,
+                Slot::Freed(freed) if freed == address => return Some(index),
+                Slot::Owned(owned, _) if owned == address => return Some(index),
+                _ => index = (index + 1) & (self.slots.len() - 1),
+            }
+        }
+    }
+
+    /// The slot where probing for `address` starts
+    fn home(&self, address: usize) -> usize {
+        let hash = ((address >> FRAME_ADDRESS_SHIFT) as u64).wrapping_mul(FIBONACCI_MULTIPLIER);
+        // The top bits of the hash index the slots, so they always fit. Probing can start anywhere.
+        usize::try_from(hash >> (u64::BITS - self.slots.len().trailing_zeros())).unwrap_or(0)
+    }
+
+    /// Moves every allocation into a new set of slots that's at most half full, dropping the
+    /// tombstones.
+    fn rebuild(&mut self) {
+        let capacity = ((self.owned + 1) * 2).next_power_of_two().max(MIN_CAPACITY);
+        let old_slots = core::mem::replace(&mut self.slots, vec![Slot::Empty; capacity]);
+        self.owned = 0;
+        self.used = 0;
+        for slot in old_slots {
+            if let Slot::Owned(address, ownership) = slot {
+                let mut index = self.home(address);
+                while !matches!(self.slots[index], Slot::Empty) {
+                    index = (index + 1) & (capacity - 1);
+                }
+                self.slots[index] = Slot::Owned(address, ownership);
+                self.owned += 1;
+                self.used += 1;
+            }
+        }
+    }
+}
+
+/// Writes how many allocations each owner has and how much memory they add up to to `console`.
+pub fn dump_owners(table: &OwnershipTable, console: &mut impl Console) {
+    let mut owners: Vec<(Owner, usize)> = Vec::new();
+    for (_, ownership) in table.iter() {
+        match owners
+            .iter_mut()
+            .find(|(owner, _)| *owner == ownership.owner)
+        {
+            Some((_, allocations)) => *allocations += 1,
+            None => owners.push((ownership.owner, 1)),
+        }
+    }
+    owners.sort_unstable_by_key(|(owner, _)| owner.0);
+    console.write_line(&format!("{} owners:", owners.len()));
+    for (owner, allocations) in owners {
+        console.write_line(&format!(
+            "  Owner {}: {allocations} allocations, {} KiB",
+            owner.0,
+            table.usage_of(owner) / 1024
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    const KERNEL: Owner = Owner(0);
+    const INIT: Owner = Owner(1);
+
+    fn ownership(owner: Owner, size: usize) -> Ownership {
+        Ownership {
+            owner,
+            tag: 0,
+            size,
+        }
+    }
+
+    /// Checks that every address in `addresses` belongs to `owner`
+    fn assert_owned(table: &OwnershipTable, addresses: impl Iterator<Item = usize>, owner: Owner) {
+        for address in addresses {
+            assert_eq!(
+                table.get(address).map(|ownership| ownership.owner),
+                Some(owner),
+                "{address:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn an_empty_table_owns_nothing() {
+        let mut table = OwnershipTable::new();
+        assert_eq!(table.get(0x1000), None);
+        assert_eq!(table.usage_of(KERNEL), 0);
+        assert_eq!(table.remove(0x1000, KERNEL), Err(OwnershipError::NotOwned));
+    }
+
+    #[test]
+    fn allocations_are_recorded_until_they_are_freed() {
+        let mut table = OwnershipTable::new();
+        let allocation = Ownership {
+            owner: INIT,
+            tag: 7,
+            size: 0x2000,
+        };
+        table.insert(0x10_0000, allocation).unwrap();
+        assert_eq!(table.get(0x10_0000), Some(allocation));
+        assert_eq!(table.get(0x10_1000), None);
+        assert_eq!(table.remove(0x10_0000, INIT), Ok(allocation));
+        assert_eq!(table.get(0x10_0000), None);
+        // The address can be allocated again once it's been freed
+        table.insert(0x10_0000, allocation).unwrap();
+        assert_eq!(table.get(0x10_0000), Some(allocation));
+    }
+
+    #[test]
+    fn an_owned_address_cannot_be_taken() {
+        let mut table = OwnershipTable::new();
+        table.insert(0x1000, ownership(KERNEL, 0x1000)).unwrap();
+        assert_eq!(
+            table.insert(0x1000, ownership(INIT, 0x1000)),
+            Err(OwnershipError::AlreadyOwned(ownership(KERNEL, 0x1000)))
+        );
+        assert_eq!(table.get(0x1000), Some(ownership(KERNEL, 0x1000)));
+    }
+
+    #[test]
+    fn bad_frees_are_detected_and_change_nothing() {
+        let mut table = OwnershipTable::new();
+        table.insert(0x1000, ownership(KERNEL, 0x1000)).unwrap();
+        table.insert(0x2000, ownership(INIT, 0x1000)).unwrap();
+        assert_eq!(
+            table.remove(0x2000, KERNEL),
+            Err(OwnershipError::WrongOwner(INIT))
+        );
+        assert_eq!(table.remove(0x3000, KERNEL), Err(OwnershipError::NotOwned));
+        table.remove(0x1000, KERNEL).unwrap();
+        assert_eq!(
+            table.remove(0x1000, KERNEL),
+            Err(OwnershipError::DoubleFree)
+        );
+        assert_eq!(table.get(0x2000), Some(ownership(INIT, 0x1000)));
+        assert_eq!(table.usage_of(INIT), 0x1000);
+        assert_eq!(table.usage_of(KERNEL), 0);
+    }
+
+    #[test]
+    fn usage_adds_up_each_owners_allocations() {
+        let mut table = OwnershipTable::new();
+        table.insert(0x1000, ownership(KERNEL, 0x1000)).unwrap();
+        table
+            .insert(0x20_0000, ownership(KERNEL, 0x20_0000))
+            .unwrap();
+        table.insert(0x40_0000, ownership(INIT, 0x4000)).unwrap();
+        assert_eq!(table.usage_of(KERNEL), 0x20_1000);
+        assert_eq!(table.usage_of(INIT), 0x4000);
+        assert_eq!(table.usage_of(Owner(2)), 0);
+        assert_eq!(table.iter().count(), 3);
+    }
+
+    #[test]
+    fn addresses_that_only_differ_in_their_high_bits_are_kept_apart() {
+        let mut table = OwnershipTable::new();
+        let addresses = || (1..=200).map(|index| index << 40);
+        for address in addresses() {
+            table.insert(address, ownership(KERNEL, 0x1000)).unwrap();
+        }
+        assert_owned(&table, addresses(), KERNEL);
+        assert_eq!(table.usage_of(KERNEL), 200 * 0x1000);
+    }
+
+    #[test]
+    fn addresses_on_large_strides_are_kept_apart() {
+        let mut table = OwnershipTable::new();
+        // Gigabyte, 2 MB, and 4 KB strides, which overlap each other, on top of each other
+        let addresses = || {
+            (0..100)
+                .map(|index| index << 30)
+                .chain((1..100).map(|index| index << 21))
+                .chain((1..100).map(|index| index << 12))
+        };
+        for address in addresses() {
+            table.insert(address, ownership(INIT, 0x1000)).unwrap();
+        }
+        assert_owned(&table, addresses(), INIT);
+        assert_eq!(table.iter().count(), 298);
+    }
+
+    #[test]
+    fn tombstones_do_not_hide_later_allocations() {
+        let mut table = OwnershipTable::new();
+        // Enough churn to fill the table with tombstones and rebuild it several times
+        for round in 0..20 {
+            for index in 0..50 {
+                let address = (round * 50 + index) << 12;
+                table.insert(address, ownership(KERNEL, 0x1000)).unwrap();
+            }
+            for index in (0..50).step_by(2) {
+                let address = (round * 50 + index) << 12;
+                table.remove(address, KERNEL).unwrap();
+            }
+        }
+        assert_eq!(table.iter().count(), 20 * 25);
+        assert_owned(
+            &table,
+            (0..20 * 50).skip(1).step_by(2).map(|index| index << 12),
+            KERNEL,
+        );
+        for index in (0..20 * 50).step_by(2) {
+            assert_eq!(table.get(index << 12), None);
+        }
+    }
+
+    #[test]
+    fn owners_are_dumped_in_order() {
+        let mut table = OwnershipTable::new();
+        table.insert(0x1000, ownership(INIT, 0x1000)).unwrap();
+        table.insert(0x2000, ownership(INIT, 0x1000)).unwrap();
+        table
+            .insert(0x20_0000, ownership(KERNEL, 0x20_0000))
+            .unwrap();
+        let mut lines: Vec<String> = Vec::new();
+        dump_owners(&table, &mut lines);
+        assert_eq!(
+            lines,
+            [
+                "2 owners:",
+                "  Owner 0: 1 allocations, 2048 KiB",
+                "  Owner 1: 2 allocations, 8 KiB",
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+use frame_allocation::{amd64::FOUR_KILOBYTES, PhysicalAddress, VirtualAddress};
+
+#[derive(Debug)]
+pub enum MapError {
+    /// There weren't any frames left for a page table
+    OutOfMemory,
+    /// Something is already mapped at the address
+    AlreadyMapped,
+}
+
+/// Something that can change what virtual addresses translate to
+pub trait PageTableEditor {
+    /**
+     * Maps the 4 KB page at `virt` to `frame` with the page table entry flags in `flags`.
+     *
+     * # Safety
+     *
+     * The frame must not be in use by anything else.
+     */
+    #[must_use = "the page might not have been mapped"]
+    unsafe fn map_4k_page(
+        &mut self,
+        virt: VirtualAddress,
+        frame: PhysicalAddress,
+        flags: u64,
+    ) -> Result<(), MapError>;
+}
+
+/**
+ * A virtual address space. Keeps track of which regions have been handed out and has a
+ * `PageTableEditor` back them with frames.
+ */
+pub struct VirtualSpace<Editor> {
+    editor: Editor,
+    bounds: Range<usize>,
+    /// The regions that have been handed out, sorted by address and never overlapping
+    allocated: Vec<Range<usize>>,
+}
+
+impl<Editor: PageTableEditor> VirtualSpace<Editor> {
+    /// Hands out regions from `bounds`, mapping them with `editor`
+    pub const fn new(editor: Editor, bounds: Range<usize>) -> Self {
+        Self {
+            editor,
+            bounds,
+            allocated: Vec::new(),
+        }
+    }
+
+    /**
+     * Reserves `size` bytes of the address space starting at a multiple of `align`. Returns `None`
+     * if there's no gap big enough. `align` must be a power of two.
+     */
+    pub fn allocate_region(&mut self, size: usize, align: usize) -> Option<VirtualAddress> {
+        let size = size.max(1).checked_next_multiple_of(FOUR_KILOBYTES)?;
+        let align = align.max(FOUR_KILOBYTES);
+        let mut gap_start = self.bounds.start;
+        for (index, region) in self.allocated.iter().enumerate() {
+            if let Some(start) = fit(gap_start..region.start, size, align) {
+                self.allocated.insert(index, start..start + size);
+                return Some(VirtualAddress::new(start));
+            }
+            gap_start = region.end;
+        }
+        let start = fit(gap_start..self.bounds.end, size, align)?;
+        self.allocated.push(start..start + size);
+        Some(VirtualAddress::new(start))
+    }
+
+    /**
+     * Maps the 4 KB page at `virt` to `frame` with the page table entry flags in `flags`.
+     *
+     * # Safety
+     *
+     * The frame must not be in use by anything else.
+     */
+    #[must_use = "the page might not have been mapped"]
+    pub unsafe fn map(
+        &mut self,
+        virt: VirtualAddress,
+        frame: PhysicalAddress,
+        flags: u64,
+    ) -> Result<(), MapError> {
+        self.editor.map_4k_page(virt, frame, flags)
+    }
+}
+
+/// Returns the first multiple of `align` in `gap` that's followed by at least `size` bytes of the
+/// gap.
+fn fit(gap: Range<usize>, size: usize, align: usize) -> Option<usize> {
+    let start = gap.start.checked_next_multiple_of(align)?;
+    (start.checked_add(size)? <= gap.end).then_some(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const TWO_MEGABYTES: usize = 0x20_0000;
+    const BOUNDS: Range<usize> = 0x80_0000_0000..0x80_0100_0000;
+
+    /// Records the pages that it's asked to map and refuses to map a page twice
+    #[derive(Default)]
+    struct TestEditor {
+        mapped: Vec<(usize, usize, u64)>,
+    }
+
+    impl PageTableEditor for TestEditor {
+        unsafe fn map_4k_page(
+            &mut self,
+            virt: VirtualAddress,
+            frame: PhysicalAddress,
+            flags: u64,
+        ) -> Result<(), MapError> {
+            if self
+                .mapped
+                .iter()
+                .any(|&(page, _, _)| page == virt.as_usize())
+            {
+                return Err(MapError::AlreadyMapped);
+            }
+            self.mapped.push((virt.as_usize(), frame.as_usize(), flags));
+            Ok(())
+        }
+    }
+
+    fn space() -> VirtualSpace<TestEditor> {
+        VirtualSpace::new(TestEditor::default(), BOUNDS)
+    }
+
+    fn allocate(space: &mut VirtualSpace<TestEditor>, size: usize, align: usize) -> Option<usize> {
+        space
+            .allocate_region(size, align)
+            .map(VirtualAddress::as_usize)
+    }
+
+    #[test]
+    fn regions_are_rounded_to_whole_pages() {
+        let mut space = space();
+        assert_eq!(allocate(&mut space, 1, 1), Some(BOUNDS.start));
+        assert_eq!(allocate(&mut space, 0, 1), Some(BOUNDS.start + 0x1000));
+        assert_eq!(allocate(&mut space, 0x1001, 8), Some(BOUNDS.start + 0x2000));
+        assert_eq!(
+            space.allocated,
+            [
+                BOUNDS.start..BOUNDS.start + 0x1000,
+                BOUNDS.start + 0x1000..BOUNDS.start + 0x2000,
+                BOUNDS.start + 0x2000..BOUNDS.start + 0x4000,
+            ]
+        );
+    }
+
+    #[test]
+    fn gaps_left_by_alignment_are_filled_later() {
+        let mut space = space();
+        assert_eq!(allocate(&mut space, 0x1000, 1), Some(BOUNDS.start));
+        let aligned = BOUNDS.start + TWO_MEGABYTES;
+        assert_eq!(
+            allocate(&mut space, TWO_MEGABYTES, TWO_MEGABYTES),
+            Some(aligned)
+        );
+        // The gap before the aligned region is used before anything after it
+        assert_eq!(allocate(&mut space, 0x3000, 1), Some(BOUNDS.start + 0x1000));
+        assert_eq!(
+            allocate(&mut space, TWO_MEGABYTES - 0x4000, 1),
+            Some(BOUNDS.start + 0x4000)
+        );
+        assert_eq!(
+            allocate(&mut space, 0x1000, 1),
+            Some(aligned + TWO_MEGABYTES)
+        );
+        let regions = &space.allocated;
+        assert!(regions.windows(2).all(|pair| pair[0].end <= pair[1].start));
+    }
+
+    #[test]
+    fn allocation_fails_when_no_gap_is_big_enough() {
+        let mut space = space();
+        let size = BOUNDS.end - BOUNDS.start;
+        assert_eq!(allocate(&mut space, size + 1, 1), None);
+        assert_eq!(allocate(&mut space, usize::MAX, 1), None);
+        // The first address with this alignment after the start of the bounds is past their end
+        assert_eq!(allocate(&mut space, 0x1000, 2 * BOUNDS.start), None);
+        assert_eq!(allocate(&mut space, size, 1), Some(BOUNDS.start));
+        assert_eq!(allocate(&mut space, 1, 1), None);
+        assert_eq!(space.allocated, vec![BOUNDS]);
+    }
+
+    #[test]
+    fn mappings_go_to_the_editor() {
+        let mut space = space();
+        let page = space.allocate_region(0x1000, 1).unwrap();
+        let frame = PhysicalAddress::new(0x1234_5000);
+        unsafe {
+            space.map(page, frame, 0b11).unwrap();
+            assert!(matches!(
+                space.map(page, frame, 0b11),
+                Err(MapError::AlreadyMapped)
+            ));
+        }
+        assert_eq!(space.editor.mapped, [(BOUNDS.start, 0x1234_5000, 0b11)]);
+    }
+}
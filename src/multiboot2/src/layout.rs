@@ -0,0 +1,67 @@
+//! The layouts of the boot information structures, from the multiboot2 specification. The
+//! bootloader writes these, so they have to match it exactly.
+
+use crate::{
+    BasicMemoryInfoHeader, BootDeviceHeader, BootInfoTagHeader, BootInformationHeader,
+    BootModuleHeader, Efi64PointerHeader, FramebufferTagHeader, MemoryMapEntry, MemoryMapHeader,
+};
+use layout_assert::layout_assert;
+
+layout_assert!(BootInformationHeader, size = 8, align = 8, {
+    total_size: 0,
+    reserved: 4,
+});
+
+layout_assert!(BootInfoTagHeader, size = 8, align = 8, {
+    tag_type: 0,
+    size: 4,
+});
+
+layout_assert!(MemoryMapHeader, size = 16, {
+    tag_header: 0,
+    entry_size: 8,
+    entry_version: 12,
+});
+
+layout_assert!(MemoryMapEntry, size = 24, {
+    base_addr: 0,
+    length: 8,
+    region_type: 16,
+    reserved: 20,
+});
+
+layout_assert!(BasicMemoryInfoHeader, size = 16, {
+    tag_header: 0,
+    mem_lower: 8,
+    mem_upper: 12,
+});
+
+// The tag is 20 bytes, but the header that starts it is aligned to 8
+layout_assert!(BootDeviceHeader, size = 24, {
+    tag_header: 0,
+    biosdev: 8,
+    partition: 12,
+    sub_partition: 16,
+});
+
+layout_assert!(Efi64PointerHeader, size = 16, {
+    tag_header: 0,
+    pointer: 8,
+});
+
+layout_assert!(BootModuleHeader, size = 16, {
+    tag_header: 0,
+    mod_start: 8,
+    mod_end: 12,
+});
+
+layout_assert!(FramebufferTagHeader, size = 32, {
+    header: 0,
+    framebuffer: 8,
+    pitch: 16,
+    width: 20,
+    height: 24,
+    bits_per_pixel: 28,
+    framebuffer_type: 29,
+    reserved: 30,
+});
@@ -2,9 +2,12 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_safety_doc)]
 
+mod layout;
+
 use core::{
-    mem::{align_of, size_of},
+    mem::{align_of, offset_of, size_of},
     ops::Range,
+    ptr::addr_of,
     slice, str,
 };
 
@@ -12,6 +15,32 @@ use core::{
 pub const AVAILABLE_MEMORY: u32 = 1;
 /// The value of the `region_type` field for `MemoryMapEntry`'s that represent ACPI memory.
 pub const ACPI_MEMORY: u32 = 3;
+/// The value of the `region_type` field for `MemoryMapEntry`'s that represent defective memory.
+pub const DEFECTIVE_MEMORY: u32 = 5;
+/// The partition number in the boot device tag when there's no partition at that level
+const UNUSED_PARTITION: u32 = 0xffff_ffff;
+
+/// Where the fields of the ELF sections tag are. The section headers come right after them, so
+/// they aren't necessarily aligned.
+const ELF_SECTIONS_NUM_OFFSET: usize = 8;
+const ELF_SECTIONS_ENTRY_SIZE_OFFSET: usize = 12;
+const ELF_SECTIONS_SHNDX_OFFSET: usize = 16;
+const ELF_SECTIONS_HEADERS_OFFSET: usize = 20;
+const ELF32_SECTION_HEADER_SIZE: usize = 40;
+const ELF64_SECTION_HEADER_SIZE: usize = 64;
+const RSDP_SIGNATURE: &[u8] = b"RSD PTR ";
+/// The size of the ACPI 1.0 RSDP, which is all that its checksum covers
+const RSDP_V1_SIZE: usize = 20;
+/// The size of the ACPI 2.0 RSDP. Later versions may have a larger `length`.
+const RSDP_V2_SIZE: usize = 36;
+const RSDP_OEM_ID: Range<usize> = 9..15;
+const RSDP_REVISION_OFFSET: usize = 15;
+const RSDP_RSDT_ADDRESS_OFFSET: usize = 16;
+const RSDP_LENGTH_OFFSET: usize = 20;
+const RSDP_XSDT_ADDRESS_OFFSET: usize = 24;
+/// The section types of symbol tables and string tables
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
 
 /// A type that can represent a tag from the multiboot2 boot information structure.
 pub trait MutibootTag<'a>: TryFrom<&'a [u8]> {
@@ -38,6 +67,43 @@ pub struct MemoryMapEntry {
     reserved: u32,
 }
 
+impl MemoryMapEntry {
+    #[must_use]
+    pub const fn new(base_addr: u64, length: u64, region_type: u32) -> Self {
+        Self {
+            base_addr,
+            length,
+            region_type,
+            reserved: 0,
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.base_addr.saturating_add(self.length)
+    }
+
+    /**
+     * Returns the addresses covered by the region, or `None` if it starts beyond the end of the
+     * address space. The end is clamped to the top of the address space, which only loses memory
+     * on 32 bit targets with more physical memory than they can address or when bogus firmware
+     * reports a region that wraps around.
+     */
+    #[must_use]
+    pub fn address_range(&self) -> Option<Range<usize>> {
+        let start = phys_to_usize(self.base_addr)?;
+        Some(start..phys_to_usize(self.end()).unwrap_or(usize::MAX))
+    }
+
+    /// Returns true if `address_range` leaves off part of the region
+    #[must_use]
+    pub fn exceeds_address_space(&self) -> bool {
+        self.base_addr
+            .checked_add(self.length)
+            .and_then(phys_to_usize)
+            .is_none()
+    }
+}
+
 /// A multiboot2 tag containing a map of the device's memory
 #[derive(Clone, Copy)]
 pub struct MemoryMapTag<'a> {
@@ -82,6 +148,94 @@ impl<'a> MutibootTag<'a> for MemoryMapTag<'a> {
     const TAG_TYPE: u32 = 6;
 }
 
+/**
+ * Storage for a cleaned up copy of a memory map with room for `N` entries.
+ *
+ * Firmware memory maps can be unsorted and can contain overlapping or empty entries. If an
+ * available entry overlaps a reserved one then treating the entries independently would hand out
+ * reserved memory, so the sanitized map gives each overlapping part the most restrictive type of
+ * the entries that cover it.
+ */
+pub struct SanitizedMemoryMap<const N: usize> {
+    entries: [MemoryMapEntry; N],
+}
+
+impl<const N: usize> SanitizedMemoryMap<N> {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            entries: [const { MemoryMapEntry::new(0, 0, 0) }; N],
+        }
+    }
+
+    /**
+     * Fills this map with the sanitized version of `memory_map` and returns it. The returned
+     * entries are sorted, don't overlap, aren't empty, and adjacent entries of the same type are
+     * merged. Returns `None` if the result has more than `N` entries.
+     *
+     * This doesn't allocate and takes time proportional to the square of the number of entries,
+     * which is fine for the few dozen entries that firmware reports.
+     */
+    pub fn sanitize(&mut self, memory_map: MemoryMapTag) -> Option<MemoryMapTag<'_>> {
+        let input = memory_map.entries;
+        let mut len: usize = 0;
+        let mut current = input
+            .iter()
+            .filter(|entry| entry.length != 0)
+            .map(|entry| entry.base_addr)
+            .min();
+        while let Some(start) = current {
+            // The next place where an entry starts or ends
+            let next = input
+                .iter()
+                .flat_map(|entry| [entry.base_addr, entry.end()])
+                .filter(|&boundary| boundary > start)
+                .min();
+            let Some(end) = next else {
+                break;
+            };
+            let region_type = input
+                .iter()
+                .filter(|entry| entry.base_addr <= start && start < entry.end())
+                .map(|entry| entry.region_type)
+                .max_by_key(|&region_type| (restrictiveness(region_type), region_type));
+            if let Some(region_type) = region_type {
+                match len.checked_sub(1).map(|last| &mut self.entries[last]) {
+                    Some(last) if last.end() == start && last.region_type == region_type => {
+                        last.length += end - start;
+                    }
+                    _ => {
+                        *self.entries.get_mut(len)? =
+                            MemoryMapEntry::new(start, end - start, region_type);
+                        len += 1;
+                    }
+                }
+            }
+            current = next;
+        }
+        Some(MemoryMapTag {
+            entries: &self.entries[..len],
+        })
+    }
+}
+
+impl<const N: usize> Default for SanitizedMemoryMap<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ranks memory types so that memory is only considered available if nothing else claims it.
+/// Unknown types are treated like reserved memory.
+fn restrictiveness(region_type: u32) -> u8 {
+    match region_type {
+        AVAILABLE_MEMORY => 0,
+        ACPI_MEMORY => 1,
+        DEFECTIVE_MEMORY => 3,
+        _ => 2,
+    }
+}
+
 /// A multiboot2 info tag describing a boot module
 pub struct BootModuleTag<'a> {
     /// The address of the start of the boot module
@@ -123,6 +277,448 @@ impl<'a> MutibootTag<'a> for BootModuleTag<'a> {
     const TAG_TYPE: u32 = 3;
 }
 
+/// A multiboot2 info tag containing the command line that the operating system was booted with
+pub struct CommandLineTag<'a> {
+    pub string: &'a str,
+}
+
+impl<'a> TryFrom<&'a [u8]> for CommandLineTag<'a> {
+    type Error = ();
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let (_, string) = value
+            .split_first_chunk::<{ size_of::<BootInfoTagHeader>() }>()
+            .ok_or(())?;
+        Ok(Self {
+            string: str::from_utf8(string)
+                .map_err(|_| ())?
+                .split('\0')
+                .next()
+                .ok_or(())?,
+        })
+    }
+}
+
+impl<'a> MutibootTag<'a> for CommandLineTag<'a> {
+    const TAG_TYPE: u32 = 1;
+}
+
+/// A multiboot2 info tag containing the amount of lower and upper memory. Bootloaders that can't
+/// provide a full memory map may still provide this.
+#[derive(Clone, Copy)]
+pub struct BasicMemoryInfoTag {
+    /// The number of kilobytes of memory that start at address 0, which is at most 640
+    pub mem_lower: u32,
+    /// The number of kilobytes of memory that start at 1 MB, up to the first hole in it
+    pub mem_upper: u32,
+}
+
+impl TryFrom<&[u8]> for BasicMemoryInfoTag {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() < size_of::<BasicMemoryInfoHeader>() {
+            Err(())
+        } else {
+            let header = unsafe {
+                &*aligned_pointer_cast::<BasicMemoryInfoHeader>(value.as_ptr()).ok_or(())?
+            };
+            Ok(Self {
+                mem_lower: header.mem_lower,
+                mem_upper: header.mem_upper,
+            })
+        }
+    }
+}
+
+impl MutibootTag<'_> for BasicMemoryInfoTag {
+    const TAG_TYPE: u32 = 4;
+}
+
+/// A multiboot2 info tag saying which BIOS disk the boot image was loaded from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootDeviceTag {
+    /// The BIOS drive number, such as 0x80 for the first hard disk
+    pub biosdev: u32,
+    /// The top level partition, if the image was loaded from one
+    pub partition: Option<u32>,
+    /// The partition within `partition`, if the image was loaded from one
+    pub sub_partition: Option<u32>,
+}
+
+impl TryFrom<&[u8]> for BootDeviceTag {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        // The header is padded out to its alignment, but the tag stops after the last field
+        if value.len() < offset_of!(BootDeviceHeader, sub_partition) + size_of::<u32>() {
+            Err(())
+        } else {
+            let header = aligned_pointer_cast::<BootDeviceHeader>(value.as_ptr()).ok_or(())?;
+            // The fields are read without making a reference to the whole header, since the
+            // padding isn't part of the tag
+            let (biosdev, partition, sub_partition) = unsafe {
+                (
+                    addr_of!((*header).biosdev).read(),
+                    addr_of!((*header).partition).read(),
+                    addr_of!((*header).sub_partition).read(),
+                )
+            };
+            let used = |number| Some(number).filter(|&number| number != UNUSED_PARTITION);
+            Ok(Self {
+                biosdev,
+                partition: used(partition),
+                sub_partition: used(sub_partition),
+            })
+        }
+    }
+}
+
+impl MutibootTag<'_> for BootDeviceTag {
+    const TAG_TYPE: u32 = 5;
+}
+
+/// A multiboot2 info tag containing the section headers of the kernel's ELF image
+#[derive(Clone, Copy)]
+pub struct ElfSectionsTag<'a> {
+    /// The index of the section that holds the section names
+    pub shndx: u32,
+    /// The size of each section header, which says whether they're 32 or 64 bit headers
+    entry_size: usize,
+    /// The section headers. They're only as aligned as the bootloader made them.
+    entries: &'a [u8],
+}
+
+impl<'a> ElfSectionsTag<'a> {
+    /// The kernel's sections, in the order of their section headers
+    pub fn sections(self) -> impl Iterator<Item = ElfSection> + 'a {
+        self.entries
+            .chunks_exact(self.entry_size)
+            .filter_map(ElfSection::parse)
+    }
+
+    /// The section with index `index`
+    #[must_use]
+    pub fn section(self, index: u32) -> Option<ElfSection> {
+        self.sections().nth(usize::try_from(index).ok()?)
+    }
+
+    /// The kernel's symbol table, which is `.symtab` unless the kernel has been stripped
+    #[must_use]
+    pub fn symbol_table(self) -> Option<ElfSection> {
+        self.sections()
+            .find(|section| section.section_type == SHT_SYMTAB)
+    }
+
+    /// The string table that holds the names in the symbol table, which is usually `.strtab`.
+    /// There can be several string tables, so this is the one that the symbol table links to.
+    #[must_use]
+    pub fn symbol_string_table(self) -> Option<ElfSection> {
+        self.section(self.symbol_table()?.link)
+            .filter(|section| section.section_type == SHT_STRTAB)
+    }
+
+    /**
+     * Looks up the name of `section` in the section name table. Returns `None` if there's no
+     * name table or the name isn't valid UTF-8 inside of it.
+     *
+     * # Safety
+     *
+     * The section name table has to be loaded and identity mapped, which bootloaders do when they
+     * pass this tag.
+     */
+    pub unsafe fn section_name(self, section: &ElfSection) -> Option<&'a str> {
+        let names = self
+            .section(self.shndx)
+            .filter(|names| names.section_type == SHT_STRTAB)?;
+        let names = slice::from_raw_parts(
+            phys_to_usize(names.address)? as *const u8,
+            phys_to_usize(names.size)?,
+        );
+        section_name_in(names, section.name_offset)
+    }
+}
+
+/// Reads the NUL terminated name at `offset` in a section name table
+fn section_name_in(names: &[u8], offset: u32) -> Option<&str> {
+    let name = names.get(usize::try_from(offset).ok()?..)?;
+    str::from_utf8(name.split(|&byte| byte == 0).next()?).ok()
+}
+
+impl<'a> TryFrom<&'a [u8]> for ElfSectionsTag<'a> {
+    type Error = ();
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let (num, entry_size, shndx) = (
+            read_u32(value, ELF_SECTIONS_NUM_OFFSET).ok_or(())?,
+            read_u32(value, ELF_SECTIONS_ENTRY_SIZE_OFFSET).ok_or(())?,
+            read_u32(value, ELF_SECTIONS_SHNDX_OFFSET).ok_or(())?,
+        );
+        let entry_size = usize::try_from(entry_size).map_err(|_| ())?;
+        if entry_size != ELF32_SECTION_HEADER_SIZE && entry_size != ELF64_SECTION_HEADER_SIZE {
+            return Err(());
+        }
+        let entries_size = usize::try_from(num)
+            .ok()
+            .and_then(|num| num.checked_mul(entry_size))
+            .ok_or(())?;
+        Ok(Self {
+            shndx,
+            entry_size,
+            entries: value
+                .get(ELF_SECTIONS_HEADERS_OFFSET..)
+                .and_then(|entries| entries.get(..entries_size))
+                .ok_or(())?,
+        })
+    }
+}
+
+impl<'a> MutibootTag<'a> for ElfSectionsTag<'a> {
+    const TAG_TYPE: u32 = 9;
+}
+
+/// A section header from the ELF sections tag. The fields of 32 bit headers are widened to match
+/// 64 bit ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ElfSection {
+    /// Where the section's name starts in the section name table
+    pub name_offset: u32,
+    /// What the section holds, such as a symbol table or a string table
+    pub section_type: u32,
+    /// The `SHF_` flags, which say whether the section is writable, allocated, or executable
+    pub flags: u64,
+    /// The address that the section was loaded at
+    pub address: u64,
+    /// The size of the section in bytes
+    pub size: u64,
+    /// The index of a related section. A symbol table links to the string table with its names.
+    pub link: u32,
+    /// The size of each entry, for sections that hold a table
+    pub entry_size: u64,
+}
+
+impl ElfSection {
+    /// Reads a section header. Whether it's a 32 or 64 bit header depends on its size.
+    fn parse(header: &[u8]) -> Option<Self> {
+        if header.len() == ELF64_SECTION_HEADER_SIZE {
+            Some(Self {
+                name_offset: read_u32(header, 0)?,
+                section_type: read_u32(header, 4)?,
+                flags: read_u64(header, 8)?,
+                address: read_u64(header, 16)?,
+                size: read_u64(header, 32)?,
+                link: read_u32(header, 40)?,
+                entry_size: read_u64(header, 56)?,
+            })
+        } else {
+            Some(Self {
+                name_offset: read_u32(header, 0)?,
+                section_type: read_u32(header, 4)?,
+                flags: read_u32(header, 8)?.into(),
+                address: read_u32(header, 12)?.into(),
+                size: read_u32(header, 20)?.into(),
+                link: read_u32(header, 24)?,
+                entry_size: read_u32(header, 36)?.into(),
+            })
+        }
+    }
+}
+
+/// A multiboot2 info tag containing a copy of the ACPI 1.0 RSDP, which says where the RSDT is.
+/// The tag is only parsed if the RSDP's signature and checksum are valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RsdpV1Tag {
+    /// The firmware vendor. It's usually ASCII, but nothing guarantees that, so it's left as bytes.
+    pub oem_id: [u8; 6],
+    /// The physical address of the RSDT
+    pub rsdt_address: u32,
+}
+
+impl TryFrom<&[u8]> for RsdpV1Tag {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let rsdp = validated_rsdp(value)?;
+        Ok(Self {
+            oem_id: rsdp_oem_id(rsdp)?,
+            rsdt_address: read_u32(rsdp, RSDP_RSDT_ADDRESS_OFFSET).ok_or(())?,
+        })
+    }
+}
+
+impl MutibootTag<'_> for RsdpV1Tag {
+    const TAG_TYPE: u32 = 14;
+}
+
+/// A multiboot2 info tag containing a copy of the ACPI 2.0 or later RSDP, which can also say where
+/// the XSDT is. The tag is only parsed if both of the RSDP's checksums are valid and the length
+/// that it claims fits in the tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RsdpV2Tag {
+    /// The firmware vendor. It's usually ASCII, but nothing guarantees that, so it's left as bytes.
+    pub oem_id: [u8; 6],
+    /// The ACPI revision, which is 2 for every version of ACPI from 2.0 on
+    pub revision: u8,
+    /// The physical address of the RSDT
+    pub rsdt_address: u32,
+    /// The physical address of the XSDT, which may be 0 if the firmware only has an RSDT
+    pub xsdt_address: u64,
+}
+
+impl TryFrom<&[u8]> for RsdpV2Tag {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let rsdp = validated_rsdp(value)?;
+        let length = read_u32(rsdp, RSDP_LENGTH_OFFSET)
+            .and_then(|length| usize::try_from(length).ok())
+            .ok_or(())?;
+        let extended = rsdp
+            .get(..length)
+            .filter(|extended| extended.len() >= RSDP_V2_SIZE)
+            .ok_or(())?;
+        if !acpi_checksum_is_valid(extended) {
+            return Err(());
+        }
+        Ok(Self {
+            oem_id: rsdp_oem_id(rsdp)?,
+            revision: rsdp[RSDP_REVISION_OFFSET],
+            rsdt_address: read_u32(rsdp, RSDP_RSDT_ADDRESS_OFFSET).ok_or(())?,
+            xsdt_address: read_u64(extended, RSDP_XSDT_ADDRESS_OFFSET).ok_or(())?,
+        })
+    }
+}
+
+impl MutibootTag<'_> for RsdpV2Tag {
+    const TAG_TYPE: u32 = 15;
+}
+
+/// The root of the ACPI tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcpiRoot {
+    /// The physical address of the XSDT, which lists tables by 8 byte addresses
+    Xsdt(u64),
+    /// The physical address of the RSDT, which lists tables by 4 byte addresses
+    Rsdt(u32),
+}
+
+/// Returns the RSDP after the tag header if its signature and the checksum over its ACPI 1.0
+/// fields are valid
+fn validated_rsdp(tag: &[u8]) -> Result<&[u8], ()> {
+    let rsdp = tag.get(size_of::<BootInfoTagHeader>()..).ok_or(())?;
+    let v1 = rsdp.get(..RSDP_V1_SIZE).ok_or(())?;
+    if v1.starts_with(RSDP_SIGNATURE) && acpi_checksum_is_valid(v1) {
+        Ok(rsdp)
+    } else {
+        Err(())
+    }
+}
+
+fn rsdp_oem_id(rsdp: &[u8]) -> Result<[u8; 6], ()> {
+    rsdp.get(RSDP_OEM_ID)
+        .and_then(|oem_id| oem_id.try_into().ok())
+        .ok_or(())
+}
+
+/// The bytes of every ACPI structure add up to 0 modulo 256
+fn acpi_checksum_is_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// A multiboot2 info tag containing the address of the 64-bit EFI system table. The kernel only
+/// passes it along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Efi64SystemTableTag {
+    pub pointer: u64,
+}
+
+impl TryFrom<&[u8]> for Efi64SystemTableTag {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pointer: efi64_pointer(value)?,
+        })
+    }
+}
+
+impl MutibootTag<'_> for Efi64SystemTableTag {
+    const TAG_TYPE: u32 = 12;
+}
+
+/// A multiboot2 info tag containing the 64-bit EFI image handle of the boot image. The kernel only
+/// passes it along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Efi64ImageHandleTag {
+    pub pointer: u64,
+}
+
+impl TryFrom<&[u8]> for Efi64ImageHandleTag {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pointer: efi64_pointer(value)?,
+        })
+    }
+}
+
+impl MutibootTag<'_> for Efi64ImageHandleTag {
+    const TAG_TYPE: u32 = 20;
+}
+
+/// A multiboot2 info tag saying that the bootloader left EFI boot services running, which it only
+/// does if the OS image asks it to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EfiBootServicesNotTerminatedTag;
+
+impl TryFrom<&[u8]> for EfiBootServicesNotTerminatedTag {
+    type Error = ();
+
+    fn try_from(_: &[u8]) -> Result<Self, Self::Error> {
+        Ok(Self)
+    }
+}
+
+impl MutibootTag<'_> for EfiBootServicesNotTerminatedTag {
+    const TAG_TYPE: u32 = 18;
+}
+
+/// Reads the 64-bit pointer that the EFI system table and image handle tags hold
+fn efi64_pointer(value: &[u8]) -> Result<u64, ()> {
+    if value.len() < size_of::<Efi64PointerHeader>() {
+        Err(())
+    } else {
+        let header =
+            unsafe { &*aligned_pointer_cast::<Efi64PointerHeader>(value.as_ptr()).ok_or(())? };
+        Ok(header.pointer)
+    }
+}
+
+/// A multiboot2 info tag containing the name of the bootloader that booted the operating system
+pub struct BootLoaderNameTag<'a> {
+    pub name: &'a str,
+}
+
+impl<'a> TryFrom<&'a [u8]> for BootLoaderNameTag<'a> {
+    type Error = ();
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let (_, name) = value
+            .split_first_chunk::<{ size_of::<BootInfoTagHeader>() }>()
+            .ok_or(())?;
+        // The name ends at the first NUL, or at the end of the tag if a bootloader leaves it out
+        let name = name.split(|&byte| byte == 0).next().ok_or(())?;
+        Ok(Self {
+            name: str::from_utf8(name).map_err(|_| ())?,
+        })
+    }
+}
+
+impl<'a> MutibootTag<'a> for BootLoaderNameTag<'a> {
+    const TAG_TYPE: u32 = 2;
+}
+
 /// A multiboot2 info tag containing information about the framebuffer
 pub struct FramebufferTag<'a> {
     /// A pointer to the framebuffer
@@ -153,8 +749,22 @@ impl<'a> TryFrom<&'a [u8]> for FramebufferTag<'a> {
             let header = unsafe {
                 &*aligned_pointer_cast::<FramebufferTagHeader>(value.as_ptr()).ok_or(())?
             };
+            // Reject dimensions that a buggy bootloader could report so that the framebuffer
+            // can't cover an absurd amount of memory
+            let size = (header.pitch as usize)
+                .checked_mul(header.height as usize)
+                .ok_or(())?;
+            let framebuffer = phys_to_usize(header.framebuffer).ok_or(())?;
+            if header.width == 0
+                || header.height == 0
+                || header.bits_per_pixel == 0
+                || header.bits_per_pixel > 64
+                || framebuffer.checked_add(size).is_none()
+            {
+                return Err(());
+            }
             Ok(Self {
-                framebuffer: header.framebuffer as *mut u8,
+                framebuffer: framebuffer as *mut u8,
                 pitch: header.pitch,
                 width: header.width,
                 height: header.height,
@@ -202,6 +812,55 @@ impl<'a> BootInformation<'a> {
         })
     }
 
+    /// The BIOS disk that the boot image was loaded from, if the bootloader says
+    #[must_use]
+    pub fn boot_device(self) -> Option<BootDeviceTag> {
+        self.tags_of_type::<BootDeviceTag>().next()
+    }
+
+    /// Whether the boot image was loaded by an EFI bootloader, which is the case if the bootloader
+    /// passed along the EFI system table or image handle or left boot services running
+    #[must_use]
+    pub fn is_efi_boot(self) -> bool {
+        self.tags_of_type::<Efi64SystemTableTag>().next().is_some()
+            || self.tags_of_type::<Efi64ImageHandleTag>().next().is_some()
+            || self
+                .tags_of_type::<EfiBootServicesNotTerminatedTag>()
+                .next()
+                .is_some()
+    }
+
+    /// The ACPI 2.0 or later RSDP, if the bootloader passed along a valid one
+    #[must_use]
+    pub fn rsdp_v2(self) -> Option<RsdpV2Tag> {
+        self.tags_of_type::<RsdpV2Tag>().next()
+    }
+
+    /// Where the ACPI tables are listed. The XSDT is preferred, then the RSDT from the ACPI 2.0
+    /// RSDP, and then the RSDT from the ACPI 1.0 RSDP.
+    #[must_use]
+    pub fn acpi_root(self) -> Option<AcpiRoot> {
+        match self.rsdp_v2() {
+            Some(rsdp) if rsdp.xsdt_address != 0 => Some(AcpiRoot::Xsdt(rsdp.xsdt_address)),
+            Some(rsdp) => Some(AcpiRoot::Rsdt(rsdp.rsdt_address)),
+            None => self.rsdp_v1().map(|rsdp| AcpiRoot::Rsdt(rsdp.rsdt_address)),
+        }
+    }
+
+    /// The ACPI 1.0 RSDP, if the bootloader passed along a valid one
+    #[must_use]
+    pub fn rsdp_v1(self) -> Option<RsdpV1Tag> {
+        self.tags_of_type::<RsdpV1Tag>().next()
+    }
+
+    /// The name of the bootloader, if it passed one along
+    #[must_use]
+    pub fn boot_loader_name(self) -> Option<&'a str> {
+        self.tags_of_type::<BootLoaderNameTag>()
+            .next()
+            .map(|tag| tag.name)
+    }
+
     pub fn address_range(self) -> Range<usize> {
         let tag_range = self.tags.as_ptr_range();
         tag_range.start as usize - size_of::<BootInformationHeader>()..tag_range.end as usize
@@ -221,6 +880,27 @@ pub struct MultibootTagIterator<'a> {
     tags: &'a [u8],
 }
 
+impl MultibootTagIterator<'_> {
+    /// Counts the tags that have not been visited yet without advancing the iterator.
+    #[must_use]
+    pub fn count_remaining(&self) -> usize {
+        MultibootTagIterator { tags: self.tags }.count()
+    }
+
+    /// Advances the iterator so that the next tag it returns is the next tag of type `tag_type`.
+    /// If there are no more tags of that type then the iterator is exhausted.
+    pub fn skip_to_type(&mut self, tag_type: u32) {
+        let mut lookahead = MultibootTagIterator { tags: self.tags };
+        while let Some(tag) = lookahead.next() {
+            if tag.tag_type == tag_type {
+                return;
+            }
+            self.tags = lookahead.tags;
+        }
+        self.tags = &[];
+    }
+}
+
 impl<'a> Iterator for MultibootTagIterator<'a> {
     type Item = BootInfoTag<'a>;
 
@@ -250,6 +930,31 @@ impl<'a> Iterator for MultibootTagIterator<'a> {
     }
 }
 
+/// The widest address that `phys_to_usize` accepts. Host tests pretend to run on a 32 bit machine
+/// so that they can hand the boot path values that don't fit.
+#[cfg(not(test))]
+type NativeAddress = usize;
+#[cfg(test)]
+type NativeAddress = u32;
+
+/// Converts a physical address or size reported by the firmware to a `usize`. Returns `None` if it
+/// doesn't fit in the address space.
+#[must_use]
+pub fn phys_to_usize(value: u64) -> Option<usize> {
+    NativeAddress::try_from(value).ok()?;
+    usize::try_from(value).ok()
+}
+
+/// Reads a `u32` from `bytes` at `offset`, which doesn't have to be aligned
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(*bytes.get(offset..)?.first_chunk()?))
+}
+
+/// Reads a `u64` from `bytes` at `offset`, which doesn't have to be aligned
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_ne_bytes(*bytes.get(offset..)?.first_chunk()?))
+}
+
 pub fn aligned_pointer_cast<T>(pointer: *const u8) -> Option<*const T> {
     let new_pointer = pointer.cast::<T>();
     if new_pointer.is_aligned() {
@@ -284,9 +989,844 @@ struct MemoryMapHeader {
     entry_version: u32,
 }
 
+#[repr(C)]
+struct BasicMemoryInfoHeader {
+    tag_header: BootInfoTagHeader,
+    mem_lower: u32,
+    mem_upper: u32,
+}
+
+#[repr(C)]
+struct BootDeviceHeader {
+    tag_header: BootInfoTagHeader,
+    biosdev: u32,
+    partition: u32,
+    sub_partition: u32,
+}
+
+#[repr(C)]
+struct Efi64PointerHeader {
+    tag_header: BootInfoTagHeader,
+    pointer: u64,
+}
+
 #[repr(C)]
 struct BootModuleHeader {
     tag_header: BootInfoTagHeader,
     mod_start: u32,
     mod_end: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{vec, vec::Vec};
+
+    const OEM_ID: [u8; 6] = *b"MICROS";
+    const RSDP_CHECKSUM_OFFSET: usize = 8;
+    const RSDP_EXTENDED_CHECKSUM_OFFSET: usize = 32;
+    const RSDT_ADDRESS: u32 = 0x7fe_1000;
+    const XSDT_ADDRESS: u64 = 0x7fe_1100;
+    const EFI_POINTER: u64 = 0x7f9_e018;
+
+    /// Lays out tags the way the bootloader does, each padded to 8 bytes. They're kept in `u64`s
+    /// so that the first one is aligned.
+    fn tags(tags: &[(u32, &[u8])]) -> Vec<u64> {
+        let mut bytes = Vec::new();
+        for (tag_type, data) in tags {
+            bytes.extend(tag_type.to_ne_bytes());
+            bytes.extend(
+                u32::try_from(size_of::<BootInfoTagHeader>() + data.len())
+                    .unwrap()
+                    .to_ne_bytes(),
+            );
+            bytes.extend(*data);
+            bytes.resize(bytes.len().next_multiple_of(8), 0);
+        }
+        bytes
+            .chunks(8)
+            .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn boot_info(tags: &[u64]) -> BootInformation<'_> {
+        BootInformation {
+            tags: unsafe { slice::from_raw_parts(tags.as_ptr().cast(), size_of_val(tags)) },
+        }
+    }
+
+    /// Sets the checksum byte at `index` so that `bytes` add up to 0
+    fn fix_checksum(bytes: &mut [u8], index: usize) {
+        bytes[index] = 0;
+        bytes[index] = 0u8.wrapping_sub(bytes.iter().fold(0, |sum, byte| sum.wrapping_add(*byte)));
+    }
+
+    fn rsdp_v1(oem_id: [u8; 6]) -> Vec<u8> {
+        let mut rsdp = Vec::from(RSDP_SIGNATURE);
+        rsdp.push(0);
+        rsdp.extend(oem_id);
+        rsdp.push(0);
+        rsdp.extend(RSDT_ADDRESS.to_ne_bytes());
+        fix_checksum(&mut rsdp, RSDP_CHECKSUM_OFFSET);
+        rsdp
+    }
+
+    fn rsdp_v2(xsdt_address: u64) -> Vec<u8> {
+        let mut rsdp = rsdp_v1(OEM_ID);
+        rsdp[RSDP_REVISION_OFFSET] = 2;
+        rsdp.extend(u32::try_from(RSDP_V2_SIZE).unwrap().to_ne_bytes());
+        rsdp.extend(xsdt_address.to_ne_bytes());
+        rsdp.extend([0; 4]);
+        fix_checksum(&mut rsdp[..RSDP_V1_SIZE], RSDP_CHECKSUM_OFFSET);
+        fix_checksum(&mut rsdp, RSDP_EXTENDED_CHECKSUM_OFFSET);
+        rsdp
+    }
+
+    #[test]
+    fn valid_rsdp_v1_is_parsed() {
+        let tags = tags(&[(RsdpV1Tag::TAG_TYPE, &rsdp_v1(OEM_ID))]);
+        let boot_info = boot_info(&tags);
+        assert_eq!(
+            boot_info.rsdp_v1(),
+            Some(RsdpV1Tag {
+                oem_id: OEM_ID,
+                rsdt_address: RSDT_ADDRESS,
+            })
+        );
+        assert_eq!(boot_info.acpi_root(), Some(AcpiRoot::Rsdt(RSDT_ADDRESS)));
+    }
+
+    #[test]
+    fn oem_id_does_not_have_to_be_utf8() {
+        let oem_id = [0xff, 0xfe, b'O', b'E', b'M', 0];
+        let tags = tags(&[(RsdpV1Tag::TAG_TYPE, &rsdp_v1(oem_id))]);
+        let boot_info = boot_info(&tags);
+        assert_eq!(boot_info.rsdp_v1().map(|rsdp| rsdp.oem_id), Some(oem_id));
+        assert_eq!(boot_info.acpi_root(), Some(AcpiRoot::Rsdt(RSDT_ADDRESS)));
+    }
+
+    #[test]
+    fn rsdp_v1_with_wrong_signature_is_rejected() {
+        let mut rsdp = rsdp_v1(OEM_ID);
+        rsdp[0] = b'X';
+        fix_checksum(&mut rsdp, RSDP_CHECKSUM_OFFSET);
+        let tags = tags(&[(RsdpV1Tag::TAG_TYPE, &rsdp)]);
+        assert_eq!(boot_info(&tags).rsdp_v1(), None);
+        assert_eq!(boot_info(&tags).acpi_root(), None);
+    }
+
+    #[test]
+    fn rsdp_v1_with_wrong_checksum_is_rejected() {
+        let mut rsdp = rsdp_v1(OEM_ID);
+        rsdp[RSDP_CHECKSUM_OFFSET] ^= 1;
+        let tags = tags(&[(RsdpV1Tag::TAG_TYPE, &rsdp)]);
+        assert_eq!(boot_info(&tags).rsdp_v1(), None);
+        assert_eq!(boot_info(&tags).acpi_root(), None);
+    }
+
+    #[test]
+    fn truncated_rsdp_v1_is_rejected() {
+        let rsdp = rsdp_v1(OEM_ID);
+        let tags = tags(&[(RsdpV1Tag::TAG_TYPE, &rsdp[..RSDP_V1_SIZE - 1])]);
+        assert_eq!(boot_info(&tags).rsdp_v1(), None);
+    }
+
+    #[test]
+    fn valid_rsdp_v2_is_parsed() {
+        let tags = tags(&[(RsdpV2Tag::TAG_TYPE, &rsdp_v2(XSDT_ADDRESS))]);
+        let boot_info = boot_info(&tags);
+        assert_eq!(
+            boot_info.rsdp_v2(),
+            Some(RsdpV2Tag {
+                oem_id: OEM_ID,
+                revision: 2,
+                rsdt_address: RSDT_ADDRESS,
+                xsdt_address: XSDT_ADDRESS,
+            })
+        );
+        assert_eq!(boot_info.acpi_root(), Some(AcpiRoot::Xsdt(XSDT_ADDRESS)));
+    }
+
+    #[test]
+    fn rsdp_v2_without_xsdt_falls_back_to_its_rsdt() {
+        let tags = tags(&[(RsdpV2Tag::TAG_TYPE, &rsdp_v2(0))]);
+        assert_eq!(
+            boot_info(&tags).acpi_root(),
+            Some(AcpiRoot::Rsdt(RSDT_ADDRESS))
+        );
+    }
+
+    #[test]
+    fn rsdp_v2_is_preferred_over_rsdp_v1() {
+        let v1 = rsdp_v1(OEM_ID);
+        let v2 = rsdp_v2(XSDT_ADDRESS);
+        let v1_first = tags(&[(RsdpV1Tag::TAG_TYPE, &v1), (RsdpV2Tag::TAG_TYPE, &v2)]);
+        let v2_first = tags(&[(RsdpV2Tag::TAG_TYPE, &v2), (RsdpV1Tag::TAG_TYPE, &v1)]);
+        for tags in [v1_first, v2_first] {
+            let boot_info = boot_info(&tags);
+            assert!(boot_info.rsdp_v1().is_some());
+            assert_eq!(boot_info.acpi_root(), Some(AcpiRoot::Xsdt(XSDT_ADDRESS)));
+        }
+    }
+
+    #[test]
+    fn rsdp_v2_with_wrong_extended_checksum_is_rejected() {
+        let mut v2 = rsdp_v2(XSDT_ADDRESS);
+        v2[RSDP_EXTENDED_CHECKSUM_OFFSET] ^= 1;
+        let v2_only = tags(&[(RsdpV2Tag::TAG_TYPE, &v2)]);
+        assert_eq!(boot_info(&v2_only).rsdp_v2(), None);
+        assert_eq!(boot_info(&v2_only).acpi_root(), None);
+
+        let with_v1 = tags(&[
+            (RsdpV2Tag::TAG_TYPE, &v2),
+            (RsdpV1Tag::TAG_TYPE, &rsdp_v1(OEM_ID)),
+        ]);
+        assert_eq!(
+            boot_info(&with_v1).acpi_root(),
+            Some(AcpiRoot::Rsdt(RSDT_ADDRESS))
+        );
+    }
+
+    #[test]
+    fn rsdp_v2_longer_than_its_tag_is_rejected() {
+        let mut v2 = rsdp_v2(XSDT_ADDRESS);
+        // The tag is padded with 4 zeroes, so the checksum would still add up if they were read
+        v2[RSDP_LENGTH_OFFSET..RSDP_LENGTH_OFFSET + 4]
+            .copy_from_slice(&u32::try_from(RSDP_V2_SIZE + 4).unwrap().to_ne_bytes());
+        fix_checksum(&mut v2, RSDP_EXTENDED_CHECKSUM_OFFSET);
+        let tags = tags(&[(RsdpV2Tag::TAG_TYPE, &v2)]);
+        assert_eq!(boot_info(&tags).rsdp_v2(), None);
+    }
+
+    #[test]
+    fn rsdp_v2_shorter_than_acpi_2_is_rejected() {
+        let mut v2 = rsdp_v2(XSDT_ADDRESS);
+        v2[RSDP_LENGTH_OFFSET..RSDP_LENGTH_OFFSET + 4]
+            .copy_from_slice(&u32::try_from(RSDP_V1_SIZE).unwrap().to_ne_bytes());
+        fix_checksum(&mut v2, RSDP_EXTENDED_CHECKSUM_OFFSET);
+        let tags = tags(&[(RsdpV2Tag::TAG_TYPE, &v2)]);
+        assert_eq!(boot_info(&tags).rsdp_v2(), None);
+    }
+
+    #[test]
+    fn efi_system_table_is_parsed() {
+        let tags = tags(&[(Efi64SystemTableTag::TAG_TYPE, &EFI_POINTER.to_ne_bytes())]);
+        let boot_info = boot_info(&tags);
+        assert_eq!(
+            boot_info.tags_of_type::<Efi64SystemTableTag>().next(),
+            Some(Efi64SystemTableTag {
+                pointer: EFI_POINTER
+            })
+        );
+        assert!(boot_info.is_efi_boot());
+    }
+
+    #[test]
+    fn efi_image_handle_is_parsed() {
+        let tags = tags(&[(Efi64ImageHandleTag::TAG_TYPE, &EFI_POINTER.to_ne_bytes())]);
+        let boot_info = boot_info(&tags);
+        assert_eq!(
+            boot_info.tags_of_type::<Efi64ImageHandleTag>().next(),
+            Some(Efi64ImageHandleTag {
+                pointer: EFI_POINTER
+            })
+        );
+        assert!(boot_info.is_efi_boot());
+    }
+
+    #[test]
+    fn boot_services_not_terminated_means_efi_boot() {
+        let tags = tags(&[(EfiBootServicesNotTerminatedTag::TAG_TYPE, &[])]);
+        assert!(boot_info(&tags).is_efi_boot());
+    }
+
+    #[test]
+    fn truncated_efi_pointer_tags_are_rejected() {
+        let data = EFI_POINTER.to_ne_bytes();
+        let tags = tags(&[
+            (Efi64SystemTableTag::TAG_TYPE, &data[..data.len() - 1]),
+            (Efi64ImageHandleTag::TAG_TYPE, &[]),
+        ]);
+        let boot_info = boot_info(&tags);
+        assert_eq!(boot_info.tags_of_type::<Efi64SystemTableTag>().next(), None);
+        assert_eq!(boot_info.tags_of_type::<Efi64ImageHandleTag>().next(), None);
+        assert!(!boot_info.is_efi_boot());
+    }
+
+    #[test]
+    fn bios_boot_is_not_efi_boot() {
+        let tags = tags(&[(RsdpV1Tag::TAG_TYPE, &rsdp_v1(OEM_ID))]);
+        assert!(!boot_info(&tags).is_efi_boot());
+        assert!(!boot_info(&[]).is_efi_boot());
+    }
+
+    const SHT_PROGBITS: u32 = 1;
+    const SHF_ALLOC_EXECINSTR: u64 = 0x6;
+
+    /// A 64 bit section header
+    fn section64(
+        name_offset: u32,
+        section_type: u32,
+        address: u64,
+        size: u64,
+        link: u32,
+    ) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend(name_offset.to_ne_bytes());
+        header.extend(section_type.to_ne_bytes());
+        header.extend(SHF_ALLOC_EXECINSTR.to_ne_bytes());
+        header.extend(address.to_ne_bytes());
+        // The offset in the file
+        header.extend(0x1000u64.to_ne_bytes());
+        header.extend(size.to_ne_bytes());
+        header.extend(link.to_ne_bytes());
+        // The extra information and the alignment
+        header.extend(0u32.to_ne_bytes());
+        header.extend(16u64.to_ne_bytes());
+        header.extend(24u64.to_ne_bytes());
+        assert_eq!(header.len(), ELF64_SECTION_HEADER_SIZE);
+        header
+    }
+
+    /// A 32 bit section header
+    fn section32(
+        name_offset: u32,
+        section_type: u32,
+        address: u32,
+        size: u32,
+        link: u32,
+    ) -> Vec<u8> {
+        let flags = u32::try_from(SHF_ALLOC_EXECINSTR).unwrap();
+        let fields = [
+            name_offset,
+            section_type,
+            flags,
+            address,
+            0x1000,
+            size,
+            link,
+            0,
+            16,
+            24,
+        ];
+        let header: Vec<u8> = fields
+            .iter()
+            .flat_map(|field| field.to_ne_bytes())
+            .collect();
+        assert_eq!(header.len(), ELF32_SECTION_HEADER_SIZE);
+        header
+    }
+
+    /// The data of an ELF sections tag after its header
+    fn elf_sections(num: u32, entry_size: usize, shndx: u32, headers: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(num.to_ne_bytes());
+        data.extend(u32::try_from(entry_size).unwrap().to_ne_bytes());
+        data.extend(shndx.to_ne_bytes());
+        data.extend(headers.concat());
+        data
+    }
+
+    /**
+     * The headers of a kernel with a symbol table whose string table comes after the section name
+     * table, so that the first string table isn't the one that the symbol table links to. Sections
+     * are given as `(name_offset, section_type, address, size, link)`.
+     */
+    const SECTIONS: [(u32, u32, u32, u32, u32); 5] = [
+        (0, 0, 0, 0, 0),
+        (1, SHT_PROGBITS, 0x10_0000, 0x5000, 0),
+        (7, SHT_SYMTAB, 0, 0x900, 4),
+        (15, SHT_STRTAB, 0, 0x30, 0),
+        (25, SHT_STRTAB, 0, 0x700, 0),
+    ];
+    const SHNDX: u32 = 3;
+
+    /// Puts an empty tag header in front of `data`, since tags are parsed with their header
+    fn with_header(data: &[u8]) -> Vec<u8> {
+        let mut value = vec![0; size_of::<BootInfoTagHeader>()];
+        value.extend(data);
+        value
+    }
+
+    fn check_kernel_sections(tag: ElfSectionsTag) {
+        assert_eq!(tag.shndx, SHNDX);
+        assert_eq!(tag.sections().count(), SECTIONS.len());
+        assert_eq!(
+            tag.section(1),
+            Some(ElfSection {
+                name_offset: 1,
+                section_type: SHT_PROGBITS,
+                flags: SHF_ALLOC_EXECINSTR,
+                address: 0x10_0000,
+                size: 0x5000,
+                link: 0,
+                entry_size: 24,
+            })
+        );
+        assert_eq!(tag.section(5), None);
+        assert_eq!(
+            tag.symbol_table().map(|section| section.name_offset),
+            Some(7)
+        );
+        assert_eq!(
+            tag.symbol_string_table().map(|section| section.name_offset),
+            Some(25)
+        );
+    }
+
+    #[test]
+    fn sixty_four_bit_section_headers_are_parsed() {
+        let headers: Vec<Vec<u8>> = SECTIONS
+            .iter()
+            .map(|&(name, section_type, address, size, link)| {
+                section64(name, section_type, address.into(), size.into(), link)
+            })
+            .collect();
+        let data = elf_sections(5, ELF64_SECTION_HEADER_SIZE, SHNDX, &headers);
+        let tags = tags(&[(ElfSectionsTag::TAG_TYPE, &data)]);
+        let tag = boot_info(&tags).tags_of_type::<ElfSectionsTag>().next();
+        check_kernel_sections(tag.unwrap());
+    }
+
+    #[test]
+    fn thirty_two_bit_section_headers_are_widened() {
+        let headers: Vec<Vec<u8>> = SECTIONS
+            .iter()
+            .map(|&(name, section_type, address, size, link)| {
+                section32(name, section_type, address, size, link)
+            })
+            .collect();
+        let data = elf_sections(5, ELF32_SECTION_HEADER_SIZE, SHNDX, &headers);
+        let tags = tags(&[(ElfSectionsTag::TAG_TYPE, &data)]);
+        let tag = boot_info(&tags).tags_of_type::<ElfSectionsTag>().next();
+        check_kernel_sections(tag.unwrap());
+    }
+
+    #[test]
+    fn misaligned_section_headers_are_parsed() {
+        let headers = [section64(1, SHT_PROGBITS, 0x10_0000, 0x5000, 0)];
+        let data = elf_sections(1, ELF64_SECTION_HEADER_SIZE, 0, &headers);
+        let mut value = vec![0; size_of::<BootInfoTagHeader>() + 1];
+        value.extend(&data);
+        // Start the tag an odd number of bytes into the buffer
+        let tag = ElfSectionsTag::try_from(&value[1..]).unwrap();
+        assert_ne!(tag.entries.as_ptr().align_offset(align_of::<u64>()), 0);
+        let section = tag.section(0).unwrap();
+        assert_eq!((section.address, section.size), (0x10_0000, 0x5000));
+    }
+
+    #[test]
+    fn unknown_section_header_sizes_are_rejected() {
+        let headers = [section64(1, SHT_PROGBITS, 0x10_0000, 0x5000, 0)];
+        for entry_size in [0, 48, 128] {
+            let data = elf_sections(1, entry_size, 0, &headers);
+            assert!(ElfSectionsTag::try_from(&with_header(&data)[..]).is_err());
+        }
+    }
+
+    #[test]
+    fn more_section_headers_than_fit_in_the_tag_are_rejected() {
+        let headers = [section64(1, SHT_PROGBITS, 0x10_0000, 0x5000, 0)];
+        for num in [2, u32::MAX] {
+            let data = elf_sections(num, ELF64_SECTION_HEADER_SIZE, 0, &headers);
+            assert!(ElfSectionsTag::try_from(&with_header(&data)[..]).is_err());
+        }
+        assert!(ElfSectionsTag::try_from(&with_header(&[0; 8])[..]).is_err());
+    }
+
+    #[test]
+    fn symbol_string_table_is_the_one_the_symbol_table_links_to() {
+        // The symbol table links to a section that isn't a string table
+        let headers = [
+            section64(0, 0, 0, 0, 0),
+            section64(1, SHT_SYMTAB, 0, 0x900, 0),
+            section64(9, SHT_STRTAB, 0, 0x700, 0),
+        ];
+        let data = elf_sections(3, ELF64_SECTION_HEADER_SIZE, 0, &headers);
+        let value = with_header(&data);
+        let tag = ElfSectionsTag::try_from(&value[..]).unwrap();
+        assert!(tag.symbol_table().is_some());
+        assert_eq!(tag.symbol_string_table(), None);
+        // A stripped kernel has no symbol table
+        let data = elf_sections(1, ELF64_SECTION_HEADER_SIZE, 0, &headers[2..]);
+        let value = with_header(&data);
+        let tag = ElfSectionsTag::try_from(&value[..]).unwrap();
+        assert_eq!(tag.symbol_table(), None);
+        assert_eq!(tag.symbol_string_table(), None);
+    }
+
+    #[test]
+    fn section_names_are_read_from_the_name_table() {
+        let names = Vec::from(*b"\0.text\0.symtab\0\xff\0");
+        assert_eq!(section_name_in(&names, 1), Some(".text"));
+        assert_eq!(section_name_in(&names, 7), Some(".symtab"));
+        assert_eq!(section_name_in(&names, 0), Some(""));
+        assert_eq!(section_name_in(&names, 15), None);
+        assert_eq!(section_name_in(&names, 100), None);
+    }
+
+    #[test]
+    fn section_names_need_a_string_table() {
+        // The name table index points at a section that isn't a string table, so nothing is read
+        // from its address
+        let headers = [section64(1, SHT_PROGBITS, 0x10_0000, 0x5000, 0)];
+        let data = elf_sections(1, ELF64_SECTION_HEADER_SIZE, 0, &headers);
+        let value = with_header(&data);
+        let tag = ElfSectionsTag::try_from(&value[..]).unwrap();
+        let section = tag.section(0).unwrap();
+        assert_eq!(unsafe { tag.section_name(&section) }, None);
+    }
+
+    #[test]
+    fn boot_loader_name_stops_at_its_nul() {
+        let tags = tags(&[(BootLoaderNameTag::TAG_TYPE, b"GRUB 2.12\0junk")]);
+        assert_eq!(boot_info(&tags).boot_loader_name(), Some("GRUB 2.12"));
+    }
+
+    #[test]
+    fn boot_loader_name_without_a_nul_stops_at_the_end_of_its_tag() {
+        // The name fills the tag to a multiple of 8 bytes, so the next tag's type comes straight
+        // after it with no padding in between
+        let unterminated = tags(&[
+            (BootLoaderNameTag::TAG_TYPE, b"GRUB2.12"),
+            (CommandLineTag::TAG_TYPE, b"quiet\0"),
+        ]);
+        assert_eq!(
+            boot_info(&unterminated).boot_loader_name(),
+            Some("GRUB2.12")
+        );
+        let empty = tags(&[(BootLoaderNameTag::TAG_TYPE, b"")]);
+        assert_eq!(boot_info(&empty).boot_loader_name(), Some(""));
+    }
+
+    #[test]
+    fn boot_loader_name_that_is_not_utf8_is_rejected() {
+        let tags = tags(&[(BootLoaderNameTag::TAG_TYPE, b"GRUB \xff\0")]);
+        assert_eq!(boot_info(&tags).boot_loader_name(), None);
+    }
+
+    fn boot_device(biosdev: u32, partition: u32, sub_partition: u32) -> Vec<u8> {
+        [biosdev, partition, sub_partition]
+            .iter()
+            .flat_map(|field| field.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn unused_partitions_are_none() {
+        let whole_disk = tags(&[(
+            BootDeviceTag::TAG_TYPE,
+            &boot_device(0x80, UNUSED_PARTITION, UNUSED_PARTITION),
+        )]);
+        assert_eq!(
+            boot_info(&whole_disk).boot_device(),
+            Some(BootDeviceTag {
+                biosdev: 0x80,
+                partition: None,
+                sub_partition: None,
+            })
+        );
+        let partitioned = tags(&[(
+            BootDeviceTag::TAG_TYPE,
+            &boot_device(0x81, 1, UNUSED_PARTITION),
+        )]);
+        assert_eq!(
+            boot_info(&partitioned).boot_device(),
+            Some(BootDeviceTag {
+                biosdev: 0x81,
+                partition: Some(1),
+                sub_partition: None,
+            })
+        );
+    }
+
+    #[test]
+    fn boot_device_tag_is_not_padded_to_its_header() {
+        let tags = tags(&[(BootDeviceTag::TAG_TYPE, &boot_device(0x80, 0, 2))]);
+        let tag = boot_info(&tags).into_iter().next().unwrap();
+        // The tag stops after its last field, short of the padding that the header struct has
+        assert_eq!(tag.data.len(), 20);
+        assert!(tag.data.len() < size_of::<BootDeviceHeader>());
+        assert_eq!(
+            BootDeviceTag::try_from(tag.data),
+            Ok(BootDeviceTag {
+                biosdev: 0x80,
+                partition: Some(0),
+                sub_partition: Some(2),
+            })
+        );
+        assert_eq!(BootDeviceTag::try_from(&tag.data[..16]), Err(()));
+    }
+
+    fn memory_map_entry(base_addr: u64, length: u64) -> MemoryMapEntry {
+        MemoryMapEntry {
+            base_addr,
+            length,
+            region_type: AVAILABLE_MEMORY,
+            reserved: 0,
+        }
+    }
+
+    /// A 32 bit RGB framebuffer at `address` with 768 rows of 1024 pixels
+    fn framebuffer(address: u64) -> Vec<u8> {
+        let mut data = Vec::from(address.to_ne_bytes());
+        for value in [4096u32, 1024, 768] {
+            data.extend(value.to_ne_bytes());
+        }
+        // The bits per pixel, the framebuffer type, and the reserved bytes
+        data.extend([32, 1, 0, 0]);
+        data
+    }
+
+    /// A command line tag, two memory map tags, and a boot loader name tag between them
+    fn mixed_tags() -> Vec<u64> {
+        tags(&[
+            (CommandLineTag::TAG_TYPE, b"quiet\0"),
+            (MemoryMapTag::TAG_TYPE, b"first"),
+            (BootLoaderNameTag::TAG_TYPE, b"GRUB\0"),
+            (MemoryMapTag::TAG_TYPE, b"second"),
+        ])
+    }
+
+    /// The data of a tag after its header
+    fn payload<'a>(tag: &BootInfoTag<'a>) -> &'a [u8] {
+        &tag.data[size_of::<BootInfoTagHeader>()..]
+    }
+
+    #[test]
+    fn counting_the_remaining_tags_does_not_advance_the_iterator() {
+        let tags = mixed_tags();
+        let mut iterator = boot_info(&tags).into_iter();
+        assert_eq!(iterator.count_remaining(), 4);
+        assert_eq!(iterator.count_remaining(), 4);
+        assert_eq!(
+            iterator.next().map(|tag| tag.tag_type),
+            Some(CommandLineTag::TAG_TYPE)
+        );
+        assert_eq!(iterator.count_remaining(), 3);
+    }
+
+    #[test]
+    fn skipping_to_a_type_lands_on_the_next_tag_of_that_type() {
+        let tags = mixed_tags();
+        let mut iterator = boot_info(&tags).into_iter();
+        iterator.skip_to_type(MemoryMapTag::TAG_TYPE);
+        assert_eq!(
+            iterator.next().map(|tag| payload(&tag)),
+            Some(&b"first"[..])
+        );
+        iterator.skip_to_type(MemoryMapTag::TAG_TYPE);
+        assert_eq!(iterator.count_remaining(), 1);
+        assert_eq!(
+            iterator.next().map(|tag| payload(&tag)),
+            Some(&b"second"[..])
+        );
+    }
+
+    #[test]
+    fn skipping_to_a_type_that_is_not_left_exhausts_the_iterator() {
+        let tags = mixed_tags();
+        let mut iterator = boot_info(&tags).into_iter();
+        iterator.skip_to_type(BootLoaderNameTag::TAG_TYPE);
+        iterator.next();
+        iterator.skip_to_type(CommandLineTag::TAG_TYPE);
+        assert_eq!(iterator.count_remaining(), 0);
+        assert!(iterator.next().is_none());
+    }
+
+    const RESERVED_MEMORY: u32 = 2;
+
+    /// Sanitizes a memory map of `(base_addr, length, region_type)` entries into room for `N`
+    /// entries
+    fn sanitize<const N: usize>(entries: &[(u64, u64, u32)]) -> Option<Vec<(u64, u64, u32)>> {
+        let entries: Vec<MemoryMapEntry> = entries
+            .iter()
+            .map(|&(base_addr, length, region_type)| {
+                MemoryMapEntry::new(base_addr, length, region_type)
+            })
+            .collect();
+        let mut sanitized = SanitizedMemoryMap::<N>::new();
+        let memory_map = sanitized.sanitize(MemoryMapTag { entries: &entries })?;
+        Some(
+            memory_map
+                .entries
+                .iter()
+                .map(|entry| (entry.base_addr, entry.length, entry.region_type))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn reserved_memory_wins_over_available_memory_that_overlaps_it() {
+        assert_eq!(
+            sanitize::<8>(&[
+                (0, 0x10_0000, AVAILABLE_MEMORY),
+                (0x8_0000, 0x1_0000, RESERVED_MEMORY),
+            ]),
+            Some(vec![
+                (0, 0x8_0000, AVAILABLE_MEMORY),
+                (0x8_0000, 0x1_0000, RESERVED_MEMORY),
+                (0x9_0000, 0x7_0000, AVAILABLE_MEMORY),
+            ])
+        );
+    }
+
+    #[test]
+    fn defective_memory_wins_over_acpi_memory_that_overlaps_it() {
+        assert_eq!(
+            sanitize::<8>(&[(0x1000, 0x3000, DEFECTIVE_MEMORY), (0, 0x2000, ACPI_MEMORY),]),
+            Some(vec![
+                (0, 0x1000, ACPI_MEMORY),
+                (0x1000, 0x3000, DEFECTIVE_MEMORY),
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_entries_are_left_out() {
+        assert_eq!(
+            sanitize::<8>(&[
+                (0x5000, 0, RESERVED_MEMORY),
+                (0x1000, 0x1000, AVAILABLE_MEMORY),
+                (0x1800, 0, DEFECTIVE_MEMORY),
+            ]),
+            Some(vec![(0x1000, 0x1000, AVAILABLE_MEMORY)])
+        );
+        assert_eq!(
+            sanitize::<8>(&[(0x1000, 0, AVAILABLE_MEMORY)]),
+            Some(vec![])
+        );
+        assert_eq!(sanitize::<8>(&[]), Some(vec![]));
+    }
+
+    #[test]
+    fn entries_are_sorted() {
+        assert_eq!(
+            sanitize::<8>(&[
+                (0x10_0000, 0x10_0000, AVAILABLE_MEMORY),
+                (0xf_0000, 0x1_0000, RESERVED_MEMORY),
+                (0, 0x9_fc00, AVAILABLE_MEMORY),
+            ]),
+            Some(vec![
+                (0, 0x9_fc00, AVAILABLE_MEMORY),
+                (0xf_0000, 0x1_0000, RESERVED_MEMORY),
+                (0x10_0000, 0x10_0000, AVAILABLE_MEMORY),
+            ])
+        );
+    }
+
+    #[test]
+    fn adjacent_entries_of_the_same_type_are_merged() {
+        assert_eq!(
+            sanitize::<8>(&[
+                (0x2000, 0x1000, AVAILABLE_MEMORY),
+                (0, 0x2000, AVAILABLE_MEMORY),
+                (0x3000, 0x1000, ACPI_MEMORY),
+                (0x1000, 0x2000, AVAILABLE_MEMORY),
+            ]),
+            Some(vec![
+                (0, 0x3000, AVAILABLE_MEMORY),
+                (0x3000, 0x1000, ACPI_MEMORY),
+            ])
+        );
+    }
+
+    #[test]
+    fn gaps_are_left_out() {
+        assert_eq!(
+            sanitize::<8>(&[
+                (0, 0x1000, AVAILABLE_MEMORY),
+                (0x3000, 0x1000, AVAILABLE_MEMORY),
+            ]),
+            Some(vec![
+                (0, 0x1000, AVAILABLE_MEMORY),
+                (0x3000, 0x1000, AVAILABLE_MEMORY),
+            ])
+        );
+    }
+
+    #[test]
+    fn too_many_entries_for_the_storage_is_none() {
+        let entries = [
+            (0, 0x1000, AVAILABLE_MEMORY),
+            (0x1000, 0x1000, RESERVED_MEMORY),
+            (0x2000, 0x1000, AVAILABLE_MEMORY),
+        ];
+        assert_eq!(sanitize::<2>(&entries), None);
+        assert_eq!(
+            sanitize::<3>(&entries).map(|entries| entries.len()),
+            Some(3)
+        );
+        // Merged entries only take up one slot
+        let merged = [
+            (0, 0x1000, AVAILABLE_MEMORY),
+            (0x1000, 0x1000, AVAILABLE_MEMORY),
+        ];
+        assert_eq!(
+            sanitize::<1>(&merged),
+            Some(vec![(0, 0x2000, AVAILABLE_MEMORY)])
+        );
+    }
+
+    #[test]
+    fn a_region_ending_at_the_top_of_the_physical_address_space_is_kept() {
+        let top = u64::MAX - 0xfff;
+        assert_eq!(
+            sanitize::<8>(&[
+                (top, 0x1000, RESERVED_MEMORY),
+                (0, 0x1000, AVAILABLE_MEMORY),
+            ]),
+            Some(vec![
+                (0, 0x1000, AVAILABLE_MEMORY),
+                (top, 0xfff, RESERVED_MEMORY),
+            ])
+        );
+        // A region that would wrap around is cut off at the top
+        assert_eq!(
+            sanitize::<8>(&[(top, u64::MAX, RESERVED_MEMORY)]),
+            Some(vec![(top, 0xfff, RESERVED_MEMORY)])
+        );
+    }
+
+    #[test]
+    fn values_past_the_address_space_are_rejected() {
+        // Host tests pretend that the address space ends at 4 GB
+        assert_eq!(phys_to_usize(0xffff_ffff), Some(0xffff_ffff));
+        assert_eq!(phys_to_usize(0x1_0000_0000), None);
+        assert_eq!(phys_to_usize(u64::MAX), None);
+    }
+
+    #[test]
+    fn memory_map_entry_past_the_address_space_is_skipped() {
+        let entry = memory_map_entry(0x1_0000_0000, 0x4000_0000);
+        assert_eq!(entry.address_range(), None);
+        assert!(entry.exceeds_address_space());
+    }
+
+    #[test]
+    fn memory_map_entry_that_runs_past_the_address_space_is_clamped() {
+        let entry = memory_map_entry(0xc000_0000, 0x8000_0000);
+        assert_eq!(
+            entry.address_range().map(|range| range.start),
+            Some(0xc000_0000)
+        );
+        assert!(entry.exceeds_address_space());
+
+        let entry = memory_map_entry(0xc000_0000, 0x3fff_ffff);
+        assert_eq!(entry.address_range(), Some(0xc000_0000..0xffff_ffff));
+        assert!(!entry.exceeds_address_space());
+    }
+
+    #[test]
+    fn framebuffer_past_the_address_space_is_rejected() {
+        let framebuffer_tag = |address| {
+            let tags = tags(&[(FramebufferTag::TAG_TYPE, &framebuffer(address))]);
+            let tag = boot_info(&tags).tags_of_type::<FramebufferTag>().next();
+            tag.map(|tag| tag.framebuffer as usize)
+        };
+        assert_eq!(framebuffer_tag(0xfd00_0000), Some(0xfd00_0000));
+        assert_eq!(framebuffer_tag(0x1_0000_0000), None);
+    }
+}
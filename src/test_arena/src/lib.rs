@@ -0,0 +1,70 @@
+//! Host memory that tests can treat as physical memory. The kernel reaches physical memory through
+//! the identity map, so an address in the arena works anywhere that the kernel expects a physical
+//! address, including in page table entries. It's a crate of its own so that the kernel's and the
+//! frame allocators' tests can share it without linking std into the no_std builds.
+
+use frame_allocation::{page_tables::TWO_MEGABYTES, PhysicalAddress};
+use std::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    ops::Range,
+};
+
+/// A zeroed block of host memory that's aligned to 2 MB so that it can hold big pages
+pub struct Arena {
+    layout: Layout,
+    start: *mut u8,
+}
+
+impl Arena {
+    /**
+     * Allocates an arena of `size` bytes, which is rounded up to a multiple of 2 MB
+     *
+     * # Panics
+     *
+     * Panics if the host can't allocate the arena
+     */
+    #[must_use]
+    pub fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size.next_multiple_of(TWO_MEGABYTES), TWO_MEGABYTES)
+            .expect("the arena is too big");
+        let start = unsafe { alloc_zeroed(layout) };
+        assert!(!start.is_null(), "failed to allocate the arena");
+        Self { layout, start }
+    }
+
+    /// The memory that the arena covers
+    #[must_use]
+    pub fn range(&self) -> Range<usize> {
+        self.start as usize..self.start as usize + self.layout.size()
+    }
+
+    /**
+     * The `frame_size` frame at `index` in the arena
+     *
+     * # Panics
+     *
+     * Panics if the frame doesn't fit in the arena
+     */
+    #[must_use]
+    pub fn frame(&self, frame_size: usize, index: usize) -> PhysicalAddress {
+        let address = self.start as usize + frame_size * index;
+        assert!(
+            address + frame_size <= self.range().end,
+            "frame {index} is outside the arena"
+        );
+        PhysicalAddress::new(address)
+    }
+
+    /// Returns true if the `size` bytes at `address` are all in the arena
+    #[must_use]
+    pub fn contains(&self, address: PhysicalAddress, size: usize) -> bool {
+        let range = self.range();
+        range.start <= address.as_usize() && address.as_usize() + size <= range.end
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.start, self.layout) }
+    }
+}
@@ -0,0 +1,240 @@
+//! Builds a micros image with the kernel's `qemu-test` feature, boots it in QEMU, and works out
+//! from QEMU's exit status and the serial log whether the kernel's tests passed. The kernel reports
+//! each test on a line of its own, in the format described in its `self_test.rs`.
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// QEMU exits with `(code << 1) | 1` when the kernel writes `code` to the isa-debug-exit device.
+/// These match `SUCCESS_CODE` and `FAILURE_CODE` in the kernel's amd64/self_checks.rs.
+pub const PASSED_STATUS: i32 = (0x10 << 1) | 1;
+pub const FAILED_STATUS: i32 = (0x11 << 1) | 1;
+
+/// How long a test boot gets before it's assumed to have hung
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A configuration of the virtual machine that the kernel's tests are run on
+#[derive(Clone, Copy, Debug)]
+pub struct Machine {
+    /// What the machine's tests are reported under
+    pub name: &'static str,
+    /// Arguments passed to QEMU on top of the ones that every test boot uses
+    pub qemu_args: &'static [&'static str],
+}
+
+/// Every machine that the kernel's tests are run on. The processor without NX checks that the
+/// kernel leaves the no-execute bit alone when it isn't there, and QEMU's default processor has
+/// neither SMEP nor SMAP, so they get a machine of their own.
+pub const MACHINES: [Machine; 3] = [
+    Machine {
+        name: "default",
+        qemu_args: &[],
+    },
+    Machine {
+        name: "no_nx",
+        qemu_args: &["-cpu", "qemu64,-nx"],
+    },
+    Machine {
+        name: "smep_smap",
+        qemu_args: &["-cpu", "qemu64,+smep,+smap"],
+    },
+];
+
+/// How a test boot ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every kernel test passed
+    Passed,
+    /// The kernel ran its tests and at least one failed
+    ChecksFailed,
+    /// QEMU exited without the kernel reporting a result, which usually means that the kernel
+    /// triple faulted or turned the machine off after failing to boot. Holds QEMU's exit status if
+    /// it had one.
+    Crashed(Option<i32>),
+    /// The kernel didn't report a result before the timeout
+    TimedOut,
+}
+
+/// The result of booting a test image
+pub struct Run {
+    pub outcome: Outcome,
+    /// Everything that the kernel wrote to the first serial port
+    pub serial_log: String,
+}
+
+impl Run {
+    /// The test results that the kernel reported
+    pub fn report(&self) -> Report {
+        parse_report(&self.serial_log)
+    }
+}
+
+/// How a single kernel test went
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    /// Why the test failed if it did
+    pub result: Result<(), String>,
+}
+
+/// The counts that the kernel reports after running its tests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Everything that the kernel reported about its tests
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub tests: Vec<TestResult>,
+    /// Missing if the kernel stopped before it finished reporting
+    pub summary: Option<Summary>,
+}
+
+impl Report {
+    /// Returns true if the summary agrees with the individual results
+    pub fn is_consistent(&self) -> bool {
+        self.summary.is_some_and(|summary| {
+            let failed = self
+                .tests
+                .iter()
+                .filter(|test| test.result.is_err())
+                .count();
+            summary.failed == failed && summary.passed == self.tests.len() - failed
+        })
+    }
+}
+
+/// Picks the test results out of a serial log. Lines that aren't part of the protocol are ignored.
+pub fn parse_report(serial_log: &str) -> Report {
+    let mut report = Report::default();
+    for line in serial_log.lines() {
+        let line = line.trim_end();
+        if let Some(fields) = line.strip_prefix("TEST ") {
+            report.tests.extend(parse_test(fields));
+        } else if let Some(fields) = line.strip_prefix("SUMMARY ") {
+            report.summary = parse_summary(fields).or(report.summary);
+        }
+    }
+    report
+}
+
+fn parse_test(fields: &str) -> Option<TestResult> {
+    let (name, rest) = fields.strip_prefix("name=")?.split_once(' ')?;
+    let result = match rest.split_once(' ') {
+        None if rest == "result=ok" => Ok(()),
+        Some(("result=fail", detail)) => Err(detail.strip_prefix("detail=")?.to_owned()),
+        None if rest == "result=fail" => Err(String::new()),
+        _ => return None,
+    };
+    Some(TestResult {
+        name: name.to_owned(),
+        result,
+    })
+}
+
+fn parse_summary(fields: &str) -> Option<Summary> {
+    let (passed, failed) = fields.split_once(' ')?;
+    Some(Summary {
+        passed: passed.strip_prefix("passed=")?.parse().ok()?,
+        failed: failed.strip_prefix("failed=")?.parse().ok()?,
+    })
+}
+
+/// Interprets QEMU's exit status. `None` means that QEMU was killed by a signal.
+pub fn interpret_exit_status(status: Option<i32>) -> Outcome {
+    match status {
+        Some(PASSED_STATUS) => Outcome::Passed,
+        Some(FAILED_STATUS) => Outcome::ChecksFailed,
+        other => Outcome::Crashed(other),
+    }
+}
+
+/// The root of the micros repository
+pub fn repository_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+/**
+ * Builds a bootable image with the kernel's `qemu-test` feature and returns its path. The image
+ * is signed with the development key, which the Makefile generates if it doesn't exist.
+ */
+pub fn build_image() -> io::Result<PathBuf> {
+    let root = repository_root();
+    let status = Command::new("make")
+        .current_dir(&root)
+        .args([
+            "iso",
+            "features=micros_kernel/qemu-test",
+            "key=build/tmp.key",
+            "cert=build/tmp.crt",
+        ])
+        // The runner's toolchain would otherwise be used for the kernel too
+        .env_remove("RUSTUP_TOOLCHAIN")
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("make failed with {status}")));
+    }
+    Ok(root.join("build/micros-amd64.iso"))
+}
+
+/// Boots `image` in QEMU on `machine` and waits up to `timeout` for the kernel to report whether
+/// its tests passed
+pub fn boot(image: &Path, machine: &Machine, timeout: Duration) -> io::Result<Run> {
+    let mut qemu = Command::new("qemu-system-x86_64")
+        .arg("-cdrom")
+        .arg(image)
+        .args(machine.qemu_args)
+        .args([
+            "-device",
+            "isa-debug-exit,iobase=0xf4,iosize=0x04",
+            "-display",
+            "none",
+            "-serial",
+            "stdio",
+            "-no-reboot",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut serial = qemu.stdout.take().expect("stdout is piped");
+    // QEMU can fill the pipe before it exits, so the log has to be read while waiting
+    let reader = thread::spawn(move || {
+        let mut log = Vec::new();
+        let _ = serial.read_to_end(&mut log);
+        log
+    });
+    let outcome = match wait_with_timeout(&mut qemu, timeout)? {
+        Some(status) => interpret_exit_status(status.code()),
+        None => {
+            qemu.kill()?;
+            qemu.wait()?;
+            Outcome::TimedOut
+        }
+    };
+    let serial_log = reader.join().unwrap_or_default();
+    Ok(Run {
+        outcome,
+        serial_log: String::from_utf8_lossy(&serial_log).into_owned(),
+    })
+}
+
+/// Waits for `child` to exit. Returns `None` if it's still running after `timeout`.
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,66 @@
+use micros_qemu_tests::{
+    interpret_exit_status, parse_report, Outcome, Summary, TestResult, FAILED_STATUS, PASSED_STATUS,
+};
+
+#[test]
+fn exit_statuses_match_the_kernel() {
+    assert_eq!(interpret_exit_status(Some(PASSED_STATUS)), Outcome::Passed);
+    assert_eq!(
+        interpret_exit_status(Some(FAILED_STATUS)),
+        Outcome::ChecksFailed
+    );
+    // A triple fault with -no-reboot makes QEMU exit normally
+    assert_eq!(interpret_exit_status(Some(0)), Outcome::Crashed(Some(0)));
+    assert_eq!(interpret_exit_status(None), Outcome::Crashed(None));
+}
+
+#[test]
+fn report_is_parsed_from_the_serial_log() {
+    let report = parse_report(
+        "[INFO] Booting\n\
+         TEST name=first result=ok\n\
+         [INFO] TEST name=logged result=ok\n\
+         TEST name=second result=fail detail=the frame was  handed out twice\r\n\
+         SUMMARY passed=1 failed=1\n",
+    );
+    assert_eq!(
+        report.tests,
+        [
+            TestResult {
+                name: "first".into(),
+                result: Ok(()),
+            },
+            TestResult {
+                name: "second".into(),
+                result: Err("the frame was  handed out twice".into()),
+            },
+        ]
+    );
+    assert_eq!(
+        report.summary,
+        Some(Summary {
+            passed: 1,
+            failed: 1
+        })
+    );
+    assert!(report.is_consistent());
+}
+
+#[test]
+fn malformed_lines_are_ignored() {
+    let report = parse_report(
+        "TEST name=first\n\
+         TEST name=second result=maybe\n\
+         TEST result=ok\n\
+         SUMMARY passed=many failed=0\n",
+    );
+    assert!(report.tests.is_empty());
+    assert_eq!(report.summary, None);
+    assert!(!report.is_consistent());
+}
+
+#[test]
+fn summary_has_to_match_the_results() {
+    let report = parse_report("TEST name=first result=ok\nSUMMARY passed=2 failed=0\n");
+    assert!(!report.is_consistent());
+}
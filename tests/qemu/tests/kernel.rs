@@ -0,0 +1,76 @@
+//! Boots the kernel's tests in QEMU and reports each of them like a libtest test, so a failing
+//! kernel test fails `cargo test` under its own name. Arguments that don't start with `-` filter
+//! the reported tests by name the way libtest's filters do. The image is booted once on each
+//! machine in `MACHINES`, and each test is reported under the machine that it ran on.
+
+use micros_qemu_tests::{boot, build_image, Machine, Outcome, Run, DEFAULT_TIMEOUT, MACHINES};
+use std::{env, process::ExitCode};
+
+fn main() -> ExitCode {
+    let filters: Vec<_> = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    let image = build_image().expect("failed to build the test image");
+    let mut passed = true;
+    for machine in &MACHINES {
+        let run = boot(&image, machine, DEFAULT_TIMEOUT).expect("failed to start QEMU");
+        passed &= print_results(machine, &run, &filters);
+    }
+    if passed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Prints the results in libtest's format and returns true if nothing went wrong
+fn print_results(machine: &Machine, run: &Run, filters: &[String]) -> bool {
+    let report = run.report();
+    let prefix = format!("kernel::{}::", machine.name);
+    let tests: Vec<_> = report
+        .tests
+        .iter()
+        .filter(|test| {
+            let name = format!("{prefix}{}", test.name);
+            filters.is_empty() || filters.iter().any(|f| name.contains(f))
+        })
+        .collect();
+    println!("\nrunning {} tests", tests.len());
+    for test in &tests {
+        let status = if test.result.is_ok() { "ok" } else { "FAILED" };
+        println!("test {prefix}{} ... {status}", test.name);
+    }
+    let failures: Vec<_> = tests
+        .iter()
+        .filter_map(|test| Some((&test.name, test.result.as_ref().err()?)))
+        .collect();
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, detail) in &failures {
+            println!("    {prefix}{name}: {detail}");
+        }
+    }
+    // Problems with the run as a whole fail it even when every test that was reported passed
+    let problem = match run.outcome {
+        _ if report.summary.is_none() => Some("the kernel didn't finish reporting its tests"),
+        _ if !report.is_consistent() => Some("the kernel's summary doesn't match its results"),
+        Outcome::ChecksFailed if report.tests.iter().all(|test| test.result.is_ok()) => {
+            Some("the kernel failed outside of its tests")
+        }
+        Outcome::Passed | Outcome::ChecksFailed => None,
+        Outcome::Crashed(_) => Some("QEMU exited without the kernel reporting a result"),
+        Outcome::TimedOut => Some("the kernel didn't report a result before the timeout"),
+    };
+    let passed = failures.is_empty() && problem.is_none();
+    if let Some(problem) = problem {
+        println!("\n{problem}\nserial log:\n{}", run.serial_log);
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed\n",
+        if passed { "ok" } else { "FAILED" },
+        tests.len() - failures.len(),
+        failures.len()
+    );
+    passed
+}